@@ -0,0 +1,69 @@
+//! Parser and resolver benchmarks on synthetic 地図XML.
+//!
+//! `cargo bench --bench parse`, or `cargo bench --bench parse -- resolve` for
+//! one group.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use mojxml::parser::{MojxmlParser, ParseBuffers, jpr_projections};
+use mojxml::synthetic::SyntheticMap;
+
+/// Grids of 100, 2,500 and 10,000 筆 (a real file has a few thousand).
+const SIZES: [(usize, usize); 3] = [(10, 10), (50, 50), (100, 100)];
+
+fn parse(c: &mut Criterion) {
+    let projections = jpr_projections();
+    let mut group = c.benchmark_group("parse");
+    for (rows, columns) in SIZES {
+        let xml = SyntheticMap::new(rows, columns).to_xml();
+        let id = rows * columns;
+        group.throughput(Throughput::Bytes(xml.len() as u64));
+        group.bench_with_input(BenchmarkId::new("reader", id), &xml, |b, xml| {
+            b.iter(|| {
+                MojxmlParser::new(xml.as_bytes(), &projections)
+                    .parse()
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("slice", id), &xml, |b, xml| {
+            let mut buffers = ParseBuffers::default();
+            b.iter(|| {
+                let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+                    .parse_with(&mut buffers)
+                    .unwrap();
+                black_box(data.fudes.len());
+                buffers.recycle(data);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn resolve(c: &mut Criterion) {
+    let projections = jpr_projections();
+    let mut group = c.benchmark_group("resolve");
+    for (rows, columns) in SIZES {
+        let xml = SyntheticMap::new(rows, columns).to_xml();
+        let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .parse()
+            .unwrap();
+        let id = rows * columns;
+        group.throughput(Throughput::Elements(data.fudes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("serial", id), &data, |b, data| {
+            b.iter(|| {
+                data.fudes
+                    .values()
+                    .map(|fude| data.resolve_surface_geo(fude.surface_id).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", id), &data, |b, data| {
+            b.iter(|| data.par_resolve_all())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, resolve);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+//! `cargo +nightly fuzz run parse`: parse arbitrary documents, and resolve
+//! the 筆 of the ones that parse.
+//!
+//! Seed the corpus with real or synthetic files (`fuzz/corpus/parse/`) to
+//! get past the XML syntax quickly.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mojxml::parser::{MojxmlParser, jpr_projections};
+
+fuzz_target!(|document: &[u8]| {
+    let projections = jpr_projections();
+    if let Ok(data) = MojxmlParser::from_slice(document, &projections).parse() {
+        for fude in data.fudes.values() {
+            let _ = data.resolve_surface_geo(fude.surface_id);
+        }
+    }
+});
@@ -0,0 +1,120 @@
+//! `bench` command.
+
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use mojxml::parser::{MojxmlParser, ParseBuffers, jpr_projections};
+use mojxml::writer::geojson::GeoJsonWriter;
+use mojxml::zip::ZipPackageIter;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct BenchArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Only read archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Number of times each stage is run
+    #[arg(long, short = 'n', default_value_t = 5)]
+    iterations: u32,
+}
+
+/// Time each stage of the conversion on an in-memory copy of the package,
+/// on a single thread.
+pub fn run(args: BenchArgs) -> Result<(), BoxError> {
+    if args.iterations == 0 {
+        return Err("--iterations must be at least 1".into());
+    }
+    let mut package = Vec::new();
+    open_input(&args.input_zip)?.read_to_end(&mut package)?;
+    let projections = jpr_projections();
+
+    let mut times = [Duration::ZERO; 4];
+    let mut xml_bytes = 0;
+    let mut files = 0;
+    let mut fudes = 0;
+    let mut buffers = ParseBuffers::default();
+    for _ in 0..args.iterations {
+        // unzip
+        let start = Instant::now();
+        let mut zip = ZipPackageIter::new(Cursor::new(package.as_slice()))?;
+        if !args.entries.is_empty() {
+            let entries = args.entries.clone();
+            zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+        }
+        // broken entries are left out
+        let xmls: Vec<_> = zip.filter_map(Result::ok).collect();
+        times[0] += start.elapsed();
+
+        // parse
+        let start = Instant::now();
+        let parsed = xmls
+            .iter()
+            .map(|(name, xml)| {
+                MojxmlParser::from_slice(xml, &projections)
+                    .parse_with(&mut buffers)
+                    .map_err(|e| format!("{}: {}", name, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        times[1] += start.elapsed();
+
+        // resolve
+        let start = Instant::now();
+        let resolved: Vec<Vec<_>> = parsed
+            .iter()
+            .map(|data| {
+                data.fudes
+                    .values()
+                    .filter_map(|fude| {
+                        Some((fude, data.resolve_surface_geo(fude.surface_id).ok()?))
+                    })
+                    .collect()
+            })
+            .collect();
+        times[2] += start.elapsed();
+
+        // write (GeoJSON into nowhere)
+        let start = Instant::now();
+        let mut writer = GeoJsonWriter::new_seq(std::io::sink());
+        for (fude, polygon) in resolved.into_iter().flatten() {
+            writer.write_fude(fude, &polygon)?;
+        }
+        times[3] += start.elapsed();
+
+        xml_bytes = xmls.iter().map(|(_, xml)| xml.len()).sum::<usize>();
+        files = xmls.len();
+        fudes = parsed.iter().map(|data| data.fudes.len()).sum::<usize>();
+        parsed.into_iter().for_each(|data| buffers.recycle(data));
+    }
+
+    println!(
+        "{} files, {:.1} MB of XML, {} 筆, {} iterations",
+        files,
+        xml_bytes as f64 / 1e6,
+        fudes,
+        args.iterations
+    );
+    println!(
+        "{:<8} {:>12} {:>10} {:>14}",
+        "stage", "ms/iter", "MB/s", "features/s"
+    );
+    let total = times.iter().sum::<Duration>();
+    for (stage, time) in ["unzip", "parse", "resolve", "write", "total"]
+        .into_iter()
+        .zip(times.into_iter().chain([total]))
+    {
+        let secs = time.as_secs_f64() / args.iterations as f64;
+        println!(
+            "{:<8} {:>12.2} {:>10.1} {:>14.0}",
+            stage,
+            secs * 1e3,
+            xml_bytes as f64 / 1e6 / secs,
+            fudes as f64 / secs
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,363 @@
+//! `mojxml-serve`: a small HTTP service for parcel lookups over a package,
+//! loaded into memory at startup.
+//!
+//! - `GET /parcel?lat=..&lon=..`: the 筆 at a position, as a GeoJSON
+//!   FeatureCollection
+//! - `GET /tiles/{z}/{x}/{y}.mvt`: a vector tile of the 筆 (a `fude` layer)
+
+use std::fs::File;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use mojxml::data::Fude;
+use mojxml::dataset::Dataset;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::writer::Schema;
+use mojxml::writer::geojson::GeoJsonWriter;
+use mojxml::writer::pmtiles::{encode_tile, tile_bounds};
+use mojxml::zip::ZipPackageParallelIter;
+use socket2::{Domain, Socket, Type};
+use tiny_http::{Header, Method, Request, Server};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Serve parcel lookups and vector tiles over a package"
+)]
+struct Args {
+    /// Input .zip file
+    #[arg()]
+    input_zip: PathBuf,
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    /// Only load archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Names of the attributes
+    #[arg(long, value_enum, default_value_t = SchemaArg::Japanese)]
+    schema: SchemaArg,
+    /// Simplification tolerance of the tiles, in tile units (of 4096 per tile)
+    #[arg(long, default_value_t = 1.0)]
+    tolerance: f64,
+    /// Number of threads answering the requests (default: number of CPUs)
+    #[arg(long)]
+    workers: Option<usize>,
+    /// Seconds after which a connection that doesn't send its request or
+    /// read its response is closed
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaArg {
+    /// 大字コード, 地番, ...
+    Japanese,
+    /// oaza_code, chiban, ...
+    English,
+}
+
+struct State {
+    dataset: Dataset,
+    schema: Schema,
+    tolerance: f64,
+}
+
+/// A response: status code, content type and body.
+type Response = (u16, &'static str, Vec<u8>);
+
+fn main() -> Result<(), BoxError> {
+    let args = Args::parse();
+    let mut zip = ZipPackageParallelIter::new(File::open(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let options = ParseOptions {
+        // only the 筆 in longitude/latitude can be looked up and tiled
+        skip_arbitrary_crs: true,
+        ..Default::default()
+    };
+    let mut dataset = Dataset::new();
+    for res in zip.parsed(options, Arc::new(jpr_projections())) {
+        match res {
+            Ok((info, data)) => dataset.insert(&info.name, data),
+            Err(e) if e.is_skipped() => {}
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    let schema = match args.schema {
+        SchemaArg::Japanese => Schema::japanese(),
+        SchemaArg::English => Schema::english(),
+    };
+    let server = bind(&args.addr, Duration::from_secs(args.timeout))?;
+    eprintln!(
+        "Loaded {} 筆, listening on http://{}",
+        dataset.len(),
+        server.server_addr()
+    );
+    let state = State {
+        dataset,
+        schema,
+        tolerance: args.tolerance,
+    };
+    let workers = args
+        .workers
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get()));
+    for worker in serve(Arc::new(server), Arc::new(state), workers) {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+/// Listen on `addr`. The connections time out after `timeout` without
+/// reading or writing, as the accepted sockets inherit the timeouts of the
+/// listening one.
+fn bind(addr: &str, timeout: Duration) -> Result<Server, BoxError> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("invalid address: {}", addr))?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Server::from_listener(TcpListener::from(socket), None)
+}
+
+/// Answer the requests with `workers` threads.
+fn serve(server: Arc<Server>, state: Arc<State>, workers: usize) -> Vec<JoinHandle<()>> {
+    (0..workers.max(1))
+        .map(|_| {
+            let (server, state) = (server.clone(), state.clone());
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            })
+        })
+        .collect()
+}
+
+fn handle(state: &State, request: Request) {
+    let (status, content_type, body) = match request.method() {
+        Method::Get => route(state, request.url()),
+        _ => (405, "text/plain", b"GET only\n".to_vec()),
+    };
+    let response = tiny_http::Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", content_type))
+        .with_header(header("Access-Control-Allow-Origin", "*"));
+    if let Err(e) = request.respond(response) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("valid header")
+}
+
+fn route(state: &State, target: &str) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let param = |name: &str| -> Option<f64> {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.parse().ok())
+    };
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments[..] {
+        ["parcel"] => match (param("lat"), param("lon")) {
+            (Some(lat), Some(lon))
+                if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+            {
+                parcels(state, lat, lon)
+            }
+            _ => bad_request("expected the lat and lon parameters, in degrees"),
+        },
+        ["tiles", z, x, y] => {
+            let tile = (|| {
+                let (z, x) = (z.parse::<u8>().ok()?, x.parse::<u32>().ok()?);
+                let y = y.strip_suffix(".mvt")?.parse::<u32>().ok()?;
+                (z <= 24 && x < 1 << z && y < 1 << z).then_some((z, x, y))
+            })();
+            match tile {
+                Some((z, x, y)) => tile_response(state, z, x, y),
+                None => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+/// The 筆 at a position, as a GeoJSON FeatureCollection.
+fn parcels(state: &State, lat: f64, lon: f64) -> Response {
+    let geojson = (|| {
+        let mut writer = GeoJsonWriter::new(Vec::new())?.with_schema(state.schema.clone());
+        for parcel in state.dataset.query_point(lon, lat) {
+            let fude = Fude {
+                attributes: parcel.attributes.clone(),
+                surface_id: Default::default(),
+            };
+            writer.write_fude(&fude, &parcel.polygon)?;
+        }
+        writer.finish()
+    })();
+    match geojson {
+        Ok(body) => (200, "application/geo+json", body),
+        Err(e) => (500, "text/plain", format!("{}\n", e).into_bytes()),
+    }
+}
+
+fn tile_response(state: &State, z: u8, x: u32, y: u32) -> Response {
+    let parcels = state.dataset.query_bbox(tile_bounds(z, x, y));
+    let tile = encode_tile(
+        z,
+        x,
+        y,
+        parcels
+            .iter()
+            .map(|parcel| (&parcel.attributes, &parcel.polygon)),
+        &state.schema,
+        state.tolerance,
+    );
+    (200, "application/vnd.mapbox-vector-tile", tile)
+}
+
+fn bad_request(message: &str) -> Response {
+    (400, "text/plain", format!("{}\n", message).into_bytes())
+}
+
+fn not_found() -> Response {
+    (404, "text/plain", b"not found\n".to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::InteriorPoint;
+    use mojxml::parser::MojxmlParser;
+    use mojxml::synthetic::SyntheticMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    /// A server over a synthetic file, with its address.
+    fn start(timeout: Duration) -> (Arc<Server>, String, geo::Point) {
+        let projections = jpr_projections();
+        let xml = SyntheticMap::new(2, 2).to_xml();
+        let data = MojxmlParser::new(xml.as_bytes(), &projections)
+            .parse()
+            .unwrap();
+        let mut dataset = Dataset::new();
+        dataset.insert("synthetic.xml", data);
+        let point = dataset.parcels()[0].polygon.interior_point().unwrap();
+        let state = State {
+            dataset,
+            schema: Schema::english(),
+            tolerance: 1.0,
+        };
+        let server = Arc::new(bind("127.0.0.1:0", timeout).unwrap());
+        let addr = server.server_addr().to_string();
+        serve(server.clone(), Arc::new(state), 2);
+        (server, addr, point)
+    }
+
+    /// Send a request, and return the status code and the body of the response.
+    fn request(addr: &str, method: &str, target: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            method, target, addr
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&response[..end]).unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[end + 4..].to_vec())
+    }
+
+    /// The tile containing a position.
+    fn tile(z: u8, point: geo::Point) -> (u32, u32) {
+        let n = (1u32 << z) as f64;
+        let x = (point.x() + 180.0) / 360.0 * n;
+        let lat = point.y().to_radians();
+        let y = (1.0 - lat.tan().asinh() / std::f64::consts::PI) / 2.0 * n;
+        (x as u32, y as u32)
+    }
+
+    #[test]
+    fn test_parcel() {
+        let (_server, addr, point) = start(Duration::from_secs(30));
+        let (status, body) = request(
+            &addr,
+            "GET",
+            &format!("/parcel?lat={}&lon={}", point.y(), point.x()),
+        );
+        assert_eq!(status, 200);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["features"].as_array().unwrap().len(), 1);
+
+        // nothing there
+        let (status, body) = request(&addr, "GET", "/parcel?lat=0&lon=0");
+        assert_eq!(status, 200);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(json["features"].as_array().unwrap().is_empty());
+
+        for target in [
+            "/parcel",
+            "/parcel?lat=35.6",
+            "/parcel?lat=abc&lon=139.7",
+            "/parcel?lat=95&lon=139.7",
+            "/parcel?lat=35.6&lon=NaN",
+        ] {
+            assert_eq!(request(&addr, "GET", target).0, 400, "{}", target);
+        }
+        assert_eq!(request(&addr, "POST", "/parcel?lat=0&lon=0").0, 405);
+        assert_eq!(request(&addr, "GET", "/unknown").0, 404);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let (_server, addr, point) = start(Duration::from_secs(30));
+        let (x, y) = tile(16, point);
+        let (status, body) = request(&addr, "GET", &format!("/tiles/16/{}/{}.mvt", x, y));
+        assert_eq!(status, 200);
+        assert!(!body.is_empty());
+
+        for target in [
+            "/tiles/3/8/0.mvt",
+            "/tiles/3/0/8.mvt",
+            "/tiles/25/0/0.mvt",
+            "/tiles/16/-1/0.mvt",
+            "/tiles/16/0/0.png",
+            "/tiles/16/0.mvt",
+        ] {
+            assert_eq!(request(&addr, "GET", target).0, 404, "{}", target);
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (_server, addr, _) = start(Duration::from_millis(200));
+        // a connection that doesn't send its request is closed
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty() || response.starts_with(b"HTTP/1.1 408"));
+    }
+}
@@ -0,0 +1,191 @@
+//! `--config` files: the options of a command as a TOML table.
+//!
+//! Keys are the long option names (`output-crs` or `output_crs`), plus `input`
+//! and `output` for the positional arguments:
+//!
+//! ```toml
+//! input = "15222-1107-2023.zip"
+//! output = "parcels.gpkg"
+//! format = "gpkg"
+//! output-crs = 3857
+//! threads = 4
+//! oaza-code = ["001", "002"]
+//!
+//! [rename]
+//! "地番" = "chiban"
+//! ```
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use toml::Value;
+
+use crate::BoxError;
+
+/// Replace `--config <file>` in the command line by the options of the file.
+///
+/// The file's options take the place of `--config`, so options given after it
+/// override them (arrays and tables add to the repeatable options).
+pub fn expand_args(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>, BoxError> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let path = if arg == "--config" {
+            match args.next() {
+                Some(path) => PathBuf::from(path),
+                None => {
+                    // let clap report the missing value
+                    expanded.push(arg);
+                    continue;
+                }
+            }
+        } else if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            PathBuf::from(path)
+        } else if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args);
+            break;
+        } else {
+            expanded.push(arg);
+            continue;
+        };
+        let text =
+            std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        expanded.extend(table_args(&table)?);
+    }
+    Ok(expanded)
+}
+
+/// Command-line arguments of a config table.
+fn table_args(table: &toml::Table) -> Result<Vec<OsString>, BoxError> {
+    let mut args: Vec<OsString> = Vec::new();
+    // positional arguments, in order
+    for key in ["input", "output"] {
+        if let Some(value) = table.get(key) {
+            args.push(scalar(key, value)?.into());
+        }
+    }
+    for (key, value) in table {
+        if key == "input" || key == "output" {
+            continue;
+        }
+        let name = key.replace('_', "-");
+        match value {
+            Value::Boolean(true) => args.push(format!("--{}", name).into()),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    args.push(format!("--{}={}", name, scalar(key, value)?).into());
+                }
+            }
+            // COLUMN=NAME options (rename, shp-field)
+            Value::Table(pairs) => {
+                for (k, value) in pairs {
+                    args.push(format!("--{}={}={}", name, k, scalar(key, value)?).into());
+                }
+            }
+            value => args.push(format!("--{}={}", name, scalar(key, value)?).into()),
+        }
+    }
+    Ok(args)
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, BoxError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        _ => Err(format!("config: {}: expected a string or a number", key).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strings(args: Vec<OsString>) -> Vec<String> {
+        args.into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_table_args() {
+        let table: toml::Table = r#"
+            output = "out.gpkg"
+            input = "in.zip"
+            format = "gpkg"
+            output_crs = 3857
+            quiet = true
+            vrt = false
+            oaza-code = ["001", "002"]
+
+            [rename]
+            "地番" = "chiban"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            strings(table_args(&table).unwrap()),
+            [
+                "in.zip",
+                "out.gpkg",
+                "--format=gpkg",
+                "--oaza-code=001",
+                "--oaza-code=002",
+                "--output-crs=3857",
+                "--quiet",
+                "--rename=地番=chiban",
+            ]
+        );
+
+        let table: toml::Table = "format = { a = [1] }".parse().unwrap();
+        assert!(table_args(&table).is_err());
+    }
+
+    #[test]
+    fn test_expand_args() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"input = \"in.zip\"\nthreads = 4\n").unwrap();
+        let path = file.path().to_str().unwrap();
+        let args = [
+            "mojxml",
+            "convert",
+            "--config",
+            path,
+            &format!("--config={}", path),
+            "--threads=2",
+            "--",
+            "--config",
+            path,
+        ];
+        let expanded = expand_args(args.iter().map(OsString::from)).unwrap();
+        assert_eq!(
+            strings(expanded),
+            [
+                "mojxml",
+                "convert",
+                "in.zip",
+                "--threads=4",
+                "in.zip",
+                "--threads=4",
+                "--threads=2",
+                "--",
+                "--config",
+                path,
+            ]
+        );
+
+        // a missing value is left to clap
+        let args = ["mojxml", "convert", "--config"].map(OsString::from);
+        assert_eq!(
+            strings(expand_args(args).unwrap()),
+            ["mojxml", "convert", "--config"]
+        );
+        let args = ["mojxml", "--config", "missing.toml"].map(OsString::from);
+        assert!(expand_args(args).is_err());
+    }
+}
@@ -0,0 +1,1777 @@
+//! `convert` command.
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use clap::ValueEnum;
+use geo::BoundingRect;
+use indicatif::{
+    HumanBytes, HumanCount, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget,
+    ProgressStyle,
+};
+
+use mojxml::codes::{AccuracyClass, CodeWidths};
+use mojxml::convert::{ConversionReport, ConvertOptions, Event, EventHook, FileHook};
+use mojxml::crs::Crs;
+use mojxml::data::{Feature, Fude, FudeAttributes, MapMetadata, ParsedData};
+use mojxml::dedup::{
+    ChibanCounter, Deduplicator, DuplicateChiban, Keep, dedup_key, suffixed_chiban,
+};
+use mojxml::municipality::{self, MunicipalityTable};
+use mojxml::parser::ParseOptions;
+use mojxml::stitch::Stitcher;
+use mojxml::text::TextNormalization;
+use mojxml::validate::{QA_FLAGS, fix_ring_roles, set_qa_flags};
+use mojxml::writer::csv::{CsvEncoding, CsvWriter};
+use mojxml::writer::fgb::FgbFudeWriter;
+use mojxml::writer::geojson::GeoJsonWriter;
+use mojxml::writer::geoparquet::GeoParquetWriter;
+use mojxml::writer::gpkg::GpkgFudeWriter;
+use mojxml::writer::kml::{KmlWriter, KmzWriter};
+use mojxml::writer::pmtiles::PmtilesWriter;
+use mojxml::writer::shapefile::{DbfEncoding, ShapefileWriter};
+use mojxml::writer::spatialite::SpatialiteWriter;
+use mojxml::writer::svg::SvgWriter;
+use mojxml::writer::vrt::VrtWriter;
+use mojxml::writer::{FeatureSink, FileBatch, Layer, Metadata, Schema, SharedFeatureSink};
+use mojxml::zip::EntryFilter;
+
+use crate::{BoxError, open_input_in};
+
+#[derive(Clone, clap::Args)]
+pub struct ConvertArgs {
+    /// Read options from a TOML file (see the README). Options given after
+    /// it override the ones of the file.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Output file (- for stdout, with the geojson, geojsonl, csv and tsv formats)
+    #[arg()]
+    output: PathBuf,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Fgb)]
+    format: Format,
+    /// CRS of the output: an EPSG code (6668, 4326, 3857), or `source` for the
+    /// plane rectangular coordinates of the input files
+    #[arg(long, value_parser = parse_output_crs, default_value = "6668")]
+    output_crs: OutputCrs,
+    /// Only process archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Also convert the files in arbitrary coordinate systems (任意座標系).
+    /// Their coordinates are written as is, without projection, and each
+    /// such file is reported.
+    #[arg(long)]
+    include_arbitrary_crs: bool,
+    /// Only write the 筆 with one of these 大字コード (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    oaza_code: Vec<String>,
+    /// Only write the 筆 whose 地番 starts with this string
+    #[arg(long)]
+    chiban_prefix: Option<String>,
+    /// Only write the 筆 with one of these 精度区分 (comma-separated, e.g.
+    /// `甲一,甲2`)
+    #[arg(long, value_delimiter = ',')]
+    accuracy: Vec<String>,
+    /// Remove the 筆 found in several files (same 市区町村コード, 大字コード,
+    /// 丁目コード and 地番), keeping only one of them
+    #[arg(long, value_enum, value_name = "KEEP")]
+    dedup: Option<DedupKeep>,
+    /// With --dedup, only treat 筆 as duplicates when their intersection covers
+    /// at least this ratio (0 to 1) of the smaller one
+    #[arg(long, value_name = "RATIO", requires = "dedup")]
+    dedup_overlap: Option<f64>,
+    /// Union the pieces of the 筆 cut by the boundaries of the sheets: 筆 of
+    /// different files with the same 市区町村コード, 大字コード, 丁目コード
+    /// and 地番 that share a boundary
+    #[arg(long, conflicts_with = "dedup")]
+    stitch: bool,
+    /// Find the 地番 given to more than one 筆 in a municipality (same
+    /// 大字コード and 丁目コード), as written, and list them in the --report.
+    /// `suffix` renames the duplicates (`12-3_2`, `12-3_3`...)
+    #[arg(long, value_enum, value_name = "MODE")]
+    duplicate_chiban: Option<DuplicateChibanArg>,
+    /// Write the 筆 sorted by 市区町村コード, 大字コード, 丁目コード and
+    /// 地番 (numerically: 2-9 before 2-10), instead of in the order of parsing
+    #[arg(long)]
+    sort: bool,
+    /// Write byte-identical outputs across runs: the XML files in the order
+    /// of their names and their 筆 by id, and a fixed time of conversion
+    /// (`SOURCE_DATE_EPOCH`, or 1970-01-01)
+    #[arg(long, conflicts_with = "parts")]
+    deterministic: bool,
+    /// Layers to write (FlatGeobuf and GeoPackage only, except for `fude`).
+    /// FlatGeobuf writes the first layer to the output file and the others
+    /// next to it, as `<output>_<layer>.fgb`.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [LayerArg::Fude])]
+    layers: Vec<LayerArg>,
+    /// Names of the attribute columns
+    #[arg(long, value_enum, default_value_t = SchemaArg::Japanese)]
+    schema: SchemaArg,
+    /// Rename an attribute column, as COLUMN=NAME (repeatable)
+    #[arg(long = "rename", value_parser = parse_field_name)]
+    renames: Vec<(String, String)>,
+    /// Add a `stable_id` column: a hash of the 市区町村コード, 大字コード,
+    /// 丁目コード, 小字コード and 地番, stable across conversions
+    #[arg(long)]
+    stable_id: bool,
+    /// Add `source_file`, `map_number` (地図番号 of the containing 図郭) and
+    /// `run_id` columns, to trace features back to their source
+    #[arg(long)]
+    provenance: bool,
+    /// Id of the conversion run in the `run_id` column (default: the start
+    /// time, in UTC)
+    #[arg(long, value_name = "ID", requires = "provenance")]
+    run_id: Option<String>,
+    /// Add columns describing the 精度区分 (with its allowed errors and its
+    /// 公差 in meters) and the 座標値種別
+    #[arg(long)]
+    code_labels: bool,
+    /// Swap the exterior ring of the surfaces with an interior ring that
+    /// contains it, for files listing the rings in the wrong roles
+    #[arg(long)]
+    fix_ring_roles: bool,
+    /// Add a `計算地積` column: the area of the 筆 in m², computed in the
+    /// plane rectangular CS of its file
+    #[arg(long)]
+    computed_area: bool,
+    /// Add quality flag columns (`true` or `false`): ring_not_closed,
+    /// self_intersects, tiny_area and arbitrary_crs
+    #[arg(long)]
+    qa_flags: bool,
+    /// Area in m² below which a 筆 is flagged as tiny_area
+    #[arg(long, value_name = "M2", default_value_t = 1.0, requires = "qa_flags")]
+    tiny_area: f64,
+    /// Add 市区町村コード and 市区町村名 columns, from the header of each file
+    /// (or the code in its name)
+    #[arg(long)]
+    municipality: bool,
+    /// Official list of the 全国地方公共団体コード (総務省), saved as UTF-8
+    /// CSV: fills the 市区町村名 missing from the headers, and warns about
+    /// files of unknown municipalities
+    #[arg(long, value_name = "FILE")]
+    municipality_table: Option<PathBuf>,
+    /// Normalize the 大字コード, 丁目コード, 小字コード and 予備コード:
+    /// convert full-width digits and zero-pad them to --code-widths
+    #[arg(long)]
+    normalize_codes: bool,
+    /// Widths of the 大字コード, 丁目コード, 小字コード and 予備コード for
+    /// --normalize-codes, e.g. `3,3,4,2` (the default; 0 leaves a code as is)
+    #[arg(long, value_name = "WIDTHS", value_parser = parse_code_widths, requires = "normalize_codes")]
+    code_widths: Option<CodeWidths>,
+    /// Normalize the text of the attributes (comma-separated), for the
+    /// software that doesn't handle some Unicode forms
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "FORMS")]
+    normalize_text: Vec<TextForm>,
+    /// Attribute columns to write, in this order (comma-separated)
+    #[arg(long, value_delimiter = ',', conflicts_with = "preset")]
+    columns: Vec<String>,
+    /// Predefined set of attribute columns
+    #[arg(long, value_enum, default_value_t = Preset::Full)]
+    preset: Preset,
+    /// Append to the output if it exists (FlatGeobuf and GeoPackage only;
+    /// implied by --resume with an existing journal)
+    #[arg(long)]
+    append: bool,
+    /// Write one output per group, named `<output>_<group>.<ext>`
+    #[arg(long, value_enum)]
+    split_by: Option<SplitBy>,
+    /// Write N part files in parallel (`<output>_part000.<ext>`, ...), each
+    /// with a share of the XML files, and a manifest listing them
+    /// (`<output>.manifest.json`), instead of a single output (fgb and parquet
+    /// only)
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["split_by", "append", "dedup", "stitch", "sort", "duplicate_chiban", "points_output"],
+    )]
+    parts: Option<usize>,
+    /// Journal of processed entries. Entries already listed in it are skipped,
+    /// and the output is appended to if the journal exists. A file is listed
+    /// once its features are synced to disk (geojsonl, csv and tsv only,
+    /// written to a file).
+    #[arg(long, conflicts_with_all = ["dedup", "stitch", "sort", "points_output"])]
+    resume: Option<PathBuf>,
+    /// Go on when an XML file fails to parse, and list the failed files at the end
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Write a JSON report of the conversion (files, warnings, statistics)
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    /// Record the SHA-256 of each XML file in the --report
+    #[arg(long, requires = "report")]
+    checksums: bool,
+    /// Directory for temporary files (FlatGeobuf features, spooled stdin).
+    /// Defaults to the system temporary directory.
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+    /// Don't write anything: parse a sample of the files and estimate the
+    /// number of features, the output size and the duration
+    #[arg(long)]
+    dry_run: bool,
+    /// Number of files parsed by --dry-run
+    #[arg(long, default_value_t = 10)]
+    sample: usize,
+    /// Number of worker threads (default: the number of CPUs)
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Limit the XML being decompressed, parsed or written at the same time to
+    /// this many megabytes (a larger file is processed alone)
+    #[arg(long, value_name = "MB")]
+    memory_budget: Option<u64>,
+    /// Don't show progress
+    #[arg(long, short)]
+    quiet: bool,
+    /// PMTiles: minimum zoom level
+    #[arg(long, default_value_t = 14)]
+    min_zoom: u8,
+    /// PMTiles: maximum zoom level
+    #[arg(long, default_value_t = 16)]
+    max_zoom: u8,
+    /// CSV/TSV: add the geometry as a WKT column
+    #[arg(long)]
+    wkt: bool,
+    /// SVG: draw only this extent, as MINX,MINY,MAXX,MAXY (longitude/latitude)
+    #[arg(long, value_parser = parse_bbox, allow_hyphen_values = true)]
+    bbox: Option<geo::Rect<f64>>,
+    /// SVG: draw only the 図郭 with this 地図番号
+    #[arg(long, conflicts_with_all = ["bbox", "split_by"])]
+    sheet: Option<String>,
+    /// SVG: label the parcels with their 地番
+    #[arg(long)]
+    labels: bool,
+    /// FlatGeobuf: also write `<output>.vrt`, an OGR VRT opening all the
+    /// layers as one GDAL datasource
+    #[arg(long)]
+    vrt: bool,
+    /// Also write one point per 筆 to this FlatGeobuf file, at a point on its
+    /// surface, with the same attributes (e.g. for 地番 labels)
+    #[arg(long, value_name = "FILE")]
+    points_output: Option<PathBuf>,
+    /// Shapefile: encoding of the .dbf attributes
+    #[arg(long, value_enum, default_value_t = EncodingArg::Utf8)]
+    shp_encoding: EncodingArg,
+    /// CSV and TSV: encoding of the file
+    #[arg(long, value_enum, default_value_t = EncodingArg::Utf8)]
+    csv_encoding: EncodingArg,
+    /// Shapefile: DBF field name of a column, as COLUMN=NAME (repeatable)
+    #[arg(long = "shp-field", value_parser = parse_field_name)]
+    shp_fields: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum LayerArg {
+    /// 筆
+    Fude,
+    /// 基準点
+    Kijunten,
+    /// 筆界点
+    #[value(alias = "hikkaiten")]
+    Fukkaiten,
+    /// 図郭
+    Zukaku,
+}
+
+impl From<LayerArg> for Layer {
+    fn from(layer: LayerArg) -> Self {
+        match layer {
+            LayerArg::Fude => Layer::Fude,
+            LayerArg::Kijunten => Layer::Kijunten,
+            LayerArg::Fukkaiten => Layer::Hikkaiten,
+            LayerArg::Zukaku => Layer::Zukaku,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Preset {
+    /// id, 大字名, 丁目名, 地番
+    Minimal,
+    /// All the columns
+    Full,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DedupKeep {
+    /// The one of the first file, in the order of the file names
+    First,
+    /// The one of the last file, in the order of the file names
+    Last,
+    /// The largest one
+    Largest,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DuplicateChibanArg {
+    /// Print a warning for each duplicate
+    Warn,
+    /// Append a suffix to the 地番 of the duplicates
+    Suffix,
+    /// Keep the duplicates silently
+    KeepAll,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SplitBy {
+    /// By 市区町村コード
+    Municipality,
+    /// By 市区町村コード and 大字コード
+    Oaza,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaArg {
+    /// 大字コード, 地番, ...
+    Japanese,
+    /// oaza_code, chiban, ...
+    English,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum TextForm {
+    /// Normalize to Unicode NFC, which composes the kana and the combining
+    /// (semi-)voiced sound marks (か + ◌゙ to が)
+    Nfc,
+    /// Convert the full-width ASCII letters, digits, symbols and spaces to
+    /// half-width (１２－３ to 12-3)
+    Halfwidth,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EncodingArg {
+    Utf8,
+    Sjis,
+}
+
+impl ConvertArgs {
+    /// With `--deterministic`, the fixed time of the conversion, in seconds
+    /// since the Unix epoch.
+    fn created_at(&self) -> Option<i64> {
+        self.deterministic.then(|| {
+            std::env::var("SOURCE_DATE_EPOCH")
+                .ok()
+                .and_then(|secs| secs.trim().parse().ok())
+                .unwrap_or(0)
+        })
+    }
+
+    /// The metadata of an output created now, or at the `--deterministic` time.
+    fn metadata(&self, source: Option<String>, map: &MapMetadata) -> Metadata {
+        let metadata = Metadata::new(source, map);
+        match self.created_at() {
+            Some(secs) => metadata.with_created_at(secs),
+            None => metadata,
+        }
+    }
+
+    fn csv_encoding(&self) -> CsvEncoding {
+        match self.csv_encoding {
+            EncodingArg::Utf8 => CsvEncoding::Utf8,
+            EncodingArg::Sjis => CsvEncoding::ShiftJis,
+        }
+    }
+
+    /// The `--normalize-text` forms.
+    fn text_normalization(&self) -> TextNormalization {
+        TextNormalization {
+            nfc: self.normalize_text.contains(&TextForm::Nfc),
+            halfwidth: self.normalize_text.contains(&TextForm::Halfwidth),
+        }
+    }
+
+    /// Whether a 筆 passes the `--oaza-code`, `--chiban-prefix` and `--accuracy` filters.
+    fn keeps(&self, fude: &FudeAttributes) -> bool {
+        let any_of = |values: &[String], value: &Option<Cow<str>>| {
+            values.is_empty()
+                || value
+                    .as_deref()
+                    .is_some_and(|v| values.iter().any(|x| x == v))
+        };
+        // 精度区分 match when they are the same class, e.g. 甲2 and 甲二
+        let same_accuracy = |value: &str| {
+            let class = AccuracyClass::parse(value);
+            self.accuracy
+                .iter()
+                .any(|x| x == value || (class.is_some() && AccuracyClass::parse(x) == class))
+        };
+        any_of(&self.oaza_code, &fude.oaza_code)
+            && (self.accuracy.is_empty()
+                || fude.accuracy_class.as_deref().is_some_and(same_accuracy))
+            && self.chiban_prefix.as_ref().is_none_or(|prefix| {
+                fude.chiban
+                    .as_ref()
+                    .is_some_and(|chiban| chiban.starts_with(prefix.as_str()))
+            })
+    }
+}
+
+fn parse_field_name(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected COLUMN=NAME, got {:?}", s))
+}
+
+fn parse_code_widths(s: &str) -> Result<CodeWidths, String> {
+    let widths: Vec<usize> = s
+        .split(',')
+        .map(|width| {
+            width
+                .trim()
+                .parse()
+                .map_err(|e| format!("{}: {:?}", e, width))
+        })
+        .collect::<Result<_, _>>()?;
+    match widths[..] {
+        [oaza, chome, koaza, yobi] => Ok(CodeWidths {
+            oaza,
+            chome,
+            koaza,
+            yobi,
+        }),
+        _ => Err(format!("expected 4 comma-separated widths, got {:?}", s)),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum OutputCrs {
+    Epsg(Crs),
+    /// The zone of the Japan Plane Rectangular CS of the input files
+    Source,
+}
+
+fn parse_output_crs(s: &str) -> Result<OutputCrs, String> {
+    if s == "source" {
+        return Ok(OutputCrs::Source);
+    }
+    let code = s.strip_prefix("EPSG:").unwrap_or(s);
+    code.parse()
+        .ok()
+        .and_then(Crs::from_epsg)
+        .map(OutputCrs::Epsg)
+        .ok_or_else(|| format!("expected 6668, 4326, 3857 or source, got {:?}", s))
+}
+
+fn parse_bbox(s: &str) -> Result<geo::Rect<f64>, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    match values[..] {
+        [min_x, min_y, max_x, max_y] => Ok(geo::Rect::new((min_x, min_y), (max_x, max_y))),
+        _ => Err(format!("expected MINX,MINY,MAXX,MAXY, got {:?}", s)),
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// FlatGeobuf
+    Fgb,
+    /// GeoJSON FeatureCollection
+    Geojson,
+    /// Newline-delimited GeoJSON (GeoJSONSeq)
+    Geojsonl,
+    /// GeoPackage
+    Gpkg,
+    /// GeoParquet
+    Parquet,
+    /// ESRI Shapefile
+    Shp,
+    /// CSV attribute table
+    Csv,
+    /// TSV attribute table
+    Tsv,
+    /// KML
+    Kml,
+    /// KML in a zip archive
+    Kmz,
+    /// Vector tiles (MVT) in a PMTiles archive
+    Pmtiles,
+    /// SpatiaLite database
+    Spatialite,
+    /// SVG image of the parcel outlines
+    Svg,
+}
+
+/// A file, or stdout for `-`.
+enum TextTarget {
+    Stdout(std::io::Stdout),
+    File(File),
+}
+
+impl Write for TextTarget {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            TextTarget::Stdout(out) => out.write(buf),
+            TextTarget::File(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            TextTarget::Stdout(out) => out.flush(),
+            TextTarget::File(out) => out.flush(),
+        }
+    }
+}
+
+type TextOutput = BufWriter<TextTarget>;
+
+fn create_text_output(path: &Path, append: bool) -> Result<TextOutput, BoxError> {
+    let out = if path == Path::new("-") {
+        TextTarget::Stdout(std::io::stdout())
+    } else if append {
+        TextTarget::File(File::options().create(true).append(true).open(path)?)
+    } else {
+        TextTarget::File(File::create(path)?)
+    };
+    Ok(BufWriter::new(out))
+}
+
+enum Output {
+    Fgb(
+        Vec<(Layer, FgbFudeWriter<'static>, PathBuf)>,
+        Option<VrtWriter>,
+    ),
+    GeoJson(GeoJsonWriter<TextOutput>),
+    Gpkg(GpkgFudeWriter),
+    Parquet(Box<GeoParquetWriter<BufWriter<File>>>),
+    Shp(Box<ShapefileWriter>),
+    Csv(CsvWriter<TextOutput>),
+    Kml(KmlWriter<BufWriter<File>>),
+    Kmz(KmzWriter<BufWriter<File>>),
+    Pmtiles(PmtilesWriter<BufWriter<File>>),
+    Spatialite(SpatialiteWriter),
+    Svg(SvgWriter, PathBuf),
+}
+
+impl Output {
+    fn create(
+        args: &ConvertArgs,
+        schema: &Schema,
+        path: PathBuf,
+        metadata: Metadata,
+        crs: Crs,
+        progress: &MultiProgress,
+    ) -> Result<Self, BoxError> {
+        let layers: Vec<Layer> = args.layers.iter().map(|&l| l.into()).collect();
+        let schema = schema.clone();
+        // a CSV appended to already has its header
+        let has_header = args.append && path.metadata().is_ok_and(|meta| meta.len() > 0);
+        Ok(match args.format {
+            Format::Fgb => {
+                let metadata_json = metadata.to_json();
+                let mut writers = Vec::with_capacity(layers.len());
+                for (i, &layer) in layers.iter().enumerate() {
+                    let path = if i == 0 {
+                        path.clone()
+                    } else {
+                        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                        path.with_file_name(format!("{}_{}.fgb", stem, layer.name()))
+                    };
+                    let fgb = match layer {
+                        Layer::Fude => FgbFudeWriter::new_with_schema(schema.clone())?,
+                        _ => FgbFudeWriter::new_layer(layer)?,
+                    };
+                    let mut fgb = fgb.with_crs(crs)?.with_metadata(metadata_json.clone())?;
+                    if args.append && path.exists() {
+                        let count = fgb.append_from(BufReader::new(File::open(&path)?))?;
+                        let message =
+                            format!("Appending to {} ({} features)", path.display(), count);
+                        log(progress, args.quiet, message);
+                    }
+                    writers.push((layer, fgb, path));
+                }
+                let vrt = args.vrt.then(|| {
+                    let mut vrt = VrtWriter::new(crs);
+                    for (layer, _, path) in &writers {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        vrt.add_layer(*layer, &name);
+                    }
+                    vrt
+                });
+                Output::Fgb(writers, vrt)
+            }
+            Format::Geojson => Output::GeoJson(
+                GeoJsonWriter::new(create_text_output(&path, false)?)?.with_schema(schema),
+            ),
+            Format::Geojsonl => Output::GeoJson(
+                GeoJsonWriter::new_seq(create_text_output(&path, args.append)?).with_schema(schema),
+            ),
+            Format::Gpkg => {
+                let gpkg = if args.append && path.exists() {
+                    GpkgFudeWriter::open_with_schema(path, schema)?
+                } else {
+                    GpkgFudeWriter::create_with_schema(path, schema)?
+                };
+                let mut gpkg = gpkg.with_crs(crs)?.with_metadata(metadata);
+                for &layer in &layers {
+                    gpkg.add_layer(layer)?;
+                }
+                Output::Gpkg(gpkg)
+            }
+            Format::Parquet => Output::Parquet(Box::new(
+                GeoParquetWriter::new_with_schema(BufWriter::new(File::create(path)?), schema)?
+                    .with_crs(crs)
+                    .with_metadata(metadata),
+            )),
+            Format::Shp => {
+                let encoding = match args.shp_encoding {
+                    EncodingArg::Utf8 => DbfEncoding::Utf8,
+                    EncodingArg::Sjis => DbfEncoding::ShiftJis,
+                };
+                let mut shp = ShapefileWriter::create(path)?
+                    .with_encoding(encoding)
+                    .with_schema(schema)
+                    .with_field_names(args.shp_fields.clone())
+                    .with_crs(crs);
+                if let Some(secs) = args.created_at() {
+                    shp = shp.with_date(secs);
+                }
+                Output::Shp(Box::new(shp))
+            }
+            Format::Csv => Output::Csv(
+                CsvWriter::new(create_text_output(&path, args.append)?)
+                    .with_encoding(args.csv_encoding())
+                    .with_wkt(args.wkt)
+                    .with_schema(schema)
+                    .with_header(!has_header),
+            ),
+            Format::Tsv => Output::Csv(
+                CsvWriter::new_tsv(create_text_output(&path, args.append)?)
+                    .with_encoding(args.csv_encoding())
+                    .with_wkt(args.wkt)
+                    .with_schema(schema)
+                    .with_header(!has_header),
+            ),
+            Format::Kml => Output::Kml(
+                KmlWriter::new(BufWriter::new(File::create(path)?))?.with_schema(schema),
+            ),
+            Format::Kmz => Output::Kmz(
+                KmlWriter::new_kmz(BufWriter::new(File::create(path)?))?.with_schema(schema),
+            ),
+            Format::Pmtiles => Output::Pmtiles(
+                PmtilesWriter::new(BufWriter::new(File::create(path)?))
+                    .with_zoom_range(args.min_zoom, args.max_zoom)
+                    .with_schema(schema),
+            ),
+            Format::Spatialite => Output::Spatialite(
+                SpatialiteWriter::create_with_schema(path, schema)?.with_crs(crs)?,
+            ),
+            Format::Svg => {
+                let mut svg = SvgWriter::new().with_labels(args.labels);
+                if let Some(bbox) = args.bbox {
+                    svg = svg.with_bbox(bbox);
+                }
+                Output::Svg(svg, path)
+            }
+        })
+    }
+
+    fn write_fude(
+        &mut self,
+        fude: &mojxml::data::Fude,
+        polygon: geo::Polygon<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        match self {
+            Output::Fgb(writers, _) => match writers.iter_mut().find(|(l, ..)| *l == Layer::Fude) {
+                Some((_, fgb, _)) => fgb.write_fude(fude, polygon),
+                None => Ok(()),
+            },
+            Output::GeoJson(geojson) => geojson.write_fude(fude, &polygon),
+            Output::Gpkg(gpkg) => gpkg.write_fude(fude, &polygon),
+            Output::Parquet(parquet) => parquet.write_fude(fude, &polygon),
+            Output::Shp(shp) => shp.write_fude(fude, &polygon),
+            Output::Csv(csv) => csv.write_fude(fude, &polygon),
+            Output::Kml(kml) => kml.write_fude(fude, &polygon),
+            Output::Kmz(kmz) => kmz.write_fude(fude, &polygon),
+            Output::Pmtiles(pmtiles) => pmtiles.write_fude(fude, &polygon),
+            Output::Spatialite(spatialite) => spatialite.write_fude(fude, &polygon),
+            Output::Svg(svg, _) => svg.write_fude(fude, &polygon),
+        }
+    }
+
+    /// Flush the features written so far and sync them to disk (geojsonl,
+    /// csv and tsv files only, the other formats are complete once finished).
+    fn sync(&mut self) -> std::io::Result<()> {
+        let out = match self {
+            Output::GeoJson(geojson) => geojson.get_mut(),
+            Output::Csv(csv) => csv.get_mut(),
+            _ => return Ok(()),
+        };
+        out.flush()?;
+        match out.get_ref() {
+            TextTarget::File(file) => file.sync_data(),
+            TextTarget::Stdout(_) => Ok(()),
+        }
+    }
+
+    /// Set the extent of the SVG image.
+    fn set_bbox(&mut self, bbox: Option<geo::Rect<f64>>) {
+        if let (Output::Svg(svg, _), Some(bbox)) = (self, bbox) {
+            svg.set_bbox(bbox);
+        }
+    }
+
+    /// Write a feature of a layer other than `fude` (FlatGeobuf and GeoPackage only).
+    fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &mojxml::data::Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        match self {
+            Output::Fgb(writers, _) => match writers.iter_mut().find(|(l, ..)| *l == layer) {
+                Some((_, fgb, _)) => fgb.write_feature(feature, geometry),
+                None => Ok(()),
+            },
+            Output::Gpkg(gpkg) => gpkg.write_feature(layer, feature, &geometry),
+            _ => Ok(()),
+        }
+    }
+
+    /// Finish the output, returning the number of values with characters
+    /// missing from the Shift_JIS encoding of a CSV or DBF.
+    fn finish(self) -> Result<usize, BoxError> {
+        let mut unmappable = 0;
+        match self {
+            Output::Fgb(writers, vrt) => {
+                if let (Some(vrt), Some((_, _, path))) = (vrt, writers.first()) {
+                    vrt.write(BufWriter::new(File::create(path.with_extension("vrt"))?))?;
+                }
+                for (_, fgb, path) in writers {
+                    fgb.finish(File::create(path)?)?;
+                }
+            }
+            Output::GeoJson(geojson) => {
+                geojson.finish()?;
+            }
+            Output::Gpkg(gpkg) => {
+                gpkg.finish()?;
+            }
+            Output::Parquet(parquet) => {
+                parquet.finish()?;
+            }
+            Output::Shp(shp) => {
+                unmappable = shp.unmappable();
+                shp.finish()?;
+            }
+            Output::Csv(csv) => {
+                unmappable = csv.unmappable();
+                csv.finish()?;
+            }
+            Output::Kml(kml) => {
+                kml.finish()?;
+            }
+            Output::Kmz(kmz) => {
+                kmz.finish_kmz()?;
+            }
+            Output::Pmtiles(pmtiles) => {
+                pmtiles.finish()?;
+            }
+            Output::Spatialite(spatialite) => {
+                spatialite.finish()?;
+            }
+            Output::Svg(svg, path) => {
+                svg.finish(BufWriter::new(File::create(path)?))?;
+            }
+        }
+        Ok(unmappable)
+    }
+}
+
+/// Print a message above the progress bars, or directly when they aren't drawn
+/// (e.g. when stderr isn't a terminal).
+fn log(progress: &MultiProgress, quiet: bool, message: String) {
+    if quiet {
+        return;
+    }
+    if progress.is_hidden() {
+        eprintln!("{}", message);
+    } else {
+        let _ = progress.println(message);
+    }
+}
+
+/// The output of a group, created on first use.
+fn get_output(
+    outputs: &mut HashMap<String, Output>,
+    key: String,
+    create: impl FnOnce(&str) -> Result<Output, BoxError>,
+) -> Result<&mut Output, BoxError> {
+    Ok(match outputs.entry(key) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let output = create(entry.key())?;
+            entry.insert(output)
+        }
+    })
+}
+
+/// Write `<output>.manifest.json`, listing the parts of `--parts` (by file
+/// name, next to it) with their number of XML files and features.
+fn write_manifest(
+    args: &ConvertArgs,
+    crs: Crs,
+    mut parts: Vec<(String, usize, usize)>,
+) -> Result<(), BoxError> {
+    parts.sort();
+    let parts: Vec<serde_json::Value> = parts
+        .into_iter()
+        .map(|(key, files, features)| {
+            let path = group_path(&args.output, &key);
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            serde_json::json!({ "path": name, "files": files, "features": features })
+        })
+        .collect();
+    let features: u64 = parts
+        .iter()
+        .filter_map(|part| part["features"].as_u64())
+        .sum();
+    let manifest = serde_json::json!({
+        "format": args.format.to_possible_value().map(|value| value.get_name().to_string()),
+        "crs": format!("EPSG:{}", crs.epsg()),
+        "features": features,
+        "parts": parts,
+    });
+    let path = args.output.with_extension("manifest.json");
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, &manifest)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Output path of a group: `<stem>_<key>.<ext>` next to the output.
+fn group_path(output: &Path, key: &str) -> PathBuf {
+    if key.is_empty() {
+        return output.to_path_buf();
+    }
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, key, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, key),
+    };
+    output.with_file_name(name)
+}
+
+/// 市区町村コード of a file, from its header or its name (`13101-0000-1.xml`).
+pub(crate) fn municipality_code(metadata: &MapMetadata, name: &str) -> String {
+    match &metadata.municipality_code {
+        Some(code) => code.clone(),
+        None => name
+            .split(['-', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// A warning about the 市区町村コード of a file, if it isn't a valid code or
+/// isn't in `--municipality-table`.
+fn check_municipality(code: &str, table: Option<&MunicipalityTable>) -> Option<String> {
+    if !municipality::is_valid_code(code) {
+        Some(format!("invalid 市区町村コード {:?}", code))
+    } else if table.is_some_and(|table| !table.contains(code)) {
+        Some(format!("unknown 市区町村コード {}", code))
+    } else {
+        None
+    }
+}
+
+/// With `--duplicate-chiban`, count a 筆 as it is written, and warn about it
+/// or rename it if its 地番 is a duplicate.
+fn check_chiban(
+    chibans: &mut Option<(ChibanCounter, DuplicateChiban)>,
+    municipality: &str,
+    fude: &mut Fude,
+    progress: &MultiProgress,
+) {
+    let Some((counter, mode)) = chibans else {
+        return;
+    };
+    let n = counter.count(dedup_key(Some(municipality), &fude.attributes));
+    if n == 0 {
+        return;
+    }
+    let attributes = &mut fude.attributes;
+    let chiban = attributes.chiban.as_deref().unwrap_or_default();
+    match mode {
+        DuplicateChiban::Warn => {
+            let place = format!(
+                "{} {}{}",
+                municipality,
+                attributes.oaza.as_deref().unwrap_or_default(),
+                attributes.chome.as_deref().unwrap_or_default()
+            );
+            let message = format!(
+                "Warning: duplicate 地番 {} in {} (筆 {})",
+                chiban,
+                place.trim_end(),
+                attributes.id
+            );
+            progress.suspend(|| eprintln!("{}", message));
+        }
+        DuplicateChiban::Suffix => {
+            attributes.chiban = Some(suffixed_chiban(chiban, n).into());
+        }
+        DuplicateChiban::KeepAll => {}
+    }
+}
+
+/// A 筆 written at the end of the conversion (see `--dedup`, `--stitch` and
+/// `--sort`), with the key of its output and its 市区町村コード.
+type Pending = (String, String, Fude<'static>);
+
+/// The steps of the conversion run on each parsed file by the workers, before
+/// its geometries are resolved: the columns and fixes of the options, the
+/// checks of its header, the 図郭 of `--sheet` (kept in `frames`, by file
+/// name), and the filters of the 筆 and layers.
+fn prepare_hook(
+    args: &ConvertArgs,
+    layers: &[Layer],
+    municipalities: Option<Arc<MunicipalityTable>>,
+    progress: &MultiProgress,
+    source_crs: &Arc<Mutex<Option<Crs>>>,
+    frames: &Arc<Mutex<HashMap<String, geo::Polygon<f64>>>>,
+) -> FileHook {
+    let code_widths = args
+        .normalize_codes
+        .then(|| args.code_widths.unwrap_or_default());
+    let text_normalization = args.text_normalization();
+    let run_id = args.provenance.then(|| {
+        args.run_id
+            .clone()
+            .unwrap_or_else(|| args.metadata(None, &MapMetadata::default()).created)
+    });
+    // the projections to compute the areas in, unless the coordinates are kept
+    let area_projections = ((args.computed_area || args.qa_flags)
+        && !matches!(args.output_crs, OutputCrs::Source))
+    .then(mojxml::parser::jpr_projections);
+    let (args, layers, progress) = (args.clone(), layers.to_vec(), progress.clone());
+    let (source_crs, frames) = (source_crs.clone(), frames.clone());
+    Arc::new(move |name: &str, data: &mut ParsedData<'static>| {
+        if let Some(run_id) = &run_id {
+            data.set_provenance(name, run_id);
+        }
+        if let Some(widths) = &code_widths {
+            data.fudes
+                .values_mut()
+                .for_each(|fude| widths.normalize(&mut fude.attributes));
+        }
+        if args.fix_ring_roles {
+            fix_ring_roles(data);
+        }
+        if args.computed_area {
+            data.set_computed_areas(area_projections.as_ref());
+        }
+        if args.qa_flags {
+            set_qa_flags(data, area_projections.as_ref(), args.tiny_area);
+        }
+        // municipality code, from the metadata or the file name
+        let municipality = municipality_code(&data.metadata, name);
+        if args.municipality {
+            data.set_municipality(Some(&municipality), municipalities.as_deref());
+        }
+        data.fudes
+            .values_mut()
+            .for_each(|fude| text_normalization.normalize(&mut fude.attributes));
+        let mut warnings = Vec::new();
+        if let Some(warning) = check_municipality(&municipality, municipalities.as_deref()) {
+            let message = format!("Warning: {}: {}", name, warning);
+            progress.suspend(|| eprintln!("{}", message));
+            warnings.push(warning);
+        }
+        if data.metadata.crs.as_deref() == Some("任意座標系") {
+            let warning = "arbitrary coordinate system, written unprojected";
+            let message = format!("Warning: {}: {}", name, warning);
+            progress.suspend(|| eprintln!("{}", message));
+            warnings.push(warning.to_string());
+        }
+        if let OutputCrs::Source = args.output_crs {
+            let mut source_crs = source_crs.lock().unwrap();
+            let file_crs = data.metadata.crs.as_deref().and_then(Crs::from_source);
+            match (*source_crs, file_crs) {
+                (Some(crs), Some(file_crs)) if crs != file_crs => {
+                    return Err(format!(
+                        "{} differs from {} of the previous files; \
+                         convert them separately with --entry",
+                        file_crs, crs
+                    ));
+                }
+                (None, Some(file_crs)) => *source_crs = Some(file_crs),
+                _ => {}
+            }
+        }
+        if let Some(sheet) = &args.sheet
+            && let Some(frame) = data
+                .zukaku
+                .iter()
+                .find(|z| z.attribute("地図番号") == Some(sheet.as_str()))
+                .and_then(|zukaku| data.resolve_surface_geo(zukaku.geometry_id).ok())
+        {
+            frames.lock().unwrap().insert(name.to_string(), frame);
+        }
+        if !layers.contains(&Layer::Fude) {
+            data.fudes.clear();
+        }
+        data.fudes.retain(|_, fude| args.keeps(&fude.attributes));
+        if !layers.contains(&Layer::Zukaku) {
+            // parsed for --sheet or --provenance only
+            data.zukaku.clear();
+        }
+        Ok(warnings)
+    })
+}
+
+/// The outputs of a conversion (or of a part of `--parts`), keyed by group (an
+/// empty key when the output isn't split), and the 筆 held until the end of
+/// the conversion by `--dedup`, `--stitch` and `--sort`.
+struct Outputs<'a> {
+    args: &'a ConvertArgs,
+    schema: &'a Schema,
+    progress: &'a MultiProgress,
+    features_bar: ProgressBar,
+    /// Name of the input, in the metadata of the outputs
+    source: Option<String>,
+    /// With `--output-crs source`, the zone of the files converted so far
+    source_crs: Arc<Mutex<Option<Crs>>>,
+    /// The 図郭 of `--sheet`, by file name
+    frames: Arc<Mutex<HashMap<String, geo::Polygon<f64>>>>,
+    /// Key of the part of `--parts`
+    part: Option<String>,
+    /// Created with the metadata of their first file
+    outputs: HashMap<String, Output>,
+    dedup: Option<Deduplicator<Pending>>,
+    stitcher: Option<Stitcher<Pending>>,
+    sorted: Option<Vec<(geo::Polygon<f64>, Pending)>>,
+    chibans: Option<(ChibanCounter, DuplicateChiban)>,
+    /// With `--points-output`, created with the CRS of the first file
+    points: Option<FgbFudeWriter<'static>>,
+    /// With `--resume`, the outputs written to since they were last synced
+    unsynced: HashSet<String>,
+    /// The file being written, its 市区町村コード and the CRS of its outputs
+    name: String,
+    metadata: MapMetadata,
+    municipality: String,
+    crs: Crs,
+    /// Number of files and features written
+    files: usize,
+    written: usize,
+}
+
+impl<'a> Outputs<'a> {
+    fn new(
+        args: &'a ConvertArgs,
+        schema: &'a Schema,
+        progress: &'a MultiProgress,
+        features_bar: ProgressBar,
+        source_crs: Arc<Mutex<Option<Crs>>>,
+        frames: Arc<Mutex<HashMap<String, geo::Polygon<f64>>>>,
+        part: Option<String>,
+    ) -> Self {
+        let dedup = args.dedup.map(|keep| {
+            let keep = match keep {
+                DedupKeep::First => Keep::First,
+                DedupKeep::Last => Keep::Last,
+                DedupKeep::Largest => Keep::Largest,
+            };
+            let dedup = Deduplicator::new(keep);
+            match args.dedup_overlap {
+                Some(ratio) => dedup.with_min_overlap(ratio),
+                None => dedup,
+            }
+        });
+        let chibans = args.duplicate_chiban.map(|mode| {
+            let mode = match mode {
+                DuplicateChibanArg::Warn => DuplicateChiban::Warn,
+                DuplicateChibanArg::Suffix => DuplicateChiban::Suffix,
+                DuplicateChibanArg::KeepAll => DuplicateChiban::KeepAll,
+            };
+            (ChibanCounter::new(), mode)
+        });
+        Outputs {
+            args,
+            schema,
+            progress,
+            features_bar,
+            source: args
+                .input_zip
+                .file_name()
+                .filter(|name| *name != "-")
+                .map(|name| name.to_string_lossy().into_owned()),
+            source_crs,
+            frames,
+            part,
+            outputs: HashMap::new(),
+            dedup,
+            stitcher: args.stitch.then(Stitcher::new),
+            sorted: args.sort.then(Vec::new),
+            chibans,
+            points: None,
+            unsynced: HashSet::new(),
+            name: String::new(),
+            metadata: MapMetadata::default(),
+            municipality: String::new(),
+            crs: Crs::default(),
+            files: 0,
+            written: 0,
+        }
+    }
+
+    /// The output of a group, created on first use with the current file.
+    fn output(&mut self, key: String) -> Result<&mut Output, mojxml::writer::Error> {
+        let create = |key: &str| {
+            let metadata = self.args.metadata(self.source.clone(), &self.metadata);
+            let path = group_path(&self.args.output, key);
+            Output::create(
+                self.args,
+                self.schema,
+                path,
+                metadata,
+                self.crs,
+                self.progress,
+            )
+        };
+        Ok(get_output(&mut self.outputs, key, create).map_err(std::io::Error::other)?)
+    }
+
+    /// Key of the output of the current file: its part, or its group.
+    fn file_key(&self) -> String {
+        match (&self.part, self.args.split_by) {
+            (Some(part), _) => part.clone(),
+            (None, Some(_)) => self.municipality.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn add_fude(
+        &mut self,
+        mut fude: Fude<'static>,
+        poly: geo::Polygon<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        let key = match self.args.split_by {
+            Some(SplitBy::Oaza) => format!(
+                "{}_{}",
+                self.municipality,
+                fude.attributes.oaza_code.as_deref().unwrap_or("unknown")
+            ),
+            _ => self.file_key(),
+        };
+        // created with the 筆, even when it's held, with the metadata of its file
+        self.output(key.clone())?;
+        let municipality = &self.municipality;
+        match (&mut self.dedup, &mut self.stitcher, &mut self.sorted) {
+            (Some(dedup), _, _) => {
+                let dedup_key = dedup_key(Some(municipality), &fude.attributes);
+                let pending = (key, municipality.clone(), fude);
+                dedup.insert(dedup_key, &self.name, poly, pending);
+            }
+            (None, Some(stitcher), _) => {
+                let dedup_key = dedup_key(Some(municipality), &fude.attributes);
+                let pending = (key, municipality.clone(), fude);
+                stitcher.insert(dedup_key, &self.name, poly, pending);
+            }
+            (None, None, Some(sorted)) => {
+                sorted.push((poly, (key, municipality.clone(), fude)));
+            }
+            (None, None, None) => {
+                check_chiban(&mut self.chibans, municipality, &mut fude, self.progress);
+                if let Some(points) = &mut self.points {
+                    points.write_fude(&fude, poly.clone())?;
+                }
+                self.outputs
+                    .get_mut(&key)
+                    .unwrap()
+                    .write_fude(&fude, poly)?;
+                self.written += 1;
+                if self.args.resume.is_some() {
+                    self.unsynced.insert(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the 筆 held by `--dedup`, `--stitch` and `--sort`, at the end of
+    /// the conversion.
+    fn write_held(&mut self) -> Result<(), BoxError> {
+        let mut pending = self.sorted.take().unwrap_or_default();
+        if let Some(dedup) = self.dedup.take() {
+            let (kept, removed) = dedup.finish();
+            pending.extend(kept);
+            let message = format!("Removed {} duplicate 筆", HumanCount(removed as u64));
+            log(self.progress, self.args.quiet, message);
+        }
+        if let Some(stitcher) = self.stitcher.take() {
+            let (stitched, merged) = stitcher.finish();
+            pending.extend(stitched);
+            let message = format!("Stitched {} pieces of 筆", HumanCount(merged as u64));
+            log(self.progress, self.args.quiet, message);
+        }
+        // with --deterministic, the 筆 kept by --dedup and --stitch are sorted too,
+        // as their order depends on hashing
+        if self.args.sort || self.args.deterministic {
+            pending.sort_by(|(_, (_, a_code, a)), (_, (_, b_code, b))| {
+                a_code
+                    .cmp(b_code)
+                    .then_with(|| a.attributes.natural_cmp(&b.attributes))
+            });
+        }
+        self.features_bar.inc(pending.len() as u64);
+        for (poly, (key, municipality, mut fude)) in pending {
+            check_chiban(&mut self.chibans, &municipality, &mut fude, self.progress);
+            if let Some(points) = &mut self.points {
+                points.write_fude(&fude, poly.clone())?;
+            }
+            // created with the 筆, when it was added
+            self.outputs
+                .get_mut(&key)
+                .unwrap()
+                .write_fude(&fude, poly)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl FeatureSink for Outputs<'_> {
+    fn begin_file(
+        &mut self,
+        name: &str,
+        metadata: &MapMetadata,
+    ) -> Result<(), mojxml::writer::Error> {
+        self.name = name.to_string();
+        self.metadata = metadata.clone();
+        self.municipality = municipality_code(metadata, name);
+        self.crs = match self.args.output_crs {
+            OutputCrs::Epsg(crs) => crs,
+            OutputCrs::Source => self.source_crs.lock().unwrap().unwrap_or_default(),
+        };
+        self.files += 1;
+        if self.args.points_output.is_some() && self.points.is_none() {
+            let writer = FgbFudeWriter::new_points_with_schema(self.schema.clone())?;
+            self.points = Some(writer.with_crs(self.crs)?);
+        }
+        let frame = self.frames.lock().unwrap().remove(name);
+        if let Some(frame) = frame {
+            let key = self.file_key();
+            self.output(key)?.set_bbox(frame.bounding_rect());
+        }
+        Ok(())
+    }
+
+    fn write_fude(
+        &mut self,
+        fude: &Fude,
+        polygon: geo::Polygon<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        self.add_fude(fude.clone().into_owned(), polygon)
+    }
+
+    fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        let key = self.file_key();
+        self.output(key)?.write_feature(layer, feature, geometry)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn write_file(&mut self, batch: FileBatch) -> Result<(), mojxml::writer::Error> {
+        let written = self.written;
+        self.begin_file(batch.name, batch.metadata)?;
+        for (fude, polygon) in batch.fudes {
+            self.add_fude(fude, polygon)?;
+        }
+        for (layer, feature, geometry) in batch.features {
+            self.write_feature(layer, &feature, geometry)?;
+        }
+        // with --resume, the file is journaled once written, so it must be on disk
+        for key in self.unsynced.drain() {
+            self.outputs.get_mut(&key).unwrap().sync()?;
+        }
+        self.features_bar.inc((self.written - written) as u64);
+        Ok(())
+    }
+}
+
+/// The parts of `--parts`. Each file is written to a part no other worker is
+/// writing to, if any.
+struct Parts<'a> {
+    parts: Vec<Mutex<Outputs<'a>>>,
+    next: AtomicUsize,
+}
+
+impl<'a> Parts<'a> {
+    fn part(&self) -> MutexGuard<'_, Outputs<'a>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let n = self.parts.len();
+        (0..n)
+            .find_map(|i| self.parts[(start + i) % n].try_lock().ok())
+            .unwrap_or_else(|| self.parts[start % n].lock().unwrap())
+    }
+}
+
+impl SharedFeatureSink for Parts<'_> {
+    fn add(&self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), mojxml::writer::Error> {
+        self.part().write_fude(fude, polygon)
+    }
+
+    fn add_feature(
+        &self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), mojxml::writer::Error> {
+        self.part().write_feature(layer, feature, geometry)
+    }
+
+    fn add_file(&self, batch: FileBatch) -> Result<(), mojxml::writer::Error> {
+        self.part().write_file(batch)
+    }
+}
+
+/// Convert the package of `args` into its outputs, with `mojxml::convert`,
+/// or only an evenly spaced sample of `sample` files of it.
+fn write_package(args: &ConvertArgs, sample: Option<usize>) -> Result<ConversionReport, BoxError> {
+    let inst = Instant::now();
+    // a resumed conversion goes on into the outputs of the interrupted one
+    let resumed;
+    let args = if args.resume.as_ref().is_some_and(|path| path.exists()) {
+        resumed = ConvertArgs {
+            append: true,
+            ..args.clone()
+        };
+        &resumed
+    } else {
+        args
+    };
+    let layers: Vec<Layer> = args.layers.iter().map(|&l| l.into()).collect();
+    let mut schema = match args.schema {
+        SchemaArg::Japanese => Schema::japanese(),
+        SchemaArg::English => Schema::english(),
+    };
+    if args.stable_id {
+        schema = schema.with_stable_id();
+    }
+    if args.provenance {
+        schema = schema.with_provenance();
+    }
+    if args.code_labels {
+        schema = schema.with_code_labels();
+    }
+    if args.computed_area {
+        schema = schema.with_computed_area();
+    }
+    if args.qa_flags {
+        for name in QA_FLAGS {
+            schema = schema.with_extra_column(name);
+        }
+    }
+    if args.municipality {
+        schema = schema.with_municipality();
+    }
+    let municipalities = match &args.municipality_table {
+        Some(path) => Some(Arc::new(
+            MunicipalityTable::from_csv(File::open(path)?)
+                .map_err(|e| format!("{}: {}", path.display(), e))?,
+        )),
+        None => None,
+    };
+    for (column, name) in &args.renames {
+        schema = schema.rename(column, name.as_str())?;
+    }
+    if !args.columns.is_empty() {
+        schema = schema.select(&args.columns)?;
+    } else if args.preset == Preset::Minimal {
+        schema = schema.select(["id", "大字名", "丁目名", "地番"])?;
+    }
+
+    let progress = if args.quiet {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
+    let input = open_input_in(&args.input_zip, args.temp_dir.as_deref())?;
+
+    let mut skip_files = Vec::new();
+    let journal = match &args.resume {
+        Some(path) => {
+            if path.exists() {
+                skip_files = BufReader::new(File::open(path)?)
+                    .lines()
+                    .collect::<Result<HashSet<_>, _>>()?
+                    .into_iter()
+                    .collect();
+                let message = format!("Skipping {} already processed entries", skip_files.len());
+                log(&progress, args.quiet, message);
+            }
+            let file = File::options().create(true).append(true).open(path)?;
+            Some(Arc::new(Mutex::new(file)))
+        }
+        None => None,
+    };
+
+    let files_bar = progress.add(
+        ProgressBar::no_length().with_style(
+            ProgressStyle::with_template(
+                "{elapsed_precise} [{wide_bar}] {pos}/{len} files ({per_sec}, ETA {eta})",
+            )?
+            .progress_chars("=> "),
+        ),
+    );
+    let features_bar = progress.add(ProgressBar::no_length().with_style(
+        ProgressStyle::with_template("{human_pos} features written ({per_sec})")?,
+    ));
+    let on_event: EventHook = {
+        let (progress, files_bar, journal) = (progress.clone(), files_bar.clone(), journal.clone());
+        let quiet = args.quiet;
+        Arc::new(move |event| {
+            let journaled = match event {
+                Event::Started { entries } => {
+                    files_bar.set_length(entries as u64);
+                    return Ok(());
+                }
+                Event::Written(file) => Some(file.name.as_str()),
+                Event::Skipped(name) => {
+                    log(&progress, quiet, format!("Skipped: {}", name));
+                    Some(name)
+                }
+                Event::Failed(_, error) => {
+                    // Not journaled, so that a resumed run retries the file. A
+                    // broken entry doesn't invalidate the rest of the archive.
+                    progress.suspend(|| eprintln!("Error: {}", error));
+                    None
+                }
+            };
+            files_bar.inc(1);
+            if let (Some(journal), Some(name)) = (&journal, journaled) {
+                let mut journal = journal.lock().unwrap();
+                writeln!(journal, "{}", name)?;
+                journal.sync_data()?;
+            }
+            Ok(())
+        })
+    };
+
+    let source_crs: Arc<Mutex<Option<Crs>>> = Arc::default();
+    let frames: Arc<Mutex<HashMap<String, geo::Polygon<f64>>>> = Arc::default();
+    let entries = args.entries.clone();
+    let options = ConvertOptions {
+        parse: ParseOptions {
+            skip_arbitrary_crs: !args.include_arbitrary_crs,
+            parse_kijunten: layers.contains(&Layer::Kijunten),
+            parse_hikkaiten: layers.contains(&Layer::Hikkaiten),
+            parse_zukaku: layers.contains(&Layer::Zukaku)
+                || args.sheet.is_some()
+                || args.provenance,
+            keep_source_coordinates: matches!(args.output_crs, OutputCrs::Source),
+            stable_ids: args.stable_id,
+            ..Default::default()
+        },
+        entry_filter: (!entries.is_empty()).then(|| -> EntryFilter {
+            Arc::new(move |name: &str| entries.iter().any(|e| name.contains(e)))
+        }),
+        continue_on_error: args.continue_on_error,
+        checksums: args.checksums,
+        prepare: Some(prepare_hook(
+            args,
+            &layers,
+            municipalities,
+            &progress,
+            &source_crs,
+            &frames,
+        )),
+        crs: match args.output_crs {
+            OutputCrs::Epsg(crs) => Some(crs),
+            OutputCrs::Source => None,
+        },
+        skip_files,
+        sample,
+        ordered: args.deterministic,
+        threads: args.threads,
+        memory_budget: args.memory_budget.map(|megabytes| megabytes * 1_000_000),
+        on_event: Some(on_event),
+        ..Default::default()
+    };
+
+    let new_outputs = |part| {
+        let (source_crs, frames) = (source_crs.clone(), frames.clone());
+        Outputs::new(
+            args,
+            &schema,
+            &progress,
+            features_bar.clone(),
+            source_crs,
+            frames,
+            part,
+        )
+    };
+    // the files rejected by the hook fail with its message, as before the conversion
+    let rejected = |e: mojxml::convert::Error| -> BoxError {
+        match e {
+            mojxml::convert::Error::Prepare { .. } => e.to_string().into(),
+            e => e.into(),
+        }
+    };
+    let (mut report, sinks) = match args.parts {
+        // the workers write each file to a free part
+        Some(parts) => {
+            let parts = Parts {
+                parts: (0..parts)
+                    .map(|part| Mutex::new(new_outputs(Some(format!("part{:03}", part)))))
+                    .collect(),
+                next: AtomicUsize::new(0),
+            };
+            let report = mojxml::convert_shared(input, &parts, &options).map_err(rejected)?;
+            let sinks = parts.parts.into_iter();
+            (
+                report,
+                sinks.map(|part| part.into_inner().unwrap()).collect(),
+            )
+        }
+        None => {
+            let mut outputs = new_outputs(None);
+            let report = mojxml::convert(input, &mut outputs, &options).map_err(rejected)?;
+            (report, vec![outputs])
+        }
+    };
+    files_bar.finish();
+
+    let mut outputs = HashMap::new();
+    // with --parts, the number of files and features written to each part
+    let mut part_counts = Vec::new();
+    let mut points = None;
+    for mut sink in sinks {
+        sink.write_held()?;
+        if let Some(part) = sink.part.take()
+            && sink.files > 0
+        {
+            part_counts.push((part, sink.files, sink.written));
+        }
+        if let Some((counter, _)) = &sink.chibans {
+            report.duplicate_chibans = counter.duplicates();
+            let message = format!(
+                "{} duplicate 地番",
+                HumanCount(report.duplicate_chibans.len() as u64)
+            );
+            log(&progress, args.quiet, message);
+        }
+        points = points.or(sink.points);
+        outputs.extend(sink.outputs);
+    }
+    features_bar.finish();
+
+    let crs = match args.output_crs {
+        OutputCrs::Epsg(crs) => crs,
+        OutputCrs::Source => source_crs.lock().unwrap().unwrap_or_default(),
+    };
+    if outputs.is_empty() && args.split_by.is_none() {
+        let key = match args.parts {
+            Some(_) => "part000".to_string(),
+            None => String::new(),
+        };
+        let metadata = args.metadata(None, &MapMetadata::default());
+        let path = group_path(&args.output, &key);
+        let output = Output::create(args, &schema, path, metadata, crs, &progress)?;
+        outputs.insert(key.clone(), output);
+        part_counts.push((key, 0, 0));
+    }
+    if let Some(path) = &args.points_output {
+        let points = match points {
+            Some(points) => points,
+            None => FgbFudeWriter::new_points_with_schema(schema.clone())?.with_crs(crs)?,
+        };
+        points.finish(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(sheet) = &args.sheet
+        && outputs
+            .values()
+            .any(|output| matches!(output, Output::Svg(svg, _) if svg.bbox().is_none()))
+    {
+        return Err(format!("図郭 not found: {}", sheet).into());
+    }
+    let spinner = progress.add(ProgressBar::new_spinner().with_message("Writing outputs..."));
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let unmappable = if args.parts.is_some() {
+        // the parts are independent, so they are finished in parallel too
+        let unmappable = std::thread::scope(|scope| {
+            let finishing: Vec<_> = outputs
+                .into_values()
+                .map(|output| scope.spawn(move || output.finish()))
+                .collect();
+            finishing.into_iter().try_fold(0, |sum, finishing| {
+                let unmappable = finishing
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+                Ok::<_, BoxError>(sum + unmappable)
+            })
+        })?;
+        write_manifest(args, crs, part_counts)?;
+        unmappable
+    } else {
+        let mut unmappable = 0;
+        for output in outputs.into_values() {
+            unmappable += output.finish()?;
+        }
+        unmappable
+    };
+    spinner.finish_and_clear();
+    if unmappable > 0 && !args.quiet {
+        eprintln!(
+            "Warning: {} values have characters missing from Shift_JIS, written as numeric character references",
+            unmappable
+        );
+    }
+
+    if !args.quiet {
+        eprintln!("Elapsed time: {:?}", inst.elapsed());
+    }
+    report.elapsed = inst.elapsed();
+    Ok(report)
+}
+
+/// `--dry-run`: convert an evenly spaced sample of the files into a temporary
+/// directory, and extrapolate to the whole package by uncompressed size.
+fn dry_run(args: &ConvertArgs) -> Result<(), BoxError> {
+    let dir = match &args.temp_dir {
+        Some(dir) => tempfile::tempdir_in(dir)?,
+        None => tempfile::tempdir()?,
+    };
+    let extension = args.output.extension().unwrap_or_default();
+    let mut sample_args = args.clone();
+    sample_args.output = dir.path().join("sample").with_extension(extension);
+    sample_args.points_output =
+        (args.points_output.as_ref()).map(|_| dir.path().join("points.fgb"));
+    sample_args.report = None;
+    sample_args.resume = None;
+    sample_args.append = false;
+    sample_args.sheet = None;
+    sample_args.continue_on_error = true;
+    sample_args.quiet = true;
+    let report = write_package(&sample_args, Some(args.sample))?;
+    let output_size: u64 = std::fs::read_dir(dir.path())?
+        .map(|entry| Ok(entry?.metadata()?.len()))
+        .sum::<std::io::Result<u64>>()?;
+
+    let sample = report.sample.clone().unwrap_or_default();
+    let ratio = sample.ratio();
+    let features = report.fudes() + report.features();
+    println!(
+        "Files:     {} ({})",
+        HumanCount(sample.total_entries as u64),
+        HumanBytes(sample.total_size)
+    );
+    println!(
+        "Sample:    {} files ({}), {} features, converted in {}",
+        sample.entries,
+        HumanBytes(sample.size),
+        HumanCount(features as u64),
+        HumanDuration(report.elapsed)
+    );
+    println!(
+        "Estimated: {} features, {} output, {}",
+        HumanCount((features as f64 * ratio) as u64),
+        HumanBytes((output_size as f64 * ratio) as u64),
+        HumanDuration(report.elapsed.mul_f64(ratio))
+    );
+    Ok(())
+}
+
+/// Convert a package into one of the output formats.
+pub fn run(args: ConvertArgs) -> Result<(), BoxError> {
+    if let Some(dir) = &args.temp_dir
+        // the FlatGeobuf features are spooled by the flatgeobuf crate, in the
+        // default directory of tempfile (set once, `fetch` runs us repeatedly)
+        && let Err(previous) = tempfile::env::override_temp_dir(dir)
+        && previous != *dir
+    {
+        return Err(format!("--temp-dir is already {}", previous.display()).into());
+    }
+    let layers: Vec<Layer> = args.layers.iter().map(|&l| l.into()).collect();
+    if !matches!(args.format, Format::Fgb | Format::Gpkg) && layers != [Layer::Fude] {
+        return Err("--layers is only supported by the fgb and gpkg formats".into());
+    }
+    if args.vrt && !matches!(args.format, Format::Fgb) {
+        return Err("--vrt is only supported by the fgb format".into());
+    }
+    if args.append && !matches!(args.format, Format::Fgb | Format::Gpkg) {
+        return Err("--append is only supported by the fgb and gpkg formats".into());
+    }
+    let geographic = matches!(args.output_crs, OutputCrs::Epsg(crs) if crs.is_geographic());
+    if !geographic
+        && matches!(
+            args.format,
+            Format::Kml | Format::Kmz | Format::Pmtiles | Format::Svg
+        )
+    {
+        return Err("kml, kmz, pmtiles and svg only support --output-crs 6668 and 4326".into());
+    }
+    if args.output == Path::new("-") {
+        if !matches!(
+            args.format,
+            Format::Geojson | Format::Geojsonl | Format::Csv | Format::Tsv
+        ) {
+            return Err(
+                "only the geojson, geojsonl, csv and tsv formats can be written to stdout".into(),
+            );
+        }
+        if args.split_by.is_some() {
+            return Err("--split-by can't be used when writing to stdout".into());
+        }
+    }
+    if let Some(parts) = args.parts {
+        if !matches!(args.format, Format::Fgb | Format::Parquet) {
+            return Err("--parts is only supported by the fgb and parquet formats".into());
+        }
+        if parts == 0 || args.output == Path::new("-") {
+            return Err("--parts needs at least 1 part, written to files".into());
+        }
+    }
+    if args.resume.is_some()
+        && (!matches!(args.format, Format::Geojsonl | Format::Csv | Format::Tsv)
+            || args.output == Path::new("-"))
+    {
+        // the other formats are only complete once finished, so the files
+        // written before an interruption would be lost but journaled
+        return Err(
+            "--resume is only supported by the geojsonl, csv and tsv formats, written to a file"
+                .into(),
+        );
+    }
+    if args.split_by == Some(SplitBy::Oaza) && layers != [Layer::Fude] {
+        return Err("--split-by oaza only supports the fude layer".into());
+    }
+    if args.dry_run {
+        return dry_run(&args);
+    }
+
+    let report = write_package(&args, None)?;
+    if let Some(path) = &args.report {
+        let mut out = BufWriter::new(File::create(path)?);
+        report.write_json(&mut out)?;
+        out.flush()?;
+    }
+
+    let failures = &report.failed_files;
+    if !failures.is_empty() {
+        eprintln!("{} files failed:", failures.len());
+        for (_, error) in failures {
+            eprintln!("  {}", error);
+        }
+        return Err(format!("{} files failed", failures.len()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! `diff` command.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use mojxml::diff::{Change, Key, Parcels, diff_parcels};
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::zip::ZipPackageParallelIter;
+use rayon::prelude::*;
+use serde_json::json;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct DiffArgs {
+    /// The old .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    old_zip: PathBuf,
+    /// The new .zip file
+    #[arg()]
+    new_zip: PathBuf,
+    /// Only read archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Ignore geometry changes of at most this area, in m²
+    #[arg(long, default_value_t = 0.01)]
+    min_area: f64,
+    /// Print the changes as JSON lines
+    #[arg(long)]
+    json: bool,
+}
+
+/// Read the 筆 of a package.
+fn read_parcels(input: &Path, entries: &[String]) -> Result<Parcels, BoxError> {
+    let mut zip = ZipPackageParallelIter::new(open_input(input)?)?;
+    if !entries.is_empty() {
+        let entries = entries.to_vec();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let parcels = Mutex::new(Parcels::new());
+    zip.parsed(ParseOptions::default(), Arc::new(jpr_projections()))
+        .par_bridge()
+        .try_for_each(|res| -> Result<(), BoxError> {
+            let (info, data) = match res {
+                Ok(parsed) => parsed,
+                Err(e @ mojxml::zip::Error::Entry { .. }) => {
+                    eprintln!("Error: {}", e);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let mut file = Parcels::new();
+            file.insert(&info.name, &data);
+            parcels.lock().unwrap().append(file);
+            Ok(())
+        })?;
+    Ok(parcels.into_inner().unwrap())
+}
+
+fn key_string(key: &Key) -> String {
+    key.iter()
+        .filter(|part| !part.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn to_json(change: &Change) -> serde_json::Value {
+    let [municipality_code, oaza_code, chome_code, chiban] = change.key();
+    let mut value = json!({
+        "municipality_code": municipality_code,
+        "oaza_code": oaza_code,
+        "chome_code": chome_code,
+        "chiban": chiban,
+    });
+    let (change, attributes, geometry) = match change {
+        Change::Added(_) => ("added", None, None),
+        Change::Removed(_) => ("removed", None, None),
+        Change::Modified {
+            attributes,
+            geometry,
+            ..
+        } => ("modified", Some(attributes), geometry.as_ref()),
+    };
+    value["change"] = json!(change);
+    if let Some(attributes) = attributes {
+        value["attributes"] = attributes
+            .iter()
+            .map(|a| (a.name.to_string(), json!({ "old": a.old, "new": a.new })))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+    if let Some(geometry) = geometry {
+        value["geometry"] = json!({
+            "old_area": geometry.old_area,
+            "new_area": geometry.new_area,
+            "changed_area": geometry.changed_area,
+        });
+    }
+    value
+}
+
+/// Print the 筆 added, removed or modified between two packages.
+pub fn run(args: DiffArgs) -> Result<(), BoxError> {
+    let old = read_parcels(&args.old_zip, &args.entries)?;
+    let new = read_parcels(&args.new_zip, &args.entries)?;
+    let changes = diff_parcels(&old, &new, args.min_area);
+
+    let mut counts = [0; 3];
+    for change in &changes {
+        let key = key_string(change.key());
+        match change {
+            Change::Added(_) => counts[0] += 1,
+            Change::Removed(_) => counts[1] += 1,
+            Change::Modified { .. } => counts[2] += 1,
+        }
+        if args.json {
+            println!("{}", to_json(change));
+            continue;
+        }
+        match change {
+            Change::Added(_) => println!("+ {}", key),
+            Change::Removed(_) => println!("- {}", key),
+            Change::Modified {
+                attributes,
+                geometry,
+                ..
+            } => {
+                let mut line = format!("~ {}", key);
+                if let Some(geometry) = geometry {
+                    line += &format!(
+                        "  area {:.2} -> {:.2}",
+                        geometry.old_area, geometry.new_area
+                    );
+                    match geometry.changed_area {
+                        Some(area) => line += &format!(" (changed {:.2})", area),
+                        None => line += " (座標系 changed)",
+                    }
+                }
+                for attribute in attributes {
+                    line += &format!(
+                        "  {}: {} -> {}",
+                        attribute.name,
+                        attribute.old.as_deref().unwrap_or("-"),
+                        attribute.new.as_deref().unwrap_or("-")
+                    );
+                }
+                println!("{}", line);
+            }
+        }
+    }
+    eprintln!(
+        "{} 筆 -> {} 筆: {} added, {} removed, {} modified",
+        old.len(),
+        new.len(),
+        counts[0],
+        counts[1],
+        counts[2]
+    );
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! `fetch` command.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use mojxml::zip::fetch::{DEFAULT_API, Portal};
+
+use crate::convert::{self, ConvertArgs};
+use crate::{BoxError, config};
+
+#[derive(clap::Args)]
+pub struct FetchArgs {
+    /// 市区町村コード (5 digits), or prefecture codes (2 digits) for all
+    /// their municipalities
+    #[arg(required = true)]
+    codes: Vec<String>,
+    /// Directory to download the packages into
+    #[arg(long, default_value = ".")]
+    dir: PathBuf,
+    /// Only list the packages, without downloading them
+    #[arg(long)]
+    dry_run: bool,
+    /// Convert each package next to it, to this format (e.g. `fgb`). Other
+    /// options of `convert` go after `--`
+    #[arg(long, value_name = "FORMAT", conflicts_with = "dry_run")]
+    convert: Option<String>,
+    /// CKAN API of the portal
+    #[arg(long, default_value = DEFAULT_API)]
+    api: String,
+    /// Options of the conversion, with --convert
+    #[arg(last = true, requires = "convert")]
+    convert_args: Vec<String>,
+}
+
+/// The arguments of `convert`, parsed from the ones given after `--`.
+#[derive(Parser)]
+struct ConvertCommand {
+    #[command(flatten)]
+    args: ConvertArgs,
+}
+
+/// Resolve the packages of the codes on G空間情報センター, download them and
+/// optionally convert them, printing the path of each package.
+pub fn run(args: FetchArgs) -> Result<(), BoxError> {
+    let portal = Portal::new().with_api(&args.api);
+    let mut packages = Vec::new();
+    for code in &args.codes {
+        packages.extend(portal.resolve(code)?);
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages.dedup_by(|a, b| a.name == b.name);
+
+    if args.dry_run {
+        for package in &packages {
+            let size = package.size.map(|size| HumanBytes(size).to_string());
+            println!(
+                "{}\t{}\t{}\t{}",
+                package.name,
+                size.as_deref().unwrap_or("-"),
+                package.dataset,
+                package.url
+            );
+        }
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&args.dir)?;
+    for package in &packages {
+        let bar = ProgressBar::new(package.size.unwrap_or(0)).with_style(
+            ProgressStyle::with_template(
+                "{msg} [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+            )?
+            .progress_chars("=> "),
+        );
+        bar.set_message(package.name.clone());
+        let path = portal.download(package, &args.dir, |bytes| bar.set_position(bytes))?;
+        bar.finish_and_clear();
+        println!("{}", path.display());
+
+        if let Some(format) = &args.convert {
+            let extension = match format.as_str() {
+                "spatialite" => "sqlite",
+                format => format,
+            };
+            let output = path.with_extension(extension);
+            let mut convert_args = vec![
+                "convert".into(),
+                path.into_os_string(),
+                output.into_os_string(),
+                "--format".into(),
+                format.into(),
+            ];
+            convert_args.extend(args.convert_args.iter().map(Into::into));
+            let convert_args = config::expand_args(convert_args)?;
+            convert::run(ConvertCommand::try_parse_from(convert_args)?.args)?;
+        }
+    }
+    Ok(())
+}
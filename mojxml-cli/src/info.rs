@@ -0,0 +1,157 @@
+//! `info` command.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use memchr::memmem;
+use mojxml::parser::{MojxmlParser, jpr_projections};
+use mojxml::zip::ZipPackageParallelIter;
+use rayon::prelude::*;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct InfoArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Only read archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Also count the 筆 and the points of each file (by scanning for their tags)
+    #[arg(long)]
+    count: bool,
+    /// Print JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Default)]
+struct Municipality {
+    name: Option<String>,
+    files: usize,
+    fudes: usize,
+    points: usize,
+}
+
+#[derive(Default)]
+struct Summary {
+    files: usize,
+    broken: usize,
+    datums: BTreeMap<String, usize>,
+    crs: BTreeMap<String, usize>,
+    municipalities: BTreeMap<String, Municipality>,
+}
+
+/// Print a summary of a package, from the headers of its files.
+pub fn run(args: InfoArgs) -> Result<(), BoxError> {
+    let mut zip = ZipPackageParallelIter::new(open_input(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let projections = jpr_projections();
+    let fude_tag = memmem::Finder::new("<筆 ");
+    let point_tag = memmem::Finder::new("<zmn:GM_Point ");
+    let summary = Mutex::new(Summary::default());
+
+    zip.par_bridge().for_each(|res| {
+        let header = res.map_err(|e| e.to_string()).and_then(|(name, data)| {
+            let header = MojxmlParser::new(Cursor::new(&data), &projections)
+                .parse_header()
+                .map_err(|e| format!("{}: {}", name, e))?;
+            let counts = if args.count {
+                (
+                    fude_tag.find_iter(&data).count(),
+                    point_tag.find_iter(&data).count(),
+                )
+            } else {
+                (0, 0)
+            };
+            Ok((header, counts))
+        });
+        let mut summary = summary.lock().unwrap();
+        let (header, (fudes, points)) = match header {
+            Ok(header) => header,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                summary.broken += 1;
+                return;
+            }
+        };
+        summary.files += 1;
+        let unknown = || "unknown".to_string();
+        *summary
+            .datums
+            .entry(header.datum.unwrap_or_else(unknown))
+            .or_default() += 1;
+        *summary
+            .crs
+            .entry(header.crs.unwrap_or_else(unknown))
+            .or_default() += 1;
+        let municipality = summary
+            .municipalities
+            .entry(header.municipality_code.unwrap_or_else(unknown))
+            .or_default();
+        if municipality.name.is_none() {
+            municipality.name = header.municipality_name;
+        }
+        municipality.files += 1;
+        municipality.fudes += fudes;
+        municipality.points += points;
+    });
+    let summary = summary.into_inner().unwrap();
+
+    if args.json {
+        let municipalities: BTreeMap<_, _> = summary
+            .municipalities
+            .iter()
+            .map(|(code, m)| {
+                let mut value = serde_json::json!({ "name": m.name, "files": m.files });
+                if args.count {
+                    value["fudes"] = m.fudes.into();
+                    value["points"] = m.points.into();
+                }
+                (code, value)
+            })
+            .collect();
+        let value = serde_json::json!({
+            "files": summary.files,
+            "broken_entries": summary.broken,
+            "datums": summary.datums,
+            "crs": summary.crs,
+            "municipalities": municipalities,
+        });
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    println!("Files: {}", summary.files);
+    if summary.broken > 0 {
+        println!("Broken entries: {}", summary.broken);
+    }
+    println!("Datum:");
+    for (datum, count) in &summary.datums {
+        println!("  {}: {}", datum, count);
+    }
+    println!("座標系:");
+    for (crs, count) in &summary.crs {
+        println!("  {}: {}", crs, count);
+    }
+    println!("Municipalities:");
+    for (code, m) in &summary.municipalities {
+        print!(
+            "  {} {}: {} files",
+            code,
+            m.name.as_deref().unwrap_or("-"),
+            m.files
+        );
+        if args.count {
+            print!(", {} 筆, {} points", m.fudes, m.points);
+        }
+        println!();
+    }
+    Ok(())
+}
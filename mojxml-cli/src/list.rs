@@ -0,0 +1,86 @@
+//! `list` command.
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use mojxml::parser::{MojxmlParser, jpr_projections};
+use mojxml::zip::ZipPackageParallelIter;
+use rayon::prelude::*;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct ListArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Only list archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Print a JSON array instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+struct Row {
+    name: String,
+    size: u64,
+    municipality_code: Option<String>,
+    crs: Option<String>,
+}
+
+/// List the XML files of a package, with the municipality and the CRS from their header.
+pub fn run(args: ListArgs) -> Result<(), BoxError> {
+    let mut zip = ZipPackageParallelIter::new(open_input(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let projections = jpr_projections();
+
+    let mut rows: Vec<Row> = zip
+        .par_bridge()
+        .filter_map(|res| {
+            let (name, data) = res.map_err(|e| eprintln!("Error: {}", e)).ok()?;
+            let header = MojxmlParser::new(Cursor::new(&data), &projections)
+                .parse_header()
+                .map_err(|e| eprintln!("Error: {}: {}", name, e))
+                .ok()?;
+            Some(Row {
+                name,
+                size: data.len() as u64,
+                municipality_code: header.municipality_code,
+                crs: header.crs,
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if args.json {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                serde_json::json!({
+                    "name": row.name,
+                    "size": row.size,
+                    "municipality_code": row.municipality_code,
+                    "crs": row.crs,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    let width = rows.iter().map(|row| row.name.len()).max().unwrap_or(0);
+    for row in &rows {
+        println!(
+            "{:<width$}  {:>12}  {:<6}  {}",
+            row.name,
+            row.size,
+            row.municipality_code.as_deref().unwrap_or("-"),
+            row.crs.as_deref().unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
@@ -1,152 +1,91 @@
-use std::array;
-use std::fs::File;
-use std::io::Cursor;
-use std::path::PathBuf;
-use std::sync::RwLock;
-use std::time::Instant;
+mod bench;
+mod config;
+mod convert;
+mod diff;
+mod fetch;
+mod info;
+mod list;
+mod stats;
+mod topology;
+mod validate;
 
-use clap::Parser;
+use std::fs::File;
+use std::io::{Read, Seek};
 
-use flatgeobuf::geozero::PropertyProcessor;
-use flatgeobuf::{ColumnType, GeometryType};
-use geozero::ColumnValue;
-use rayon::prelude::*;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
-struct Args {
-    /// Input .zip file
-    #[arg()]
-    input_zip: PathBuf,
-    /// Output .fgb file
-    #[arg()]
-    output_fgb: PathBuf,
+#[command(version, about, args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-
-    let inst = Instant::now();
-    let zip = mojxml::zip::ZipPackageParallelIter::new(File::open(args.input_zip)?)?;
-
-    let mut fgb = flatgeobuf::FgbWriter::create_with_options(
-        "mojxml",
-        GeometryType::Polygon,
-        flatgeobuf::FgbWriterOptions {
-            crs: flatgeobuf::FgbCrs {
-                code: 6668, // JGD2011
-                ..Default::default()
-            },
-            ..Default::default()
-        },
-    )?;
-
-    fgb.add_column("id", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("大字コード", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("丁目コード", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("小字コード", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("予備コード", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("大字名", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("丁目名", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("小字名", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("予備名", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("地番", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("精度区分", ColumnType::String, |_fbb, _col| {});
-    fgb.add_column("座標値種別", ColumnType::String, |_fbb, _col| {});
-
-    let fgb_rw = RwLock::new(fgb);
-
-    let projections: [jprect::etmerc::ExtendedTransverseMercatorProjection; 19] =
-        array::from_fn(|i| {
-            jprect::JPRZone::from_number(i + 1)
-                .expect("ok")
-                .projection()
-        });
-
-    zip.par_bridge().try_for_each(|res| match res {
-        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()),
-        Ok((name, data)) => {
-            eprintln!("File: {}", name);
-
-            let mut reader = Cursor::new(data);
-            let mut parser = mojxml::parser::MojxmlParser::new(&mut reader, &projections);
-            parser.skip_arbitrary_crs(true);
-
-            match parser.parse() {
-                Ok(data) => {
-                    for (fude_id, fude) in data.fudes.iter() {
-                        if let Ok(poly) = data.resolve_surface_geo(&fude.surface_id) {
-                            let geom = geo::geometry::Geometry::Polygon(poly);
-                            let mut fgb = fgb_rw.write().unwrap();
-
-                            fgb.add_feature_geom(geom, |feat| {
-                                feat.property(0, "id", &ColumnValue::String(fude_id))
-                                    .unwrap();
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a package into FlatGeobuf, GeoPackage, GeoJSON, ...
+    Convert(Box<convert::ConvertArgs>),
+    /// List the XML files of a package, with their municipality and CRS
+    List(list::ListArgs),
+    /// Print a summary of a package: datums, 座標系, municipalities
+    Info(info::InfoArgs),
+    /// Parse and check a package without writing anything
+    Validate(validate::ValidateArgs),
+    /// Print per-municipality statistics: counts, areas, 精度区分
+    Stats(stats::StatsArgs),
+    /// Print the 筆 added, removed or modified between two packages
+    Diff(diff::DiffArgs),
+    /// Export the graph of references between 筆, surfaces, curves and points
+    Topology(topology::TopologyArgs),
+    /// Time the stages of the conversion (unzip, parse, resolve, write) on a package
+    Bench(bench::BenchArgs),
+    /// Download the packages of municipalities from G空間情報センター
+    Fetch(fetch::FetchArgs),
+}
 
-                                if let Some(s) = &fude.attributes.oaza_code {
-                                    feat.property(1, "大字コード", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.chome_code {
-                                    feat.property(2, "丁目コード", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.koaza_code {
-                                    feat.property(3, "小字コード", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.yobi_code {
-                                    feat.property(4, "予備コード", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
+trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
 
-                                if let Some(s) = &fude.attributes.oaza {
-                                    feat.property(5, "大字名", &ColumnValue::String(s)).unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.chome {
-                                    feat.property(6, "丁目名", &ColumnValue::String(s)).unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.koaza {
-                                    feat.property(7, "小字名", &ColumnValue::String(s)).unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.yobi {
-                                    feat.property(8, "予備名", &ColumnValue::String(s)).unwrap();
-                                }
+fn open_input(input: &std::path::Path) -> std::io::Result<Box<dyn ReadSeek>> {
+    open_input_in(input, None)
+}
 
-                                if let Some(s) = &fude.attributes.chiban {
-                                    feat.property(9, "地番", &ColumnValue::String(s)).unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.accuracy_class {
-                                    feat.property(10, "精度区分", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
-                                if let Some(s) = &fude.attributes.coord_class {
-                                    feat.property(11, "座標値種別", &ColumnValue::String(s))
-                                        .unwrap();
-                                }
-                                // if let Some(s) = &fude.attributes.hikkai_mitei {
-                                //     feat.property(12, "筆界未定構成筆", &ColumnValue::String(s)).unwrap();
-                                // }
-                            })
-                            .unwrap();
-                        }
-                    }
-                    Ok(())
-                }
-                Err(mojxml::parser::Error::SkipAll) => Ok(()),
-                Err(e) => {
-                    eprintln!("Error: {}", e);
-                    Err(e)
-                }
-            }
+/// Same as `open_input`, spooling stdin to `temp_dir` if given.
+fn open_input_in(
+    input: &std::path::Path,
+    temp_dir: Option<&std::path::Path>,
+) -> std::io::Result<Box<dyn ReadSeek>> {
+    match input.to_str() {
+        Some("-") => {
+            // Reading a zip requires seeking, so spool stdin to a temporary file
+            let mut file = match temp_dir {
+                Some(dir) => tempfile::tempfile_in(dir)?,
+                None => tempfile::tempfile()?,
+            };
+            std::io::copy(&mut std::io::stdin().lock(), &mut file)?;
+            file.rewind()?;
+            Ok(Box::new(file))
         }
-    })?;
-
-    // Write .fgb file
-    eprintln!("Writing .fgb file...");
-    let fgb = fgb_rw.into_inner().unwrap();
-    let file = std::fs::File::create(args.output_fgb)?;
-    fgb.write(file)?;
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            Ok(Box::new(mojxml::zip::remote::HttpRangeReader::new(url)?))
+        }
+        _ => Ok(Box::new(File::open(input)?)),
+    }
+}
 
-    eprintln!("Elapsed time: {:?}", inst.elapsed());
-    Ok(())
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn main() -> Result<(), BoxError> {
+    let args = config::expand_args(std::env::args_os())?;
+    match Cli::parse_from(args).command {
+        Command::Convert(args) => convert::run(*args),
+        Command::List(args) => list::run(args),
+        Command::Info(args) => info::run(args),
+        Command::Validate(args) => validate::run(args),
+        Command::Stats(args) => stats::run(args),
+        Command::Diff(args) => diff::run(args),
+        Command::Topology(args) => topology::run(args),
+        Command::Bench(args) => bench::run(args),
+        Command::Fetch(args) => fetch::run(args),
+    }
 }
@@ -0,0 +1,274 @@
+//! `stats` command.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use geo::GeodesicArea;
+use mojxml::data::OazaStats;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::zip::ZipPackageParallelIter;
+use rayon::prelude::*;
+use serde_json::json;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Only read archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+    /// Don't compute the area of the 筆, which skips their geometries and is
+    /// much faster
+    #[arg(long)]
+    no_area: bool,
+    /// Print the statistics of each 大字 and 丁目 instead (with their planar
+    /// areas, in the local coordinates for a 任意座標系)
+    #[arg(long)]
+    by_oaza: bool,
+}
+
+#[derive(Default)]
+struct Stats {
+    name: Option<String>,
+    files: usize,
+    arbitrary_crs_files: usize,
+    fudes: usize,
+    /// Geodesic area of the 筆 in public coordinate systems, in m²
+    area: f64,
+    /// Number of 筆 by 精度区分
+    accuracy_classes: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        if self.name.is_none() {
+            self.name.clone_from(&other.name);
+        }
+        self.files += other.files;
+        self.arbitrary_crs_files += other.arbitrary_crs_files;
+        self.fudes += other.fudes;
+        self.area += other.area;
+        for (class, count) in &other.accuracy_classes {
+            *self.accuracy_classes.entry(class.clone()).or_default() += count;
+        }
+    }
+
+    fn arbitrary_crs_ratio(&self) -> f64 {
+        match self.files {
+            0 => 0.0,
+            files => self.arbitrary_crs_files as f64 / files as f64,
+        }
+    }
+
+    /// The statistics as JSON (`area_m2` is null without `area`).
+    fn to_json(&self, area: bool) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "files": self.files,
+            "arbitrary_crs_files": self.arbitrary_crs_files,
+            "arbitrary_crs_ratio": self.arbitrary_crs_ratio(),
+            "fudes": self.fudes,
+            "area_m2": area.then_some(self.area),
+            "accuracy_classes": self.accuracy_classes,
+        })
+    }
+}
+
+/// Print per-municipality statistics of a package.
+pub fn run(args: StatsArgs) -> Result<(), BoxError> {
+    let mut zip = ZipPackageParallelIter::new(open_input(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let options = ParseOptions {
+        skip_arbitrary_crs: false,
+        skip_geometric: args.no_area,
+        ..Default::default()
+    };
+    let municipalities: Mutex<BTreeMap<String, Stats>> = Mutex::new(BTreeMap::new());
+    // with --by-oaza, by 市区町村コード, 大字コード and 丁目コード
+    let oazas: Mutex<BTreeMap<[String; 3], OazaStats>> = Mutex::new(BTreeMap::new());
+    let projections = Arc::new(jpr_projections());
+
+    zip.parsed(options, projections.clone())
+        .par_bridge()
+        .try_for_each(|res| -> Result<(), BoxError> {
+            let (info, data) = match res {
+                Ok(parsed) => parsed,
+                Err(e @ mojxml::zip::Error::Entry { .. }) => {
+                    eprintln!("Error: {}", e);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let arbitrary_crs = data.metadata.crs.as_deref() == Some("任意座標系");
+            let mut stats = Stats {
+                name: data.metadata.municipality_name.clone(),
+                files: 1,
+                arbitrary_crs_files: arbitrary_crs as usize,
+                fudes: data.fudes.len(),
+                ..Default::default()
+            };
+            for fude in data.fudes.values() {
+                let class = fude.attributes.accuracy_class.as_deref().unwrap_or("-");
+                *stats.accuracy_classes.entry(class.to_string()).or_default() += 1;
+                // arbitrary coordinates can't be measured on the ellipsoid
+                if !arbitrary_crs
+                    && !args.no_area
+                    && let Ok(polygon) = data.resolve_surface_geo(fude.surface_id)
+                {
+                    stats.area += polygon.geodesic_area_unsigned();
+                }
+            }
+
+            let code = match &data.metadata.municipality_code {
+                Some(code) => code.clone(),
+                None => info
+                    .name
+                    .split(['-', '.'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            if args.by_oaza {
+                let mut oazas = oazas.lock().unwrap();
+                for ((oaza_code, chome_code), stats) in data.aggregate_by_oaza(Some(&projections)) {
+                    oazas
+                        .entry([code.clone(), oaza_code, chome_code])
+                        .or_default()
+                        .add(&stats);
+                }
+            }
+            municipalities
+                .lock()
+                .unwrap()
+                .entry(code)
+                .or_default()
+                .add(&stats);
+            Ok(())
+        })?;
+
+    let municipalities = municipalities.into_inner().unwrap();
+    let mut total = Stats::default();
+    for stats in municipalities.values() {
+        total.add(stats);
+    }
+    total.name = None;
+
+    if args.by_oaza {
+        return print_oazas(&oazas.into_inner().unwrap(), &args);
+    }
+    if args.json {
+        let value = json!({
+            "municipalities": municipalities
+                .iter()
+                .map(|(code, stats)| (code.clone(), stats.to_json(!args.no_area)))
+                .collect::<serde_json::Map<_, _>>(),
+            "total": total.to_json(!args.no_area),
+        });
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<12} {:>6} {:>14} {:>10} {:>16}  精度区分",
+        "code", "name", "files", "任意座標系", "筆", "area (m²)"
+    );
+    let print_row = |code: &str, stats: &Stats| {
+        let classes = stats
+            .accuracy_classes
+            .iter()
+            .map(|(class, count)| format!("{}:{}", class, count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let area = match args.no_area {
+            true => "-".to_string(),
+            false => format!("{:.1}", stats.area),
+        };
+        println!(
+            "{:<8} {:<12} {:>6} {:>6} ({:>5.1}%) {:>10} {:>16}  {}",
+            code,
+            stats.name.as_deref().unwrap_or("-"),
+            stats.files,
+            stats.arbitrary_crs_files,
+            stats.arbitrary_crs_ratio() * 100.0,
+            stats.fudes,
+            area,
+            classes
+        );
+    };
+    for (code, stats) in &municipalities {
+        print_row(code, stats);
+    }
+    print_row("total", &total);
+    Ok(())
+}
+
+/// Print the statistics of each 大字 and 丁目 (`--by-oaza`).
+fn print_oazas(oazas: &BTreeMap<[String; 3], OazaStats>, args: &StatsArgs) -> Result<(), BoxError> {
+    let classes = |stats: &OazaStats| -> BTreeMap<String, usize> {
+        stats
+            .accuracy_classes
+            .iter()
+            .map(|(class, count)| (class.as_deref().unwrap_or("-").to_string(), *count))
+            .collect()
+    };
+    if args.json {
+        let value: Vec<_> = oazas
+            .iter()
+            .map(|([code, oaza_code, chome_code], stats)| {
+                json!({
+                    "municipality_code": code,
+                    "oaza_code": oaza_code,
+                    "chome_code": chome_code,
+                    "oaza": stats.oaza,
+                    "chome": stats.chome,
+                    "fudes": stats.fudes,
+                    "area_m2": (!args.no_area).then_some(stats.area),
+                    "accuracy_classes": classes(stats),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<5} {:<5} {:<16} {:>10} {:>16}  精度区分",
+        "code", "大字", "丁目", "name", "筆", "area (m²)"
+    );
+    for ([code, oaza_code, chome_code], stats) in oazas {
+        let name = [stats.oaza.as_deref(), stats.chome.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<String>();
+        let classes = classes(stats)
+            .iter()
+            .map(|(class, count)| format!("{}:{}", class, count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let area = match args.no_area {
+            true => "-".to_string(),
+            false => format!("{:.1}", stats.area),
+        };
+        println!(
+            "{:<8} {:<5} {:<5} {:<16} {:>10} {:>16}  {}",
+            code,
+            oaza_code,
+            chome_code,
+            if name.is_empty() { "-" } else { &name },
+            stats.fudes,
+            area,
+            classes
+        );
+    }
+    Ok(())
+}
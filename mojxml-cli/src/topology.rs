@@ -0,0 +1,76 @@
+//! `topology` command.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::writer::topology::{TopologyFormat, TopologyWriter};
+use mojxml::zip::ZipPackageIter;
+
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct TopologyArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Output file, or - for stdout
+    #[arg()]
+    output: PathBuf,
+    /// Output format
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+    /// Only export archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Keep the plane rectangular coordinates of the points instead of
+    /// converting them to longitude/latitude
+    #[arg(long)]
+    keep_source_coordinates: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Edge list: source,source_kind,target,target_kind,ring,index,x,y
+    Csv,
+    Graphml,
+}
+
+/// Write the reference graph of the 筆, surfaces, curves and points of a
+/// package. The nodes are prefixed with the name of their file.
+pub fn run(args: TopologyArgs) -> Result<(), BoxError> {
+    let mut zip = ZipPackageIter::new(open_input(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let options = ParseOptions {
+        skip_arbitrary_crs: false,
+        keep_source_coordinates: args.keep_source_coordinates,
+        ..Default::default()
+    };
+    let projections = jpr_projections();
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let format = match args.format {
+        Format::Csv => TopologyFormat::Csv,
+        Format::Graphml => TopologyFormat::GraphMl,
+    };
+    let mut writer = TopologyWriter::new(BufWriter::new(out), format)?;
+    while let Some(res) = zip.next_parsed(&options, &projections) {
+        match res {
+            Ok((info, data)) => {
+                let stem = info.name.strip_suffix(".xml").unwrap_or(&info.name);
+                writer.write_data(&data, &format!("{}:", stem))?;
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
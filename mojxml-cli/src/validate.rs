@@ -0,0 +1,182 @@
+//! `validate` command.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use geo::InteriorPoint;
+use mojxml::gaps::GapFinder;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::validate::{Severity, validate};
+use mojxml::zip::ZipPackageParallelIter;
+use rayon::prelude::*;
+use serde_json::{Value, json};
+
+use crate::convert::municipality_code;
+use crate::{BoxError, open_input};
+
+#[derive(clap::Args)]
+pub struct ValidateArgs {
+    /// Input .zip file (an http(s):// URL, or - for stdin)
+    #[arg()]
+    input_zip: PathBuf,
+    /// Only validate archive entries whose name contains this string (repeatable)
+    #[arg(long = "entry")]
+    entries: Vec<String>,
+    /// Fail when there are more errors than this
+    #[arg(long, default_value_t = 0)]
+    max_errors: usize,
+    /// Fail when there are more warnings than this (default: no limit)
+    #[arg(long)]
+    max_warnings: Option<usize>,
+    /// Also report the gaps between the 筆 of each 大字 (holes that no 筆
+    /// covers), as warnings. The coordinates are then kept in the plane
+    /// rectangular CS of the files
+    #[arg(long)]
+    gaps: bool,
+    /// Area in m² below which gaps are ignored
+    #[arg(long, value_name = "M2", default_value_t = 0.01, requires = "gaps")]
+    gap_min_area: f64,
+}
+
+struct FileResult {
+    name: String,
+    errors: Vec<Value>,
+    warnings: Vec<Value>,
+    /// With --gaps, the 筆 with the key of their 大字
+    polygons: Vec<(String, geo::Polygon)>,
+}
+
+/// Parse and check every file of a package, printing a JSON report to stdout.
+pub fn run(args: ValidateArgs) -> Result<(), BoxError> {
+    let mut zip = ZipPackageParallelIter::new(open_input(&args.input_zip)?)?;
+    if !args.entries.is_empty() {
+        let entries = args.entries.clone();
+        zip = zip.with_entry_filter(move |name| entries.iter().any(|e| name.contains(e)));
+    }
+    let options = ParseOptions {
+        skip_arbitrary_crs: false,
+        parse_kijunten: true,
+        parse_hikkaiten: true,
+        parse_zukaku: true,
+        keep_source_coordinates: args.gaps,
+        ..Default::default()
+    };
+
+    let mut files: Vec<FileResult> = zip
+        .parsed(options, Arc::new(jpr_projections()))
+        .par_bridge()
+        .map(|res| {
+            let (info, data) = match res {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let kind = match e {
+                        mojxml::zip::Error::Entry { .. } => "broken_entry",
+                        mojxml::zip::Error::Parse { .. } => "parse_error",
+                    };
+                    return FileResult {
+                        name: e.entry_name().to_string(),
+                        errors: vec![json!({ "kind": kind, "message": e.to_string() })],
+                        warnings: Vec::new(),
+                        polygons: Vec::new(),
+                    };
+                }
+            };
+            let mut file = FileResult {
+                name: info.name,
+                errors: Vec::new(),
+                warnings: Vec::new(),
+                polygons: Vec::new(),
+            };
+            for issue in validate(&data) {
+                let value = json!({
+                    "kind": issue.kind.name(),
+                    "id": issue.id,
+                    "message": issue.message,
+                });
+                match issue.kind.severity() {
+                    Severity::Error => file.errors.push(value),
+                    Severity::Warning => file.warnings.push(value),
+                }
+            }
+            if args.gaps {
+                let municipality = municipality_code(&data.metadata, &file.name);
+                file.polygons = data
+                    .fudes
+                    .values()
+                    .filter_map(|fude| {
+                        let oaza = fude.attributes.oaza_code.as_deref().unwrap_or_default();
+                        let polygon = data.resolve_surface_geo(fude.surface_id).ok()?;
+                        Some((format!("{}-{}", municipality, oaza), polygon))
+                    })
+                    .collect();
+            }
+            file
+        })
+        .collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut gaps = Vec::new();
+    if args.gaps {
+        let mut finder = GapFinder::new();
+        for (key, polygon) in files.iter_mut().flat_map(|file| file.polygons.drain(..)) {
+            finder.insert(&key, polygon);
+        }
+        gaps = finder.finish(args.gap_min_area);
+    }
+
+    let errors: usize = files.iter().map(|file| file.errors.len()).sum();
+    let warnings: usize = files.iter().map(|file| file.warnings.len()).sum::<usize>() + gaps.len();
+    let mut report = json!({
+        "files": files
+            .iter()
+            .map(|file| json!({
+                "name": file.name,
+                "errors": file.errors,
+                "warnings": file.warnings,
+            }))
+            .collect::<Vec<_>>(),
+        "totals": {
+            "files": files.len(),
+            "errors": errors,
+            "warnings": warnings,
+        },
+    });
+    if args.gaps {
+        let gaps: Vec<Value> = gaps
+            .iter()
+            .map(|gap| {
+                let (municipality_code, oaza_code) = gap.key.split_once('-').unwrap_or_default();
+                // a point inside the gap, to locate it
+                let point = gap
+                    .polygon
+                    .interior_point()
+                    .map(|point| [point.x(), point.y()]);
+                json!({
+                    "municipality_code": municipality_code,
+                    "oaza_code": oaza_code,
+                    "area": gap.area,
+                    "point": point,
+                })
+            })
+            .collect();
+        report["totals"]["gaps"] = json!(gaps.len());
+        report["gaps"] = Value::Array(gaps);
+    }
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    eprintln!(
+        "{} files, {} errors, {} warnings",
+        files.len(),
+        errors,
+        warnings
+    );
+
+    if errors > args.max_errors {
+        return Err(format!("too many errors ({} > {})", errors, args.max_errors).into());
+    }
+    if let Some(max_warnings) = args.max_warnings
+        && warnings > max_warnings
+    {
+        return Err(format!("too many warnings ({} > {})", warnings, max_warnings).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+/*
+ * C API of mojxml: read the 筆 of a package of 地図XML files.
+ *
+ *     MojxmlReader *reader = mojxml_open("15222-1107-2023.zip", 0, 1);
+ *     if (!reader) { fprintf(stderr, "%s\n", mojxml_last_error()); return 1; }
+ *     const MojxmlFeature *feature;
+ *     while ((feature = mojxml_next(reader)) || mojxml_last_error()) {
+ *         if (!feature) { fprintf(stderr, "%s\n", mojxml_last_error()); continue; }
+ *         size_t len;
+ *         const uint8_t *wkb = mojxml_feature_wkb(feature, &len);
+ *         ...
+ *     }
+ *     mojxml_close(reader);
+ *
+ * Strings are UTF-8 and NUL-terminated. A feature is owned by its reader and
+ * stays valid until the next call of mojxml_next or mojxml_close.
+ */
+
+#ifndef MOJXML_H
+#define MOJXML_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MojxmlReader MojxmlReader;
+typedef struct MojxmlFeature MojxmlFeature;
+
+/* Message of the last error of the thread, or NULL if the last call succeeded. */
+const char *mojxml_last_error(void);
+
+/*
+ * Open a package (.zip). Files in a 任意座標系 are skipped unless
+ * include_arbitrary_crs is nonzero; the others are in longitude/latitude.
+ * english names the attributes in English. Returns NULL on error.
+ */
+MojxmlReader *mojxml_open(const char *path, int include_arbitrary_crs, int english);
+
+/*
+ * The next 筆, or NULL at the end or on error (then mojxml_last_error is not
+ * NULL, and the next call continues with the next file).
+ */
+const MojxmlFeature *mojxml_next(MojxmlReader *reader);
+
+/* Close a reader, freeing its current feature. */
+void mojxml_close(MojxmlReader *reader);
+
+/* Attribute columns of the features of a reader. */
+size_t mojxml_attribute_count(const MojxmlReader *reader);
+const char *mojxml_attribute_name(const MojxmlReader *reader, size_t index);
+
+/* The polygon as little-endian WKB, or NULL when it can't be resolved. */
+const uint8_t *mojxml_feature_wkb(const MojxmlFeature *feature, size_t *len);
+
+/* Value of an attribute column, or NULL if the 筆 doesn't have it. */
+const char *mojxml_feature_attribute(const MojxmlFeature *feature, size_t index);
+
+/* Name of the XML file, and its 座標系 (or NULL). */
+const char *mojxml_feature_source(const MojxmlFeature *feature);
+const char *mojxml_feature_crs(const MojxmlFeature *feature);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
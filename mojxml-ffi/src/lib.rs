@@ -0,0 +1,338 @@
+//! C API, declared in `include/mojxml.h`: open a package, iterate over its
+//! 筆, and read the WKB polygon and attributes of each.
+//!
+//! The strings are UTF-8 and NUL-terminated. A feature returned by
+//! [`mojxml_next`] is owned by its reader and stays valid until the next call
+//! of [`mojxml_next`] or [`mojxml_close`].
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::fs::File;
+use std::ptr;
+
+use jprect::etmerc::ExtendedTransverseMercatorProjection;
+use mojxml::data::ParsedData;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::writer::Schema;
+use mojxml::zip::ZipPackageIter;
+
+type Projections = [ExtendedTransverseMercatorProjection; 19];
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl ToString) {
+    // an interior NUL can't be represented, so cut the message there
+    let mut message = message.to_string().into_bytes();
+    if let Some(nul) = message.iter().position(|&b| b == 0) {
+        message.truncate(nul);
+    }
+    let message = CString::new(message).expect("no interior NUL");
+    LAST_ERROR.with_borrow_mut(|error| *error = Some(message));
+}
+
+fn clear_error() {
+    LAST_ERROR.with_borrow_mut(|error| *error = None);
+}
+
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).expect("no interior NUL")
+}
+
+/// A package being read.
+pub struct MojxmlReader {
+    files: ZipPackageIter<File>,
+    options: ParseOptions,
+    projections: Box<Projections>,
+    schema: Schema,
+    names: Vec<CString>,
+    pending: VecDeque<MojxmlFeature>,
+    current: Option<MojxmlFeature>,
+}
+
+/// A 筆.
+pub struct MojxmlFeature {
+    /// `None` when the surface can't be resolved
+    wkb: Option<Vec<u8>>,
+    /// One per column of the schema
+    values: Vec<Option<CString>>,
+    source: CString,
+    crs: Option<CString>,
+}
+
+impl MojxmlReader {
+    fn push_features(&mut self, source: &str, data: &ParsedData) {
+        let source = c_string(source);
+        let crs = data.metadata.crs.as_deref().map(c_string);
+        let mut fudes: Vec<_> = data.fudes.values().collect();
+        fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+        self.pending.extend(fudes.into_iter().map(|fude| {
+            MojxmlFeature {
+                wkb: data.resolve_surface_wkb(fude.surface_id).ok(),
+                values: self
+                    .schema
+                    .columns()
+                    .iter()
+                    .map(|column| column.get(&fude.attributes).as_deref().map(c_string))
+                    .collect(),
+                source: source.clone(),
+                crs: crs.clone(),
+            }
+        }));
+    }
+
+    fn next_feature(&mut self) -> Result<Option<MojxmlFeature>, mojxml::zip::Error> {
+        loop {
+            if let Some(feature) = self.pending.pop_front() {
+                return Ok(Some(feature));
+            }
+            match self.files.next_parsed(&self.options, &self.projections) {
+                None => return Ok(None),
+                Some(Err(e)) if e.is_skipped() => continue,
+                Some(Err(e)) => return Err(e),
+                Some(Ok((info, data))) => self.push_features(&info.name, &data),
+            }
+        }
+    }
+}
+
+/// The message of the last error of the thread, or NULL if the last call
+/// succeeded. Valid until the next call on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn mojxml_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| error.as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Open a package (.zip). Files in a 任意座標系 are skipped unless
+/// `include_arbitrary_crs` is nonzero; the others are in longitude/latitude.
+/// `english` names the attributes in English. Returns NULL on error.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_open(
+    path: *const c_char,
+    include_arbitrary_crs: c_int,
+    english: c_int,
+) -> *mut MojxmlReader {
+    clear_error();
+    if path.is_null() {
+        set_error("path is NULL");
+        return ptr::null_mut();
+    }
+    let path = match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(path) => path,
+        Err(e) => {
+            set_error(e);
+            return ptr::null_mut();
+        }
+    };
+    let files = match File::open(path).and_then(ZipPackageIter::new) {
+        Ok(files) => files,
+        Err(e) => {
+            set_error(format_args!("{}: {}", path, e));
+            return ptr::null_mut();
+        }
+    };
+    let schema = match english {
+        0 => Schema::japanese(),
+        _ => Schema::english(),
+    };
+    let names = schema
+        .columns()
+        .iter()
+        .map(|column| c_string(column.name()))
+        .collect();
+    let reader = MojxmlReader {
+        files,
+        options: ParseOptions {
+            skip_arbitrary_crs: include_arbitrary_crs == 0,
+            ..Default::default()
+        },
+        projections: Box::new(jpr_projections()),
+        schema,
+        names,
+        pending: VecDeque::new(),
+        current: None,
+    };
+    Box::into_raw(Box::new(reader))
+}
+
+/// The next 筆, or NULL at the end or on error (see [`mojxml_last_error`]).
+/// After an error in a file, the next call continues with the next file.
+///
+/// # Safety
+///
+/// `reader` must come from [`mojxml_open`] and not be closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_next(reader: *mut MojxmlReader) -> *const MojxmlFeature {
+    clear_error();
+    let Some(reader) = (unsafe { reader.as_mut() }) else {
+        set_error("reader is NULL");
+        return ptr::null();
+    };
+    reader.current = match reader.next_feature() {
+        Ok(feature) => feature,
+        Err(e) => {
+            set_error(e);
+            None
+        }
+    };
+    reader
+        .current
+        .as_ref()
+        .map_or(ptr::null(), |feature| feature as *const MojxmlFeature)
+}
+
+/// Close a reader, freeing its current feature.
+///
+/// # Safety
+///
+/// `reader` must come from [`mojxml_open`] and not be closed, or be NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_close(reader: *mut MojxmlReader) {
+    if !reader.is_null() {
+        drop(unsafe { Box::from_raw(reader) });
+    }
+}
+
+/// Number of attribute columns of the features of a reader.
+///
+/// # Safety
+///
+/// `reader` must come from [`mojxml_open`] and not be closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_attribute_count(reader: *const MojxmlReader) -> usize {
+    unsafe { reader.as_ref() }.map_or(0, |reader| reader.names.len())
+}
+
+/// Name of an attribute column, or NULL if `index` is out of range.
+///
+/// # Safety
+///
+/// `reader` must come from [`mojxml_open`] and not be closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_attribute_name(
+    reader: *const MojxmlReader,
+    index: usize,
+) -> *const c_char {
+    unsafe { reader.as_ref() }
+        .and_then(|reader| reader.names.get(index))
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
+
+/// The polygon of a feature as WKB (little-endian), with its length in
+/// `len`, or NULL when its surface can't be resolved.
+///
+/// # Safety
+///
+/// `feature` must come from [`mojxml_next`] and still be valid; `len` must
+/// be writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_feature_wkb(
+    feature: *const MojxmlFeature,
+    len: *mut usize,
+) -> *const u8 {
+    let wkb = unsafe { feature.as_ref() }.and_then(|feature| feature.wkb.as_deref());
+    if let Some(len) = unsafe { len.as_mut() } {
+        *len = wkb.map_or(0, <[u8]>::len);
+    }
+    wkb.map_or(ptr::null(), <[u8]>::as_ptr)
+}
+
+/// Value of an attribute of a feature (in the order of
+/// [`mojxml_attribute_name`]), or NULL if it's missing.
+///
+/// # Safety
+///
+/// `feature` must come from [`mojxml_next`] and still be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_feature_attribute(
+    feature: *const MojxmlFeature,
+    index: usize,
+) -> *const c_char {
+    unsafe { feature.as_ref() }
+        .and_then(|feature| feature.values.get(index)?.as_ref())
+        .map_or(ptr::null(), |value| value.as_ptr())
+}
+
+/// Name of the XML file of a feature.
+///
+/// # Safety
+///
+/// `feature` must come from [`mojxml_next`] and still be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_feature_source(feature: *const MojxmlFeature) -> *const c_char {
+    unsafe { feature.as_ref() }.map_or(ptr::null(), |feature| feature.source.as_ptr())
+}
+
+/// 座標系 of the XML file of a feature, or NULL if it has none.
+///
+/// # Safety
+///
+/// `feature` must come from [`mojxml_next`] and still be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mojxml_feature_crs(feature: *const MojxmlFeature) -> *const c_char {
+    unsafe { feature.as_ref() }
+        .and_then(|feature| feature.crs.as_ref())
+        .map_or(ptr::null(), |crs| crs.as_ptr())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use test_log::test;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_read_package() {
+        let xml = mojxml::synthetic::SyntheticMap::new(1, 2).to_xml();
+        let path = std::env::temp_dir().join(format!("mojxml-test-ffi-{}.zip", std::process::id()));
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        writer
+            .start_file("13101-0000-1.xml", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(xml.as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let reader = mojxml_open(c_path.as_ptr(), 0, 1);
+            assert!(!reader.is_null());
+            let count = mojxml_attribute_count(reader);
+            let names: Vec<_> = (0..count)
+                .map(|i| CStr::from_ptr(mojxml_attribute_name(reader, i)))
+                .collect();
+            let chiban = names.iter().position(|name| *name == c"chiban").unwrap();
+
+            let mut chibans = Vec::new();
+            loop {
+                let feature = mojxml_next(reader);
+                if feature.is_null() {
+                    break;
+                }
+                let mut len = 0;
+                assert!(!mojxml_feature_wkb(feature, &mut len).is_null());
+                assert_eq!(len, 1 + 4 + 4 + 4 + 5 * 16);
+                assert_eq!(
+                    CStr::from_ptr(mojxml_feature_source(feature)),
+                    c"13101-0000-1.xml"
+                );
+                let value = CStr::from_ptr(mojxml_feature_attribute(feature, chiban));
+                chibans.push(value.to_str().unwrap().to_string());
+            }
+            assert!(mojxml_last_error().is_null());
+            assert_eq!(chibans, ["1", "2"]);
+            mojxml_close(reader);
+
+            let missing = CString::new("/nonexistent.zip").unwrap();
+            assert!(mojxml_open(missing.as_ptr(), 0, 0).is_null());
+            assert!(!mojxml_last_error().is_null());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
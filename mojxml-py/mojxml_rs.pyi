@@ -0,0 +1,36 @@
+from collections.abc import Iterator
+from os import PathLike
+
+class Feature:
+    """A 筆: its polygon as WKB and its attributes."""
+
+    @property
+    def wkb(self) -> bytes | None:
+        """The polygon (in longitude/latitude, unless in a 任意座標系), or
+        None when its surface can't be resolved."""
+    @property
+    def properties(self) -> dict[str, str]: ...
+    @property
+    def source(self) -> str:
+        """Name of the XML file."""
+    @property
+    def crs(self) -> str | None:
+        """座標系 of the XML file."""
+
+class FeatureIter(Iterator[Feature]):
+    def __iter__(self) -> FeatureIter: ...
+    def __next__(self) -> Feature: ...
+
+def parse_zip(
+    path: str | PathLike[str],
+    include_arbitrary_crs: bool = False,
+    english: bool = False,
+) -> list[Feature]:
+    """Read all the 筆 of a package."""
+
+def iter_zip(
+    path: str | PathLike[str],
+    include_arbitrary_crs: bool = False,
+    english: bool = False,
+) -> FeatureIter:
+    """Read the 筆 of a package as the files are parsed."""
@@ -0,0 +1,188 @@
+//! Python bindings: `mojxml_rs.parse_zip` and `mojxml_rs.iter_zip` read the
+//! 筆 of a package as WKB polygons with dict properties, ready for
+//! `geopandas.GeoSeries.from_wkb`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use mojxml::data::ParsedData;
+use mojxml::parser::{ParseOptions, jpr_projections};
+use mojxml::writer::schema::Schema;
+use mojxml::zip::{ZipPackageParallelIter, ZipPackageParsedIter};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+/// A 筆: its polygon as WKB and its attributes.
+#[pyclass(frozen, module = "mojxml_rs")]
+struct Feature {
+    /// `None` when the surface can't be resolved
+    wkb: Option<Vec<u8>>,
+    properties: Vec<(String, String)>,
+    source: String,
+    crs: Option<String>,
+}
+
+#[pymethods]
+impl Feature {
+    #[getter]
+    fn wkb<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        self.wkb.as_deref().map(|wkb| PyBytes::new(py, wkb))
+    }
+
+    #[getter]
+    fn properties<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        for (name, value) in &self.properties {
+            dict.set_item(name, value)?;
+        }
+        Ok(dict)
+    }
+
+    #[getter]
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    #[getter]
+    fn crs(&self) -> Option<&str> {
+        self.crs.as_deref()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Feature(source={:?}, properties={:?})",
+            self.source, self.properties
+        )
+    }
+}
+
+/// The 筆 of a file, in the order of their ids.
+fn features(schema: &Schema, source: &str, data: &ParsedData) -> Vec<Feature> {
+    let mut fudes: Vec<_> = data.fudes.values().collect();
+    fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+    fudes
+        .into_iter()
+        .map(|fude| Feature {
+            wkb: data.resolve_surface_wkb(fude.surface_id).ok(),
+            properties: schema
+                .columns()
+                .iter()
+                .filter_map(|column| {
+                    let value = column.get(&fude.attributes)?;
+                    Some((column.name().to_string(), value.to_string()))
+                })
+                .collect(),
+            source: source.to_string(),
+            crs: data.metadata.crs.clone(),
+        })
+        .collect()
+}
+
+struct Features {
+    entries: ZipPackageParsedIter,
+    schema: Schema,
+    pending: VecDeque<Feature>,
+}
+
+impl Features {
+    fn open(path: PathBuf, include_arbitrary_crs: bool, english: bool) -> PyResult<Self> {
+        let zip = ZipPackageParallelIter::new(File::open(path)?)?;
+        let options = ParseOptions {
+            skip_arbitrary_crs: !include_arbitrary_crs,
+            ..Default::default()
+        };
+        Ok(Self {
+            entries: zip.parsed(options, Arc::new(jpr_projections())),
+            schema: match english {
+                true => Schema::english(),
+                false => Schema::japanese(),
+            },
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn next_feature(&mut self) -> PyResult<Option<Feature>> {
+        loop {
+            if let Some(feature) = self.pending.pop_front() {
+                return Ok(Some(feature));
+            }
+            match self.entries.next() {
+                None => return Ok(None),
+                Some(Err(e)) if e.is_skipped() => continue,
+                Some(Err(e)) => return Err(PyValueError::new_err(e.to_string())),
+                Some(Ok((info, data))) => {
+                    self.pending
+                        .extend(features(&self.schema, &info.name, &data));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator of the 筆 of a package, parsed in the background.
+#[pyclass(module = "mojxml_rs")]
+struct FeatureIter {
+    features: Mutex<Features>,
+}
+
+#[pymethods]
+impl FeatureIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<Feature>> {
+        py.allow_threads(|| self.features.lock().unwrap().next_feature())
+    }
+}
+
+/// Read all the 筆 of a package.
+///
+/// Files in a 任意座標系 are skipped unless `include_arbitrary_crs`; the
+/// others are in longitude/latitude (JGD2000 or JGD2011). `english` names
+/// the properties in English.
+#[pyfunction]
+#[pyo3(signature = (path, include_arbitrary_crs = false, english = false))]
+fn parse_zip(
+    py: Python<'_>,
+    path: PathBuf,
+    include_arbitrary_crs: bool,
+    english: bool,
+) -> PyResult<Vec<Feature>> {
+    py.allow_threads(|| {
+        let mut features = Features::open(path, include_arbitrary_crs, english)?;
+        let mut all = Vec::new();
+        while let Some(feature) = features.next_feature()? {
+            all.push(feature);
+        }
+        Ok(all)
+    })
+}
+
+/// Read the 筆 of a package as the files are parsed, without keeping them
+/// all in memory. The options are those of `parse_zip`.
+#[pyfunction]
+#[pyo3(signature = (path, include_arbitrary_crs = false, english = false))]
+fn iter_zip(
+    py: Python<'_>,
+    path: PathBuf,
+    include_arbitrary_crs: bool,
+    english: bool,
+) -> PyResult<FeatureIter> {
+    let features = py.allow_threads(|| Features::open(path, include_arbitrary_crs, english))?;
+    Ok(FeatureIter {
+        features: Mutex::new(features),
+    })
+}
+
+#[pymodule]
+fn mojxml_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Feature>()?;
+    m.add_class::<FeatureIter>()?;
+    m.add_function(wrap_pyfunction!(parse_zip, m)?)?;
+    m.add_function(wrap_pyfunction!(iter_zip, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! WebAssembly bindings, for converters running entirely in the browser:
+//! `toGeoJson` takes the bytes of a 地図XML file (e.g. a `Uint8Array` read
+//! from a `File`) and returns a GeoJSON FeatureCollection of its 筆.
+//!
+//! Build with `wasm-pack build mojxml-wasm --target web`. The core crate is
+//! used without its `zip` and `rayon` features, so unzip the package in
+//! JavaScript.
+
+use mojxml::parser::{self, MojxmlParser, ParseOptions, jpr_projections};
+use mojxml::writer::Schema;
+use mojxml::writer::geojson::GeoJsonWriter;
+use wasm_bindgen::prelude::*;
+
+/// Convert a 地図XML file into a GeoJSON FeatureCollection of its 筆, in
+/// longitude/latitude.
+///
+/// Files in a 任意座標系 are rejected unless `includeArbitraryCrs`, as their
+/// coordinates are local. `english` names the properties in English. 筆
+/// whose surface can't be resolved are left out.
+#[wasm_bindgen(js_name = toGeoJson)]
+pub fn to_geojson(
+    xml: &[u8],
+    #[wasm_bindgen(js_name = includeArbitraryCrs)] include_arbitrary_crs: Option<bool>,
+    english: Option<bool>,
+) -> Result<String, JsError> {
+    convert(
+        xml,
+        include_arbitrary_crs.unwrap_or(false),
+        english.unwrap_or(false),
+    )
+    .map_err(|e| JsError::new(&e))
+}
+
+fn convert(xml: &[u8], include_arbitrary_crs: bool, english: bool) -> Result<String, String> {
+    let projections = jpr_projections();
+    let options = ParseOptions {
+        skip_arbitrary_crs: !include_arbitrary_crs,
+        ..Default::default()
+    };
+    let data = match MojxmlParser::from_slice(xml, &projections)
+        .with_options(options)
+        .parse()
+    {
+        Ok(data) => data,
+        Err(parser::Error::SkipAll) => {
+            return Err("the file is in an arbitrary coordinate system (任意座標系)".to_string());
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let schema = match english {
+        true => Schema::english(),
+        false => Schema::japanese(),
+    };
+    let mut writer = GeoJsonWriter::new(Vec::new())
+        .map_err(|e| e.to_string())?
+        .with_schema(schema);
+    let mut fudes: Vec<_> = data.fudes.values().collect();
+    fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+    for fude in fudes {
+        if let Ok(polygon) = data.resolve_surface_geo(fude.surface_id) {
+            writer
+                .write_fude(fude, &polygon)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    let out = writer.finish().map_err(|e| e.to_string())?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::convert;
+    use mojxml::synthetic::SyntheticMap;
+    use test_log::test;
+
+    #[test]
+    fn test_convert() {
+        let xml = SyntheticMap::new(1, 2).to_xml();
+        let geojson = convert(xml.as_bytes(), false, true).unwrap();
+        assert!(geojson.starts_with(
+            r#"{"type":"FeatureCollection","features":[{"type":"Feature","properties":{"id":"H1","#
+        ));
+        assert_eq!(geojson.matches(r#""type":"Polygon""#).count(), 2);
+        assert!(convert(b"<", false, false).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Arrow RecordBatches of parcels.
+
+use std::sync::Arc;
+
+use arrow_array::builder::{BinaryBuilder, Float64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+
+use crate::data::{Fude, ParsedData};
+use crate::writer::schema::{self, Column, ColumnType, Value};
+
+/// Default number of rows per batch of [`ParsedData::to_arrow`].
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Schema of the parcel batches: a WKB `geometry` column (null when the
+/// surface can't be resolved) followed by one column per attribute: strings,
+/// or doubles for the numeric columns (e.g. the computed area).
+pub fn fude_schema() -> SchemaRef {
+    fude_schema_with(&schema::Schema::default())
+}
+
+/// [`fude_schema`] with the given attribute columns.
+pub fn fude_schema_with(schema: &schema::Schema) -> SchemaRef {
+    let mut fields = vec![Field::new("geometry", DataType::Binary, true)];
+    fields.extend(schema.columns().iter().map(|column| {
+        let data_type = match column.column_type() {
+            ColumnType::Text => DataType::Utf8,
+            ColumnType::Real => DataType::Float64,
+        };
+        Field::new(column.name(), data_type, true)
+    }));
+    Arc::new(Schema::new(fields))
+}
+
+/// Accumulates parcels into a [`RecordBatch`] of [`fude_schema`].
+pub struct FudeBatchBuilder {
+    schema: SchemaRef,
+    attributes: Vec<Column>,
+    geometry: BinaryBuilder,
+    columns: Vec<ColumnBuilder>,
+    len: usize,
+}
+
+/// The builder of an attribute column, by its type.
+enum ColumnBuilder {
+    Text(StringBuilder),
+    Real(Float64Builder),
+}
+
+impl Default for FudeBatchBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FudeBatchBuilder {
+    pub fn new() -> Self {
+        Self::new_with_schema(schema::Schema::default())
+    }
+
+    /// A builder of [`fude_schema_with`] the given attribute columns.
+    pub fn new_with_schema(schema: schema::Schema) -> Self {
+        Self {
+            schema: fude_schema_with(&schema),
+            geometry: BinaryBuilder::new(),
+            columns: schema
+                .columns()
+                .iter()
+                .map(|column| match column.column_type() {
+                    ColumnType::Text => ColumnBuilder::Text(StringBuilder::new()),
+                    ColumnType::Real => ColumnBuilder::Real(Float64Builder::new()),
+                })
+                .collect(),
+            attributes: schema.columns().to_vec(),
+            len: 0,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Append a parcel with its geometry as WKB.
+    pub fn push(&mut self, fude: &Fude, wkb: Option<&[u8]>) {
+        self.geometry.append_option(wkb);
+        for (builder, column) in self.columns.iter_mut().zip(&self.attributes) {
+            let value = column.value(&fude.attributes);
+            match builder {
+                ColumnBuilder::Text(builder) => builder.append_option(match value {
+                    Some(Value::Text(value)) => Some(value),
+                    _ => None,
+                }),
+                ColumnBuilder::Real(builder) => builder.append_option(match value {
+                    Some(Value::Real(value)) => Some(value),
+                    _ => None,
+                }),
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Build a batch of the rows appended so far, and reset the builder.
+    pub fn finish(&mut self) -> Result<RecordBatch, ArrowError> {
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(self.geometry.finish())];
+        arrays.extend(self.columns.iter_mut().map(|builder| match builder {
+            ColumnBuilder::Text(builder) => Arc::new(builder.finish()) as ArrayRef,
+            ColumnBuilder::Real(builder) => Arc::new(builder.finish()) as ArrayRef,
+        }));
+        self.len = 0;
+        RecordBatch::try_new(self.schema.clone(), arrays)
+    }
+}
+
+impl ParsedData<'_> {
+    /// Convert the parcels into RecordBatches of [`fude_schema`], with up to
+    /// `batch_size` rows each.
+    pub fn to_arrow(
+        &self,
+        batch_size: usize,
+    ) -> impl Iterator<Item = Result<RecordBatch, ArrowError>> + '_ {
+        let batch_size = batch_size.max(1);
+        let mut fudes = self.fudes.values().peekable();
+        std::iter::from_fn(move || {
+            fudes.peek()?;
+            let mut builder = FudeBatchBuilder::new();
+            for fude in fudes.by_ref().take(batch_size) {
+                let wkb = self.resolve_surface_wkb(fude.surface_id).ok();
+                builder.push(fude, wkb.as_deref());
+            }
+            Some(builder.finish())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::{Fude, FudeAttributes, Ids, ParsedData, PointRef, Surfaces};
+    use arrow_array::Array;
+    use hashbrown::HashMap;
+    use test_log::test;
+
+    #[test]
+    fn test_to_arrow() {
+        let mut ids = Ids::default();
+        let [c1, c2, c3] = ["C1", "C2", "C3"].map(|id| ids.intern(id));
+        let fudes = (0..5)
+            .map(|i| {
+                let fude = Fude {
+                    attributes: FudeAttributes {
+                        id: format!("H{}", i).into(),
+                        ..Default::default()
+                    },
+                    // only the first parcel has a surface
+                    surface_id: ids.intern(format!("S{}", i)),
+                };
+                (fude.attributes.id.to_string(), fude)
+            })
+            .collect();
+        let data = ParsedData {
+            points: HashMap::new(),
+            segments: HashMap::from([
+                (c1, [PointRef::Direct([0., 0.]), PointRef::Direct([1., 0.])]),
+                (c2, [PointRef::Direct([1., 0.]), PointRef::Direct([1., 1.])]),
+                (c3, [PointRef::Direct([1., 1.]), PointRef::Direct([0., 0.])]),
+            ]),
+            surfaces: Surfaces::from_iter([(ids.get("S0").unwrap(), [[c1, c2, c3]])]),
+            fudes,
+            ids,
+            ..Default::default()
+        };
+
+        let batches = data.to_arrow(2).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            [2, 2, 1]
+        );
+        let null_geometries: usize = batches.iter().map(|b| b.column(0).null_count()).sum();
+        assert_eq!(null_geometries, 4);
+    }
+}
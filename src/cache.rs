@@ -0,0 +1,461 @@
+//! Compact binary cache of [`ParsedData`], so that repeated analyses of the
+//! same files skip the XML parsing.
+//!
+//! The format is flat little-endian: a magic number and a version, the
+//! metadata, the ids in the order of their handles, then the points, curves,
+//! surfaces, 筆, 基準点, 筆界点 and 図郭. Strings are prefixed by their
+//! length, optional values by a 0/1 byte. A cache written by another version
+//! of the format is rejected, so regenerate it from the XML.
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::data::{Feature, Fude, FudeAttributes, Id, MapMetadata, ParsedData, PointRef};
+
+const MAGIC: &[u8; 8] = b"MOJXMLC\0";
+const VERSION: u32 = 5;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid cache: {0}")]
+    InvalidData(String),
+}
+
+impl ParsedData<'_> {
+    /// Write the data into a cache, read back by [`read_cache`](ParsedData::read_cache).
+    pub fn write_cache<W: Write>(&self, out: W) -> Result<(), Error> {
+        let mut out = CacheWriter(BufWriter::new(out));
+        out.0.write_all(MAGIC)?;
+        out.u32(VERSION)?;
+        let metadata = &self.metadata;
+        for value in [
+            &metadata.map_name,
+            &metadata.municipality_code,
+            &metadata.municipality_name,
+            &metadata.crs,
+            &metadata.datum,
+        ] {
+            out.opt_str(value.as_deref())?;
+        }
+
+        out.len(self.ids.len())?;
+        for (_, name) in self.ids.iter() {
+            out.str(name)?;
+        }
+        // in the order of the ids, so that the cache of a file is always the same
+        out.len(self.points.len())?;
+        for (id, _) in self.ids.iter() {
+            if let Some(&[x, y]) = self.points.get(&id) {
+                out.id(id)?;
+                out.f64(x)?;
+                out.f64(y)?;
+            }
+        }
+        out.len(self.segments.len())?;
+        for (id, _) in self.ids.iter() {
+            let Some(point_refs) = self.segments.get(&id) else {
+                continue;
+            };
+            out.id(id)?;
+            for point_ref in point_refs {
+                match *point_ref {
+                    PointRef::Indirect(point_id) => {
+                        out.0.write_all(&[0])?;
+                        out.id(point_id)?;
+                    }
+                    PointRef::Direct([x, y]) => {
+                        out.0.write_all(&[1])?;
+                        out.f64(x)?;
+                        out.f64(y)?;
+                    }
+                }
+            }
+        }
+        out.len(self.surfaces.len())?;
+        for (id, _) in self.ids.iter() {
+            let Some(surface) = self.surfaces.get(id) else {
+                continue;
+            };
+            out.id(id)?;
+            out.len(surface.len())?;
+            for ring in surface.rings() {
+                out.len(ring.len())?;
+                for &curve_id in ring {
+                    out.id(curve_id)?;
+                }
+            }
+        }
+
+        let mut fudes: Vec<_> = self.fudes.iter().collect();
+        fudes.sort_by(|a, b| a.0.cmp(b.0));
+        out.len(fudes.len())?;
+        for (key, fude) in fudes {
+            out.str(key)?;
+            out.id(fude.surface_id)?;
+            let attributes = &fude.attributes;
+            out.str(&attributes.id)?;
+            for value in [
+                &attributes.oaza_code,
+                &attributes.chome_code,
+                &attributes.koaza_code,
+                &attributes.yobi_code,
+                &attributes.oaza,
+                &attributes.chome,
+                &attributes.koaza,
+                &attributes.yobi,
+                &attributes.chiban,
+                &attributes.hikkai_mitei,
+                &attributes.accuracy_class,
+                &attributes.coord_class,
+            ] {
+                out.opt_str(value.as_deref())?;
+            }
+            out.opt_str(attributes.stable_id.as_deref())?;
+            out.opt_str(attributes.source_file.as_deref())?;
+            out.opt_str(attributes.map_number.as_deref())?;
+            out.opt_str(attributes.run_id.as_deref())?;
+            out.opt_str(attributes.municipality_code.as_deref())?;
+            out.opt_str(attributes.municipality_name.as_deref())?;
+            out.opt_f64(attributes.computed_area)?;
+            out.len(attributes.extra.len())?;
+            for (key, value) in &attributes.extra {
+                out.str(key)?;
+                out.str(value)?;
+            }
+        }
+        for features in [&self.kijunten, &self.hikkaiten, &self.zukaku] {
+            out.len(features.len())?;
+            for feature in features {
+                out.str(&feature.id)?;
+                out.id(feature.geometry_id)?;
+                out.len(feature.attributes.len())?;
+                for (key, value) in &feature.attributes {
+                    out.str(key)?;
+                    out.str(value)?;
+                }
+            }
+        }
+        out.0.flush()?;
+        Ok(())
+    }
+
+    /// Read a cache written by [`write_cache`](ParsedData::write_cache).
+    pub fn read_cache<R: Read>(input: R) -> Result<ParsedData<'static>, Error> {
+        let mut input = CacheReader {
+            input: BufReader::new(input),
+            ids: 0,
+        };
+        let mut magic = [0; 8];
+        input.input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidData("not a cache file".to_string()));
+        }
+        let version = input.u32()?;
+        if version != VERSION {
+            return Err(Error::InvalidData(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let metadata = MapMetadata {
+            map_name: input.opt_str()?,
+            municipality_code: input.opt_str()?,
+            municipality_name: input.opt_str()?,
+            crs: input.opt_str()?,
+            datum: input.opt_str()?,
+        };
+
+        let mut data = ParsedData {
+            metadata,
+            ..Default::default()
+        };
+        let count = input.len()?;
+        data.ids.reserve(count.min(1 << 20));
+        for _ in 0..count {
+            let name = input.str()?;
+            data.ids.intern(name);
+        }
+        input.ids = data.ids.len();
+        for _ in 0..input.len()? {
+            let id = input.id()?;
+            let point = [input.f64()?, input.f64()?];
+            data.points.insert(id, point);
+        }
+        for _ in 0..input.len()? {
+            let id = input.id()?;
+            let point_refs = [input.point_ref()?, input.point_ref()?];
+            data.segments.insert(id, point_refs);
+        }
+        for _ in 0..input.len()? {
+            let id = input.id()?;
+            let mut rings = Vec::new();
+            for _ in 0..input.len()? {
+                let ring = (0..input.len()?)
+                    .map(|_| input.id())
+                    .collect::<Result<Vec<_>, _>>()?;
+                rings.push(ring);
+            }
+            // resolving a surface needs its exterior ring, and a ring its curves
+            if rings.is_empty() || rings.iter().any(Vec::is_empty) {
+                return Err(Error::InvalidData(format!(
+                    "surface {} with an empty ring",
+                    data.ids.name(id)
+                )));
+            }
+            data.surfaces.insert(id, rings);
+        }
+
+        for _ in 0..input.len()? {
+            let key = input.str()?;
+            let surface_id = input.id()?;
+            let attributes = FudeAttributes {
+                id: input.str()?.into(),
+                oaza_code: input.opt_str()?.map(Into::into),
+                chome_code: input.opt_str()?.map(Into::into),
+                koaza_code: input.opt_str()?.map(Into::into),
+                yobi_code: input.opt_str()?.map(Into::into),
+                oaza: input.opt_str()?.map(Into::into),
+                chome: input.opt_str()?.map(Into::into),
+                koaza: input.opt_str()?.map(Into::into),
+                yobi: input.opt_str()?.map(Into::into),
+                chiban: input.opt_str()?.map(Into::into),
+                hikkai_mitei: input.opt_str()?.map(Into::into),
+                accuracy_class: input.opt_str()?.map(Into::into),
+                coord_class: input.opt_str()?.map(Into::into),
+                stable_id: input.opt_str()?,
+                source_file: input.opt_str()?.map(Arc::from),
+                map_number: input.opt_str()?,
+                run_id: input.opt_str()?.map(Arc::from),
+                municipality_code: input.opt_str()?.map(Arc::from),
+                municipality_name: input.opt_str()?.map(Arc::from),
+                computed_area: input.opt_f64()?,
+                extra: (0..input.len()?)
+                    .map(|_| Ok((input.str()?, input.str()?)))
+                    .collect::<Result<_, Error>>()?,
+            };
+            data.fudes.insert(
+                key,
+                Fude {
+                    attributes,
+                    surface_id,
+                },
+            );
+        }
+        for features in [&mut data.kijunten, &mut data.hikkaiten, &mut data.zukaku] {
+            for _ in 0..input.len()? {
+                let id = input.str()?;
+                let geometry_id = input.id()?;
+                let attributes = (0..input.len()?)
+                    .map(|_| Ok((input.str()?, input.str()?)))
+                    .collect::<Result<_, Error>>()?;
+                features.push(Feature {
+                    id,
+                    geometry_id,
+                    attributes,
+                });
+            }
+        }
+        Ok(data)
+    }
+}
+
+struct CacheWriter<W: Write>(BufWriter<W>);
+
+impl<W: Write> CacheWriter<W> {
+    fn u32(&mut self, value: u32) -> Result<(), Error> {
+        self.0.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn len(&mut self, len: usize) -> Result<(), Error> {
+        let len = u32::try_from(len)
+            .map_err(|_| Error::InvalidData(format!("too many items: {}", len)))?;
+        self.u32(len)
+    }
+
+    fn id(&mut self, id: Id) -> Result<(), Error> {
+        self.u32(id.index() as u32)
+    }
+
+    fn f64(&mut self, value: f64) -> Result<(), Error> {
+        self.0.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn str(&mut self, value: &str) -> Result<(), Error> {
+        self.len(value.len())?;
+        self.0.write_all(value.as_bytes())?;
+        Ok(())
+    }
+
+    fn opt_str(&mut self, value: Option<&str>) -> Result<(), Error> {
+        match value {
+            Some(value) => {
+                self.0.write_all(&[1])?;
+                self.str(value)
+            }
+            None => {
+                self.0.write_all(&[0])?;
+                Ok(())
+            }
+        }
+    }
+
+    fn opt_f64(&mut self, value: Option<f64>) -> Result<(), Error> {
+        match value {
+            Some(value) => {
+                self.0.write_all(&[1])?;
+                self.f64(value)
+            }
+            None => {
+                self.0.write_all(&[0])?;
+                Ok(())
+            }
+        }
+    }
+}
+
+struct CacheReader<R: Read> {
+    input: BufReader<R>,
+    /// number of ids, to check the handles
+    ids: usize,
+}
+
+impl<R: Read> CacheReader<R> {
+    fn u8(&mut self) -> Result<u8, Error> {
+        let mut buf = [0; 1];
+        self.input.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        self.input.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn id(&mut self) -> Result<Id, Error> {
+        let index = self.len()?;
+        if index >= self.ids {
+            return Err(Error::InvalidData(format!("unknown id {}", index)));
+        }
+        Ok(Id::from_index(index))
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        let mut buf = [0; 8];
+        self.input.read_exact(&mut buf)?;
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    fn str(&mut self) -> Result<String, Error> {
+        let len = self.len()?;
+        let mut buf = Vec::new();
+        // don't trust the length for the allocation
+        (&mut self.input).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        String::from_utf8(buf).map_err(|e| Error::InvalidData(e.to_string()))
+    }
+
+    fn opt_str(&mut self) -> Result<Option<String>, Error> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.str()?)),
+            flag => Err(Error::InvalidData(format!("invalid flag {}", flag))),
+        }
+    }
+
+    fn opt_f64(&mut self) -> Result<Option<f64>, Error> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.f64()?)),
+            flag => Err(Error::InvalidData(format!("invalid flag {}", flag))),
+        }
+    }
+
+    fn point_ref(&mut self) -> Result<PointRef, Error> {
+        match self.u8()? {
+            0 => Ok(PointRef::Indirect(self.id()?)),
+            1 => Ok(PointRef::Direct([self.f64()?, self.f64()?])),
+            tag => Err(Error::InvalidData(format!("invalid point tag {}", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use crate::data::ParsedData;
+    use crate::parser::{MojxmlParser, jpr_projections};
+    use crate::synthetic::SyntheticMap;
+    use test_log::test;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let projections = jpr_projections();
+        let xml = SyntheticMap::new(3, 2).to_xml();
+        let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .parse()
+            .unwrap();
+        let mut cache = Vec::new();
+        data.write_cache(&mut cache).unwrap();
+
+        let read = ParsedData::read_cache(cache.as_slice()).unwrap();
+        assert_eq!(read.metadata.crs, data.metadata.crs);
+        assert_eq!(read.ids.len(), data.ids.len());
+        assert_eq!(read.points.len(), data.points.len());
+        assert_eq!(read.fudes.len(), data.fudes.len());
+        for (key, fude) in &data.fudes {
+            let read_fude = &read.fudes[key];
+            assert_eq!(read_fude.attributes.oaza, fude.attributes.oaza);
+            assert_eq!(read_fude.surface_id, fude.surface_id);
+            assert_eq!(
+                read.resolve_surface(read_fude.surface_id).unwrap(),
+                data.resolve_surface(fude.surface_id).unwrap()
+            );
+        }
+        let mut again = Vec::new();
+        read.write_cache(&mut again).unwrap();
+        assert_eq!(again, cache);
+
+        for len in [4, 12, cache.len() / 2, cache.len() - 1] {
+            assert!(ParsedData::read_cache(&cache[..len]).is_err());
+        }
+        // a surface without rings, then with an empty ring: the surface comes
+        // last, as its id, 1 ring and 1 curve, before the 4 empty lists
+        let mut data = ParsedData::default();
+        let surface = data.ids.intern("S1");
+        let curve = data.ids.intern("C1");
+        data.surfaces.insert(surface, [[curve]]);
+        let mut small = Vec::new();
+        data.write_cache(&mut small).unwrap();
+        let n = small.len();
+        assert!(ParsedData::read_cache(small.as_slice()).is_ok());
+        let zero = 0u32.to_le_bytes();
+        for corrupt in [
+            [&small[..n - 28], &zero, &small[n - 16..]].concat(),
+            [&small[..n - 24], &zero, &small[n - 16..]].concat(),
+        ] {
+            assert!(matches!(
+                ParsedData::read_cache(corrupt.as_slice()),
+                Err(Error::InvalidData(_))
+            ));
+        }
+
+        cache[8] = 99;
+        assert!(matches!(
+            ParsedData::read_cache(cache.as_slice()),
+            Err(Error::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+//! Code lists of the attributes of the 筆: 精度区分 and 座標値種別, with
+//! descriptive labels, and normalization of the 大字コード, 丁目コード, ...
+
+use std::borrow::Cow;
+
+use crate::data::FudeAttributes;
+
+/// A 精度区分 of the map, with the errors allowed for the positions of its
+/// 筆界点 (国土調査法施行令 別表第四, which 不動産登記規則 第10条 applies to
+/// the 地図).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AccuracyClass {
+    Kou1,
+    Kou2,
+    Kou3,
+    Otsu1,
+    Otsu2,
+    Otsu3,
+}
+
+impl AccuracyClass {
+    pub const ALL: [AccuracyClass; 6] = [
+        AccuracyClass::Kou1,
+        AccuracyClass::Kou2,
+        AccuracyClass::Kou3,
+        AccuracyClass::Otsu1,
+        AccuracyClass::Otsu2,
+        AccuracyClass::Otsu3,
+    ];
+
+    /// Parse a 精度区分 as written in the XML (`甲二`), or with an ASCII or
+    /// full-width digit (`甲2`, `甲２`).
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut chars = value.trim().chars();
+        let (Some(series), Some(number), None) = (chars.next(), chars.next(), chars.next()) else {
+            return None;
+        };
+        let number = match number {
+            '一' | '1' | '１' => 0,
+            '二' | '2' | '２' => 1,
+            '三' | '3' | '３' => 2,
+            _ => return None,
+        };
+        match series {
+            '甲' => Some(Self::ALL[number]),
+            '乙' => Some(Self::ALL[3 + number]),
+            _ => None,
+        }
+    }
+
+    /// The name used in the XML (`甲二`).
+    pub fn name(self) -> &'static str {
+        match self {
+            AccuracyClass::Kou1 => "甲一",
+            AccuracyClass::Kou2 => "甲二",
+            AccuracyClass::Kou3 => "甲三",
+            AccuracyClass::Otsu1 => "乙一",
+            AccuracyClass::Otsu2 => "乙二",
+            AccuracyClass::Otsu3 => "乙三",
+        }
+    }
+
+    /// A description with the allowed errors (`甲二 (平均二乗誤差 7cm、公差 20cm)`).
+    pub fn label(self) -> &'static str {
+        match self {
+            AccuracyClass::Kou1 => "甲一 (平均二乗誤差 2cm、公差 6cm)",
+            AccuracyClass::Kou2 => "甲二 (平均二乗誤差 7cm、公差 20cm)",
+            AccuracyClass::Kou3 => "甲三 (平均二乗誤差 15cm、公差 45cm)",
+            AccuracyClass::Otsu1 => "乙一 (平均二乗誤差 25cm、公差 75cm)",
+            AccuracyClass::Otsu2 => "乙二 (平均二乗誤差 50cm、公差 150cm)",
+            AccuracyClass::Otsu3 => "乙三 (平均二乗誤差 100cm、公差 300cm)",
+        }
+    }
+
+    /// [`label`](Self::label) in English (`Kou 2 (RMSE 7 cm, tolerance 20 cm)`).
+    pub fn english_label(self) -> &'static str {
+        match self {
+            AccuracyClass::Kou1 => "Kou 1 (RMSE 2 cm, tolerance 6 cm)",
+            AccuracyClass::Kou2 => "Kou 2 (RMSE 7 cm, tolerance 20 cm)",
+            AccuracyClass::Kou3 => "Kou 3 (RMSE 15 cm, tolerance 45 cm)",
+            AccuracyClass::Otsu1 => "Otsu 1 (RMSE 25 cm, tolerance 75 cm)",
+            AccuracyClass::Otsu2 => "Otsu 2 (RMSE 50 cm, tolerance 150 cm)",
+            AccuracyClass::Otsu3 => "Otsu 3 (RMSE 100 cm, tolerance 300 cm)",
+        }
+    }
+
+    /// 平均二乗誤差 (root mean square error) of the positions of the 筆界点,
+    /// in meters.
+    pub fn standard_error(self) -> f64 {
+        match self {
+            AccuracyClass::Kou1 => 0.02,
+            AccuracyClass::Kou2 => 0.07,
+            AccuracyClass::Kou3 => 0.15,
+            AccuracyClass::Otsu1 => 0.25,
+            AccuracyClass::Otsu2 => 0.5,
+            AccuracyClass::Otsu3 => 1.0,
+        }
+    }
+
+    /// 公差 (maximum error) of the positions of the 筆界点, in meters.
+    pub fn tolerance(self) -> f64 {
+        match self {
+            AccuracyClass::Kou1 => 0.06,
+            AccuracyClass::Kou2 => 0.2,
+            AccuracyClass::Kou3 => 0.45,
+            AccuracyClass::Otsu1 => 0.75,
+            AccuracyClass::Otsu2 => 1.5,
+            AccuracyClass::Otsu3 => 3.0,
+        }
+    }
+
+    /// [`tolerance`](Self::tolerance) as text, for output columns.
+    pub(crate) fn tolerance_text(self) -> &'static str {
+        match self {
+            AccuracyClass::Kou1 => "0.06",
+            AccuracyClass::Kou2 => "0.2",
+            AccuracyClass::Kou3 => "0.45",
+            AccuracyClass::Otsu1 => "0.75",
+            AccuracyClass::Otsu2 => "1.5",
+            AccuracyClass::Otsu3 => "3",
+        }
+    }
+}
+
+/// A 座標値種別: how the coordinates of the 筆 were obtained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CoordClass {
+    /// 測量成果: from a survey
+    Surveyed,
+    /// 図上測量: measured on a paper map
+    Digitized,
+}
+
+impl CoordClass {
+    /// Parse a 座標値種別 as written in the XML.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "測量成果" => Some(CoordClass::Surveyed),
+            "図上測量" => Some(CoordClass::Digitized),
+            _ => None,
+        }
+    }
+
+    /// The name used in the XML.
+    pub fn name(self) -> &'static str {
+        match self {
+            CoordClass::Surveyed => "測量成果",
+            CoordClass::Digitized => "図上測量",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CoordClass::Surveyed => "測量成果 (測量で得た座標値)",
+            CoordClass::Digitized => "図上測量 (図面から読み取った座標値)",
+        }
+    }
+
+    pub fn english_label(self) -> &'static str {
+        match self {
+            CoordClass::Surveyed => "surveyed",
+            CoordClass::Digitized => "digitized from a paper map",
+        }
+    }
+}
+
+/// Widths to which the codes of the 筆 are zero-padded by
+/// [`normalize`](Self::normalize). A width of 0 leaves the code as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeWidths {
+    pub oaza: usize,
+    pub chome: usize,
+    pub koaza: usize,
+    pub yobi: usize,
+}
+
+impl Default for CodeWidths {
+    /// The widths of the 地図XML specification: 3 digits for the 大字コード
+    /// and 丁目コード, 4 for the 小字コード and 2 for the 予備コード.
+    fn default() -> Self {
+        Self {
+            oaza: 3,
+            chome: 3,
+            koaza: 4,
+            yobi: 2,
+        }
+    }
+}
+
+impl CodeWidths {
+    /// Normalize the 大字コード, 丁目コード, 小字コード and 予備コード of a 筆,
+    /// which are written with inconsistent padding by some municipalities
+    /// (see [`normalize_code`]).
+    pub fn normalize(&self, attributes: &mut FudeAttributes) {
+        for (code, width) in [
+            (&mut attributes.oaza_code, self.oaza),
+            (&mut attributes.chome_code, self.chome),
+            (&mut attributes.koaza_code, self.koaza),
+            (&mut attributes.yobi_code, self.yobi),
+        ] {
+            if let Some(value) = code
+                && let Cow::Owned(normalized) = normalize_code(value, width)
+            {
+                *value = normalized.into();
+            }
+        }
+    }
+}
+
+/// Normalize a numeric code: trim it, convert its full-width digits to ASCII
+/// (`０１` to `01`) and zero-pad it to `width` digits. Codes with other
+/// characters are only trimmed.
+pub fn normalize_code(code: &str, width: usize) -> Cow<'_, str> {
+    let trimmed = code.trim();
+    let is_digit = |c: char| c.is_ascii_digit() || ('０'..='９').contains(&c);
+    if trimmed.is_empty() || !trimmed.chars().all(is_digit) {
+        return match trimmed.len() == code.len() {
+            true => Cow::Borrowed(code),
+            false => Cow::Owned(trimmed.to_string()),
+        };
+    }
+    let digits = trimmed.chars().map(|c| match c {
+        '０'..='９' => char::from(b'0' + (c as u32 - '０' as u32) as u8),
+        _ => c,
+    });
+    let len = trimmed.chars().count();
+    if len >= width && trimmed.is_ascii() && trimmed.len() == code.len() {
+        return Cow::Borrowed(code);
+    }
+    let padding = std::iter::repeat_n('0', width.saturating_sub(len));
+    Cow::Owned(padding.chain(digits).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccuracyClass, CodeWidths, CoordClass, normalize_code};
+    use crate::data::FudeAttributes;
+    use test_log::test;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(AccuracyClass::parse("甲二"), Some(AccuracyClass::Kou2));
+        assert_eq!(AccuracyClass::parse("乙3"), Some(AccuracyClass::Otsu3));
+        assert_eq!(AccuracyClass::parse("甲１"), Some(AccuracyClass::Kou1));
+        assert_eq!(AccuracyClass::parse("丙一"), None);
+        assert_eq!(AccuracyClass::parse("甲"), None);
+        for class in AccuracyClass::ALL {
+            assert_eq!(AccuracyClass::parse(class.name()), Some(class));
+            let text: f64 = class.tolerance_text().parse().unwrap();
+            assert!((text - class.tolerance()).abs() < 1e-9);
+        }
+        assert_eq!(CoordClass::parse("図上測量"), Some(CoordClass::Digitized));
+        assert_eq!(CoordClass::parse("その他"), None);
+    }
+
+    #[test]
+    fn test_normalize_code() {
+        assert_eq!(normalize_code("1", 3), "001");
+        assert_eq!(normalize_code("０１２", 3), "012");
+        assert_eq!(normalize_code(" 12 ", 4), "0012");
+        assert_eq!(normalize_code("1234", 3), "1234");
+        assert_eq!(normalize_code("1", 0), "1");
+        assert_eq!(normalize_code("A1 ", 3), "A1");
+        assert!(matches!(
+            normalize_code("001", 3),
+            std::borrow::Cow::Borrowed(_)
+        ));
+
+        let mut attributes = FudeAttributes {
+            oaza_code: Some("１".into()),
+            koaza_code: Some("12".into()),
+            ..Default::default()
+        };
+        CodeWidths::default().normalize(&mut attributes);
+        assert_eq!(attributes.oaza_code.as_deref(), Some("001"));
+        assert_eq!(attributes.chome_code, None);
+        assert_eq!(attributes.koaza_code.as_deref(), Some("0012"));
+    }
+}
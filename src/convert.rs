@@ -0,0 +1,913 @@
+//! One-call conversion of a package: unzip, parse, project and write.
+
+use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "serde")]
+use std::io::Write;
+use std::io::{Read, Seek};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use thiserror::Error;
+
+use crate::crs::Crs;
+use crate::data::{Feature, Fude, FudeAttributes, MapMetadata, ParsedData};
+use crate::parser::{ParseOptions, jpr_projections};
+use crate::writer::{FeatureSink, FileBatch, Layer, SharedFeatureSink};
+use crate::zip::{EntryFilter, EntryInfo, ZipPackageParallelIter};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zip(#[from] crate::zip::Error),
+    #[error(transparent)]
+    Writer(#[from] crate::writer::Error),
+    #[error(transparent)]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    /// A file rejected by [`ConvertOptions::prepare`].
+    #[error("{name}: {message}")]
+    Prepare { name: String, message: String },
+}
+
+/// Changes the attributes of each 筆 before it is written, e.g. to normalize
+/// codes, redact fields, or add [`extra`](FudeAttributes::extra) columns.
+pub type AttributeTransform = Arc<dyn Fn(&mut FudeAttributes, &MapMetadata) + Send + Sync>;
+
+/// Changes a parsed file before its geometries are resolved, e.g. to filter
+/// its 筆 or compute columns from the whole file. Returns warnings about the
+/// file, recorded in its report, or an error failing the file.
+pub type FileHook =
+    Arc<dyn Fn(&str, &mut ParsedData<'static>) -> Result<Vec<String>, String> + Send + Sync>;
+
+/// Follows the steps of a conversion. An error stops the conversion.
+pub type EventHook = Arc<dyn Fn(Event) -> std::io::Result<()> + Send + Sync>;
+
+/// A step of a conversion, given to [`ConvertOptions::on_event`] as it happens.
+pub enum Event<'a> {
+    /// The package is opened, with this many outer entries to read (see
+    /// [`ZipPackageParallelIter::total_entries`]).
+    Started { entries: usize },
+    /// An XML file was written to the sink.
+    Written(&'a FileReport),
+    /// An XML file was skipped by the parser (arbitrary coordinate systems).
+    Skipped(&'a str),
+    /// An entry couldn't be read, or an XML file failed, with the error.
+    Failed(&'a str, &'a str),
+}
+
+/// Options of [`convert()`].
+#[derive(Clone)]
+pub struct ConvertOptions {
+    /// Options of the parser. The layers other than 筆 are written when they are parsed.
+    pub parse: ParseOptions,
+    /// Only read the outer entries whose name satisfies the predicate.
+    pub entry_filter: Option<EntryFilter>,
+    /// Record the XML files which fail to parse in the report and go on,
+    /// instead of aborting the conversion.
+    pub continue_on_error: bool,
+    /// Called on the attributes of each 筆, with the header of its file.
+    /// Columns added as extra attributes must be in the schema of the writer
+    /// (see [`Schema::with_extra_column`](crate::writer::Schema::with_extra_column)).
+    pub transform: Option<AttributeTransform>,
+    /// Record the SHA-256 of each XML file in the report.
+    pub checksums: bool,
+    /// Called on each parsed file, with its name, after `transform`.
+    pub prepare: Option<FileHook>,
+    /// CRS of the written geometries, transformed from the parsed coordinates
+    /// (see [`Crs::transform`]). They are written as parsed by default.
+    pub crs: Option<Crs>,
+    /// Names of XML files not to convert, e.g. the ones written by an
+    /// interrupted conversion (see [`ZipPackageParallelIter::with_skip_set`]).
+    pub skip_files: Vec<String>,
+    /// Only convert this many outer entries, evenly spaced, and describe them
+    /// in [`ConversionReport::sample`], e.g. to estimate the output of the
+    /// whole package.
+    pub sample: Option<usize>,
+    /// Write the files to the sink in the order of their names, and their 筆
+    /// by id, for reproducible outputs.
+    pub ordered: bool,
+    /// Number of worker threads (default: the number of CPUs).
+    pub threads: Option<usize>,
+    /// Limit on the XML being decompressed, parsed or written at the same
+    /// time, in bytes (see [`ZipPackageParallelIter::with_memory_budget`]).
+    pub memory_budget: Option<u64>,
+    /// Called at each step of the conversion, e.g. to show its progress.
+    pub on_event: Option<EventHook>,
+}
+
+impl Default for ConvertOptions {
+    /// Skips the files in arbitrary coordinate systems, writes the 筆 only.
+    fn default() -> Self {
+        Self {
+            parse: ParseOptions {
+                skip_arbitrary_crs: true,
+                ..Default::default()
+            },
+            entry_filter: None,
+            continue_on_error: false,
+            transform: None,
+            checksums: false,
+            prepare: None,
+            crs: None,
+            skip_files: Vec::new(),
+            sample: None,
+            ordered: false,
+            threads: None,
+            memory_budget: None,
+            on_event: None,
+        }
+    }
+}
+
+impl ConvertOptions {
+    fn event(&self, event: Event) -> std::io::Result<()> {
+        match &self.on_event {
+            Some(on_event) => on_event(event),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Statistics of a converted XML file. In JSON, the header is flattened into
+/// the file and the times are in seconds.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FileReport {
+    /// Name of the XML file
+    pub name: String,
+    /// Size of the XML file in bytes
+    pub size: u64,
+    /// SHA-256 of the XML file (see [`ConvertOptions::checksums`])
+    pub sha256: Option<String>,
+    /// Header of the XML file
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub metadata: MapMetadata,
+    /// Number of 筆 written
+    pub fudes: usize,
+    /// Number of features of the other layers written
+    pub features: usize,
+    /// Number of 筆 and other features dropped because their geometry couldn't be resolved
+    pub dropped: usize,
+    /// Why the features were dropped
+    pub warnings: Vec<String>,
+    /// Time spent parsing
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_secs"))]
+    pub parse_time: Duration,
+    /// Time spent resolving the geometries
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_secs"))]
+    pub resolve_time: Duration,
+    /// Time spent in the sink (including waiting for other threads to finish
+    /// writing to it)
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_secs"))]
+    pub write_time: Duration,
+}
+
+/// Summary of a conversion.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConversionReport {
+    /// The converted XML files, sorted by name
+    pub files: Vec<FileReport>,
+    /// XML files skipped by the parser (arbitrary coordinate systems), sorted
+    pub skipped_files: Vec<String>,
+    /// Entries which could not be read, with the error
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_errors"))]
+    pub broken_entries: Vec<(String, String)>,
+    /// XML files which failed to parse, with the error (see [`ConvertOptions::continue_on_error`])
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_errors"))]
+    pub failed_files: Vec<(String, String)>,
+    /// 市区町村コード, 大字コード, 丁目コード and 地番 of more than one 筆,
+    /// with their number of 筆, sorted. Left to the caller, which counts them
+    /// with a [`ChibanCounter`](crate::dedup::ChibanCounter).
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "serialize_duplicate_chibans")
+    )]
+    pub duplicate_chibans: Vec<([String; 4], usize)>,
+    /// The sample of [`ConvertOptions::sample`]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub sample: Option<SampleReport>,
+    /// Wall-clock time of the conversion
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_secs"))]
+    pub elapsed: Duration,
+}
+
+/// The outer entries converted with [`ConvertOptions::sample`], and those of
+/// the whole package.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SampleReport {
+    /// Number of entries of the sample
+    pub entries: usize,
+    /// Uncompressed size of the entries of the sample
+    pub size: u64,
+    /// Number of entries of the package
+    pub total_entries: usize,
+    /// Uncompressed size of the entries of the package
+    pub total_size: u64,
+}
+
+impl SampleReport {
+    /// How many times larger the package is than the sample, by uncompressed
+    /// size, to extrapolate the results of the sample.
+    pub fn ratio(&self) -> f64 {
+        if self.size > 0 {
+            self.total_size as f64 / self.size as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+impl ConversionReport {
+    /// Total number of 筆 written.
+    pub fn fudes(&self) -> usize {
+        self.files.iter().map(|file| file.fudes).sum()
+    }
+
+    /// Total number of features of the other layers written.
+    pub fn features(&self) -> usize {
+        self.files.iter().map(|file| file.features).sum()
+    }
+
+    /// Total number of dropped 筆 and features.
+    pub fn dropped(&self) -> usize {
+        self.files.iter().map(|file| file.dropped).sum()
+    }
+
+    /// Write the report as JSON, followed by the `totals` of the files and
+    /// their stage `timings` (in seconds).
+    #[cfg(feature = "serde")]
+    pub fn write_json(&self, out: &mut impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer(out, &ReportJson::new(self))
+    }
+
+    /// The report as a JSON string (see [`write_json`](Self::write_json)).
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&ReportJson::new(self)).expect("the report serializes to JSON")
+    }
+}
+
+/// The JSON layout of a [`ConversionReport`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct ReportJson<'a> {
+    #[serde(flatten)]
+    report: &'a ConversionReport,
+    totals: Totals,
+    timings: Timings,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct Totals {
+    files: usize,
+    skipped_files: usize,
+    broken_entries: usize,
+    failed_files: usize,
+    fudes: usize,
+    features: usize,
+    dropped: usize,
+}
+
+/// The stage times summed over the files, in seconds.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct Timings {
+    parse: f64,
+    resolve: f64,
+    write: f64,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> ReportJson<'a> {
+    fn new(report: &'a ConversionReport) -> Self {
+        let total = |time: fn(&FileReport) -> Duration| {
+            report
+                .files
+                .iter()
+                .map(time)
+                .sum::<Duration>()
+                .as_secs_f64()
+        };
+        Self {
+            report,
+            totals: Totals {
+                files: report.files.len(),
+                skipped_files: report.skipped_files.len(),
+                broken_entries: report.broken_entries.len(),
+                failed_files: report.failed_files.len(),
+                fudes: report.fudes(),
+                features: report.features(),
+                dropped: report.dropped(),
+            },
+            timings: Timings {
+                parse: total(|file| file.parse_time),
+                resolve: total(|file| file.resolve_time),
+                write: total(|file| file.write_time),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_secs<S: serde::Serializer>(time: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(time.as_secs_f64())
+}
+
+/// `(name, error)` pairs as an array of objects.
+#[cfg(feature = "serde")]
+fn serialize_errors<S: serde::Serializer>(
+    errors: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(serde::Serialize)]
+    struct Entry<'a> {
+        name: &'a str,
+        error: &'a str,
+    }
+    serializer.collect_seq(errors.iter().map(|(name, error)| Entry { name, error }))
+}
+
+#[cfg(feature = "serde")]
+fn serialize_duplicate_chibans<S: serde::Serializer>(
+    duplicates: &[([String; 4], usize)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(serde::Serialize)]
+    struct Duplicate<'a> {
+        municipality_code: &'a str,
+        oaza_code: &'a str,
+        chome_code: &'a str,
+        chiban: &'a str,
+        count: usize,
+    }
+    serializer.collect_seq(duplicates.iter().map(
+        |([municipality_code, oaza_code, chome_code, chiban], count)| Duplicate {
+            municipality_code,
+            oaza_code,
+            chome_code,
+            chiban,
+            count: *count,
+        },
+    ))
+}
+
+/// Convert a package (the zip file distributed by MOJ) into a sink.
+///
+/// The XML files are read, parsed and resolved in parallel, and written to
+/// the sink one file at a time by a writer thread, so that the workers never
+/// wait for each other's writes. Broken entries are listed in the report and
+/// don't stop the conversion. Any other error stops it, unless
+/// [`ConvertOptions::continue_on_error`] is set.
+pub fn convert<R, S>(
+    input: R,
+    mut sink: S,
+    options: &ConvertOptions,
+) -> Result<ConversionReport, Error>
+where
+    R: Read + Seek + Send + 'static,
+    S: FeatureSink + Send,
+{
+    in_pool(options, || {
+        let start = Instant::now();
+        let report = Mutex::new(ConversionReport::default());
+        // The channel is bounded, so that a slow sink holds back the workers
+        // instead of buffering the whole package
+        let (sender, receiver) = mpsc::sync_channel(2 * rayon::current_num_threads());
+        std::thread::scope(|scope| {
+            let sink = &mut sink;
+            let report = &report;
+            let writer = scope.spawn(move || -> Result<(), Error> {
+                let mut reorder = Reorder::default();
+                for (index, file) in receiver {
+                    let files = match options.ordered {
+                        true => reorder.push(index, file),
+                        false => Vec::from_iter(file),
+                    };
+                    for file in files {
+                        file.write(|batch| sink.write_file(batch), report, options)?;
+                    }
+                }
+                Ok(())
+            });
+            let resolved = resolve_package(input, options, report, |index, file| {
+                sender
+                    .send((index, file))
+                    .map_err(|_| std::io::Error::other("the writer thread stopped").into())
+            });
+            drop(sender);
+            // the writer's errors first, as the workers fail when it stops
+            writer
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            resolved
+        })?;
+        Ok(finish_report(report.into_inner().unwrap(), start))
+    })
+}
+
+/// Convert a package into a sink shared with other threads, e.g. to convert
+/// several packages into the same output at once. The workers write their
+/// files to the sink themselves.
+pub fn convert_shared<R, S>(
+    input: R,
+    sink: &S,
+    options: &ConvertOptions,
+) -> Result<ConversionReport, Error>
+where
+    R: Read + Seek + Send + 'static,
+    S: SharedFeatureSink + ?Sized,
+{
+    in_pool(options, || {
+        let start = Instant::now();
+        let report = Mutex::new(ConversionReport::default());
+        // with `ordered`, the files are written in turn, under this lock
+        let reorder = Mutex::new(Reorder::default());
+        resolve_package(input, options, &report, |index, file| {
+            let write = |file: Resolved| file.write(|batch| sink.add_file(batch), &report, options);
+            if !options.ordered {
+                return file.map_or(Ok(()), write);
+            }
+            let mut reorder = reorder.lock().unwrap();
+            reorder.push(index, file).into_iter().try_for_each(write)
+        })?;
+        Ok(finish_report(report.into_inner().unwrap(), start))
+    })
+}
+
+/// Run a conversion on a pool of [`ConvertOptions::threads`] threads, or on
+/// the current one.
+fn in_pool<T: Send>(
+    options: &ConvertOptions,
+    op: impl FnOnce() -> Result<T, Error> + Send,
+) -> Result<T, Error> {
+    match options.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?
+            .install(op),
+        None => op(),
+    }
+}
+
+/// Read, parse and resolve the XML files of a package on the workers, and
+/// hand each outer entry to `deliver` with its index (in the order of the
+/// names with [`ConvertOptions::ordered`]): its file, or `None` for the
+/// entries without one to write.
+fn resolve_package<R, D>(
+    input: R,
+    options: &ConvertOptions,
+    report: &Mutex<ConversionReport>,
+    deliver: D,
+) -> Result<(), Error>
+where
+    R: Read + Seek + Send + 'static,
+    D: Fn(usize, Option<Resolved>) -> Result<(), Error> + Sync,
+{
+    let mut zip = ZipPackageParallelIter::new(input)?;
+    if let Some(filter) = options.entry_filter.clone() {
+        zip = zip.with_entry_filter(move |name| filter(name));
+    }
+    if !options.skip_files.is_empty() {
+        zip = zip.with_skip_set(options.skip_files.iter().cloned());
+    }
+    if let Some(sample) = options.sample {
+        let total_entries = zip.total_entries();
+        let step = total_entries.div_ceil(sample.max(1)).max(1);
+        let names: HashSet<String> = zip
+            .entries()
+            .step_by(step)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        report.lock().unwrap().sample = Some(SampleReport {
+            entries: names.len(),
+            size: zip
+                .entries()
+                .filter(|(name, _)| names.contains(*name))
+                .map(|(_, size)| size)
+                .sum(),
+            total_entries,
+            total_size: zip.total_uncompressed_size(),
+        });
+        zip = zip.with_entry_filter(move |name| names.contains(name));
+    }
+    if let Some(bytes) = options.memory_budget {
+        zip = zip.with_memory_budget(bytes);
+    }
+    if let Some(threads) = options.threads {
+        zip = zip.with_threads(threads);
+    }
+    if options.checksums {
+        zip = zip.with_checksums();
+    }
+    if options.ordered {
+        zip = zip.with_ordered();
+    }
+    options.event(Event::Started {
+        entries: zip.total_entries(),
+    })?;
+    let projections = Arc::new(jpr_projections());
+
+    zip.parsed(options.parse.clone(), projections)
+        .enumerate()
+        .par_bridge()
+        .try_for_each(|(index, res)| -> Result<(), Error> {
+            let file = match res {
+                Ok((info, data)) => match resolve_file(info, data, options) {
+                    Ok(file) => Some(file),
+                    Err(Error::Prepare { name, message }) if options.continue_on_error => {
+                        let error = format!("{}: {}", name, message);
+                        options.event(Event::Failed(&name, &error))?;
+                        report.lock().unwrap().failed_files.push((name, error));
+                        None
+                    }
+                    Err(e) => return Err(e),
+                },
+                Err(e) if e.is_skipped() => {
+                    let name = e.entry_name().to_string();
+                    options.event(Event::Skipped(&name))?;
+                    report.lock().unwrap().skipped_files.push(name);
+                    None
+                }
+                Err(e @ crate::zip::Error::Entry { .. }) => {
+                    let entry = (e.entry_name().to_string(), e.to_string());
+                    options.event(Event::Failed(&entry.0, &entry.1))?;
+                    report.lock().unwrap().broken_entries.push(entry);
+                    None
+                }
+                Err(e) if options.continue_on_error => {
+                    let file = (e.entry_name().to_string(), e.to_string());
+                    options.event(Event::Failed(&file.0, &file.1))?;
+                    report.lock().unwrap().failed_files.push(file);
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            };
+            deliver(index, file)
+        })
+}
+
+/// A file resolved by a worker, waiting to be written.
+struct Resolved {
+    report: FileReport,
+    fudes: Vec<(Fude<'static>, geo::Polygon<f64>)>,
+    features: Vec<(Layer, Feature, geo::Geometry<f64>)>,
+    /// Holds the share of the memory budget of the file until it's written
+    _entry: EntryInfo,
+}
+
+impl Resolved {
+    /// Write the file with `write`, and record it in the report.
+    fn write(
+        self,
+        write: impl FnOnce(FileBatch) -> Result<(), crate::writer::Error>,
+        report: &Mutex<ConversionReport>,
+        options: &ConvertOptions,
+    ) -> Result<(), Error> {
+        let Resolved {
+            report: mut file,
+            fudes,
+            features,
+            ..
+        } = self;
+        let write_start = Instant::now();
+        write(FileBatch {
+            name: &file.name,
+            metadata: &file.metadata,
+            fudes,
+            features,
+        })?;
+        file.write_time = write_start.elapsed();
+        options.event(Event::Written(&file))?;
+        report.lock().unwrap().files.push(file);
+        Ok(())
+    }
+}
+
+/// Prepare a parsed file and resolve its geometries, moving its features out
+/// of `data`. The features whose geometry can't be resolved are dropped, with
+/// a warning.
+fn resolve_file(
+    info: EntryInfo,
+    mut data: ParsedData<'static>,
+    options: &ConvertOptions,
+) -> Result<Resolved, Error> {
+    if let Some(transform) = &options.transform {
+        for fude in data.fudes.values_mut() {
+            transform(&mut fude.attributes, &data.metadata);
+        }
+    }
+    let mut warnings = match &options.prepare {
+        Some(prepare) => prepare(&info.name, &mut data).map_err(|message| Error::Prepare {
+            name: info.name.clone(),
+            message,
+        })?,
+        None => Vec::new(),
+    };
+
+    let resolve_start = Instant::now();
+    let prepared = warnings.len();
+    let resolved: Vec<_> = std::mem::take(&mut data.fudes)
+        .into_values()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|fude| {
+            let polygon = data.resolve_surface_geo(fude.surface_id);
+            (fude, polygon)
+        })
+        .collect();
+    let mut fudes = Vec::with_capacity(resolved.len());
+    for (fude, polygon) in resolved {
+        match polygon {
+            Ok(mut polygon) => {
+                if let Some(crs) = options.crs {
+                    crs.transform(&mut polygon);
+                }
+                fudes.push((fude, polygon));
+            }
+            Err(e) => warnings.push(format!("筆 {}: {}", fude.attributes.id, e)),
+        }
+    }
+    if options.ordered {
+        fudes.sort_by(|(a, _), (b, _)| a.attributes.id.cmp(&b.attributes.id));
+    }
+    let mut features = Vec::new();
+    for (layer, layer_features) in [
+        (Layer::Kijunten, std::mem::take(&mut data.kijunten)),
+        (Layer::Hikkaiten, std::mem::take(&mut data.hikkaiten)),
+        (Layer::Zukaku, std::mem::take(&mut data.zukaku)),
+    ] {
+        for feature in layer_features {
+            match data.resolve_feature_geo(&feature) {
+                Some(mut geometry) => {
+                    if let Some(crs) = options.crs {
+                        crs.transform(&mut geometry);
+                    }
+                    features.push((layer, feature, geometry));
+                }
+                None => warnings.push(format!(
+                    "{} {}: geometry id={} not found",
+                    layer.name(),
+                    feature.id,
+                    data.ids.name(feature.geometry_id)
+                )),
+            }
+        }
+    }
+    let report = FileReport {
+        name: info.name.clone(),
+        size: info.size,
+        sha256: info.sha256.clone(),
+        metadata: data.metadata,
+        fudes: fudes.len(),
+        features: features.len(),
+        dropped: warnings.len() - prepared,
+        warnings,
+        parse_time: info.parse_time,
+        resolve_time: resolve_start.elapsed(),
+        ..Default::default()
+    };
+    Ok(Resolved {
+        report,
+        fudes,
+        features,
+        _entry: info,
+    })
+}
+
+/// Hands the files over in the order of their index, with
+/// [`ConvertOptions::ordered`].
+#[derive(Default)]
+struct Reorder {
+    /// The files (or entries without one) done ahead of their turn
+    ahead: BTreeMap<usize, Option<Resolved>>,
+    next: usize,
+}
+
+impl Reorder {
+    /// Add the file at `index`, returning the files whose turn came.
+    fn push(&mut self, index: usize, file: Option<Resolved>) -> Vec<Resolved> {
+        self.ahead.insert(index, file);
+        let mut ready = Vec::new();
+        while let Some(file) = self.ahead.remove(&self.next) {
+            ready.extend(file);
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+/// Sort the lists of the report, and record the time of the conversion.
+fn finish_report(mut report: ConversionReport, start: Instant) -> ConversionReport {
+    report.files.sort_by(|a, b| a.name.cmp(&b.name));
+    report.skipped_files.sort();
+    report.broken_entries.sort();
+    report.failed_files.sort();
+    report.elapsed = start.elapsed();
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConvertOptions, Event, convert, convert_shared};
+    use crate::data::{Feature, Fude, MapMetadata};
+    use crate::writer::{Error, FeatureSink, Layer};
+    use std::io::{Cursor, Write};
+    use std::sync::{Arc, Mutex};
+    use test_log::test;
+    use zip::write::SimpleFileOptions;
+
+    /// A map file with one 筆 (a triangle).
+    fn sample_xml(crs: &str) -> String {
+        let curve = |id: &str, [x, y]: [f64; 2]| {
+            format!(
+                r#"<zmn:GM_Curve id="{id}"><zmn:GM_Position.direct><zmn:X>{x}</zmn:X><zmn:Y>{y}</zmn:Y></zmn:GM_Position.direct><zmn:GM_Position.direct><zmn:X>0</zmn:X><zmn:Y>0</zmn:Y></zmn:GM_Position.direct></zmn:GM_Curve>"#
+            )
+        };
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<地図 xmlns:zmn="http://www.moj.go.jp/MINJI/tizuzumen">
+<地図名>sample</地図名><市区町村コード>13101</市区町村コード><座標系>{crs}</座標系>
+<空間属性>{}{}{}
+<zmn:GM_Surface id="S1"><zmn:GM_SurfaceBoundary.exterior><zmn:GM_Ring><zmn:GM_CompositeCurve.generator idref="C1"/><zmn:GM_CompositeCurve.generator idref="C2"/><zmn:GM_CompositeCurve.generator idref="C3"/></zmn:GM_Ring></zmn:GM_SurfaceBoundary.exterior></zmn:GM_Surface>
+</空間属性>
+<主題属性><筆 id="H1"><大字コード>001</大字コード><地番>1</地番><形状 idref="S1"/></筆></主題属性>
+</地図>"#,
+            curve("C1", [0.0, 0.0]),
+            curve("C2", [0.0, 10.0]),
+            curve("C3", [10.0, 0.0]),
+        )
+    }
+
+    #[derive(Default)]
+    struct Collect {
+        files: Vec<(String, Option<String>)>,
+        fudes: Vec<String>,
+    }
+
+    impl FeatureSink for Collect {
+        fn begin_file(&mut self, name: &str, metadata: &MapMetadata) -> Result<(), Error> {
+            self.files
+                .push((name.to_string(), metadata.municipality_code.clone()));
+            Ok(())
+        }
+
+        fn write_fude(&mut self, fude: &Fude, _polygon: geo::Polygon<f64>) -> Result<(), Error> {
+            self.fudes.push(fude.attributes.id.to_string());
+            Ok(())
+        }
+
+        fn write_feature(&mut self, _: Layer, _: &Feature, _: geo::Geometry) -> Result<(), Error> {
+            unreachable!("only the 筆 are parsed")
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        for (name, data) in [
+            ("a.xml", sample_xml("公共座標9系").into_bytes()),
+            ("b.xml", sample_xml("任意座標系").into_bytes()),
+            ("c.zip", b"not a zip".to_vec()),
+            ("d.xml", "<地図><a></b></地図>".as_bytes().to_vec()),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(&data).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let options = ConvertOptions::default();
+        assert!(convert(Cursor::new(data.clone()), Collect::default(), &options).is_err());
+
+        let options = ConvertOptions {
+            continue_on_error: true,
+            checksums: true,
+            ..options
+        };
+        let mut sink = Collect::default();
+        let report = convert(Cursor::new(data.clone()), &mut sink, &options).unwrap();
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].name, "a.xml");
+        assert_eq!(report.skipped_files, ["b.xml"]);
+        assert_eq!(report.broken_entries[0].0, "c.zip");
+        assert_eq!(report.failed_files[0].0, "d.xml");
+        assert_eq!((report.fudes(), report.dropped()), (1, 0));
+        assert_eq!(
+            sink.files,
+            [("a.xml".to_string(), Some("13101".to_string()))]
+        );
+        assert_eq!(sink.fudes, ["H1"]);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = report.to_json();
+            assert!(json.starts_with(r#"{"files":[{"name":"a.xml","size":"#));
+            assert!(json.contains(r#""skipped_files":["b.xml"]"#));
+            let sha256 = report.files[0].sha256.as_deref().unwrap();
+            assert_eq!(sha256.len(), 64);
+            assert!(json.contains(&format!(r#""sha256":"{}""#, sha256)));
+            assert!(json.contains(
+                r#""totals":{"files":1,"skipped_files":1,"broken_entries":1,"failed_files":1,"fudes":1,"features":0,"dropped":0}"#
+            ));
+            assert!(json.contains(r#""municipality_code":"13101""#));
+            assert!(json.contains(r#""broken_entries":[{"name":"c.zip","error":"#));
+        }
+
+        let options = ConvertOptions {
+            transform: Some(Arc::new(|attributes, metadata| {
+                let code = metadata.municipality_code.as_deref().unwrap_or_default();
+                attributes.id = format!("{}-{}", code, attributes.id).into();
+            })),
+            ..options
+        };
+        let mut sink = Collect::default();
+        convert(Cursor::new(data), &mut sink, &options).unwrap();
+        assert_eq!(sink.fudes, ["13101-H1"]);
+    }
+
+    #[test]
+    fn test_convert_options() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        for name in ["f.xml", "e.xml", "d.xml", "c.xml", "b.xml", "a.xml"] {
+            writer.start_file(name, options).unwrap();
+            writer
+                .write_all(sample_xml("公共座標9系").as_bytes())
+                .unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let on_event = {
+            let events = events.clone();
+            Arc::new(move |event: Event| {
+                let event = match event {
+                    Event::Started { entries } => format!("started {}", entries),
+                    Event::Written(file) => format!("written {}", file.name),
+                    Event::Skipped(name) => format!("skipped {}", name),
+                    Event::Failed(name, _) => format!("failed {}", name),
+                };
+                events.lock().unwrap().push(event);
+                Ok(())
+            })
+        };
+        let options = ConvertOptions {
+            prepare: Some(Arc::new(|name, data| match name {
+                "c.xml" => Err("rejected".to_string()),
+                _ => {
+                    data.fudes.values_mut().for_each(|fude| {
+                        fude.attributes.id = format!("{}-{}", name, fude.attributes.id).into();
+                    });
+                    Ok(vec!["prepared".to_string()])
+                }
+            })),
+            skip_files: vec!["f.xml".to_string()],
+            ordered: true,
+            threads: Some(3),
+            continue_on_error: true,
+            on_event: Some(on_event),
+            ..Default::default()
+        };
+        let mut sink = Collect::default();
+        let report = convert(Cursor::new(data.clone()), &mut sink, &options).unwrap();
+        assert_eq!(sink.fudes, ["a.xml-H1", "b.xml-H1", "d.xml-H1", "e.xml-H1"]);
+        assert_eq!(
+            report.failed_files[0],
+            ("c.xml".into(), "c.xml: rejected".into())
+        );
+        assert_eq!(report.files[0].warnings, ["prepared"]);
+        assert_eq!(report.dropped(), 0);
+        // the files are written in order, the failures come from the workers
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], "started 5");
+        assert!(events.contains(&"failed c.xml".to_string()));
+        let written: Vec<_> = events.iter().filter(|e| e.starts_with("written")).collect();
+        assert_eq!(
+            written,
+            [
+                "written a.xml",
+                "written b.xml",
+                "written d.xml",
+                "written e.xml"
+            ]
+        );
+
+        let options = ConvertOptions {
+            sample: Some(2),
+            on_event: None,
+            ..options
+        };
+        let sink = Mutex::new(Collect::default());
+        let report = convert_shared(Cursor::new(data), &sink, &options).unwrap();
+        let sample = report.sample.unwrap();
+        assert_eq!((sample.entries, sample.total_entries), (2, 5));
+        assert_eq!(sample.ratio(), 2.5);
+        // every third entry, in the order of the archive
+        assert_eq!(sink.into_inner().unwrap().fudes, ["b.xml-H1", "e.xml-H1"]);
+    }
+}
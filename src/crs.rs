@@ -0,0 +1,255 @@
+//! Coordinate reference systems of the output geometries.
+
+use std::fmt;
+
+/// WKT of JGD2011 (EPSG:6668), the CRS of the parsed geometries.
+const JGD2011_WKT: &str = r#"GEOGCS["JGD2011",DATUM["Japanese_Geodetic_Datum_2011",SPHEROID["GRS 1980",6378137,298.257222101,AUTHORITY["EPSG","7019"]],AUTHORITY["EPSG","1128"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","6668"]]"#;
+
+const WGS84_WKT: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]"#;
+
+const PROJJSON_SCHEMA: &str = "https://proj.org/schemas/v0.7/projjson.schema.json";
+
+/// Radius of the Web Mercator sphere
+const WEB_MERCATOR_RADIUS: f64 = 6378137.0;
+
+/// Origins (latitude, longitude in degrees and minutes) of the zones of the
+/// Japan Plane Rectangular CS.
+const JPR_ORIGINS: [(f64, f64, f64); 19] = [
+    (33.0, 129.0, 30.0),
+    (33.0, 131.0, 0.0),
+    (36.0, 132.0, 10.0),
+    (33.0, 133.0, 30.0),
+    (36.0, 134.0, 20.0),
+    (36.0, 136.0, 0.0),
+    (36.0, 137.0, 10.0),
+    (36.0, 138.0, 30.0),
+    (36.0, 139.0, 50.0),
+    (40.0, 140.0, 50.0),
+    (44.0, 140.0, 15.0),
+    (44.0, 142.0, 15.0),
+    (44.0, 144.0, 15.0),
+    (26.0, 142.0, 0.0),
+    (26.0, 127.0, 30.0),
+    (26.0, 124.0, 0.0),
+    (26.0, 131.0, 0.0),
+    (20.0, 136.0, 0.0),
+    (26.0, 154.0, 0.0),
+];
+
+const ROMAN: [&str; 19] = [
+    "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X", "XI", "XII", "XIII", "XIV", "XV",
+    "XVI", "XVII", "XVIII", "XIX",
+];
+
+/// A CRS the geometries can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Crs {
+    /// JGD2011 longitude/latitude (EPSG:6668), as parsed
+    #[default]
+    Jgd2011,
+    /// WGS 84 longitude/latitude (EPSG:4326). JGD2011 coordinates are used as
+    /// is: the datums differ by a few centimeters at most.
+    Wgs84,
+    /// Web Mercator (EPSG:3857)
+    WebMercator,
+    /// A zone (1-19) of the Japan Plane Rectangular CS on JGD2011
+    /// (EPSG:6669-6687), as easting/northing. The coordinates must be parsed
+    /// with [`ParseOptions::keep_source_coordinates`](crate::parser::ParseOptions).
+    Jpr(u8),
+}
+
+impl Crs {
+    /// The CRS with this EPSG code, if supported.
+    pub fn from_epsg(code: i32) -> Option<Self> {
+        match code {
+            6668 => Some(Crs::Jgd2011),
+            4326 => Some(Crs::Wgs84),
+            3857 => Some(Crs::WebMercator),
+            6669..=6687 => Some(Crs::Jpr((code - 6668) as u8)),
+            _ => None,
+        }
+    }
+
+    /// The CRS of the source coordinates of a map file, from its 座標系
+    /// (`公共座標9系`). `None` for arbitrary coordinate systems.
+    pub fn from_source(crs: &str) -> Option<Self> {
+        crs.strip_prefix("公共座標")
+            .and_then(|s| s.strip_suffix("系"))
+            .and_then(|num_str| num_str.parse::<u8>().ok())
+            .filter(|zone| (1..=19).contains(zone))
+            .map(Crs::Jpr)
+    }
+
+    pub fn epsg(self) -> i32 {
+        match self {
+            Crs::Jgd2011 => 6668,
+            Crs::Wgs84 => 4326,
+            Crs::WebMercator => 3857,
+            Crs::Jpr(zone) => 6668 + zone as i32,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Crs::Jgd2011 => "JGD2011".to_string(),
+            Crs::Wgs84 => "WGS 84".to_string(),
+            Crs::WebMercator => "WGS 84 / Pseudo-Mercator".to_string(),
+            Crs::Jpr(zone) => format!(
+                "JGD2011 / Japan Plane Rectangular CS {}",
+                ROMAN[zone as usize - 1]
+            ),
+        }
+    }
+
+    /// Whether the coordinates are longitude/latitude.
+    pub fn is_geographic(self) -> bool {
+        matches!(self, Crs::Jgd2011 | Crs::Wgs84)
+    }
+
+    fn jpr_origin(zone: u8) -> (f64, f64) {
+        let (lat, lng_deg, lng_min) = JPR_ORIGINS[zone as usize - 1];
+        (lat, lng_deg + lng_min / 60.0)
+    }
+
+    /// OGC WKT (version 1), e.g. for `.prj` files.
+    pub fn wkt(self) -> String {
+        let projcs = |name: &str, geogcs: &str, projection: &str, lat0: f64, lng0: f64, k0: f64| {
+            format!(
+                r#"PROJCS["{name}",{geogcs},PROJECTION["{projection}"],PARAMETER["latitude_of_origin",{lat0}],PARAMETER["central_meridian",{lng0}],PARAMETER["scale_factor",{k0}],PARAMETER["false_easting",0],PARAMETER["false_northing",0],UNIT["metre",1,AUTHORITY["EPSG","9001"]],AUTHORITY["EPSG","{}"]]"#,
+                self.epsg()
+            )
+        };
+        match self {
+            Crs::Jgd2011 => JGD2011_WKT.to_string(),
+            Crs::Wgs84 => WGS84_WKT.to_string(),
+            Crs::WebMercator => projcs(&self.name(), WGS84_WKT, "Mercator_1SP", 0.0, 0.0, 1.0),
+            Crs::Jpr(zone) => {
+                let (lat0, lng0) = Self::jpr_origin(zone);
+                projcs(
+                    &self.name(),
+                    JGD2011_WKT,
+                    "Transverse_Mercator",
+                    lat0,
+                    lng0,
+                    0.9999,
+                )
+            }
+        }
+    }
+
+    /// PROJ.4 definition, e.g. for SpatiaLite.
+    pub fn proj4(self) -> String {
+        match self {
+            Crs::Jgd2011 => "+proj=longlat +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +no_defs".to_string(),
+            Crs::Wgs84 => "+proj=longlat +datum=WGS84 +no_defs".to_string(),
+            Crs::WebMercator => "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +nadgrids=@null +wktext +no_defs".to_string(),
+            Crs::Jpr(zone) => {
+                let (lat0, lng0) = Self::jpr_origin(zone);
+                format!(
+                    "+proj=tmerc +lat_0={lat0} +lon_0={lng0} +k=0.9999 +x_0=0 +y_0=0 +ellps=GRS80 +towgs84=0,0,0,0,0,0,0 +units=m +no_defs"
+                )
+            }
+        }
+    }
+
+    /// PROJJSON, e.g. for GeoParquet.
+    pub fn projjson(self) -> String {
+        let id = format!(r#""id":{{"authority":"EPSG","code":{}}}"#, self.epsg());
+        let geographic = |crs: Crs| {
+            let (name, datum, ellipsoid, inverse_flattening) = match crs {
+                Crs::Wgs84 | Crs::WebMercator => (
+                    "WGS 84",
+                    "World Geodetic System 1984",
+                    "WGS 84",
+                    298.257223563,
+                ),
+                _ => (
+                    "JGD2011",
+                    "Japanese Geodetic Datum 2011",
+                    "GRS 1980",
+                    298.257222101,
+                ),
+            };
+            format!(
+                r#""type":"GeographicCRS","name":"{name}","datum":{{"type":"GeodeticReferenceFrame","name":"{datum}","ellipsoid":{{"name":"{ellipsoid}","semi_major_axis":6378137,"inverse_flattening":{inverse_flattening}}}}},"coordinate_system":{{"subtype":"ellipsoidal","axis":[{{"name":"Geodetic latitude","abbreviation":"Lat","direction":"north","unit":"degree"}},{{"name":"Geodetic longitude","abbreviation":"Lon","direction":"east","unit":"degree"}}]}}"#
+            )
+        };
+        let projected = |method: &str, lat0: f64, lng0: f64, k0: f64| {
+            format!(
+                r#""type":"ProjectedCRS","name":"{}","base_crs":{{{}}},"conversion":{{"name":"{}","method":{{"name":"{method}"}},"parameters":[{{"name":"Latitude of natural origin","value":{lat0},"unit":"degree"}},{{"name":"Longitude of natural origin","value":{lng0},"unit":"degree"}},{{"name":"Scale factor at natural origin","value":{k0},"unit":"unity"}},{{"name":"False easting","value":0,"unit":"metre"}},{{"name":"False northing","value":0,"unit":"metre"}}]}},"coordinate_system":{{"subtype":"Cartesian","axis":[{{"name":"Easting","abbreviation":"X","direction":"east","unit":"metre"}},{{"name":"Northing","abbreviation":"Y","direction":"north","unit":"metre"}}]}}"#,
+                self.name(),
+                geographic(self),
+                self.name(),
+            )
+        };
+        let body = match self {
+            Crs::Jgd2011 | Crs::Wgs84 => geographic(self),
+            Crs::WebMercator => projected("Popular Visualisation Pseudo Mercator", 0.0, 0.0, 1.0),
+            Crs::Jpr(zone) => {
+                let (lat0, lng0) = Self::jpr_origin(zone);
+                projected("Transverse Mercator", lat0, lng0, 0.9999)
+            }
+        };
+        format!(r#"{{"$schema":"{PROJJSON_SCHEMA}",{body},{id}}}"#)
+    }
+
+    /// Transform a coordinate from the parsed coordinates (JGD2011
+    /// longitude/latitude, or the source coordinates for [`Crs::Jpr`]).
+    pub fn transform_coord(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            Crs::Jgd2011 | Crs::Wgs84 | Crs::Jpr(_) => [x, y],
+            Crs::WebMercator => {
+                let lat = y.to_radians();
+                [
+                    WEB_MERCATOR_RADIUS * x.to_radians(),
+                    WEB_MERCATOR_RADIUS * (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln(),
+                ]
+            }
+        }
+    }
+
+    /// Transform a geometry in place (see [`transform_coord`](Self::transform_coord)).
+    #[cfg(feature = "geo")]
+    pub fn transform<G: geo::MapCoordsInPlace<f64>>(self, geometry: &mut G) {
+        if matches!(self, Crs::WebMercator) {
+            geometry.map_coords_in_place(|c| {
+                let [x, y] = self.transform_coord([c.x, c.y]);
+                geo::Coord { x, y }
+            });
+        }
+    }
+}
+
+impl fmt::Display for Crs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EPSG:{}", self.epsg())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Crs;
+    use test_log::test;
+
+    #[test]
+    fn test_crs() {
+        assert_eq!(Crs::from_source("公共座標9系"), Some(Crs::Jpr(9)));
+        assert_eq!(Crs::from_source("任意座標系"), None);
+        assert_eq!(Crs::Jpr(9).epsg(), 6677);
+        assert_eq!(Crs::from_epsg(6677), Some(Crs::Jpr(9)));
+        assert!(
+            Crs::Jpr(9).wkt().starts_with(
+                r#"PROJCS["JGD2011 / Japan Plane Rectangular CS IX",GEOGCS["JGD2011""#
+            )
+        );
+        assert!(
+            Crs::Jpr(9)
+                .proj4()
+                .contains("+lat_0=36 +lon_0=139.83333333333334")
+        );
+
+        let [x, y] = Crs::WebMercator.transform_coord([139.0, 35.0]);
+        assert!((x - 15473409.22).abs() < 0.01, "{}", x);
+        assert!((y - 4163881.14).abs() < 0.01, "{}", y);
+    }
+}
@@ -1,100 +1,1044 @@
-use hashbrown::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, OnceLock};
+
+use hashbrown::{DefaultHashBuilder, HashMap, HashTable};
+#[cfg(feature = "geo")]
+use jprect::etmerc::ExtendedTransverseMercatorProjection;
+use smallvec::SmallVec;
+
+#[cfg(feature = "geo")]
+use crate::crs::Crs;
+use crate::municipality::MunicipalityTable;
 
 pub type Point = [f64; 2];
 
+/// The rings of a resolved surface, the exterior first. Most surfaces have
+/// no holes, so the one ring is stored inline.
+pub type Rings = SmallVec<[Vec<Point>; 1]>;
+
+/// Handle of a point, curve or surface id of a file (see [`Ids`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Id(u32);
+
+impl Id {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Id(index as u32)
+    }
+}
+
+/// The ids of the points, curves and surfaces of a file, each stored once.
+///
+/// The geometries reference each other by [`Id`] handles, which are much
+/// smaller than the ids themselves.
+#[derive(Default)]
+pub struct Ids<'a> {
+    names: Vec<Cow<'a, str>>,
+    table: HashTable<Id>,
+    hasher: DefaultHashBuilder,
+}
+
+impl<'a> Ids<'a> {
+    /// The handle of an id, added if it's new.
+    pub fn intern(&mut self, name: impl Into<Cow<'a, str>>) -> Id {
+        let name = name.into();
+        let hash = self.hasher.hash_one(&*name);
+        if let Some(&id) = self
+            .table
+            .find(hash, |&id| self.names[id.0 as usize] == name)
+        {
+            return id;
+        }
+        let id = Id(self.names.len() as u32);
+        self.names.push(name);
+        let Self {
+            names,
+            table,
+            hasher,
+        } = self;
+        table.insert_unique(hash, id, |&id| hasher.hash_one(&*names[id.0 as usize]));
+        id
+    }
+
+    /// Reserve room for `additional` more ids.
+    pub fn reserve(&mut self, additional: usize) {
+        self.names.reserve(additional);
+        let Self {
+            names,
+            table,
+            hasher,
+        } = self;
+        table.reserve(additional, |&id| hasher.hash_one(&*names[id.0 as usize]));
+    }
+
+    /// The handle of an id, if it was interned.
+    pub fn get(&self, name: &str) -> Option<Id> {
+        let hash = self.hasher.hash_one(name);
+        self.table
+            .find(hash, |&id| &*self.names[id.0 as usize] == name)
+            .copied()
+    }
+
+    /// The original id of a handle.
+    pub fn name(&self, id: Id) -> &str {
+        &self.names[id.0 as usize]
+    }
+
+    /// The handles and original ids, in the order they were added.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (Id, &str)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Id(i as u32), &**name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// The GM_Surfaces of a file, stored flat: the curve ids of all the rings in
+/// one array, the end of each ring in another, and the range of rings of each
+/// surface.
+#[derive(Debug, Default)]
+pub struct Surfaces {
+    curves: Vec<Id>,
+    ring_ends: Vec<u32>,
+    index: HashMap<Id, (u32, u32)>,
+    /// first ring of the surface being built
+    open: u32,
+}
+
+impl Surfaces {
+    pub fn get(&self, id: Id) -> Option<Surface<'_>> {
+        let &(first, last) = self.index.get(&id)?;
+        Some(self.surface(first, last))
+    }
+
+    fn surface(&self, first: u32, last: u32) -> Surface<'_> {
+        let start = match first {
+            0 => 0,
+            first => self.ring_ends[first as usize - 1],
+        };
+        Surface {
+            curves: &self.curves,
+            start,
+            ring_ends: &self.ring_ends[first as usize..last as usize],
+        }
+    }
+
+    pub fn contains(&self, id: Id) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id, Surface<'_>)> {
+        self.index
+            .iter()
+            .map(|(&id, &(first, last))| (id, self.surface(first, last)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Add a surface, with its exterior ring first. A surface without rings
+    /// is ignored, as it has no exterior ring to resolve.
+    pub fn insert<R: IntoIterator<Item = Id>>(
+        &mut self,
+        id: Id,
+        rings: impl IntoIterator<Item = R>,
+    ) {
+        for ring in rings {
+            self.curves.extend(ring);
+            self.end_ring();
+        }
+        if self.open_rings() > 0 {
+            self.finish_surface(id, 0);
+        }
+    }
+
+    /// Make the ring `exterior` of a surface its exterior ring, for files
+    /// that list the rings in the wrong roles. The surface is added again.
+    pub fn set_exterior(&mut self, id: Id, exterior: usize) {
+        let Some(surface) = self.get(id) else {
+            return;
+        };
+        if exterior == 0 || exterior >= surface.len() {
+            return;
+        }
+        let rings: Vec<Vec<Id>> = surface.rings().map(<[Id]>::to_vec).collect();
+        for ring in rings {
+            self.curves.extend(ring);
+            self.end_ring();
+        }
+        self.finish_surface(id, exterior);
+    }
+
+    /// Add a curve to the ring being built.
+    pub(crate) fn push_curve(&mut self, curve_id: Id) {
+        self.curves.push(curve_id);
+    }
+
+    /// Complete the ring being built.
+    pub(crate) fn end_ring(&mut self) {
+        self.ring_ends.push(self.curves.len() as u32);
+    }
+
+    /// Number of rings of the surface being built.
+    pub(crate) fn open_rings(&self) -> usize {
+        self.ring_ends.len() - self.open as usize
+    }
+
+    /// Complete the surface being built, whose ring `exterior` is moved first.
+    pub(crate) fn finish_surface(&mut self, id: Id, exterior: usize) {
+        let first = self.open as usize;
+        let last = self.ring_ends.len();
+        if exterior > 0 {
+            let ring_start = |i: usize| match i {
+                0 => 0,
+                i => self.ring_ends[i - 1] as usize,
+            };
+            let start = ring_start(first);
+            let (ext_start, ext_end) = (
+                ring_start(first + exterior),
+                self.ring_ends[first + exterior] as usize,
+            );
+            let mut ends: Vec<u32> = Vec::with_capacity(exterior + 1);
+            ends.push((start + ext_end - ext_start) as u32);
+            for i in first..first + exterior {
+                let len = self.ring_ends[i] as usize - ring_start(i);
+                ends.push(ends[ends.len() - 1] + len as u32);
+            }
+            self.curves[start..ext_end].rotate_right(ext_end - ext_start);
+            self.ring_ends[first..=first + exterior].copy_from_slice(&ends);
+        }
+        self.index.insert(id, (first as u32, last as u32));
+        self.open = last as u32;
+    }
+
+    /// Reserve room for `surfaces` more surfaces referencing `curves` curves in total.
+    pub fn reserve(&mut self, surfaces: usize, curves: usize) {
+        self.index.reserve(surfaces);
+        self.ring_ends.reserve(surfaces);
+        self.curves.reserve(curves);
+    }
+
+    /// Remove all the surfaces, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.curves.clear();
+        self.ring_ends.clear();
+        self.index.clear();
+        self.open = 0;
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.curves.capacity()
+    }
+}
+
+impl<R: IntoIterator<Item = Id>, S: IntoIterator<Item = R>> FromIterator<(Id, S)> for Surfaces {
+    fn from_iter<T: IntoIterator<Item = (Id, S)>>(iter: T) -> Self {
+        let mut surfaces = Self::default();
+        for (id, rings) in iter {
+            surfaces.insert(id, rings);
+        }
+        surfaces
+    }
+}
+
+/// A surface of [`Surfaces`]: its rings, as curve ids, the exterior first.
+#[derive(Clone, Copy, Debug)]
+pub struct Surface<'s> {
+    curves: &'s [Id],
+    start: u32,
+    ring_ends: &'s [u32],
+}
+
+impl<'s> Surface<'s> {
+    /// Number of rings
+    pub fn len(&self) -> usize {
+        self.ring_ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring_ends.is_empty()
+    }
+
+    pub fn ring(&self, index: usize) -> &'s [Id] {
+        let start = match index {
+            0 => self.start,
+            i => self.ring_ends[i - 1],
+        };
+        &self.curves[start as usize..self.ring_ends[index] as usize]
+    }
+
+    pub fn exterior(&self) -> &'s [Id] {
+        self.ring(0)
+    }
+
+    pub fn interiors(&self) -> impl ExactSizeIterator<Item = &'s [Id]> + use<'s> {
+        let surface = *self;
+        (1..self.len()).map(move |i| surface.ring(i))
+    }
+
+    pub fn rings(&self) -> impl ExactSizeIterator<Item = &'s [Id]> + use<'s> {
+        let surface = *self;
+        (0..self.len()).map(move |i| surface.ring(i))
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PointRef {
-    Indirect(String),
+    Indirect(Id),
     Direct(Point),
 }
 
-pub struct Fude {
-    pub attributes: FudeAttributes,
-    pub surface_id: String,
+/// A 筆. Its attributes borrow from the document with
+/// [`MojxmlParser::from_slice`](crate::parser::MojxmlParser::from_slice).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fude<'a> {
+    pub attributes: FudeAttributes<'a>,
+    pub surface_id: Id,
 }
 
-#[derive(Default, Debug)]
-pub struct FudeAttributes {
+impl Fude<'_> {
+    /// The 筆 with its attributes copied out of the document.
+    pub fn into_owned(self) -> Fude<'static> {
+        Fude {
+            attributes: self.attributes.into_owned(),
+            surface_id: self.surface_id,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FudeAttributes<'a> {
     /// 筆ID
-    pub id: String,
+    pub id: Cow<'a, str>,
     /// 大字コード
-    pub oaza_code: Option<String>,
+    pub oaza_code: Option<Cow<'a, str>>,
     /// 丁目コード
-    pub chome_code: Option<String>,
+    pub chome_code: Option<Cow<'a, str>>,
     /// 小字コード
-    pub koaza_code: Option<String>,
+    pub koaza_code: Option<Cow<'a, str>>,
     /// 予備コード
-    pub yobi_code: Option<String>,
+    pub yobi_code: Option<Cow<'a, str>>,
     /// 大字名
-    pub oaza: Option<String>,
+    pub oaza: Option<Cow<'a, str>>,
     /// 丁目名
-    pub chome: Option<String>,
+    pub chome: Option<Cow<'a, str>>,
     /// 小字名
-    pub koaza: Option<String>,
+    pub koaza: Option<Cow<'a, str>>,
     /// 予備名
-    pub yobi: Option<String>,
+    pub yobi: Option<Cow<'a, str>>,
     /// 地番
-    pub chiban: Option<String>,
+    pub chiban: Option<Cow<'a, str>>,
     /// 筆界未定構成筆
-    pub hikkai_mitei: Option<String>,
+    pub hikkai_mitei: Option<Cow<'a, str>>,
     /// 精度区分
-    pub accuracy_class: Option<String>,
+    pub accuracy_class: Option<Cow<'a, str>>,
     /// 座標値種別
-    pub coord_class: Option<String>,
+    pub coord_class: Option<Cow<'a, str>>,
+    /// See [`compute_stable_id`](Self::compute_stable_id) (only with
+    /// [`ParseOptions::stable_ids`](crate::parser::ParseOptions))
+    pub stable_id: Option<String>,
+    /// Name of the source XML file (see [`ParsedData::set_provenance`])
+    pub source_file: Option<Arc<str>>,
+    /// 地図番号 of the 図郭 containing the 筆 (see [`ParsedData::set_provenance`])
+    pub map_number: Option<String>,
+    /// Id of the conversion run (see [`ParsedData::set_provenance`])
+    pub run_id: Option<Arc<str>>,
+    /// 市区町村コード of the file (see [`ParsedData::set_municipality`])
+    pub municipality_code: Option<Arc<str>>,
+    /// 市区町村名 of the file (see [`ParsedData::set_municipality`])
+    pub municipality_name: Option<Arc<str>>,
+    /// 計算地積: the area computed from the geometry, in m² (see
+    /// [`ParsedData::set_computed_areas`])
+    pub computed_area: Option<f64>,
+    /// Attributes added after parsing (e.g. computed by a conversion
+    /// transform), written by the columns of
+    /// [`Schema::with_extra_column`](crate::writer::Schema::with_extra_column)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub extra: Vec<(String, String)>,
+}
+
+impl FudeAttributes<'_> {
+    /// Value of an [`extra`](Self::extra) attribute.
+    pub fn extra(&self, name: &str) -> Option<&str> {
+        self.extra
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Set an [`extra`](Self::extra) attribute, replacing its value if it's
+    /// already set.
+    pub fn set_extra(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.extra.iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value,
+            None => self.extra.push((name.to_string(), value)),
+        }
+    }
+
+    /// The attributes with their text copied out of the document.
+    pub fn into_owned(self) -> FudeAttributes<'static> {
+        fn owned(value: Option<Cow<str>>) -> Option<Cow<'static, str>> {
+            value.map(|value| Cow::Owned(value.into_owned()))
+        }
+        FudeAttributes {
+            id: Cow::Owned(self.id.into_owned()),
+            oaza_code: owned(self.oaza_code),
+            chome_code: owned(self.chome_code),
+            koaza_code: owned(self.koaza_code),
+            yobi_code: owned(self.yobi_code),
+            oaza: owned(self.oaza),
+            chome: owned(self.chome),
+            koaza: owned(self.koaza),
+            yobi: owned(self.yobi),
+            chiban: owned(self.chiban),
+            hikkai_mitei: owned(self.hikkai_mitei),
+            accuracy_class: owned(self.accuracy_class),
+            coord_class: owned(self.coord_class),
+            stable_id: self.stable_id,
+            source_file: self.source_file,
+            map_number: self.map_number,
+            run_id: self.run_id,
+            municipality_code: self.municipality_code,
+            municipality_name: self.municipality_name,
+            computed_area: self.computed_area,
+            extra: self.extra,
+        }
+    }
+
+    /// The address of the 筆: 市区町村名 (see
+    /// [`ParsedData::set_municipality`]), 大字名, 丁目名, 小字名 and 地番,
+    /// concatenated (`千代田区丸の内一丁目1-1`). Missing and blank parts are
+    /// omitted, as well as a part equal to the previous one, and the
+    /// 市区町村名 is removed from the start of the other parts (a 大字名 like
+    /// `千代田区丸の内`).
+    pub fn full_address(&self) -> String {
+        let municipality = self.municipality_name.as_deref().map(str::trim);
+        let parts = [
+            self.oaza.as_deref(),
+            self.chome.as_deref(),
+            self.koaza.as_deref(),
+            self.chiban.as_deref(),
+        ];
+        let mut address = municipality.unwrap_or_default().to_string();
+        let mut previous = "";
+        for part in parts.into_iter().flatten().map(str::trim) {
+            let part = municipality
+                .filter(|m| !m.is_empty())
+                .and_then(|m| part.strip_prefix(m))
+                .map_or(part, str::trim_start);
+            if part.is_empty() || part == previous {
+                continue;
+            }
+            address.push_str(part);
+            previous = part;
+        }
+        address
+    }
+
+    /// The order of the 筆 of a municipality in sorted outputs: by 大字コード,
+    /// 丁目コード, then 地番 in [natural order](natural_cmp), and 筆ID.
+    /// Chain it after the 市区町村コード to sort several municipalities.
+    pub fn natural_cmp(&self, other: &Self) -> Ordering {
+        let cmp = |a: Option<&str>, b: Option<&str>| match (a, b) {
+            (Some(a), Some(b)) => natural_cmp(a, b),
+            _ => a.cmp(&b),
+        };
+        cmp(self.oaza_code.as_deref(), other.oaza_code.as_deref())
+            .then_with(|| cmp(self.chome_code.as_deref(), other.chome_code.as_deref()))
+            .then_with(|| cmp(self.chiban.as_deref(), other.chiban.as_deref()))
+            .then_with(|| natural_cmp(&self.id, &other.id))
+    }
+
+    /// An id derived from the 市区町村コード, 大字コード, 丁目コード, 小字コード
+    /// and 地番 (a 64-bit FNV-1a hash, as 16 hex digits), unlike the 筆ID
+    /// which is only unique within a file. It stays the same across
+    /// conversions and republished files, as long as these attributes do.
+    pub fn compute_stable_id(&self, municipality_code: Option<&str>) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        };
+        let parts = [
+            municipality_code,
+            self.oaza_code.as_deref(),
+            self.chome_code.as_deref(),
+            self.koaza_code.as_deref(),
+            self.chiban.as_deref(),
+        ];
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                // separate the parts, so that ("1", "23") and ("12", "3") differ
+                feed(0x1f);
+            }
+            part.unwrap_or_default().bytes().for_each(&mut feed);
+        }
+        format!("{:016x}", hash)
+    }
+}
+
+/// Compare strings in natural order: runs of ASCII digits by their numeric
+/// value, so that the 地番 `2-10` comes after `2-9`, and `10` after `9`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    // between equal numbers, fewer leading zeros first, if nothing else differs
+    let mut zeros_ordering = Ordering::Equal;
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return zeros_ordering,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (x, rest_a) = a.split_at(digits(a));
+                let (y, rest_b) = b.split_at(digits(b));
+                let zeros = |s: &[u8]| s.iter().take_while(|&&c| c == b'0').count();
+                let (tx, ty) = (&x[zeros(x)..], &y[zeros(y)..]);
+                // the number with fewer digits is the smaller one
+                let ordering = tx.len().cmp(&ty.len()).then_with(|| tx.cmp(ty));
+                if ordering.is_ne() {
+                    return ordering;
+                }
+                zeros_ordering = zeros_ordering.then(x.len().cmp(&y.len()));
+                (a, b) = (rest_a, rest_b);
+            }
+            // comparing UTF-8 bytes orders by code point
+            (Some(x), Some(y)) if x != y => return x.cmp(y),
+            _ => (a, b) = (&a[1..], &b[1..]),
+        }
+    }
+}
+
+/// A 基準点, 筆界点 or 図郭.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Feature {
+    pub id: String,
+    /// id of the GM_Point (基準点, 筆界点) or GM_Surface (図郭)
+    pub geometry_id: Id,
+    /// Child elements with text content, in document order
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Feature {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Header of a map file.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapMetadata {
+    /// 地図名
+    pub map_name: Option<String>,
+    /// 市区町村コード
+    pub municipality_code: Option<String>,
+    /// 市区町村名
+    pub municipality_name: Option<String>,
+    /// 座標系
+    pub crs: Option<String>,
+    /// 測地系判別
+    pub datum: Option<String>,
+}
+
+/// The contents of a map file. Its ids and attributes borrow from the
+/// document with [`MojxmlParser::from_slice`](crate::parser::MojxmlParser::from_slice).
+#[derive(Default)]
+pub struct ParsedData<'a> {
+    pub metadata: MapMetadata,
+    /// The ids of the points, curves and surfaces
+    pub ids: Ids<'a>,
+    pub points: HashMap<Id, Point>,
+    pub segments: HashMap<Id, [PointRef; 2]>,
+    pub surfaces: Surfaces,
+    pub fudes: HashMap<String, Fude<'a>>,
+    /// 基準点 (only with [`ParseOptions::parse_kijunten`](crate::parser::ParseOptions))
+    pub kijunten: Vec<Feature>,
+    /// 筆界点 (only with [`ParseOptions::parse_hikkaiten`](crate::parser::ParseOptions))
+    pub hikkaiten: Vec<Feature>,
+    /// 図郭 (only with [`ParseOptions::parse_zukaku`](crate::parser::ParseOptions))
+    pub zukaku: Vec<Feature>,
+    /// Index of [`find_fude`](Self::find_fude)
+    pub fude_index: FudeIndex,
+}
+
+/// 大字コード → 地番 → keys of [`ParsedData::fudes`], built by the first
+/// lookup.
+#[derive(Default)]
+pub struct FudeIndex(OnceLock<FudeTable>);
+
+type FudeTable = HashMap<String, HashMap<String, SmallVec<[String; 1]>>>;
+
+/// Statistics of the 筆 of a 大字 and 丁目 (see
+/// [`ParsedData::aggregate_by_oaza`]).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OazaStats {
+    /// 大字名 (of the first 筆 that has one)
+    pub oaza: Option<String>,
+    /// 丁目名 (of the first 筆 that has one)
+    pub chome: Option<String>,
+    /// Number of 筆
+    pub fudes: usize,
+    /// Total planar area of the 筆, in m²
+    pub area: f64,
+    /// Number of 筆 by 精度区分 (`None` for the 筆 without one)
+    pub accuracy_classes: BTreeMap<Option<String>, usize>,
 }
 
-pub struct ParsedData {
-    pub points: HashMap<String, Point>,
-    pub segments: HashMap<String, [PointRef; 2]>,
-    pub surfaces: HashMap<String, Vec<Vec<String>>>,
-    pub fudes: HashMap<String, Fude>,
+impl OazaStats {
+    /// Add the statistics of other 筆 of the same 大字 and 丁目, e.g. of
+    /// another file.
+    pub fn add(&mut self, other: &OazaStats) {
+        if self.oaza.is_none() {
+            self.oaza.clone_from(&other.oaza);
+        }
+        if self.chome.is_none() {
+            self.chome.clone_from(&other.chome);
+        }
+        self.fudes += other.fudes;
+        self.area += other.area;
+        for (class, count) in &other.accuracy_classes {
+            *self.accuracy_classes.entry(class.clone()).or_default() += count;
+        }
+    }
 }
 
-impl ParsedData {
-    pub fn resolve_surface(&self, surface_id: &str) -> Result<Vec<Vec<Point>>, String> {
+impl<'a> ParsedData<'a> {
+    /// The 筆 with a 大字コード and 地番, e.g. to join the registry.
+    ///
+    /// The first call indexes the 筆; after changing `fudes`, call
+    /// [`reset_fude_index`](Self::reset_fude_index). 筆 without 大字コード
+    /// are found with `""`. When several 筆 match (in different 丁目), the
+    /// one with the smallest id is returned; see [`find_fudes`](Self::find_fudes).
+    pub fn find_fude(&self, oaza_code: &str, chiban: &str) -> Option<&Fude<'a>> {
+        self.find_fudes(oaza_code, chiban).next()
+    }
+
+    /// All the 筆 with a 大字コード and 地番, in the order of their ids.
+    pub fn find_fudes(&self, oaza_code: &str, chiban: &str) -> impl Iterator<Item = &Fude<'a>> {
+        self.fude_index()
+            .get(oaza_code)
+            .and_then(|chibans| chibans.get(chiban))
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.fudes.get(key))
+    }
+
+    /// Move the contents of another file into this one, e.g. to handle the
+    /// sheets of a municipality as one dataset.
+    ///
+    /// The ids of a file are only unique within it, so those of `other` (of
+    /// the geometries, 筆, 基準点, 筆界点 and 図郭) are prefixed with
+    /// `namespace` and `:`, e.g. the file name. Merge every file into an
+    /// empty `ParsedData` to namespace them all. The metadata missing in this
+    /// one are taken from `other`.
+    pub fn merge(&mut self, namespace: &str, other: ParsedData<'a>) {
+        let ids: Vec<Id> = other
+            .ids
+            .names
+            .iter()
+            .map(|name| self.ids.intern(format!("{}:{}", namespace, name)))
+            .collect();
+        let id = |id: Id| ids[id.0 as usize];
+        let key = |key: &str| format!("{}:{}", namespace, key);
+
+        let metadata = &mut self.metadata;
+        for (value, other) in [
+            (&mut metadata.map_name, other.metadata.map_name),
+            (
+                &mut metadata.municipality_code,
+                other.metadata.municipality_code,
+            ),
+            (
+                &mut metadata.municipality_name,
+                other.metadata.municipality_name,
+            ),
+            (&mut metadata.crs, other.metadata.crs),
+            (&mut metadata.datum, other.metadata.datum),
+        ] {
+            if value.is_none() {
+                *value = other;
+            }
+        }
+        self.points.extend(
+            other
+                .points
+                .into_iter()
+                .map(|(point_id, point)| (id(point_id), point)),
+        );
+        self.segments
+            .extend(other.segments.into_iter().map(|(curve_id, point_refs)| {
+                let point_refs = point_refs.map(|point_ref| match point_ref {
+                    PointRef::Indirect(point_id) => PointRef::Indirect(id(point_id)),
+                    direct => direct,
+                });
+                (id(curve_id), point_refs)
+            }));
+        for (surface_id, surface) in other.surfaces.iter() {
+            self.surfaces.insert(
+                id(surface_id),
+                surface
+                    .rings()
+                    .map(|ring| ring.iter().map(|&curve_id| id(curve_id))),
+            );
+        }
+        self.fudes
+            .extend(other.fudes.into_iter().map(|(fude_key, mut fude)| {
+                fude.attributes.id = key(&fude.attributes.id).into();
+                fude.surface_id = id(fude.surface_id);
+                (key(&fude_key), fude)
+            }));
+        for (features, others) in [
+            (&mut self.kijunten, other.kijunten),
+            (&mut self.hikkaiten, other.hikkaiten),
+            (&mut self.zukaku, other.zukaku),
+        ] {
+            features.extend(others.into_iter().map(|mut feature| {
+                feature.id = key(&feature.id);
+                feature.geometry_id = id(feature.geometry_id);
+                feature
+            }));
+        }
+        self.reset_fude_index();
+    }
+
+    /// Drop the index of [`find_fude`](Self::find_fude), rebuilt by the next
+    /// lookup.
+    pub fn reset_fude_index(&mut self) {
+        self.fude_index = FudeIndex::default();
+    }
+
+    fn fude_index(&self) -> &FudeTable {
+        self.fude_index.0.get_or_init(|| {
+            let mut index = FudeTable::new();
+            for (key, fude) in &self.fudes {
+                let attributes = &fude.attributes;
+                let Some(chiban) = attributes.chiban.as_deref() else {
+                    continue;
+                };
+                index
+                    .entry_ref(attributes.oaza_code.as_deref().unwrap_or(""))
+                    .or_default()
+                    .entry_ref(chiban)
+                    .or_default()
+                    .push(key.clone());
+            }
+            for keys in index.values_mut().flat_map(|chibans| chibans.values_mut()) {
+                keys.sort();
+            }
+            index
+        })
+    }
+
+    pub fn resolve_surface(&self, surface_id: Id) -> Result<Rings, String> {
         self.surfaces
             .get(surface_id)
             .map(|surface| {
                 // rings
                 surface
-                    .iter()
+                    .rings()
                     .map(|ring| {
                         // segments
                         ring.iter()
                             .map(|segment_id| match self.segments.get(segment_id) {
                                 Some(point_ref) => match point_ref[0] {
                                     PointRef::Direct(point) => Ok(point),
-                                    PointRef::Indirect(ref point_id) => self
-                                        .points
-                                        .get(point_id)
-                                        .copied()
-                                        .ok_or(format!("Point id={} not found", point_id)),
+                                    PointRef::Indirect(point_id) => {
+                                        self.points.get(&point_id).copied().ok_or(format!(
+                                            "Point id={} not found",
+                                            self.ids.name(point_id)
+                                        ))
+                                    }
                                 },
-                                None => Err(format!("Curve if={} not found", segment_id)),
+                                None => Err(format!(
+                                    "Curve if={} not found",
+                                    self.ids.name(*segment_id)
+                                )),
                             })
                             .collect::<Result<Vec<Point>, _>>()
                     })
-                    .collect::<Result<Vec<Vec<Point>>, _>>()
+                    .collect::<Result<Rings, _>>()
             })
-            .ok_or(format!("Surface id={} not found", surface_id))?
+            .ok_or(format!(
+                "Surface id={} not found",
+                self.ids.name(surface_id)
+            ))?
+    }
+
+    pub fn resolve_point(&self, point_id: Id) -> Option<Point> {
+        self.points.get(&point_id).copied()
+    }
+
+    /// Resolve the start and end points of a curve.
+    pub fn resolve_curve(&self, curve_id: Id) -> Result<[Point; 2], String> {
+        let Some(point_refs) = self.segments.get(&curve_id) else {
+            return Err(format!("Curve id={} not found", self.ids.name(curve_id)));
+        };
+        let resolve = |point_ref: &PointRef| match point_ref {
+            PointRef::Direct(point) => Ok(*point),
+            PointRef::Indirect(point_id) => self
+                .resolve_point(*point_id)
+                .ok_or(format!("Point id={} not found", self.ids.name(*point_id))),
+        };
+        Ok([resolve(&point_refs[0])?, resolve(&point_refs[1])?])
+    }
+
+    /// Resolve a surface into a WKB polygon (little-endian, 2D).
+    pub fn resolve_surface_wkb(&self, surface_id: Id) -> Result<Vec<u8>, String> {
+        let rings = self.resolve_surface(surface_id)?;
+        let mut wkb = Vec::new();
+        crate::writer::wkb::write_polygon(&mut wkb, rings.iter().map(|ring| closed_ring(ring)));
+        Ok(wkb)
+    }
+
+    /// Resolve a surface into a WKT polygon.
+    pub fn resolve_surface_wkt(&self, surface_id: Id) -> Result<String, String> {
+        let rings = self.resolve_surface(surface_id)?;
+        let mut wkt = String::new();
+        crate::writer::wkt::write_polygon(&mut wkt, rings.iter().map(|ring| closed_ring(ring)));
+        Ok(wkt)
     }
 
     #[cfg(feature = "geo")]
-    pub fn resolve_surface_geo(&self, surface_id: &str) -> Result<geo::geometry::Polygon, String> {
+    pub fn resolve_surface_geo(&self, surface_id: Id) -> Result<geo::geometry::Polygon, String> {
         let Some(surface) = self.surfaces.get(surface_id) else {
-            return Err(format!("Surface id={} not found", surface_id));
+            return Err(format!(
+                "Surface id={} not found",
+                self.ids.name(surface_id)
+            ));
         };
-        let exterior = self.ring_to_geo_linestring(&surface[0])?;
-        let interiors = surface[1..]
-            .iter()
+        let exterior = self.ring_to_geo_linestring(surface.exterior())?;
+        let interiors = surface
+            .interiors()
             .map(|ring| self.ring_to_geo_linestring(ring))
             .collect::<Result<Vec<geo::geometry::LineString<f64>>, _>>()?;
         Ok(geo::geometry::Polygon::new(exterior, interiors))
     }
 
+    /// Resolve the surfaces of all the 筆 in parallel, in the order of
+    /// [`fudes`](Self::fudes).
+    #[cfg(all(feature = "geo", feature = "rayon"))]
+    pub fn par_resolve_all(&self) -> Vec<(&Fude<'_>, Result<geo::geometry::Polygon, String>)> {
+        use rayon::prelude::*;
+
+        let fudes: Vec<&Fude> = self.fudes.values().collect();
+        fudes
+            .into_par_iter()
+            .map(|fude| (fude, self.resolve_surface_geo(fude.surface_id)))
+            .collect()
+    }
+
+    /// Resolve the geometry of a 基準点, 筆界点 (a point) or 図郭 (a polygon).
+    #[cfg(feature = "geo")]
+    pub fn resolve_feature_geo(&self, feature: &Feature) -> Option<geo::Geometry> {
+        match self.resolve_point(feature.geometry_id) {
+            Some([x, y]) => Some(geo::Point::new(x, y).into()),
+            None => self
+                .resolve_surface_geo(feature.geometry_id)
+                .ok()
+                .map(Into::into),
+        }
+    }
+
+    /// Record where the 筆 come from: the source XML file, the conversion run,
+    /// and the 地図番号 of the 図郭 containing each 筆 (only when the 図郭 are
+    /// parsed, see [`ParseOptions::parse_zukaku`](crate::parser::ParseOptions)).
+    #[cfg(feature = "geo")]
+    pub fn set_provenance(&mut self, source_file: &str, run_id: &str) {
+        use geo::{Contains, InteriorPoint};
+
+        let sheets: Vec<(&str, geo::Polygon)> = self
+            .zukaku
+            .iter()
+            .filter_map(|zukaku| {
+                let map_number = zukaku.attribute("地図番号")?;
+                let polygon = self.resolve_surface_geo(zukaku.geometry_id).ok()?;
+                Some((map_number, polygon))
+            })
+            .collect();
+        let map_numbers: Vec<(String, Option<String>)> = self
+            .fudes
+            .iter()
+            .map(|(key, fude)| {
+                let map_number = match sheets.as_slice() {
+                    [] => None,
+                    [(map_number, _)] => Some(map_number.to_string()),
+                    _ => self
+                        .resolve_surface_geo(fude.surface_id)
+                        .ok()
+                        .and_then(|polygon| polygon.interior_point())
+                        .and_then(|point| {
+                            sheets
+                                .iter()
+                                .find(|(_, sheet)| sheet.contains(&point))
+                                .map(|(map_number, _)| map_number.to_string())
+                        }),
+                };
+                (key.clone(), map_number)
+            })
+            .collect();
+
+        let source_file: Arc<str> = source_file.into();
+        let run_id: Arc<str> = run_id.into();
+        for (key, map_number) in map_numbers {
+            let attributes = &mut self.fudes.get_mut(&key).unwrap().attributes;
+            attributes.source_file = Some(source_file.clone());
+            attributes.map_number = map_number;
+            attributes.run_id = Some(run_id.clone());
+        }
+    }
+
+    /// Copy the 市区町村コード and 市区町村名 of the file to its 筆, for
+    /// outputs merging many files. A code missing from the header is taken
+    /// from `fallback_code` (e.g. the beginning of the file name), and a
+    /// missing name from `table`; the header is completed with them.
+    pub fn set_municipality(
+        &mut self,
+        fallback_code: Option<&str>,
+        table: Option<&MunicipalityTable>,
+    ) {
+        let metadata = &mut self.metadata;
+        if metadata.municipality_code.is_none() {
+            metadata.municipality_code = fallback_code.map(str::to_string);
+        }
+        if metadata.municipality_name.is_none()
+            && let (Some(code), Some(table)) = (&metadata.municipality_code, table)
+        {
+            metadata.municipality_name = table.name(code).map(str::to_string);
+        }
+        let code: Option<Arc<str>> = metadata.municipality_code.as_deref().map(Arc::from);
+        let name: Option<Arc<str>> = metadata.municipality_name.as_deref().map(Arc::from);
+        for fude in self.fudes.values_mut() {
+            fude.attributes.municipality_code = code.clone();
+            fude.attributes.municipality_name = name.clone();
+        }
+    }
+
+    /// Fill the 計算地積 of the 筆: their planar area in the plane
+    /// rectangular CS of the file, in m², to compare with the registered 地積.
+    ///
+    /// Pass the `projections` of the parser when the coordinates were
+    /// converted to longitude/latitude, so that the polygons are projected
+    /// back, and `None` when they were kept (see
+    /// [`ParseOptions::keep_source_coordinates`](crate::parser::ParseOptions)).
+    /// The coordinates of arbitrary coordinate systems are used as they are.
+    #[cfg(feature = "geo")]
+    pub fn set_computed_areas(
+        &mut self,
+        projections: Option<&[ExtendedTransverseMercatorProjection; 19]>,
+    ) {
+        for (key, area) in self.planar_areas(projections) {
+            self.fudes.get_mut(&key).unwrap().attributes.computed_area = area;
+        }
+    }
+
+    /// The area of each 筆 (by key) in the plane rectangular CS of the file
+    /// (see [`set_computed_areas`](Self::set_computed_areas)), `None` when its
+    /// surface can't be resolved.
+    #[cfg(feature = "geo")]
+    pub(crate) fn planar_areas(
+        &self,
+        projections: Option<&[ExtendedTransverseMercatorProjection; 19]>,
+    ) -> Vec<(String, Option<f64>)> {
+        use geo::{Area, MapCoords};
+
+        let zone = match self.metadata.crs.as_deref().and_then(Crs::from_source) {
+            Some(Crs::Jpr(zone)) => Some(zone as usize),
+            _ => None,
+        };
+        let projection = projections
+            .zip(zone)
+            .map(|(projections, zone)| &projections[zone - 1]);
+        self.fudes
+            .iter()
+            .map(|(key, fude)| {
+                let area = self
+                    .resolve_surface_geo(fude.surface_id)
+                    .ok()
+                    .and_then(|polygon| match projection {
+                        Some(projection) => polygon
+                            .try_map_coords(|coord| {
+                                projection
+                                    .project_forward(coord.x, coord.y, 0.0)
+                                    .map(|(x, y, _)| geo::Coord { x, y })
+                                    .map_err(|_| ())
+                            })
+                            .ok(),
+                        None => Some(polygon),
+                    })
+                    .map(|polygon| polygon.unsigned_area());
+                (key.clone(), area)
+            })
+            .collect()
+    }
+
+    /// Statistics of the 筆 by 大字コード and 丁目コード (empty when
+    /// missing): their number, total area and 精度区分, e.g. for dashboards.
+    /// The areas are computed like [`set_computed_areas`](Self::set_computed_areas),
+    /// with the same `projections`; the 筆 whose surface can't be resolved
+    /// don't count in them. Merge the statistics of several files with
+    /// [`OazaStats::add`].
+    #[cfg(feature = "geo")]
+    pub fn aggregate_by_oaza(
+        &self,
+        projections: Option<&[ExtendedTransverseMercatorProjection; 19]>,
+    ) -> BTreeMap<(String, String), OazaStats> {
+        let mut stats: BTreeMap<(String, String), OazaStats> = BTreeMap::new();
+        for (key, area) in self.planar_areas(projections) {
+            let attributes = &self.fudes[&key].attributes;
+            let code = |code: &Option<Cow<str>>| code.as_deref().unwrap_or_default().to_string();
+            let oaza = stats
+                .entry((code(&attributes.oaza_code), code(&attributes.chome_code)))
+                .or_default();
+            if oaza.oaza.is_none() {
+                oaza.oaza = attributes.oaza.as_deref().map(str::to_string);
+            }
+            if oaza.chome.is_none() {
+                oaza.chome = attributes.chome.as_deref().map(str::to_string);
+            }
+            oaza.fudes += 1;
+            oaza.area += area.unwrap_or_default();
+            let class = attributes.accuracy_class.as_deref().map(str::to_string);
+            *oaza.accuracy_classes.entry(class).or_default() += 1;
+        }
+        stats
+    }
+
     #[cfg(feature = "geo")]
     fn ring_to_geo_linestring(
         &self,
-        ring: &[String],
+        ring: &[Id],
     ) -> Result<geo::geometry::LineString<f64>, String> {
         ring.iter()
             .map(|segment_id| match self.segments.get(segment_id) {
@@ -103,14 +1047,375 @@ impl ParsedData {
                         x: point[0],
                         y: point[1],
                     }),
-                    PointRef::Indirect(ref point_id) => self
+                    PointRef::Indirect(point_id) => self
                         .points
-                        .get(point_id)
+                        .get(&point_id)
                         .map(|p| geo::Coord { x: p[0], y: p[1] })
-                        .ok_or(format!("Point id={} not found", point_id)),
+                        .ok_or(format!("Point id={} not found", self.ids.name(point_id))),
                 },
-                None => Err(format!("Curve if={} not found", segment_id)),
+                None => Err(format!("Curve if={} not found", self.ids.name(*segment_id))),
             })
             .collect::<Result<geo::geometry::LineString<f64>, _>>()
     }
 }
+
+/// The points of a ring, with the first point repeated at the end.
+pub(crate) fn closed_ring(ring: &[Point]) -> impl Iterator<Item = Point> + '_ {
+    let closing = match (ring.first(), ring.last()) {
+        (Some(first), Some(last)) if first != last => Some(*first),
+        _ => None,
+    };
+    ring.iter().copied().chain(closing)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_stable_id() {
+        let attributes = FudeAttributes {
+            id: "H000000001".into(),
+            oaza_code: Some("001".into()),
+            chiban: Some("12-3".into()),
+            ..Default::default()
+        };
+        let stable_id = attributes.compute_stable_id(Some("13101"));
+        assert_eq!(stable_id.len(), 16);
+        let other = FudeAttributes {
+            id: "H000000099".into(),
+            ..attributes
+        };
+        assert_eq!(other.compute_stable_id(Some("13101")), stable_id);
+        assert_ne!(other.compute_stable_id(Some("13102")), stable_id);
+    }
+
+    #[test]
+    fn test_full_address() {
+        let attributes = FudeAttributes {
+            municipality_name: Some("千代田区".into()),
+            oaza: Some("丸の内".into()),
+            chome: Some("一丁目".into()),
+            koaza: Some(" ".into()),
+            chiban: Some("1-1".into()),
+            ..Default::default()
+        };
+        assert_eq!(attributes.full_address(), "千代田区丸の内一丁目1-1");
+        // a 大字名 starting with the 市区町村名
+        let prefixed = FudeAttributes {
+            oaza: Some("千代田区丸の内".into()),
+            ..attributes.clone()
+        };
+        assert_eq!(prefixed.full_address(), "千代田区丸の内一丁目1-1");
+        let attributes = FudeAttributes {
+            oaza: Some("大字上野".into()),
+            koaza: Some("字中島".into()),
+            chiban: Some("45".into()),
+            ..Default::default()
+        };
+        assert_eq!(attributes.full_address(), "大字上野字中島45");
+        // a 小字名 starting with the 大字名 is kept whole
+        let attributes = FudeAttributes {
+            oaza: Some("中".into()),
+            koaza: Some("中島".into()),
+            chiban: Some("3".into()),
+            ..Default::default()
+        };
+        assert_eq!(attributes.full_address(), "中中島3");
+        let attributes = FudeAttributes {
+            oaza: Some("丸の内".into()),
+            chome: Some("丸の内".into()),
+            ..Default::default()
+        };
+        assert_eq!(attributes.full_address(), "丸の内");
+        assert_eq!(FudeAttributes::default().full_address(), "");
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut chibans = ["10", "2-10", "2", "2-9", "1", "02", "2-9甲", "甲1"];
+        chibans.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            chibans,
+            ["1", "2", "02", "2-9", "2-9甲", "2-10", "10", "甲1"]
+        );
+
+        let fude = |oaza_code: &str, chiban: &str| FudeAttributes {
+            oaza_code: Some(oaza_code.to_string().into()),
+            chiban: Some(chiban.to_string().into()),
+            ..Default::default()
+        };
+        let mut fudes = [fude("002", "1"), fude("001", "10"), fude("001", "9")];
+        fudes.sort_by(FudeAttributes::natural_cmp);
+        let keys: Vec<_> = fudes
+            .iter()
+            .map(|f| {
+                (
+                    f.oaza_code.as_deref().unwrap(),
+                    f.chiban.as_deref().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(keys, [("001", "9"), ("001", "10"), ("002", "1")]);
+    }
+
+    #[cfg(feature = "geo")]
+    #[test]
+    fn test_computed_areas() {
+        use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+
+        let xml = crate::synthetic::SyntheticMap::new(1, 2)
+            .with_cell_size(10.0)
+            .to_xml();
+        let projections = jpr_projections();
+        for keep_source_coordinates in [true, false] {
+            let mut data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+                .with_options(ParseOptions {
+                    keep_source_coordinates,
+                    ..Default::default()
+                })
+                .parse()
+                .unwrap();
+            data.set_computed_areas((!keep_source_coordinates).then_some(&projections));
+            for fude in data.fudes.values() {
+                let area = fude.attributes.computed_area.unwrap();
+                assert!((area - 100.0).abs() < 1e-6, "{}", area);
+            }
+        }
+    }
+
+    #[cfg(feature = "geo")]
+    #[test]
+    fn test_aggregate_by_oaza() {
+        use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+
+        // 10 rows in 大字 001, and 2 in 002
+        let xml = crate::synthetic::SyntheticMap::new(12, 1)
+            .with_cell_size(10.0)
+            .to_xml();
+        let projections = jpr_projections();
+        let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .with_options(ParseOptions {
+                keep_source_coordinates: true,
+                ..Default::default()
+            })
+            .parse()
+            .unwrap();
+        let stats = data.aggregate_by_oaza(None);
+        assert_eq!(stats.len(), 2);
+        let oaza = &stats[&("002".to_string(), String::new())];
+        assert_eq!(oaza.oaza.as_deref(), Some("大字2"));
+        assert_eq!(oaza.chome, None);
+        assert_eq!(oaza.fudes, 2);
+        assert!((oaza.area - 200.0).abs() < 1e-6);
+        assert_eq!(
+            oaza.accuracy_classes,
+            BTreeMap::from([(Some("甲二".to_string()), 2)])
+        );
+
+        let mut total = OazaStats::default();
+        for oaza in stats.values() {
+            total.add(oaza);
+        }
+        assert_eq!(total.fudes, 12);
+        assert_eq!(total.oaza.as_deref(), Some("大字1"));
+    }
+
+    #[test]
+    fn test_find_fude() {
+        let fude = |id: &str, oaza_code: Option<&str>, chome_code: &str, chiban: &str| {
+            let attributes = FudeAttributes {
+                id: id.to_string().into(),
+                oaza_code: oaza_code.map(|code| code.to_string().into()),
+                chome_code: Some(chome_code.to_string().into()),
+                chiban: Some(chiban.to_string().into()),
+                ..Default::default()
+            };
+            let surface_id = Id::default();
+            (
+                id.to_string(),
+                Fude {
+                    attributes,
+                    surface_id,
+                },
+            )
+        };
+        let mut data = ParsedData {
+            fudes: HashMap::from([
+                fude("H3", Some("001"), "02", "1"),
+                fude("H1", Some("001"), "01", "1"),
+                fude("H2", Some("001"), "01", "2"),
+                fude("H4", None, "01", "1"),
+            ]),
+            ..Default::default()
+        };
+        let id = |fude: Option<&Fude>| fude.map(|fude| fude.attributes.id.to_string());
+        assert_eq!(id(data.find_fude("001", "1")).as_deref(), Some("H1"));
+        let ids: Vec<_> = data
+            .find_fudes("001", "1")
+            .map(|fude| fude.attributes.id.as_ref())
+            .collect();
+        assert_eq!(ids, ["H1", "H3"]);
+        assert_eq!(id(data.find_fude("", "1")).as_deref(), Some("H4"));
+        assert!(data.find_fude("002", "1").is_none());
+
+        data.fudes.remove("H1");
+        data.reset_fude_index();
+        assert_eq!(id(data.find_fude("001", "1")).as_deref(), Some("H3"));
+    }
+
+    #[test]
+    fn test_merge() {
+        use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+        use crate::synthetic::SyntheticMap;
+
+        let projections = jpr_projections();
+        let options = ParseOptions {
+            keep_source_coordinates: true,
+            ..Default::default()
+        };
+        let documents = [
+            SyntheticMap::new(2, 2).to_xml(),
+            SyntheticMap::new(1, 3).to_xml(),
+        ];
+        let files: Vec<_> = documents
+            .iter()
+            .map(|xml| {
+                MojxmlParser::from_slice(xml.as_bytes(), &projections)
+                    .with_options(options.clone())
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        let expected: Vec<_> = files
+            .iter()
+            .map(|data| data.resolve_surface(data.fudes["H1"].surface_id).unwrap())
+            .collect();
+
+        let mut merged = ParsedData::default();
+        for (i, data) in files.into_iter().enumerate() {
+            merged.merge(&format!("sheet{}", i), data);
+        }
+        assert_eq!(merged.metadata.crs.as_deref(), Some("公共座標9系"));
+        assert_eq!(merged.fudes.len(), 4 + 3);
+        assert_eq!(merged.surfaces.len(), 4 + 3);
+        assert_eq!(merged.points.len(), 3 * 3 + 2 * 4);
+        for (i, rings) in expected.iter().enumerate() {
+            let fude = &merged.fudes[&format!("sheet{}:H1", i)];
+            assert_eq!(fude.attributes.id, format!("sheet{}:H1", i));
+            assert_eq!(&merged.resolve_surface(fude.surface_id).unwrap(), rings);
+        }
+        assert_eq!(merged.find_fudes("001", "1").count(), 2);
+    }
+
+    #[test]
+    fn test_ids() {
+        let mut ids = Ids::default();
+        let a = ids.intern("P1");
+        let b = ids.intern("C1");
+        assert_ne!(a, b);
+        assert_eq!(ids.intern("P1"), a);
+        assert_eq!(ids.get("C1"), Some(b));
+        assert_eq!(ids.get("S1"), None);
+        assert_eq!(ids.name(b), "C1");
+        assert_eq!(ids.len(), 2);
+    }
+
+    #[test]
+    fn test_surfaces() {
+        let mut ids = Ids::default();
+        let [c1, c2, c3, c4, c5, s1, s2] =
+            ["C1", "C2", "C3", "C4", "C5", "S1", "S2"].map(|id| ids.intern(id));
+        let mut surfaces = Surfaces::from_iter([(s1, [vec![c1, c2]])]);
+        // an interior ring before the exterior one
+        surfaces.push_curve(c3);
+        surfaces.end_ring();
+        surfaces.push_curve(c4);
+        surfaces.push_curve(c5);
+        surfaces.end_ring();
+        surfaces.finish_surface(s2, 1);
+
+        assert_eq!(surfaces.len(), 2);
+        assert_eq!(surfaces.get(s1).unwrap().exterior(), [c1, c2]);
+        let surface = surfaces.get(s2).unwrap();
+        assert_eq!(surface.len(), 2);
+        assert_eq!(surface.exterior(), [c4, c5]);
+        assert_eq!(surface.interiors().collect::<Vec<_>>(), [[c3]]);
+        assert!(surfaces.get(c1).is_none());
+
+        // without an exterior ring, there is no surface to resolve
+        surfaces.insert(c1, Vec::<Vec<Id>>::new());
+        assert!(surfaces.get(c1).is_none());
+        assert_eq!(surfaces.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_surface_wkt_wkb() {
+        let mut ids = Ids::default();
+        let [p1, c1, c2, c3, s1, s2] =
+            ["P1", "C1", "C2", "C3", "S1", "S2"].map(|id| ids.intern(id));
+        let data = ParsedData {
+            points: HashMap::from([(p1, [139.0, 35.0])]),
+            segments: HashMap::from([
+                (
+                    c1,
+                    [PointRef::Indirect(p1), PointRef::Direct([139.5, 35.0])],
+                ),
+                (
+                    c2,
+                    [
+                        PointRef::Direct([139.5, 35.0]),
+                        PointRef::Direct([139.5, 35.5]),
+                    ],
+                ),
+                (
+                    c3,
+                    [PointRef::Direct([139.5, 35.5]), PointRef::Indirect(p1)],
+                ),
+            ]),
+            surfaces: Surfaces::from_iter([(s1, [[c1, c2, c3]])]),
+            fudes: [("H1", s1), ("H2", s2)]
+                .into_iter()
+                .map(|(id, surface_id)| {
+                    let attributes = FudeAttributes {
+                        id: id.into(),
+                        ..Default::default()
+                    };
+                    (
+                        id.to_string(),
+                        Fude {
+                            attributes,
+                            surface_id,
+                        },
+                    )
+                })
+                .collect(),
+            ids,
+            ..Default::default()
+        };
+        assert_eq!(
+            data.resolve_surface_wkt(s1).unwrap(),
+            "POLYGON ((139 35, 139.5 35, 139.5 35.5, 139 35))"
+        );
+        let wkb = data.resolve_surface_wkb(s1).unwrap();
+        // byte order + type + ring count + point count + 4 points
+        assert_eq!(wkb.len(), 1 + 4 + 4 + 4 + 4 * 16);
+        assert_eq!(&wkb[..5], &[1, 3, 0, 0, 0]);
+        assert_eq!(
+            data.resolve_surface_wkb(s2).unwrap_err(),
+            "Surface id=S2 not found"
+        );
+
+        #[cfg(all(feature = "geo", feature = "rayon"))]
+        {
+            let mut resolved: Vec<_> = data
+                .par_resolve_all()
+                .into_iter()
+                .map(|(fude, polygon)| (fude.attributes.id.as_ref(), polygon.is_ok()))
+                .collect();
+            resolved.sort();
+            assert_eq!(resolved, [("H1", true), ("H2", false)]);
+        }
+    }
+}
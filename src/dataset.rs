@@ -0,0 +1,266 @@
+//! In-memory index of the 筆 of one or more files, for lookups by position
+//! and by 地番, e.g. in a lightweight parcel-lookup service.
+
+use geo::{BoundingRect, Intersects, Point, Polygon, Rect};
+use hashbrown::HashMap;
+
+use crate::data::{FudeAttributes, ParsedData};
+use crate::dedup::dedup_key;
+
+/// Default size of the cells of the spatial index, for longitude/latitude
+/// (about 100 m).
+pub const DEFAULT_CELL_SIZE: f64 = 0.001;
+
+/// The 筆 spanning more cells than this (on either axis) are kept out of the
+/// grid, and checked by every query.
+const MAX_CELLS: i64 = 64;
+
+/// A 筆 of a [`Dataset`].
+#[derive(Clone, Debug)]
+pub struct Parcel {
+    /// Name of the source file
+    pub source: String,
+    /// 市区町村コード of the file
+    pub municipality_code: Option<String>,
+    pub attributes: FudeAttributes<'static>,
+    pub polygon: Polygon,
+    bbox: Rect,
+}
+
+/// The 筆 of one or more files, indexed by position (on a grid) and by
+/// 市区町村コード, 大字コード, 丁目コード and 地番.
+///
+/// The 筆 of every file are kept, so a 筆 at the boundary of two sheets may
+/// be found twice (see [`dedup`](crate::dedup) to remove them beforehand).
+pub struct Dataset {
+    parcels: Vec<Parcel>,
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    /// The 筆 too large for the grid
+    large: Vec<usize>,
+    chibans: HashMap<[String; 4], Vec<usize>>,
+}
+
+impl Default for Dataset {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dataset {
+    pub fn new() -> Self {
+        Self {
+            parcels: Vec::new(),
+            cell_size: DEFAULT_CELL_SIZE,
+            cells: HashMap::new(),
+            large: Vec::new(),
+            chibans: HashMap::new(),
+        }
+    }
+
+    /// Set the size of the cells of the spatial index, in the units of the
+    /// coordinates (e.g. 100 for meters, with
+    /// [`ParseOptions::keep_source_coordinates`](crate::parser::ParseOptions)).
+    /// Only takes effect before the first [`insert`](Self::insert).
+    pub fn with_cell_size(mut self, cell_size: f64) -> Self {
+        if self.parcels.is_empty() && cell_size > 0.0 {
+            self.cell_size = cell_size;
+        }
+        self
+    }
+
+    /// Add the 筆 of a file named `source`. The 筆 whose surface can't be
+    /// resolved are left out.
+    pub fn insert(&mut self, source: &str, mut data: ParsedData<'static>) {
+        let municipality_code = data.metadata.municipality_code.clone();
+        let mut fudes: Vec<_> = std::mem::take(&mut data.fudes).into_values().collect();
+        fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+        for fude in fudes {
+            let Ok(polygon) = data.resolve_surface_geo(fude.surface_id) else {
+                continue;
+            };
+            let Some(bbox) = polygon.bounding_rect() else {
+                continue;
+            };
+            let index = self.parcels.len();
+            if let Some(key) = dedup_key(municipality_code.as_deref(), &fude.attributes) {
+                self.chibans.entry(key).or_default().push(index);
+            }
+            let ([x0, y0], [x1, y1]) = self.cell_range(bbox);
+            if x1 - x0 >= MAX_CELLS || y1 - y0 >= MAX_CELLS {
+                self.large.push(index);
+            } else {
+                for x in x0..=x1 {
+                    for y in y0..=y1 {
+                        self.cells.entry((x, y)).or_default().push(index);
+                    }
+                }
+            }
+            self.parcels.push(Parcel {
+                source: source.to_string(),
+                municipality_code: municipality_code.clone(),
+                attributes: fude.attributes,
+                polygon,
+                bbox,
+            });
+        }
+    }
+
+    fn cell_range(&self, rect: Rect) -> ([i64; 2], [i64; 2]) {
+        let cell = |x: f64| (x / self.cell_size).floor() as i64;
+        (
+            [cell(rect.min().x), cell(rect.min().y)],
+            [cell(rect.max().x), cell(rect.max().y)],
+        )
+    }
+
+    /// The 筆 containing a point, or with the point on their boundary (then
+    /// usually with their neighbors).
+    pub fn query_point(&self, x: f64, y: f64) -> Vec<&Parcel> {
+        let point = Point::new(x, y);
+        self.query_bbox(Rect::new(point.0, point.0))
+    }
+
+    /// The 筆 intersecting a rectangle, in the order they were inserted.
+    pub fn query_bbox(&self, rect: Rect) -> Vec<&Parcel> {
+        let ([x0, y0], [x1, y1]) = self.cell_range(rect);
+        let mut candidates: Vec<usize> = self.large.clone();
+        if (x1 - x0 + 1).saturating_mul(y1 - y0 + 1) > self.cells.len() as i64 {
+            // more cells than indexed: scan the indexed ones instead
+            candidates.extend(self.cells.iter().flat_map(|(_, indices)| indices));
+        } else {
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    if let Some(indices) = self.cells.get(&(x, y)) {
+                        candidates.extend(indices);
+                    }
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .map(|index| &self.parcels[index])
+            .filter(|parcel| parcel.bbox.intersects(&rect) && parcel.polygon.intersects(&rect))
+            .collect()
+    }
+
+    /// The 筆 with a 地番, in a municipality, 大字 and 丁目 (an empty
+    /// 丁目コード for the 大字 without 丁目). Usually one, but a 地番 is
+    /// sometimes duplicated (see [`ChibanCounter`](crate::dedup::ChibanCounter)).
+    pub fn get_by_chiban(
+        &self,
+        municipality_code: &str,
+        oaza_code: &str,
+        chome_code: &str,
+        chiban: &str,
+    ) -> Vec<&Parcel> {
+        let key = [municipality_code, oaza_code, chome_code, chiban].map(str::to_string);
+        self.chibans
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.parcels[index])
+            .collect()
+    }
+
+    /// All the 筆, in the order they were inserted.
+    pub fn parcels(&self) -> &[Parcel] {
+        &self.parcels
+    }
+
+    pub fn len(&self) -> usize {
+        self.parcels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parcels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{Fude, Ids, MapMetadata, PointRef};
+    use test_log::test;
+
+    /// A file with one square 筆 per 地番, at the given lower-left corners.
+    fn data(squares: &[(&str, f64, f64, f64)]) -> ParsedData<'static> {
+        let mut data = ParsedData {
+            metadata: MapMetadata {
+                municipality_code: Some("13101".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut ids = Ids::default();
+        for (i, &(chiban, x, y, size)) in squares.iter().enumerate() {
+            let corners = [[x, y], [x + size, y], [x + size, y + size], [x, y + size]];
+            let curves: Vec<_> = (0..4)
+                .map(|j| {
+                    let curve = ids.intern(format!("C{}_{}", i, j));
+                    let (a, b) = (corners[j], corners[(j + 1) % 4]);
+                    data.segments
+                        .insert(curve, [PointRef::Direct(a), PointRef::Direct(b)]);
+                    curve
+                })
+                .collect();
+            let surface = ids.intern(format!("S{}", i));
+            data.surfaces.insert(surface, vec![curves]);
+            let fude = Fude {
+                attributes: FudeAttributes {
+                    id: format!("H{}", i).into(),
+                    oaza_code: Some("001".into()),
+                    chiban: Some(chiban.to_string().into()),
+                    ..Default::default()
+                },
+                surface_id: surface,
+            };
+            data.fudes.insert(format!("H{}", i), fude);
+        }
+        data.ids = ids;
+        data
+    }
+
+    #[test]
+    fn test_dataset() {
+        let mut dataset = Dataset::new().with_cell_size(1.0);
+        dataset.insert(
+            "a.xml",
+            data(&[
+                ("1", 0.0, 0.0, 1.0),
+                ("2", 1.0, 0.0, 1.0),
+                ("3", 10.0, 10.0, 100.0),
+            ]),
+        );
+        dataset.insert("b.xml", data(&[("1", 5.0, 0.0, 0.5)]));
+        assert_eq!(dataset.len(), 4);
+
+        let chibans = |parcels: Vec<&Parcel>| -> Vec<String> {
+            parcels
+                .iter()
+                .map(|parcel| {
+                    format!(
+                        "{}:{}",
+                        parcel.source,
+                        parcel.attributes.chiban.as_deref().unwrap()
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(chibans(dataset.query_point(0.5, 0.5)), ["a.xml:1"]);
+        assert_eq!(
+            chibans(dataset.query_point(1.0, 0.5)),
+            ["a.xml:1", "a.xml:2"]
+        );
+        assert_eq!(chibans(dataset.query_point(50.0, 50.0)), ["a.xml:3"]);
+        assert!(dataset.query_point(3.0, 0.5).is_empty());
+        let rect = Rect::new((1.5, 0.2), (5.2, 0.3));
+        assert_eq!(chibans(dataset.query_bbox(rect)), ["a.xml:2", "b.xml:1"]);
+
+        let parcels = dataset.get_by_chiban("13101", "001", "", "1");
+        assert_eq!(chibans(parcels), ["a.xml:1", "b.xml:1"]);
+        assert!(dataset.get_by_chiban("13101", "002", "", "1").is_empty());
+    }
+}
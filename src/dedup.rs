@@ -0,0 +1,265 @@
+//! Removal of the 筆 that appear in more than one file.
+//!
+//! Sheets of a municipality overlap at their boundaries, and a 筆 on the
+//! boundary is sometimes in both XML files. [`Deduplicator`] collects the 筆
+//! of all the files and keeps one of each duplicate group.
+
+use geo::{Area, BooleanOps, Polygon};
+use hashbrown::HashMap;
+
+use crate::data::FudeAttributes;
+
+/// Which 筆 of a duplicate group to keep.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Keep {
+    /// The one of the first file, in the order of the file names
+    #[default]
+    First,
+    /// The one of the last file, in the order of the file names
+    Last,
+    /// The one with the largest area
+    Largest,
+}
+
+/// The key of the duplicates: 市区町村コード, 大字コード, 丁目コード and 地番.
+///
+/// `None` for a 筆 without 地番, which is never a duplicate.
+pub fn dedup_key(
+    municipality_code: Option<&str>,
+    attributes: &FudeAttributes,
+) -> Option<[String; 4]> {
+    let chiban = attributes.chiban.as_deref()?;
+    Some([
+        municipality_code.unwrap_or_default().to_string(),
+        attributes
+            .oaza_code
+            .as_deref()
+            .unwrap_or_default()
+            .to_string(),
+        attributes
+            .chome_code
+            .as_deref()
+            .unwrap_or_default()
+            .to_string(),
+        chiban.to_string(),
+    ])
+}
+
+struct Entry<T> {
+    source: String,
+    seq: usize,
+    area: f64,
+    polygon: Polygon,
+    item: T,
+}
+
+/// Collects 筆 (with any payload `T`) and removes the duplicates: 筆 of
+/// different files with the same key.
+pub struct Deduplicator<T> {
+    keep: Keep,
+    min_overlap: Option<f64>,
+    groups: HashMap<[String; 4], Vec<Vec<Entry<T>>>>,
+    unique: Vec<(Polygon, T)>,
+    seq: usize,
+}
+
+impl<T> Deduplicator<T> {
+    pub fn new(keep: Keep) -> Self {
+        Self {
+            keep,
+            min_overlap: None,
+            groups: HashMap::new(),
+            unique: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    /// Only treat 筆 with the same key as duplicates when their intersection
+    /// covers at least this ratio (0 to 1) of the smaller one.
+    pub fn with_min_overlap(mut self, ratio: f64) -> Self {
+        self.min_overlap = Some(ratio);
+        self
+    }
+
+    /// Add a 筆 of the file `source`, with its key (see [`dedup_key`]).
+    pub fn insert(&mut self, key: Option<[String; 4]>, source: &str, polygon: Polygon, item: T) {
+        let Some(key) = key else {
+            self.unique.push((polygon, item));
+            return;
+        };
+        let entry = Entry {
+            source: source.to_string(),
+            seq: self.seq,
+            area: polygon.unsigned_area(),
+            polygon,
+            item,
+        };
+        self.seq += 1;
+        // the 筆 of a file are distinct, even with the same key (see
+        // [`ChibanCounter`]), so a cluster has at most one 筆 of each file
+        let clusters = self.groups.entry(key).or_default();
+        let cluster = clusters.iter_mut().find(|cluster| {
+            cluster.iter().all(|other| other.source != entry.source)
+                && (self.min_overlap).is_none_or(|ratio| overlaps(&cluster[0], &entry, ratio))
+        });
+        match cluster {
+            Some(cluster) => cluster.push(entry),
+            None => clusters.push(vec![entry]),
+        }
+    }
+
+    /// The kept 筆, and the number of removed duplicates.
+    pub fn finish(self) -> (Vec<(Polygon, T)>, usize) {
+        let mut kept = self.unique;
+        let mut removed = 0;
+        for cluster in self.groups.into_values().flatten() {
+            removed += cluster.len() - 1;
+            let order = |e: &Entry<T>| (e.source.clone(), e.seq);
+            let entry = match self.keep {
+                Keep::First => cluster.into_iter().min_by_key(order),
+                Keep::Last => cluster.into_iter().max_by_key(order),
+                Keep::Largest => cluster
+                    .into_iter()
+                    .max_by(|a, b| a.area.total_cmp(&b.area).then(order(b).cmp(&order(a)))),
+            };
+            if let Some(entry) = entry {
+                kept.push((entry.polygon, entry.item));
+            }
+        }
+        (kept, removed)
+    }
+}
+
+/// What to do with the 筆 whose 地番 is a duplicate in its municipality
+/// (see [`ChibanCounter`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateChiban {
+    /// Keep them, with a warning
+    #[default]
+    Warn,
+    /// Append a suffix to their 地番 (see [`suffixed_chiban`])
+    Suffix,
+    /// Keep them silently (they are still counted)
+    KeepAll,
+}
+
+/// Counts the 筆 by key (see [`dedup_key`]) as they are written, to find the
+/// 地番 that are duplicates in a municipality, which break the joins with
+/// other data such as tax rolls.
+///
+/// Unlike [`Deduplicator`], it doesn't compare the geometries, and keeps the
+/// 筆 in the same file.
+#[derive(Debug, Default)]
+pub struct ChibanCounter {
+    counts: HashMap<[String; 4], usize>,
+}
+
+impl ChibanCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a 筆, and return the number of 筆 with the same key counted
+    /// before it: 0 for the first one, or for a 筆 without 地番.
+    pub fn count(&mut self, key: Option<[String; 4]>) -> usize {
+        let Some(key) = key else {
+            return 0;
+        };
+        let count = self.counts.entry(key).or_default();
+        *count += 1;
+        *count - 1
+    }
+
+    /// The keys counted more than once, with their number of 筆, sorted.
+    pub fn duplicates(&self) -> Vec<([String; 4], usize)> {
+        let mut duplicates: Vec<_> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        duplicates.sort();
+        duplicates
+    }
+}
+
+/// The 地番 of a duplicate, the `n`th after the first one: `12-3_2` for the
+/// first duplicate of `12-3`.
+pub fn suffixed_chiban(chiban: &str, n: usize) -> String {
+    format!("{}_{}", chiban, n + 1)
+}
+
+fn overlaps<T>(a: &Entry<T>, b: &Entry<T>, ratio: f64) -> bool {
+    let smaller = a.area.min(b.area);
+    if smaller == 0.0 {
+        return false;
+    }
+    a.polygon.intersection(&b.polygon).unsigned_area() >= ratio * smaller
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::{Rect, coord};
+    use test_log::test;
+
+    fn square(x: f64, size: f64) -> Polygon {
+        Rect::new(coord! { x: x, y: 0.0 }, coord! { x: x + size, y: size }).to_polygon()
+    }
+
+    fn key(chiban: &str) -> Option<[String; 4]> {
+        let attributes = FudeAttributes {
+            oaza_code: Some("001".into()),
+            chiban: Some(chiban.into()),
+            ..Default::default()
+        };
+        dedup_key(Some("13101"), &attributes)
+    }
+
+    /// Add the test 筆, and return the kept ones.
+    fn run(mut dedup: Deduplicator<&'static str>) -> (Vec<&'static str>, usize) {
+        dedup.insert(key("1"), "b.xml", square(0.0, 2.0), "b1");
+        dedup.insert(key("1"), "a.xml", square(0.5, 1.0), "a1");
+        dedup.insert(key("1"), "c.xml", square(10.0, 1.0), "c1");
+        dedup.insert(key("2"), "a.xml", square(3.0, 1.0), "a2");
+        dedup.insert(None, "a.xml", square(3.0, 1.0), "a-");
+        dedup.insert(None, "b.xml", square(3.0, 1.0), "b-");
+        let (kept, removed) = dedup.finish();
+        let mut items: Vec<_> = kept.into_iter().map(|(_, item)| item).collect();
+        items.sort();
+        (items, removed)
+    }
+
+    #[test]
+    fn test_dedup() {
+        let kept = run(Deduplicator::new(Keep::First));
+        assert_eq!(kept, (vec!["a-", "a1", "a2", "b-"], 2));
+        let kept = run(Deduplicator::new(Keep::Last));
+        assert_eq!(kept, (vec!["a-", "a2", "b-", "c1"], 2));
+        // c1 doesn't overlap the others, so it's another 筆
+        let kept = run(Deduplicator::new(Keep::Largest).with_min_overlap(0.5));
+        assert_eq!(kept, (vec!["a-", "a2", "b-", "b1", "c1"], 1));
+
+        // the 筆 of a file are never duplicates of each other
+        let mut dedup = Deduplicator::new(Keep::Last);
+        dedup.insert(key("3"), "a.xml", square(0.0, 1.0), "a3");
+        dedup.insert(key("3"), "a.xml", square(5.0, 1.0), "a3'");
+        dedup.insert(key("3"), "b.xml", square(0.0, 1.0), "b3");
+        let (kept, removed) = dedup.finish();
+        let mut items: Vec<_> = kept.into_iter().map(|(_, item)| item).collect();
+        items.sort();
+        assert_eq!((items, removed), (vec!["a3'", "b3"], 1));
+    }
+
+    #[test]
+    fn test_chiban_counter() {
+        let mut counter = ChibanCounter::new();
+        let counts: Vec<_> = [key("1"), key("2"), key("1"), None, key("1"), None]
+            .into_iter()
+            .map(|key| counter.count(key))
+            .collect();
+        assert_eq!(counts, [0, 0, 1, 0, 2, 0]);
+        assert_eq!(counter.duplicates(), [(key("1").unwrap(), 3)]);
+        assert_eq!(suffixed_chiban("12-3", 1), "12-3_2");
+    }
+}
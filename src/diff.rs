@@ -0,0 +1,352 @@
+//! Differences between two versions of the 筆 of a municipality, e.g.
+//! between the monthly updates of the registry.
+//!
+//! The 筆 are matched by their 市区町村コード, 大字コード, 丁目コード and
+//! 地番 (see [`dedup_key`]); their ids are numbered per file, so they can't
+//! be compared.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+
+use geo::orient::{Direction, Orient};
+use geo::{Area, BooleanOps, GeodesicArea, LineString, MultiPolygon, Polygon};
+
+use crate::data::ParsedData;
+use crate::dedup::dedup_key;
+use crate::writer::FUDE_COLUMNS;
+
+/// 市区町村コード, 大字コード, 丁目コード and 地番.
+pub type Key = [String; 4];
+
+/// The compared attributes, besides those of the key.
+const COLUMNS: [&str; 8] = [
+    "小字コード",
+    "予備コード",
+    "大字名",
+    "丁目名",
+    "小字名",
+    "予備名",
+    "精度区分",
+    "座標値種別",
+];
+
+/// A 筆 of one version.
+struct Parcel {
+    source: String,
+    attributes: [Option<String>; COLUMNS.len()],
+    /// Empty when the surface can't be resolved
+    polygon: Polygon,
+    /// In longitude/latitude, rather than in a 任意座標系
+    geographic: bool,
+}
+
+impl Parcel {
+    fn area(&self) -> f64 {
+        area(&self.polygon, self.geographic)
+    }
+}
+
+fn area<A: Area<f64> + GeodesicArea<f64> + Orient>(geometry: &A, geographic: bool) -> f64 {
+    match geographic {
+        // the geodesic area of a clockwise ring is the rest of the ellipsoid
+        true => geometry.orient(Direction::Default).geodesic_area_unsigned(),
+        false => geometry.unsigned_area(),
+    }
+}
+
+/// The 筆 of one version, from any number of files.
+///
+/// The files must be parsed with the default options, i.e. in
+/// longitude/latitude. 筆 without 地番 are left out. When a 筆 is in several
+/// files (at the boundaries of the sheets), the one of the first file in the
+/// order of the names is kept.
+#[derive(Default)]
+pub struct Parcels {
+    parcels: BTreeMap<Key, Parcel>,
+}
+
+impl Parcels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the 筆 of a file named `source`.
+    pub fn insert(&mut self, source: &str, data: &ParsedData) {
+        let municipality_code = data.metadata.municipality_code.as_deref();
+        let geographic = data.metadata.crs.as_deref() != Some("任意座標系");
+        for fude in data.fudes.values() {
+            let Some(key) = dedup_key(municipality_code, &fude.attributes) else {
+                continue;
+            };
+            let attributes = COLUMNS.map(|name| {
+                FUDE_COLUMNS
+                    .iter()
+                    .find(|(column, _)| *column == name)
+                    .and_then(|(_, get)| get(&fude.attributes))
+                    .map(str::to_string)
+            });
+            let parcel = Parcel {
+                source: source.to_string(),
+                attributes,
+                polygon: data
+                    .resolve_surface_geo(fude.surface_id)
+                    .unwrap_or_else(|_| Polygon::new(LineString::new(vec![]), vec![])),
+                geographic,
+            };
+            self.insert_parcel(key, parcel);
+        }
+    }
+
+    /// Move the 筆 of `other` into this one, e.g. to merge the 筆 of files
+    /// parsed in parallel.
+    pub fn append(&mut self, other: Parcels) {
+        for (key, parcel) in other.parcels {
+            self.insert_parcel(key, parcel);
+        }
+    }
+
+    fn insert_parcel(&mut self, key: Key, parcel: Parcel) {
+        match self.parcels.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(parcel);
+            }
+            Entry::Occupied(mut entry) => {
+                if parcel.source < entry.get().source {
+                    entry.insert(parcel);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parcels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parcels.is_empty()
+    }
+}
+
+/// A changed attribute of a 筆.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttributeChange {
+    pub name: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A changed geometry of a 筆. The areas are in m², or in the squared unit
+/// of a 任意座標系.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeometryChange {
+    pub old_area: f64,
+    pub new_area: f64,
+    /// Area of the symmetric difference of the old and new polygons; `None`
+    /// when one of them is in a 任意座標系 and the other isn't.
+    pub changed_area: Option<f64>,
+}
+
+/// A difference between the two versions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added(Key),
+    Removed(Key),
+    Modified {
+        key: Key,
+        attributes: Vec<AttributeChange>,
+        /// `None` if the polygons are the same
+        geometry: Option<GeometryChange>,
+    },
+}
+
+impl Change {
+    pub fn key(&self) -> &Key {
+        match self {
+            Change::Added(key) | Change::Removed(key) => key,
+            Change::Modified { key, .. } => key,
+        }
+    }
+}
+
+/// The differences between two versions, in the order of the keys.
+///
+/// Polygons whose symmetric difference is at most `min_area` (m²) are
+/// considered the same, e.g. when their rings start at another vertex.
+pub fn diff_parcels(old: &Parcels, new: &Parcels, min_area: f64) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut old_iter = old.parcels.iter().peekable();
+    let mut new_iter = new.parcels.iter().peekable();
+    loop {
+        let ordering = match (old_iter.peek(), new_iter.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key),
+        };
+        match ordering {
+            Ordering::Less => {
+                let (key, _) = old_iter.next().unwrap();
+                changes.push(Change::Removed(key.clone()));
+            }
+            Ordering::Greater => {
+                let (key, _) = new_iter.next().unwrap();
+                changes.push(Change::Added(key.clone()));
+            }
+            Ordering::Equal => {
+                let (key, old) = old_iter.next().unwrap();
+                let (_, new) = new_iter.next().unwrap();
+                if let Some(change) = compare(key, old, new, min_area) {
+                    changes.push(change);
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// The differences between two versions of a single file.
+pub fn diff(old: &ParsedData, new: &ParsedData, min_area: f64) -> Vec<Change> {
+    let mut old_parcels = Parcels::new();
+    old_parcels.insert("", old);
+    let mut new_parcels = Parcels::new();
+    new_parcels.insert("", new);
+    diff_parcels(&old_parcels, &new_parcels, min_area)
+}
+
+fn compare(key: &Key, old: &Parcel, new: &Parcel, min_area: f64) -> Option<Change> {
+    let attributes: Vec<_> = COLUMNS
+        .iter()
+        .zip(old.attributes.iter().zip(&new.attributes))
+        .filter(|(_, (old, new))| old != new)
+        .map(|(name, (old, new))| AttributeChange {
+            name,
+            old: old.clone(),
+            new: new.clone(),
+        })
+        .collect();
+
+    let geometry = if old.polygon == new.polygon && old.geographic == new.geographic {
+        None
+    } else if old.geographic != new.geographic {
+        Some(GeometryChange {
+            old_area: old.area(),
+            new_area: new.area(),
+            changed_area: None,
+        })
+    } else {
+        let xor: MultiPolygon = old.polygon.xor(&new.polygon);
+        let changed_area = area(&xor, old.geographic);
+        (changed_area > min_area).then(|| GeometryChange {
+            old_area: old.area(),
+            new_area: new.area(),
+            changed_area: Some(changed_area),
+        })
+    };
+
+    if attributes.is_empty() && geometry.is_none() {
+        return None;
+    }
+    Some(Change::Modified {
+        key: key.clone(),
+        attributes,
+        geometry,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{Fude, FudeAttributes, MapMetadata, PointRef};
+    use test_log::test;
+
+    /// A file of square 筆 given as (地番, 精度区分, x, size) in arbitrary
+    /// coordinates.
+    fn data(fudes: &[(&str, &str, f64, f64)]) -> ParsedData<'static> {
+        let mut data = ParsedData {
+            metadata: MapMetadata {
+                municipality_code: Some("13101".to_string()),
+                crs: Some("任意座標系".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for (i, &(chiban, accuracy_class, x, size)) in fudes.iter().enumerate() {
+            let corners = [[x, 0.0], [x + size, 0.0], [x + size, size], [x, size]];
+            let curves: Vec<_> = (0..4)
+                .map(|j| {
+                    let id = data.ids.intern(format!("C{}-{}", i, j));
+                    data.segments.insert(
+                        id,
+                        [
+                            PointRef::Direct(corners[j]),
+                            PointRef::Direct(corners[(j + 1) % 4]),
+                        ],
+                    );
+                    id
+                })
+                .collect();
+            let surface_id = data.ids.intern(format!("S{}", i));
+            data.surfaces.insert(surface_id, [curves]);
+            let attributes = FudeAttributes {
+                id: format!("H{}", i).into(),
+                oaza_code: Some("001".into()),
+                chiban: Some(chiban.to_string().into()),
+                accuracy_class: Some(accuracy_class.to_string().into()),
+                ..Default::default()
+            };
+            data.fudes.insert(
+                format!("H{}", i),
+                Fude {
+                    attributes,
+                    surface_id,
+                },
+            );
+        }
+        data
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = data(&[
+            ("1", "甲二", 0.0, 10.0),
+            ("2", "甲二", 20.0, 10.0),
+            ("3", "甲二", 40.0, 10.0),
+            ("4", "甲二", 60.0, 10.0),
+        ]);
+        let new = data(&[
+            ("2", "甲一", 20.0, 10.0),
+            ("3", "甲二", 40.0, 12.0),
+            ("4", "甲二", 60.0, 10.0),
+            ("5", "甲二", 80.0, 10.0),
+        ]);
+        let key = |chiban: &str| ["13101", "001", "", chiban].map(str::to_string);
+        assert_eq!(
+            diff(&old, &new, 0.01),
+            vec![
+                Change::Removed(key("1")),
+                Change::Modified {
+                    key: key("2"),
+                    attributes: vec![AttributeChange {
+                        name: "精度区分",
+                        old: Some("甲二".to_string()),
+                        new: Some("甲一".to_string()),
+                    }],
+                    geometry: None,
+                },
+                Change::Modified {
+                    key: key("3"),
+                    attributes: vec![],
+                    geometry: Some(GeometryChange {
+                        old_area: 100.0,
+                        new_area: 144.0,
+                        changed_area: Some(44.0),
+                    }),
+                },
+                Change::Added(key("5")),
+            ]
+        );
+        // below the threshold
+        assert_eq!(diff(&old, &new, 50.0).len(), 3);
+    }
+}
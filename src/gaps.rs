@@ -0,0 +1,125 @@
+//! Detection of the gaps (voids) between the 筆 of a 大字.
+//!
+//! The 筆 of a 大字 should cover its area without leaving any land
+//! unclaimed. [`GapFinder`] collects the 筆 of one or more files by 大字,
+//! dissolves them, and reports the holes of the dissolved boundary that no 筆
+//! covers.
+
+use geo::{Area, BooleanOps, MultiPolygon, Polygon};
+use hashbrown::HashMap;
+
+/// A hole in the dissolved 筆 of a 大字.
+#[derive(Clone, Debug)]
+pub struct Gap {
+    /// The key of the 大字, as given to [`GapFinder::insert`]
+    pub key: String,
+    pub polygon: Polygon,
+    /// In the units of the coordinates (m² in a plane rectangular CS)
+    pub area: f64,
+}
+
+/// Collects the 筆 of each 大字 and finds the gaps between them.
+#[derive(Default)]
+pub struct GapFinder {
+    oazas: HashMap<String, Vec<Polygon>>,
+}
+
+impl GapFinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a 筆 of the 大字 `key` (e.g. its 市区町村コード and 大字コード, as
+    /// the 大字コード are only unique in a municipality).
+    pub fn insert(&mut self, key: &str, polygon: Polygon) {
+        self.oazas.entry_ref(key).or_default().push(polygon);
+    }
+
+    /// The gaps of at least `min_area` (which skips the slivers left by
+    /// rounding), sorted by key and by decreasing area.
+    pub fn finish(self, min_area: f64) -> Vec<Gap> {
+        let mut gaps: Vec<Gap> = self
+            .oazas
+            .into_iter()
+            .flat_map(|(key, polygons)| {
+                dissolve(polygons)
+                    .into_iter()
+                    .flat_map(|polygon| polygon.into_inner().1)
+                    .map(|ring| Polygon::new(ring, vec![]))
+                    .map(|polygon| (polygon.unsigned_area(), polygon))
+                    .filter(|(area, _)| *area >= min_area)
+                    .map(move |(area, polygon)| Gap {
+                        key: key.clone(),
+                        polygon,
+                        area,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        gaps.sort_by(|a, b| a.key.cmp(&b.key).then(b.area.total_cmp(&a.area)));
+        gaps
+    }
+}
+
+/// Union polygons, pairing them up level by level so that each union stays
+/// small.
+fn dissolve(polygons: Vec<Polygon>) -> MultiPolygon {
+    let mut level: Vec<MultiPolygon> = polygons
+        .into_iter()
+        .map(|polygon| MultiPolygon::new(vec![polygon]))
+        .collect();
+    while level.len() > 1 {
+        let mut pairs = level.into_iter();
+        let mut next = Vec::with_capacity(pairs.len().div_ceil(2));
+        while let Some(a) = pairs.next() {
+            next.push(match pairs.next() {
+                Some(b) => a.union(&b),
+                None => a,
+            });
+        }
+        level = next;
+    }
+    level.pop().unwrap_or_else(|| MultiPolygon::new(vec![]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geo::{Rect, coord};
+    use test_log::test;
+
+    fn rect(x0: f64, y0: f64, x1: f64, y1: f64) -> Polygon {
+        Rect::new(coord! { x: x0, y: y0 }, coord! { x: x1, y: y1 }).to_polygon()
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut finder = GapFinder::new();
+        // a ring of 4 筆 around a 1x1 void, in 大字 001
+        for polygon in [
+            rect(0.0, 0.0, 3.0, 1.0),
+            rect(0.0, 2.0, 3.0, 3.0),
+            rect(0.0, 1.0, 1.0, 2.0),
+            rect(2.0, 1.0, 3.0, 2.0),
+        ] {
+            finder.insert("13101-001", polygon);
+        }
+        // in 002, the void is filled, and a sliver is left
+        for polygon in [
+            rect(0.0, 0.0, 3.0, 1.0),
+            rect(0.0, 2.0, 3.0, 3.0),
+            rect(0.0, 1.0, 1.0, 2.0),
+            rect(2.0, 1.0, 3.0, 2.0),
+            rect(1.0, 1.0, 1.999, 2.0),
+        ] {
+            finder.insert("13101-002", polygon);
+        }
+        // 003 covers the void of 001, but the 大字 are dissolved separately
+        finder.insert("13101-003", rect(1.0, 1.0, 2.0, 2.0));
+
+        let gaps = finder.finish(0.01);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].key, "13101-001");
+        assert!((gaps[0].area - 1.0).abs() < 1e-9);
+    }
+}
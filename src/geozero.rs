@@ -0,0 +1,120 @@
+//! [`GeozeroDatasource`] over the parsed parcels, so that any geozero
+//! processor (GeoJSON, WKB, GDAL, SVG, ...) can consume them.
+
+use geozero::error::Result;
+use geozero::{ColumnValue, FeatureProcessor, GeozeroDatasource};
+
+use crate::data::{ParsedData, closed_ring};
+use crate::writer::FUDE_COLUMNS;
+
+impl GeozeroDatasource for ParsedData<'_> {
+    /// Process the parcels as polygon features with string properties.
+    ///
+    /// Parcels whose surface can't be resolved are skipped.
+    fn process<P: FeatureProcessor>(&mut self, processor: &mut P) -> Result<()> {
+        processor.dataset_begin(Some("fude"))?;
+        let mut idx = 0;
+        for fude in self.fudes.values() {
+            let Ok(rings) = self.resolve_surface(fude.surface_id) else {
+                continue;
+            };
+            processor.feature_begin(idx)?;
+
+            processor.properties_begin()?;
+            for (i, (name, get)) in FUDE_COLUMNS.iter().enumerate() {
+                if let Some(value) = get(&fude.attributes)
+                    && processor.property(i, name, &ColumnValue::String(value))?
+                {
+                    break;
+                }
+            }
+            processor.properties_end()?;
+
+            processor.geometry_begin()?;
+            processor.polygon_begin(true, rings.len(), 0)?;
+            for (ring_idx, ring) in rings.iter().enumerate() {
+                processor.linestring_begin(false, closed_ring(ring).count(), ring_idx)?;
+                for (i, [x, y]) in closed_ring(ring).enumerate() {
+                    processor.xy(x, y, i)?;
+                }
+                processor.linestring_end(false, ring_idx)?;
+            }
+            processor.polygon_end(true, 0)?;
+            processor.geometry_end()?;
+
+            processor.feature_end(idx)?;
+            idx += 1;
+        }
+        processor.dataset_end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::{Fude, FudeAttributes, Ids, ParsedData, PointRef, Surfaces};
+    use geozero::error::Result;
+    use geozero::{
+        ColumnValue, FeatureProcessor, GeomProcessor, GeozeroDatasource, PropertyProcessor,
+    };
+    use hashbrown::HashMap;
+    use test_log::test;
+
+    #[derive(Default)]
+    struct Counter {
+        features: usize,
+        properties: Vec<String>,
+        coords: usize,
+    }
+
+    impl FeatureProcessor for Counter {
+        fn feature_end(&mut self, _idx: u64) -> Result<()> {
+            self.features += 1;
+            Ok(())
+        }
+    }
+
+    impl PropertyProcessor for Counter {
+        fn property(&mut self, _idx: usize, name: &str, value: &ColumnValue) -> Result<bool> {
+            self.properties.push(format!("{}={}", name, value));
+            Ok(false)
+        }
+    }
+
+    impl GeomProcessor for Counter {
+        fn xy(&mut self, _x: f64, _y: f64, _idx: usize) -> Result<()> {
+            self.coords += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_datasource() {
+        let mut ids = Ids::default();
+        let [c1, c2, c3, s1] = ["C1", "C2", "C3", "S1"].map(|id| ids.intern(id));
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H1".into(),
+                chiban: Some("1-2".into()),
+                ..Default::default()
+            },
+            surface_id: s1,
+        };
+        let mut data = ParsedData {
+            points: HashMap::new(),
+            segments: HashMap::from([
+                (c1, [PointRef::Direct([0., 0.]), PointRef::Direct([1., 0.])]),
+                (c2, [PointRef::Direct([1., 0.]), PointRef::Direct([1., 1.])]),
+                (c3, [PointRef::Direct([1., 1.]), PointRef::Direct([0., 0.])]),
+            ]),
+            surfaces: Surfaces::from_iter([(s1, [[c1, c2, c3]])]),
+            fudes: HashMap::from([("H1".to_string(), fude)]),
+            ids,
+            ..Default::default()
+        };
+        let mut counter = Counter::default();
+        data.process(&mut counter).unwrap();
+        assert_eq!(counter.features, 1);
+        assert_eq!(counter.properties, ["id=H1", "地番=1-2"]);
+        assert_eq!(counter.coords, 4);
+    }
+}
@@ -1,5 +1,38 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod cache;
+pub mod codes;
+#[cfg(all(feature = "zip", feature = "rayon", feature = "geo"))]
+pub mod convert;
+pub mod crs;
 pub mod data;
+#[cfg(feature = "geo")]
+pub mod dataset;
+#[cfg(feature = "geo")]
+pub mod dedup;
+#[cfg(feature = "geo")]
+pub mod diff;
+#[cfg(feature = "geo")]
+pub mod gaps;
+#[cfg(feature = "geozero")]
+pub mod geozero;
+pub mod municipality;
 pub mod parser;
+#[cfg(feature = "serde")]
+mod serde;
+#[cfg(feature = "geo")]
+pub mod shape;
+#[cfg(feature = "geo")]
+pub mod stitch;
+pub mod synthetic;
+#[cfg(feature = "text")]
+pub mod text;
+#[cfg(feature = "geo")]
+pub mod validate;
+pub mod writer;
 
 #[cfg(feature = "zip")]
 pub mod zip;
+
+#[cfg(all(feature = "zip", feature = "rayon", feature = "geo"))]
+pub use convert::{ConversionReport, ConvertOptions, Event, SampleReport, convert, convert_shared};
@@ -0,0 +1,174 @@
+//! 全国地方公共団体コード: validation of the 市区町村コード and lookup of
+//! their names.
+//!
+//! The XML files and their names use the 5-digit codes, without the check
+//! digit of the 6-digit ones. The names of the prefectures are built in; the
+//! names of the municipalities change with mergers, so they are read from the
+//! official list of 総務省 (see [`MunicipalityTable::from_csv`]).
+
+use std::io::{BufRead, BufReader, Read};
+
+use hashbrown::HashMap;
+
+/// Names of the prefectures, by code (01 to 47).
+const PREFECTURES: [&str; 47] = [
+    "北海道",
+    "青森県",
+    "岩手県",
+    "宮城県",
+    "秋田県",
+    "山形県",
+    "福島県",
+    "茨城県",
+    "栃木県",
+    "群馬県",
+    "埼玉県",
+    "千葉県",
+    "東京都",
+    "神奈川県",
+    "新潟県",
+    "富山県",
+    "石川県",
+    "福井県",
+    "山梨県",
+    "長野県",
+    "岐阜県",
+    "静岡県",
+    "愛知県",
+    "三重県",
+    "滋賀県",
+    "京都府",
+    "大阪府",
+    "兵庫県",
+    "奈良県",
+    "和歌山県",
+    "鳥取県",
+    "島根県",
+    "岡山県",
+    "広島県",
+    "山口県",
+    "徳島県",
+    "香川県",
+    "愛媛県",
+    "高知県",
+    "福岡県",
+    "佐賀県",
+    "長崎県",
+    "熊本県",
+    "大分県",
+    "宮崎県",
+    "鹿児島県",
+    "沖縄県",
+];
+
+/// Name of the prefecture of a 市区町村コード (or of a 2-digit prefecture code).
+pub fn prefecture_name(code: &str) -> Option<&'static str> {
+    let prefecture: usize = code.get(..2)?.parse().ok()?;
+    PREFECTURES.get(prefecture.checked_sub(1)?).copied()
+}
+
+/// Check digit of a 5-digit 市区町村コード, the sixth digit of the code in
+/// the official list.
+pub fn check_digit(code: &str) -> Option<u8> {
+    if code.len() != 5 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let sum: u32 = code
+        .bytes()
+        .zip([6, 5, 4, 3, 2])
+        .map(|(b, weight)| (b - b'0') as u32 * weight)
+        .sum();
+    Some(((11 - sum % 11) % 10) as u8)
+}
+
+/// Whether a code looks like a 市区町村コード: 5 digits (or 6 with a valid
+/// check digit) in a prefecture. It may still not exist.
+pub fn is_valid_code(code: &str) -> bool {
+    let valid_digits = match code.len() {
+        5 => check_digit(code).is_some(),
+        6 => check_digit(&code[..5]) == Some(code.as_bytes()[5].wrapping_sub(b'0')),
+        _ => false,
+    };
+    valid_digits && prefecture_name(code).is_some()
+}
+
+/// Names of the municipalities, by 5-digit 市区町村コード.
+#[derive(Clone, Debug, Default)]
+pub struct MunicipalityTable {
+    names: HashMap<String, String>,
+}
+
+impl MunicipalityTable {
+    /// Read the official list of 総務省 (「都道府県コード及び市区町村コード」,
+    /// saved as CSV): lines of `団体コード,都道府県名,市区町村名,...`, with 6-
+    /// or 5-digit codes. Other lines (headers, prefectures) are ignored.
+    pub fn from_csv<R: Read>(reader: R) -> std::io::Result<Self> {
+        let mut table = Self::default();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let mut fields = line
+                .trim_start_matches('\u{feff}')
+                .split(',')
+                .map(|field| field.trim().trim_matches('"'));
+            let (Some(code), Some(_), Some(name)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if !name.is_empty() && is_valid_code(code) {
+                table.insert(&code[..5], name);
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, code: &str, name: &str) {
+        self.names.insert(code.to_string(), name.to_string());
+    }
+
+    /// Name of a municipality, by its 5- or 6-digit code.
+    pub fn name(&self, code: &str) -> Option<&str> {
+        self.names.get(code.get(..5)?).map(String::as_str)
+    }
+
+    pub fn contains(&self, code: &str) -> bool {
+        self.name(code).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(check_digit("13101"), Some(6));
+        assert_eq!(check_digit("13104"), Some(1));
+        assert_eq!(check_digit("13113"), Some(0));
+        assert_eq!(check_digit("1310"), None);
+        assert!(is_valid_code("15222"));
+        assert!(is_valid_code("131016"));
+        assert!(!is_valid_code("131017"));
+        assert!(!is_valid_code("48001"));
+        assert!(!is_valid_code("00000"));
+        assert_eq!(prefecture_name("15222"), Some("新潟県"));
+
+        let csv = "\u{feff}団体コード,都道府県名（漢字）,市区町村名（漢字）\n\
+                   130001,東京都,,トウキョウト,\n\
+                   131016,東京都,千代田区,トウキョウト,チヨダク\n\
+                   \"152226\",\"新潟県\",\"上越市\"\n";
+        let table = MunicipalityTable::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.name("13101"), Some("千代田区"));
+        assert_eq!(table.name("152226"), Some("上越市"));
+        assert!(!table.contains("13102"));
+    }
+}
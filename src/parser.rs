@@ -1,11 +1,16 @@
+use std::borrow::Cow;
 use std::io::BufRead;
 
 use hashbrown::HashMap;
 use jprect::etmerc::ExtendedTransverseMercatorProjection;
-use quick_xml::{Reader, events::Event};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::QName;
 use thiserror::Error;
 
-use crate::data::{Fude, FudeAttributes, ParsedData, Point, PointRef};
+use crate::data::{
+    Feature, Fude, FudeAttributes, Id, Ids, MapMetadata, ParsedData, Point, PointRef, Surfaces,
+};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -19,46 +24,363 @@ pub enum Error {
     SkipAll,
 }
 
-pub struct MojxmlParser<'a, R: BufRead> {
-    reader: Reader<R>,
-    skip_arbitrary_crs: bool,
+/// Build the projections of the 19 zones of the Japan Plane Rectangular CS.
+pub fn jpr_projections() -> [ExtendedTransverseMercatorProjection; 19] {
+    std::array::from_fn(|i| {
+        jprect::JPRZone::from_number(i + 1)
+            .expect("zone numbers 1..=19 are valid")
+            .projection()
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    /// Abort with [`Error::SkipAll`] when the file uses an arbitrary coordinate system.
+    pub skip_arbitrary_crs: bool,
+    /// Collect the 基準点 into [`ParsedData::kijunten`].
+    pub parse_kijunten: bool,
+    /// Collect the 筆界点 into [`ParsedData::hikkaiten`].
+    pub parse_hikkaiten: bool,
+    /// Collect the 図郭 into [`ParsedData::zukaku`].
+    pub parse_zukaku: bool,
+    /// Keep the Japan Plane Rectangular coordinates as easting/northing
+    /// instead of converting them to longitude/latitude
+    /// (see [`Crs::Jpr`](crate::crs::Crs::Jpr)).
+    pub keep_source_coordinates: bool,
+    /// Fill [`FudeAttributes::stable_id`](crate::data::FudeAttributes::stable_id).
+    pub stable_ids: bool,
+    /// Skip the 空間属性 (the points, curves and surfaces), for a quick parse
+    /// of the attributes. The geometries then can't be resolved.
+    pub skip_geometric: bool,
+    /// Skip the 主題属性 (the 筆, 基準点 and 筆界点), e.g. to parse only the
+    /// geometries or the 図郭.
+    pub skip_thematic: bool,
+}
+
+fn unexpected_eof() -> Error {
+    Error::InvalidData("Unexpected EOF".to_string())
+}
+
+/// The value of the attribute `key` of an element.
+fn attribute<'s>(start: &'s BytesStart, key: &[u8]) -> Result<Option<Cow<'s, [u8]>>, Error> {
+    for attr in start.attributes() {
+        let attr = attr.map_err(|e| {
+            Error::InvalidData(format!(
+                "invalid attribute in <{}>: {}",
+                String::from_utf8_lossy(start.name().as_ref()),
+                e
+            ))
+        })?;
+        if attr.key.as_ref() == key {
+            return Ok(Some(attr.value));
+        }
+    }
+    Ok(None)
+}
+
+fn id_attribute(start: &BytesStart) -> Result<String, Error> {
+    match attribute(start, b"id")? {
+        Some(value) => Ok(String::from_utf8_lossy(&value).into_owned()),
+        None => Err(Error::InvalidData("missing id attribute".to_string())),
+    }
+}
+
+/// Where the parser reads the XML events from.
+enum Source<'d, R> {
+    /// A reader, whose events are copied into the parser's buffers
+    Buffered(Reader<R>),
+    /// An in-memory document, whose events borrow from it
+    Borrowed(Reader<&'d [u8]>),
+}
+
+impl<'d, R: BufRead> Source<'d, R> {
+    fn read_event_into<'b>(&mut self, buf: &'b mut Vec<u8>) -> quick_xml::Result<Event<'b>>
+    where
+        'd: 'b,
+    {
+        match self {
+            Source::Buffered(reader) => {
+                buf.clear();
+                reader.read_event_into(buf)
+            }
+            Source::Borrowed(reader) => reader.read_event(),
+        }
+    }
+
+    fn read_to_end_into(&mut self, end: QName, buf: &mut Vec<u8>) -> quick_xml::Result<()> {
+        match self {
+            Source::Buffered(reader) => {
+                buf.clear();
+                reader.read_to_end_into(end, buf)?
+            }
+            Source::Borrowed(reader) => reader.read_to_end(end)?,
+        };
+        Ok(())
+    }
+}
+
+fn configure<R>(reader: &mut Reader<R>) {
+    reader.config_mut().trim_text(true);
+    reader.config_mut().check_end_names = true;
+    reader.config_mut().expand_empty_elements = true;
+}
+
+/// A string of the document: borrowed when `bytes` lie in the in-memory
+/// `document` (see [`MojxmlParser::from_slice`]), copied otherwise.
+fn document_str<'d>(document: &'d [u8], bytes: &[u8]) -> Cow<'d, str> {
+    let start = (bytes.as_ptr() as usize).wrapping_sub(document.as_ptr() as usize);
+    let borrowed = start
+        .checked_add(bytes.len())
+        .and_then(|end| document.get(start..end))
+        .and_then(|slice| std::str::from_utf8(slice).ok());
+    match borrowed {
+        Some(s) => Cow::Borrowed(s),
+        None => Cow::Owned(String::from_utf8_lossy(bytes).into_owned()),
+    }
+}
+
+/// Unescaped text of the document (see [`document_str`]).
+fn document_text<'d>(document: &'d [u8], text: Cow<'_, str>) -> Cow<'d, str> {
+    match text {
+        Cow::Borrowed(s) => document_str(document, s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s),
+    }
+}
+
+/// Parse a coordinate value.
+#[cfg(feature = "fast-float")]
+fn parse_coordinate(text: &str) -> Option<f64> {
+    lexical_core::parse(text.as_bytes()).ok()
+}
+
+/// Parse a coordinate value.
+#[cfg(not(feature = "fast-float"))]
+fn parse_coordinate(text: &str) -> Option<f64> {
+    text.parse().ok()
+}
+
+/// The handle of an id of the document, interned the first time it's seen.
+fn intern_id<'d>(ids: &mut Ids<'d>, document: &'d [u8], bytes: &[u8]) -> Id {
+    match ids.get(&String::from_utf8_lossy(bytes)) {
+        Some(id) => id,
+        None => ids.intern(document_str(document, bytes)),
+    }
+}
+
+/// Number of elements of a document, counted by a quick scan of its bytes
+/// to reserve the tables before parsing.
+#[derive(Clone, Copy, Debug, Default)]
+struct ElementCounts {
+    points: usize,
+    curves: usize,
+    surfaces: usize,
+    curve_refs: usize,
+    fudes: usize,
+}
+
+impl ElementCounts {
+    fn scan(document: &[u8]) -> Self {
+        // start tags with attributes, e.g. `<zmn:GM_Point id="P1">`
+        let count = |tag: &[u8]| memchr::memmem::find_iter(document, tag).count();
+        Self {
+            points: count(b"GM_Point "),
+            curves: count(b"GM_Curve "),
+            surfaces: count(b"GM_Surface "),
+            curve_refs: count(b"GM_CompositeCurve.generator "),
+            fudes: count("<筆 ".as_bytes()),
+        }
+    }
+}
+
+/// Buffers of the parser, kept from one file to the next with
+/// [`MojxmlParser::parse_with`] instead of being allocated for each file.
+#[derive(Default)]
+pub struct ParseBuffers {
     buf: Vec<u8>,
     buf2: Vec<u8>,
-    points: HashMap<String, Point>,
-    segments: HashMap<String, [PointRef; 2]>,
-    surfaces: HashMap<String, Vec<Vec<String>>>,
-    fudes: HashMap<String, Fude>,
+    points: HashMap<Id, Point>,
+    segments: HashMap<Id, [PointRef; 2]>,
+    surfaces: Surfaces,
+}
+
+impl ParseBuffers {
+    /// Take back the tables of parsed data that is no longer needed, for the
+    /// next file to reuse their memory.
+    pub fn recycle(&mut self, data: ParsedData<'_>) {
+        fn keep_larger<K, V>(kept: &mut HashMap<K, V>, mut table: HashMap<K, V>) {
+            if table.capacity() > kept.capacity() {
+                table.clear();
+                *kept = table;
+            }
+        }
+        keep_larger(&mut self.points, data.points);
+        keep_larger(&mut self.segments, data.segments);
+        if data.surfaces.capacity() > self.surfaces.capacity() {
+            self.surfaces = data.surfaces;
+            self.surfaces.clear();
+        }
+    }
+
+    /// Free the memory of the buffers (e.g. after an unusually large file).
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Parser of a map file.
+///
+/// `'d` is the lifetime of the document for [`from_slice`](Self::from_slice),
+/// whose ids and attributes are borrowed instead of copied.
+pub struct MojxmlParser<'a, 'd, R: BufRead> {
+    reader: Source<'d, R>,
+    /// The in-memory document (empty when reading from a `BufRead`)
+    document: &'d [u8],
+    options: ParseOptions,
+    /// Sizes of the tables to reserve
+    counts: Option<ElementCounts>,
+    buf: Vec<u8>,
+    buf2: Vec<u8>,
+    metadata: MapMetadata,
+    ids: Ids<'d>,
+    points: HashMap<Id, Point>,
+    segments: HashMap<Id, [PointRef; 2]>,
+    surfaces: Surfaces,
+    fudes: HashMap<String, Fude<'d>>,
+    kijunten: Vec<Feature>,
+    hikkaiten: Vec<Feature>,
+    zukaku: Vec<Feature>,
     projection: Option<&'a ExtendedTransverseMercatorProjection>,
     jpr_projections: &'a [ExtendedTransverseMercatorProjection; 19],
 }
 
-impl<'a, R: BufRead> MojxmlParser<'a, R> {
+impl<'a, R: BufRead> MojxmlParser<'a, 'static, R> {
     pub fn new(reader: R, projections: &'a [ExtendedTransverseMercatorProjection; 19]) -> Self {
         let mut reader = Reader::from_reader(reader);
-        reader.config_mut().trim_text(true);
-        reader.config_mut().check_end_names = true;
-        reader.config_mut().expand_empty_elements = true;
+        configure(&mut reader);
+        Self::with_source(Source::Buffered(reader), &[], projections)
+    }
+}
+
+impl<'a, 'd> MojxmlParser<'a, 'd, &'d [u8]> {
+    /// Parse an in-memory document without copying it: the ids and the
+    /// attributes of the 筆 borrow from it, which saves an allocation for
+    /// each of them.
+    pub fn from_slice(
+        document: &'d [u8],
+        projections: &'a [ExtendedTransverseMercatorProjection; 19],
+    ) -> Self {
+        let mut reader = Reader::from_reader(document);
+        configure(&mut reader);
+        Self::with_source(Source::Borrowed(reader), document, projections)
+            .with_capacity_for(document)
+    }
+}
 
+impl<'a, 'd, R: BufRead> MojxmlParser<'a, 'd, R> {
+    fn with_source(
+        reader: Source<'d, R>,
+        document: &'d [u8],
+        projections: &'a [ExtendedTransverseMercatorProjection; 19],
+    ) -> Self {
         Self {
             reader,
-            skip_arbitrary_crs: false,
+            document,
+            options: ParseOptions::default(),
+            counts: None,
             buf: Vec::new(),
             buf2: Vec::new(),
+            metadata: MapMetadata::default(),
+            ids: Ids::default(),
             points: HashMap::new(),
             segments: HashMap::new(),
-            surfaces: HashMap::new(),
+            surfaces: Surfaces::default(),
             fudes: HashMap::new(),
+            kijunten: Vec::new(),
+            hikkaiten: Vec::new(),
+            zukaku: Vec::new(),
             projection: None,
             jpr_projections: projections,
         }
     }
 
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reserve the tables for the elements of `document`, the bytes being
+    /// parsed, to avoid growing them during the parse of a large file.
+    /// Done by [`from_slice`](MojxmlParser::from_slice).
+    pub fn with_capacity_for(mut self, document: &[u8]) -> Self {
+        self.counts = Some(ElementCounts::scan(document));
+        self
+    }
+
     pub fn skip_arbitrary_crs(&mut self, skip: bool) {
-        self.skip_arbitrary_crs = skip;
+        self.options.skip_arbitrary_crs = skip;
     }
 
-    pub fn parse(mut self) -> Result<ParsedData, Error> {
-        // Parse the root
+    pub fn parse(self) -> Result<ParsedData<'d>, Error> {
+        self.parse_with(&mut ParseBuffers::default())
+    }
+
+    /// Parse with the buffers of previous files, to save their allocations
+    /// when parsing many files in a row.
+    pub fn parse_with(mut self, buffers: &mut ParseBuffers) -> Result<ParsedData<'d>, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("parse").entered();
+        self.buf = std::mem::take(&mut buffers.buf);
+        self.buf2 = std::mem::take(&mut buffers.buf2);
+        self.points = std::mem::take(&mut buffers.points);
+        self.segments = std::mem::take(&mut buffers.segments);
+        self.surfaces = std::mem::take(&mut buffers.surfaces);
+        if let Some(counts) = self.counts {
+            if !self.options.skip_geometric {
+                self.ids
+                    .reserve(counts.points + counts.curves + counts.surfaces);
+                self.points.reserve(counts.points);
+                self.segments.reserve(counts.curves);
+                self.surfaces.reserve(counts.surfaces, counts.curve_refs);
+            }
+            if !self.options.skip_thematic {
+                self.fudes.reserve(counts.fudes);
+            }
+        }
+        let result = self.parse_root();
+        buffers.buf = std::mem::take(&mut self.buf);
+        buffers.buf2 = std::mem::take(&mut self.buf2);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(()) => {}
+            Err(Error::SkipAll) => tracing::debug!("skipped"),
+            Err(e) => tracing::debug!(error = %e, "failed"),
+        }
+        result?;
+
+        let data = ParsedData {
+            metadata: self.metadata,
+            ids: self.ids,
+            points: self.points,
+            segments: self.segments,
+            surfaces: self.surfaces,
+            fudes: self.fudes,
+            kijunten: self.kijunten,
+            hikkaiten: self.hikkaiten,
+            zukaku: self.zukaku,
+            fude_index: Default::default(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            points = data.points.len(),
+            curves = data.segments.len(),
+            surfaces = data.surfaces.len(),
+            fudes = data.fudes.len(),
+            "parsed"
+        );
+        Ok(data)
+    }
+
+    fn parse_root(&mut self) -> Result<(), Error> {
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
                 Event::Start(start) => {
@@ -76,23 +398,54 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         "Unexpected text outside of element".to_string(),
                     ));
                 }
-                Event::Eof => break,
+                Event::Eof => return Ok(()),
                 _ => {}
             }
         }
+    }
 
-        Ok(ParsedData {
-            points: self.points,
-            segments: self.segments,
-            surfaces: self.surfaces,
-            fudes: self.fudes,
-        })
+    /// Parse only the header of the file (地図名, 市区町村コード, 座標系, ...),
+    /// stopping at the 空間属性.
+    pub fn parse_header(mut self) -> Result<MapMetadata, Error> {
+        loop {
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) => match start.local_name().as_ref() {
+                    // 空間属性, 主題属性
+                    b"\xe7\xa9\xba\xe9\x96\x93\xe5\xb1\x9e\xe6\x80\xa7"
+                    | b"\xe4\xb8\xbb\xe9\xa1\x8c\xe5\xb1\x9e\xe6\x80\xa7" => break,
+                    // 地図名
+                    b"\xe5\x9c\xb0\xe5\x9b\xb3\xe5\x90\x8d" => {
+                        self.metadata.map_name = self.element_text()?;
+                    }
+                    // 市区町村コード
+                    b"\xe5\xb8\x82\xe5\x8c\xba\xe7\x94\xba\xe6\x9d\x91\xe3\x82\xb3\xe3\x83\xbc\xe3\x83\x89" => {
+                        self.metadata.municipality_code = self.element_text()?;
+                    }
+                    // 市区町村名
+                    b"\xe5\xb8\x82\xe5\x8c\xba\xe7\x94\xba\xe6\x9d\x91\xe5\x90\x8d" => {
+                        self.metadata.municipality_name = self.element_text()?;
+                    }
+                    // 座標系
+                    b"\xe5\xba\xa7\xe6\xa8\x99\xe7\xb3\xbb" => {
+                        self.metadata.crs = self.element_text()?;
+                    }
+                    // 測地系判別
+                    b"\xe6\xb8\xac\xe5\x9c\xb0\xe7\xb3\xbb\xe5\x88\xa4\xe5\x88\xa5" => {
+                        self.metadata.datum = self.element_text()?;
+                    }
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(self.metadata)
     }
 
-    fn expect_text(&mut self) -> Result<String, Error> {
+    fn expect_text(&mut self) -> Result<Cow<'d, str>, Error> {
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
-                Event::Text(text) => return Ok(text.unescape()?.into_owned()),
+                Event::Text(text) => return Ok(document_text(self.document, text.unescape()?)),
                 Event::Start(_) => {
                     return Err(Error::InvalidData(
                         "Expected text but found a start tag".to_string(),
@@ -103,6 +456,25 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         "Expected text but found an end tag".to_string(),
                     ));
                 }
+                Event::Eof => return Err(unexpected_eof()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Text of a simple element (`None` if it's empty), consuming its end tag.
+    fn element_text(&mut self) -> Result<Option<String>, Error> {
+        let mut text = None;
+        loop {
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Text(t) => text = Some(t.unescape()?.into_owned()),
+                Event::Start(_) => {
+                    return Err(Error::InvalidData(
+                        "Expected text but found a start tag".to_string(),
+                    ));
+                }
+                Event::End(_) => return Ok(text),
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
@@ -118,36 +490,67 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                     match start.local_name().as_ref() {
                         // 空間属性
                         b"\xe7\xa9\xba\xe9\x96\x93\xe5\xb1\x9e\xe6\x80\xa7" => {
-                            self.parse_geometric()?;
+                            if self.options.skip_geometric {
+                                self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            } else {
+                                self.parse_geometric()?;
+                            }
                         }
                         // 主題属性
                         b"\xe4\xb8\xbb\xe9\xa1\x8c\xe5\xb1\x9e\xe6\x80\xa7" => {
-                            self.parse_thematic()?;
+                            if self.options.skip_thematic {
+                                self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            } else {
+                                self.parse_thematic()?;
+                            }
                         }
                         // 図郭
                         b"\xe5\x9b\xb3\xe9\x83\xad" => {
-                            self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            if self.options.parse_zukaku {
+                                let id = id_attribute(&start)?;
+                                if let Some(feature) = self.parse_feature(id)? {
+                                    self.zukaku.push(feature);
+                                }
+                            } else {
+                                self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            }
                         }
                         // 座標系
                         b"\xe5\xba\xa7\xe6\xa8\x99\xe7\xb3\xbb" => {
                             let crs_text = self.expect_text()?;
                             // Skip arbitrary coordinate systems
-                            if self.skip_arbitrary_crs && crs_text == "任意座標系" {
+                            if self.options.skip_arbitrary_crs && crs_text == "任意座標系" {
                                 return Err(Error::SkipAll);
                             }
+                            self.metadata.crs = Some(crs_text.to_string());
                             if let Some(zone_number) = crs_text
                                 .strip_prefix("公共座標")
                                 .and_then(|s| s.strip_suffix("系"))
                                 .and_then(|num_str| num_str.parse::<u8>().ok())
+                                && (1..=19).contains(&zone_number)
                             {
-                                if (1..=19).contains(&zone_number) {
-                                    self.projection =
-                                        Some(&self.jpr_projections[zone_number as usize - 1]);
-                                }
+                                self.projection =
+                                    Some(&self.jpr_projections[zone_number as usize - 1]);
                             }
 
                             level += 1;
                         }
+                        // 地図名
+                        b"\xe5\x9c\xb0\xe5\x9b\xb3\xe5\x90\x8d" => {
+                            self.metadata.map_name = self.element_text()?;
+                        }
+                        // 市区町村コード
+                        b"\xe5\xb8\x82\xe5\x8c\xba\xe7\x94\xba\xe6\x9d\x91\xe3\x82\xb3\xe3\x83\xbc\xe3\x83\x89" => {
+                            self.metadata.municipality_code = self.element_text()?;
+                        }
+                        // 市区町村名
+                        b"\xe5\xb8\x82\xe5\x8c\xba\xe7\x94\xba\xe6\x9d\x91\xe5\x90\x8d" => {
+                            self.metadata.municipality_name = self.element_text()?;
+                        }
+                        // 測地系判別
+                        b"\xe6\xb8\xac\xe5\x9c\xb0\xe7\xb3\xbb\xe5\x88\xa4\xe5\x88\xa5" => {
+                            self.metadata.datum = self.element_text()?;
+                        }
                         _ => {
                             level += 1;
                         }
@@ -159,6 +562,7 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         return Ok(());
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
@@ -170,14 +574,8 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
                 Event::Start(start) => {
-                    let mut id = None;
-                    for attr in start.attributes() {
-                        let attr = attr.unwrap();
-                        if attr.key.as_ref() == b"id" {
-                            id = Some(String::from_utf8_lossy(&attr.value).to_string());
-                            break;
-                        }
-                    }
+                    let id = attribute(&start, b"id")?
+                        .map(|value| intern_id(&mut self.ids, self.document, &value));
                     if let Some(id) = id {
                         match start.local_name().as_ref() {
                             b"GM_Point" => {
@@ -203,12 +601,13 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                 Event::End(_) => {
                     return Ok(());
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 
-    fn parse_point(&mut self, id: String) -> Result<(), Error> {
+    fn parse_point(&mut self, id: Id) -> Result<(), Error> {
         let mut level = 0;
         let mut point = None;
 
@@ -230,6 +629,7 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         return Ok(());
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
@@ -264,16 +664,14 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                 Event::Text(text) => {
                     match mode {
                         Mode::X => {
-                            x =
-                                Some(text.unescape()?.parse().map_err(|_| {
-                                    Error::InvalidData("invalid X value".to_string())
-                                })?);
+                            x = Some(parse_coordinate(&text.unescape()?).ok_or_else(|| {
+                                Error::InvalidData("invalid X value".to_string())
+                            })?);
                         }
                         Mode::Y => {
-                            y =
-                                Some(text.unescape()?.parse().map_err(|_| {
-                                    Error::InvalidData("invalid Y value".to_string())
-                                })?);
+                            y = Some(parse_coordinate(&text.unescape()?).ok_or_else(|| {
+                                Error::InvalidData("invalid Y value".to_string())
+                            })?);
                         }
                         Mode::None => {
                             return Err(Error::InvalidData(
@@ -286,6 +684,10 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                     Mode::None => match (x, y) {
                         (Some(x), Some(y)) => {
                             if let Some(projection) = self.projection {
+                                if self.options.keep_source_coordinates {
+                                    // X is the northing
+                                    return Ok([y, x]);
+                                }
                                 let Ok((x, y, _)) = projection.project_inverse(y, x, 0.0) else {
                                     return Err(Error::InvalidData( "failed to project a point from Japan Plane Rectangular to lat/lng".to_string()));
                                 };
@@ -303,12 +705,13 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         mode = Mode::None;
                     }
                 },
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 
-    fn parse_curve_segment(&mut self, id: String) -> Result<(), Error> {
+    fn parse_curve_segment(&mut self, id: Id) -> Result<(), Error> {
         let mut level = 0;
         let mut num_points = 0;
         let mut points: [PointRef; 2] = [PointRef::Direct([0., 0.]), PointRef::Direct([0., 0.])];
@@ -324,14 +727,8 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                                     "Too many points in GM_Curve".to_string(),
                                 ));
                             }
-                            let mut idref = None;
-                            for attr in start.attributes() {
-                                let attr = attr.unwrap();
-                                if attr.key.as_ref() == b"idref" {
-                                    idref = Some(String::from_utf8_lossy(&attr.value).to_string());
-                                    break;
-                                }
-                            }
+                            let idref = attribute(&start, b"idref")?
+                                .map(|value| intern_id(&mut self.ids, self.document, &value));
                             if let Some(idref) = idref {
                                 points[num_points] = PointRef::Indirect(idref);
                                 num_points += 1;
@@ -366,15 +763,15 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         return Ok(());
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 
-    fn parse_surface(&mut self, id: String) -> Result<(), Error> {
+    fn parse_surface(&mut self, id: Id) -> Result<(), Error> {
         let mut level = 0;
-        let mut found_exterior = false;
-        let mut surface: Vec<Vec<String>> = Vec::with_capacity(1);
+        let mut exterior = None;
 
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
@@ -382,16 +779,18 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                     level += 1;
                     match start.local_name().as_ref() {
                         b"GM_SurfaceBoundary.exterior" => {
-                            assert!(!found_exterior, "Multiple exterior rings in GM_Surface");
+                            if exterior.is_some() {
+                                return Err(Error::InvalidData(
+                                    "Multiple exterior rings in GM_Surface".to_string(),
+                                ));
+                            }
                             level -= 1;
-                            let ring = self.parse_ring()?;
-                            surface.insert(0, ring);
-                            found_exterior = true;
+                            exterior = Some(self.surfaces.open_rings());
+                            self.parse_ring()?;
                         }
                         b"GM_SurfaceBoundary.interior" => {
                             level -= 1;
-                            let ring = self.parse_ring()?;
-                            surface.push(ring);
+                            self.parse_ring()?;
                         }
                         _ => {}
                     }
@@ -399,45 +798,44 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                 Event::End(_) => {
                     level -= 1;
                     if level < 0 {
-                        if !found_exterior {
+                        let Some(exterior) = exterior else {
                             return Err(Error::InvalidData(
                                 "Missing exterior ring in GM_Surface".to_string(),
                             ));
-                        }
-                        self.surfaces.insert(id, surface);
+                        };
+                        self.surfaces.finish_surface(id, exterior);
                         return Ok(());
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 
-    fn parse_ring(&mut self) -> Result<Vec<String>, Error> {
+    /// Parse a ring into the surface being built.
+    fn parse_ring(&mut self) -> Result<(), Error> {
         let mut level = 0;
-        let mut ring: Vec<String> = Vec::with_capacity(4);
 
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
                 Event::Start(start) => {
                     level += 1;
-                    if start.local_name().as_ref() == b"GM_CompositeCurve.generator" {
-                        for attr in start.attributes() {
-                            let attr = attr.unwrap();
-                            if attr.key.as_ref() == b"idref" {
-                                let idref = String::from_utf8_lossy(&attr.value).to_string();
-                                ring.push(idref);
-                                break;
-                            }
-                        }
+                    if start.local_name().as_ref() == b"GM_CompositeCurve.generator"
+                        && let Some(value) = attribute(&start, b"idref")?
+                    {
+                        let idref = intern_id(&mut self.ids, self.document, &value);
+                        self.surfaces.push_curve(idref);
                     }
                 }
                 Event::End(_) => {
                     level -= 1;
                     if level < 0 {
-                        return Ok(ring);
+                        self.surfaces.end_ring();
+                        return Ok(());
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
@@ -447,37 +845,53 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
         loop {
             match self.reader.read_event_into(&mut self.buf)? {
                 Event::Start(start) => {
-                    let mut id = None;
-                    for attr in start.attributes() {
-                        let attr = attr.unwrap();
-                        if attr.key.as_ref() == b"id" {
-                            id = Some(String::from_utf8_lossy(&attr.value).to_string());
-                            break;
-                        }
-                    }
+                    let id =
+                        attribute(&start, b"id")?.map(|value| document_str(self.document, &value));
                     match start.local_name().as_ref() {
                         // <筆>
                         b"\xe7\xad\x86" => {
                             let Some(id) = id else {
                                 return Err(Error::InvalidData("missing id attribute".to_string()));
                             };
-                            let fude = self.parse_fude()?;
+                            let mut fude = self.parse_fude()?;
+                            fude.attributes.id = id.clone();
+                            if self.options.stable_ids {
+                                let municipality_code = self.metadata.municipality_code.as_deref();
+                                fude.attributes.stable_id =
+                                    Some(fude.attributes.compute_stable_id(municipality_code));
+                            }
                             match fude.attributes.chiban.as_deref() {
                                 Some(s) if s.contains("地区外") || s.contains("別図") => {
                                     // skip
                                 }
                                 _ => {
-                                    self.fudes.insert(id, fude);
+                                    self.fudes.insert(id.into_owned(), fude);
                                 }
                             };
                         }
-                        // <基準点> (skip)
+                        // <基準点>
                         b"\xe5\x9f\xba\xe6\xba\x96\xe7\x82\xb9" => {
-                            self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            if self.options.parse_kijunten
+                                && let Some(id) = id
+                            {
+                                if let Some(feature) = self.parse_feature(id.into_owned())? {
+                                    self.kijunten.push(feature);
+                                }
+                            } else {
+                                self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            }
                         }
-                        // <筆界点> (skip)
+                        // <筆界点>
                         b"\xe7\xad\x86\xe7\x95\x8c\xe7\x82\xb9" => {
-                            self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            if self.options.parse_hikkaiten
+                                && let Some(id) = id
+                            {
+                                if let Some(feature) = self.parse_feature(id.into_owned())? {
+                                    self.hikkaiten.push(feature);
+                                }
+                            } else {
+                                self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                            }
                         }
                         // <仮行政界線> (skip)
                         b"\xe4\xbb\xae\xe8\xa1\x8c\xe6\x94\xbf\xe7\x95\x8c\xe7\xb7\x9a" => {
@@ -498,12 +912,57 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                 Event::End(_) => {
                     return Ok(());
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 
-    fn parse_fude(&mut self) -> Result<Fude, Error> {
+    /// Parse a 基準点, 筆界点 or 図郭 element. Returns `None` if it has no
+    /// geometry.
+    fn parse_feature(&mut self, id: String) -> Result<Option<Feature>, Error> {
+        let mut attributes = Vec::new();
+        let mut geometry_id = None;
+
+        loop {
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) => {
+                    let idref = attribute(&start, b"idref")?
+                        .map(|value| intern_id(&mut self.ids, self.document, &value));
+                    if let Some(idref) = idref {
+                        // <形状>, <図郭線>, ...
+                        geometry_id.get_or_insert(idref);
+                        self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                        continue;
+                    }
+
+                    let key = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                    match self.reader.read_event_into(&mut self.buf2)? {
+                        Event::Text(text) => {
+                            attributes.push((key, text.unescape()?.into_owned()));
+                            self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                        }
+                        Event::End(_) => {}
+                        // nested elements are not supported
+                        _ => {
+                            self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
+                        }
+                    }
+                }
+                Event::End(_) => {
+                    return Ok(geometry_id.map(|geometry_id| Feature {
+                        id,
+                        geometry_id,
+                        attributes,
+                    }));
+                }
+                Event::Eof => return Err(unexpected_eof()),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_fude(&mut self) -> Result<Fude<'d>, Error> {
         let mut level = 0;
 
         let mut attributes = FudeAttributes::default();
@@ -514,45 +973,39 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                 Event::Start(start) => match start.local_name().as_ref() {
                     // <形状>
                     b"\xe5\xbd\xa2\xe7\x8a\xb6" => {
-                        for attr in start.attributes() {
-                            let attr = attr.unwrap();
-                            if attr.key.as_ref() == b"idref" {
-                                let idref = String::from_utf8_lossy(&attr.value).to_string();
-                                surface_id = Some(idref);
-                                break;
-                            }
+                        if let Some(value) = attribute(&start, b"idref")? {
+                            surface_id = Some(intern_id(&mut self.ids, self.document, &value));
                         }
                         level += 1;
                     }
                     // other
                     name => {
-                        let key = String::from_utf8_lossy(name).into_owned();
-                        if key == "筆界未定構成筆" {
+                        // <筆界未定構成筆>
+                        if name == "筆界未定構成筆".as_bytes() {
                             // TODO: ?
                             self.reader.read_to_end_into(start.name(), &mut self.buf2)?;
                             continue;
                         }
-                        let value = self.expect_text()?;
-
-                        match key.as_ref() {
-                            "大字コード" => attributes.oaza_code = Some(value),
-                            "丁目コード" => attributes.chome_code = Some(value),
-                            "小字コード" => attributes.koaza_code = Some(value),
-                            "予備コード" => attributes.yobi_code = Some(value),
-                            "大字名" => attributes.oaza = Some(value),
-                            "丁目名" => attributes.chome = Some(value),
-                            "小字名" => attributes.koaza = Some(value),
-                            "予備名" => attributes.yobi = Some(value),
-                            "地番" => attributes.chiban = Some(value),
-                            "精度区分" => attributes.accuracy_class = Some(value),
-                            "座標値種別" => attributes.coord_class = Some(value),
+                        let field = match std::str::from_utf8(name) {
+                            Ok("大字コード") => &mut attributes.oaza_code,
+                            Ok("丁目コード") => &mut attributes.chome_code,
+                            Ok("小字コード") => &mut attributes.koaza_code,
+                            Ok("予備コード") => &mut attributes.yobi_code,
+                            Ok("大字名") => &mut attributes.oaza,
+                            Ok("丁目名") => &mut attributes.chome,
+                            Ok("小字名") => &mut attributes.koaza,
+                            Ok("予備名") => &mut attributes.yobi,
+                            Ok("地番") => &mut attributes.chiban,
+                            Ok("精度区分") => &mut attributes.accuracy_class,
+                            Ok("座標値種別") => &mut attributes.coord_class,
                             _ => {
                                 return Err(Error::InvalidData(format!(
                                     "Unexpected attribute: {:?}",
-                                    key,
+                                    String::from_utf8_lossy(name),
                                 )));
                             }
-                        }
+                        };
+                        *field = Some(self.expect_text()?);
                         level += 1;
                     }
                 },
@@ -567,8 +1020,115 @@ impl<'a, R: BufRead> MojxmlParser<'a, R> {
                         });
                     }
                 }
+                Event::Eof => return Err(unexpected_eof()),
                 _ => {}
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::synthetic::SyntheticMap;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    /// An edit of the document.
+    #[derive(Clone, Debug)]
+    enum Mutation {
+        Replace(Index, u8),
+        Insert(Index, u8),
+        Delete(Index, usize),
+        /// Copy a range of the document to another position
+        Duplicate(Index, usize, Index),
+        Truncate(Index),
+    }
+
+    fn mutation() -> impl Strategy<Value = Mutation> {
+        // mostly the bytes of the markup
+        let byte = prop_oneof![
+            prop::sample::select(b"<>/=\"' :&;#xX.-0123456789".to_vec()),
+            any::<u8>(),
+        ];
+        prop_oneof![
+            (any::<Index>(), byte.clone()).prop_map(|(i, b)| Mutation::Replace(i, b)),
+            (any::<Index>(), byte).prop_map(|(i, b)| Mutation::Insert(i, b)),
+            (any::<Index>(), 1..64usize).prop_map(|(i, n)| Mutation::Delete(i, n)),
+            (any::<Index>(), 1..512usize, any::<Index>())
+                .prop_map(|(i, n, j)| Mutation::Duplicate(i, n, j)),
+            any::<Index>().prop_map(Mutation::Truncate),
+        ]
+    }
+
+    fn mutate(document: &mut Vec<u8>, mutation: &Mutation) {
+        if document.is_empty() {
+            return;
+        }
+        let len = document.len();
+        match *mutation {
+            Mutation::Replace(i, b) => document[i.index(len)] = b,
+            Mutation::Insert(i, b) => document.insert(i.index(len + 1), b),
+            Mutation::Delete(i, n) => {
+                let start = i.index(len);
+                document.drain(start..(start + n).min(len));
+            }
+            Mutation::Duplicate(i, n, j) => {
+                let start = i.index(len);
+                let range = document[start..(start + n).min(len)].to_vec();
+                let at = j.index(len + 1);
+                document.splice(at..at, range);
+            }
+            Mutation::Truncate(i) => document.truncate(i.index(len)),
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let projections = jpr_projections();
+        let xml = SyntheticMap::new(1, 1).to_xml();
+        let parse = |document: &str| {
+            MojxmlParser::from_slice(document.as_bytes(), &projections)
+                .parse()
+                .map(|_| ())
+        };
+        let error = |document: &str| match parse(document) {
+            Err(Error::InvalidData(message)) => message,
+            other => panic!("expected InvalidData, got {:?}", other),
+        };
+        assert!(parse(&xml).is_ok());
+        assert!(error(&xml.replace("idref=\"C1\"", "idref=C1")).starts_with("invalid attribute"));
+        let exterior = "<zmn:GM_SurfaceBoundary.exterior>";
+        assert_eq!(
+            error(&xml.replace(
+                exterior,
+                &format!("{0}</zmn:GM_SurfaceBoundary.exterior>{0}", exterior)
+            )),
+            "Multiple exterior rings in GM_Surface"
+        );
+        assert_eq!(
+            error(&xml[..xml.find("</主題属性>").unwrap()]),
+            "Unexpected EOF"
+        );
+    }
+
+    proptest! {
+        /// Malformed documents give an error, never a panic.
+        #[test]
+        fn test_parse_mutated(mutations in prop::collection::vec(mutation(), 1..8)) {
+            let mut document = SyntheticMap::new(2, 2).to_xml().into_bytes();
+            for mutation in &mutations {
+                mutate(&mut document, mutation);
+            }
+            let projections = jpr_projections();
+            let buffered = MojxmlParser::new(document.as_slice(), &projections).parse();
+            let borrowed = MojxmlParser::from_slice(&document, &projections).parse();
+            prop_assert_eq!(buffered.is_ok(), borrowed.is_ok());
+            if let Ok(data) = borrowed {
+                for fude in data.fudes.values() {
+                    let _ = data.resolve_surface(fude.surface_id);
+                }
+            }
+        }
+    }
+}
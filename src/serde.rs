@@ -0,0 +1,311 @@
+//! [`Serialize`] and [`Deserialize`] for [`ParsedData`], e.g. to cache the
+//! parsed files or to inspect them as JSON.
+//!
+//! The geometries reference each other by their ids in the file, rather
+//! than by [`Id`] handles, and are listed in the order of the document:
+//!
+//! ```json
+//! {
+//!   "metadata": {"map_name": "...", "crs": "公共座標9系", ...},
+//!   "points": {"P1": [-6000.0, -35000.0], ...},
+//!   "curves": {"C1": [{"indirect": "P1"}, {"direct": [-5980.0, -35000.0]}], ...},
+//!   "surfaces": {"S1": [["C1", "C2", "C3", "C4"]], ...},
+//!   "fudes": {"H1": {"attributes": {"id": "H1", "chiban": "1", ...}, "surface": "S1"}, ...},
+//!   "kijunten": [{"id": "K1", "geometry": "P9", "attributes": [["名称", "..."]]}],
+//!   "hikkaiten": [],
+//!   "zukaku": []
+//! }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::data::{
+    Feature, Fude, FudeAttributes, Id, Ids, MapMetadata, ParsedData, Point, PointRef,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PointRefRepr<S> {
+    Indirect(S),
+    Direct(Point),
+}
+
+#[derive(Serialize)]
+struct FudeRef<'s, 'a> {
+    attributes: &'s FudeAttributes<'a>,
+    surface: &'s str,
+}
+
+#[derive(Deserialize)]
+struct FudeRepr<'a> {
+    attributes: FudeAttributes<'a>,
+    surface: String,
+}
+
+#[derive(Serialize)]
+struct FeatureRef<'s> {
+    id: &'s str,
+    geometry: &'s str,
+    attributes: &'s [(String, String)],
+}
+
+#[derive(Deserialize)]
+struct FeatureRepr {
+    id: String,
+    geometry: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl Serialize for ParsedData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ids = &self.ids;
+        let points: Vec<_> = ids
+            .iter()
+            .filter_map(|(id, name)| Some((name, self.points.get(&id)?)))
+            .collect();
+        let curves: Vec<_> = ids
+            .iter()
+            .filter_map(|(id, name)| {
+                let point_refs =
+                    self.segments
+                        .get(&id)?
+                        .each_ref()
+                        .map(|point_ref| match point_ref {
+                            PointRef::Indirect(point_id) => {
+                                PointRefRepr::Indirect(ids.name(*point_id))
+                            }
+                            PointRef::Direct(point) => PointRefRepr::Direct(*point),
+                        });
+                Some((name, point_refs))
+            })
+            .collect();
+        let surfaces: Vec<_> = ids
+            .iter()
+            .filter_map(|(id, name)| {
+                let rings: Vec<Vec<&str>> = self
+                    .surfaces
+                    .get(id)?
+                    .rings()
+                    .map(|ring| ring.iter().map(|&curve_id| ids.name(curve_id)).collect())
+                    .collect();
+                Some((name, rings))
+            })
+            .collect();
+        let mut fudes: Vec<_> = self
+            .fudes
+            .iter()
+            .map(|(key, fude)| {
+                let fude = FudeRef {
+                    attributes: &fude.attributes,
+                    surface: ids.name(fude.surface_id),
+                };
+                (key, fude)
+            })
+            .collect();
+        fudes.sort_by(|a, b| a.0.cmp(b.0));
+        let mut state = serializer.serialize_struct("ParsedData", 8)?;
+        state.serialize_field("metadata", &self.metadata)?;
+        state.serialize_field("points", &Entries(points))?;
+        state.serialize_field("curves", &Entries(curves))?;
+        state.serialize_field("surfaces", &Entries(surfaces))?;
+        state.serialize_field("fudes", &Entries(fudes))?;
+        state.serialize_field("kijunten", &feature_refs(ids, &self.kijunten))?;
+        state.serialize_field("hikkaiten", &feature_refs(ids, &self.hikkaiten))?;
+        state.serialize_field("zukaku", &feature_refs(ids, &self.zukaku))?;
+        state.end()
+    }
+}
+
+fn feature_refs<'s>(ids: &'s Ids, features: &'s [Feature]) -> Vec<FeatureRef<'s>> {
+    features
+        .iter()
+        .map(|feature| FeatureRef {
+            id: &feature.id,
+            geometry: ids.name(feature.geometry_id),
+            attributes: &feature.attributes,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "ParsedData")]
+struct Repr<'a> {
+    metadata: MapMetadata,
+    points: Entries<String, Point>,
+    curves: Entries<String, [PointRefRepr<String>; 2]>,
+    surfaces: Entries<String, Vec<Vec<String>>>,
+    fudes: Entries<String, FudeRepr<'a>>,
+    kijunten: Vec<FeatureRepr>,
+    hikkaiten: Vec<FeatureRepr>,
+    zukaku: Vec<FeatureRepr>,
+}
+
+impl<'de, 'a> Deserialize<'de> for ParsedData<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        let mut ids = Ids::default();
+        let points = repr
+            .points
+            .0
+            .into_iter()
+            .map(|(name, point)| (ids.intern(name), point))
+            .collect();
+        let segments = repr
+            .curves
+            .0
+            .into_iter()
+            .map(|(name, point_refs)| {
+                let id = ids.intern(name);
+                let point_refs = point_refs.map(|point_ref| match point_ref {
+                    PointRefRepr::Indirect(point_id) => PointRef::Indirect(ids.intern(point_id)),
+                    PointRefRepr::Direct(point) => PointRef::Direct(point),
+                });
+                (id, point_refs)
+            })
+            .collect();
+        let surfaces = repr
+            .surfaces
+            .0
+            .into_iter()
+            .map(|(name, rings)| {
+                // resolving a surface needs its exterior ring, and a ring its curves
+                if rings.is_empty() || rings.iter().any(Vec::is_empty) {
+                    let message = format!("surface {} with an empty ring", name);
+                    return Err(de::Error::custom(message));
+                }
+                let id = ids.intern(name);
+                let rings: Vec<Vec<Id>> = rings
+                    .into_iter()
+                    .map(|ring| {
+                        ring.into_iter()
+                            .map(|curve_id| ids.intern(curve_id))
+                            .collect()
+                    })
+                    .collect();
+                Ok((id, rings))
+            })
+            .collect::<Result<_, _>>()?;
+        let fudes = repr
+            .fudes
+            .0
+            .into_iter()
+            .map(|(key, fude)| {
+                let fude = Fude {
+                    attributes: fude.attributes,
+                    surface_id: ids.intern(fude.surface),
+                };
+                (key, fude)
+            })
+            .collect();
+        let mut features = |features: Vec<FeatureRepr>| -> Vec<Feature> {
+            features
+                .into_iter()
+                .map(|feature| Feature {
+                    id: feature.id,
+                    geometry_id: ids.intern(feature.geometry),
+                    attributes: feature.attributes,
+                })
+                .collect()
+        };
+        let kijunten = features(repr.kijunten);
+        let hikkaiten = features(repr.hikkaiten);
+        let zukaku = features(repr.zukaku);
+        Ok(ParsedData {
+            metadata: repr.metadata,
+            ids,
+            points,
+            segments,
+            surfaces,
+            fudes,
+            kijunten,
+            hikkaiten,
+            zukaku,
+            ..Default::default()
+        })
+    }
+}
+
+/// A map whose entries keep their order (of the document).
+struct Entries<K, V>(Vec<(K, V)>);
+
+impl<K: Serialize, V: Serialize> Serialize for Entries<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Entries<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<K, V> {
+            type Value = Entries<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::ParsedData;
+    use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+    use crate::synthetic::SyntheticMap;
+    use test_log::test;
+
+    #[test]
+    fn test_json_round_trip() {
+        let projections = jpr_projections();
+        let xml = SyntheticMap::new(2, 3).to_xml();
+        let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .with_options(ParseOptions {
+                keep_source_coordinates: true,
+                ..Default::default()
+            })
+            .parse()
+            .unwrap();
+        let json = serde_json::to_string(&data).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["fudes"]["H1"]["surface"], "S1");
+        assert_eq!(value["curves"]["C1"][0]["indirect"], "P1");
+
+        let read: ParsedData = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.metadata.crs, data.metadata.crs);
+        assert_eq!(read.points.len(), data.points.len());
+        assert_eq!(read.fudes.len(), data.fudes.len());
+        for (key, fude) in &data.fudes {
+            let read_fude = &read.fudes[key];
+            assert_eq!(read_fude.attributes.chiban, fude.attributes.chiban);
+            assert_eq!(
+                read.resolve_surface(read_fude.surface_id).unwrap(),
+                data.resolve_surface(fude.surface_id).unwrap()
+            );
+        }
+        // the same document order
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+
+        for rings in [serde_json::json!([]), serde_json::json!([[]])] {
+            let mut value = value.clone();
+            value["surfaces"]["S1"] = rings;
+            let error = serde_json::from_value::<ParsedData>(value).err().unwrap();
+            assert!(error.to_string().contains("surface S1 with an empty ring"));
+        }
+    }
+}
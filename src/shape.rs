@@ -0,0 +1,128 @@
+//! Shape measures of resolved 筆 (see
+//! [`ParsedData::resolve_surface_geo`](crate::data::ParsedData::resolve_surface_geo)),
+//! e.g. to find elongated 旗竿地 (flagpole lots).
+//!
+//! Lengths and areas are in the units of the coordinates: meters with
+//! [`ParseOptions::keep_source_coordinates`](crate::parser::ParseOptions),
+//! degrees in longitude/latitude.
+
+use geo::{Area, ConvexHull, Line, MinimumRotatedRect, Polygon};
+
+/// Shape measures of a polygon.
+pub trait ParcelShape {
+    /// Convex hull.
+    fn hull(&self) -> Polygon;
+
+    /// Oriented minimum bounding rectangle: the enclosing rectangle with the
+    /// smallest area, in any orientation. `None` for an empty polygon.
+    fn oriented_mbr(&self) -> Option<Polygon>;
+
+    /// Width and length (the shorter and longer sides) of the
+    /// [`oriented_mbr`](Self::oriented_mbr).
+    fn mbr_size(&self) -> Option<(f64, f64)>;
+
+    /// Longest edge of the rings.
+    fn longest_edge(&self) -> Option<Line>;
+
+    /// Length over width of the [`oriented_mbr`](Self::oriented_mbr): 1 for
+    /// a square, large for a long strip. Infinite when the polygon has no
+    /// width.
+    fn elongation(&self) -> Option<f64>;
+
+    /// Area over the area of the [`hull`](Self::hull): 1 for a convex
+    /// polygon, low for a flagpole lot. `None` without area.
+    fn convexity(&self) -> Option<f64>;
+}
+
+impl ParcelShape for Polygon {
+    fn hull(&self) -> Polygon {
+        self.convex_hull()
+    }
+
+    fn oriented_mbr(&self) -> Option<Polygon> {
+        self.minimum_rotated_rect()
+    }
+
+    fn mbr_size(&self) -> Option<(f64, f64)> {
+        let mbr = self.oriented_mbr()?;
+        let corners = &mbr.exterior().0;
+        let side = |i: usize| {
+            let (a, b) = (corners.get(i)?, corners.get(i + 1)?);
+            Some((b.x - a.x).hypot(b.y - a.y))
+        };
+        let (a, b) = (side(0)?, side(1)?);
+        Some((a.min(b), a.max(b)))
+    }
+
+    fn longest_edge(&self) -> Option<Line> {
+        std::iter::once(self.exterior())
+            .chain(self.interiors())
+            .flat_map(|ring| ring.lines())
+            .max_by(|a, b| length(a).total_cmp(&length(b)))
+    }
+
+    fn elongation(&self) -> Option<f64> {
+        let (width, length) = self.mbr_size()?;
+        Some(if width > 0.0 {
+            length / width
+        } else if length > 0.0 {
+            f64::INFINITY
+        } else {
+            1.0
+        })
+    }
+
+    fn convexity(&self) -> Option<f64> {
+        let hull_area = self.hull().unsigned_area();
+        (hull_area > 0.0).then(|| self.unsigned_area() / hull_area)
+    }
+}
+
+fn length(line: &Line) -> f64 {
+    line.dx().hypot(line.dy())
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParcelShape;
+    use geo::{Area, polygon};
+    use test_log::test;
+
+    #[test]
+    fn test_shape() {
+        // a flagpole lot: a 10 m square with a 2 m x 20 m pole
+        let flagpole = polygon![
+            (x: 0.0, y: 0.0),
+            (x: 2.0, y: 0.0),
+            (x: 2.0, y: 20.0),
+            (x: 10.0, y: 20.0),
+            (x: 10.0, y: 30.0),
+            (x: 0.0, y: 30.0),
+        ];
+        assert_eq!(
+            flagpole.hull().unsigned_area(),
+            10.0 * 30.0 - 8.0 * 20.0 / 2.0
+        );
+        let convexity = flagpole.convexity().unwrap();
+        assert!((convexity - 140.0 / 220.0).abs() < 1e-9);
+        let edge = flagpole.longest_edge().unwrap();
+        assert_eq!((edge.start.x, edge.start.y, edge.end.y), (0.0, 30.0, 0.0));
+
+        let strip = polygon![
+            (x: 0.0, y: 0.0),
+            (x: 30.0, y: 30.0),
+            (x: 29.0, y: 31.0),
+            (x: -1.0, y: 1.0),
+        ];
+        let (width, length) = strip.mbr_size().unwrap();
+        assert!((width - 2f64.sqrt()).abs() < 1e-9);
+        assert!((length - 30.0 * 2f64.sqrt()).abs() < 1e-9);
+        assert!((strip.elongation().unwrap() - 30.0).abs() < 1e-9);
+        assert_eq!(strip.oriented_mbr().unwrap().exterior().0.len(), 5);
+
+        let empty = polygon![];
+        assert_eq!(empty.oriented_mbr(), None);
+        assert_eq!(empty.longest_edge(), None);
+        assert_eq!(empty.convexity(), None);
+    }
+}
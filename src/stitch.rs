@@ -0,0 +1,204 @@
+//! Stitching of the 筆 split across 図郭 boundaries.
+//!
+//! A 筆 crossing the boundary of two sheets is sometimes cut into pieces, one
+//! in each XML file. [`Stitcher`] collects the 筆 of all the files and groups
+//! the pieces with the same key (see [`dedup_key`](crate::dedup::dedup_key))
+//! that share a boundary, to union them into a single 筆.
+
+use geo::{BooleanOps, MultiPolygon, Polygon, Relate};
+use hashbrown::HashMap;
+
+struct Piece<T> {
+    source: String,
+    seq: usize,
+    polygon: Polygon,
+    item: T,
+}
+
+/// Collects 筆 (with any payload `T`) and stitches their pieces.
+pub struct Stitcher<T> {
+    groups: HashMap<[String; 4], Vec<Piece<T>>>,
+    unique: Vec<(Polygon, T)>,
+    seq: usize,
+}
+
+impl<T> Default for Stitcher<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Stitcher<T> {
+    pub fn new() -> Self {
+        Self {
+            groups: HashMap::new(),
+            unique: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    /// Add a 筆 of the file `source`, with its key (see
+    /// [`dedup_key`](crate::dedup::dedup_key)).
+    pub fn insert(&mut self, key: Option<[String; 4]>, source: &str, polygon: Polygon, item: T) {
+        let Some(key) = key else {
+            self.unique.push((polygon, item));
+            return;
+        };
+        self.groups.entry(key).or_default().push(Piece {
+            source: source.to_string(),
+            seq: self.seq,
+            polygon,
+            item,
+        });
+        self.seq += 1;
+    }
+
+    /// The groups of pieces of the same 筆: pieces of different files, with
+    /// the same key, that share a boundary (or overlap), each group in the
+    /// order of the file names. The other 筆 are groups of one.
+    pub fn groups(self) -> Vec<Vec<(Polygon, T)>> {
+        let mut groups: Vec<Vec<(Polygon, T)>> = self
+            .unique
+            .into_iter()
+            .map(|piece| vec![piece])
+            .collect();
+        for pieces in self.groups.into_values() {
+            groups.extend(cluster(pieces).into_iter().map(|cluster| {
+                cluster
+                    .into_iter()
+                    .map(|piece| (piece.polygon, piece.item))
+                    .collect()
+            }));
+        }
+        groups
+    }
+
+    /// The stitched 筆: the pieces of each group (see [`groups`](Self::groups))
+    /// unioned into one polygon, with the item of the first file. A group
+    /// whose union isn't a single polygon is kept as is. Also returns the
+    /// number of pieces merged into others.
+    pub fn finish(self) -> (Vec<(Polygon, T)>, usize) {
+        let mut stitched = Vec::new();
+        let mut merged = 0;
+        for group in self.groups() {
+            if group.len() == 1 {
+                stitched.extend(group);
+                continue;
+            }
+            let union = group
+                .iter()
+                .fold(MultiPolygon::new(vec![]), |union, (polygon, _)| {
+                    union.union(polygon)
+                });
+            match <[Polygon; 1]>::try_from(union.0) {
+                Ok([polygon]) => {
+                    merged += group.len() - 1;
+                    let (_, item) = group.into_iter().next().unwrap();
+                    stitched.push((polygon, item));
+                }
+                Err(_) => stitched.extend(group),
+            }
+        }
+        (stitched, merged)
+    }
+}
+
+/// Split the pieces with the same key into groups of connected pieces.
+fn cluster<T>(mut pieces: Vec<Piece<T>>) -> Vec<Vec<Piece<T>>> {
+    pieces.sort_by(|a, b| (&a.source, a.seq).cmp(&(&b.source, b.seq)));
+    // union-find over the pairs of adjacent pieces
+    let mut parents: Vec<usize> = (0..pieces.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for i in 0..pieces.len() {
+        for j in i + 1..pieces.len() {
+            if pieces[i].source != pieces[j].source
+                && adjacent(&pieces[i].polygon, &pieces[j].polygon)
+            {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let roots: Vec<usize> = (0..pieces.len())
+        .map(|i| root(&mut parents, i))
+        .collect();
+    let mut clusters: Vec<Vec<Piece<T>>> = Vec::new();
+    let mut cluster_of: HashMap<usize, usize> = HashMap::new();
+    for (piece, root) in pieces.into_iter().zip(roots) {
+        let index = *cluster_of.entry(root).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[index].push(piece);
+    }
+    clusters
+}
+
+/// Whether two polygons share a stretch of boundary, or overlap.
+fn adjacent(a: &Polygon, b: &Polygon) -> bool {
+    let matrix = a.relate(b);
+    matrix.is_intersects()
+        && (matrix.matches("T********").unwrap_or(false)
+            || matrix.matches("****1****").unwrap_or(false))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::FudeAttributes;
+    use crate::dedup::dedup_key;
+    use geo::{Area, Rect, coord};
+    use test_log::test;
+
+    fn rect(x0: f64, x1: f64, y0: f64) -> Polygon {
+        Rect::new(coord! { x: x0, y: y0 }, coord! { x: x1, y: y0 + 1.0 }).to_polygon()
+    }
+
+    fn key(chiban: &str) -> Option<[String; 4]> {
+        let attributes = FudeAttributes {
+            oaza_code: Some("001".into()),
+            chiban: Some(chiban.into()),
+            ..Default::default()
+        };
+        dedup_key(Some("13101"), &attributes)
+    }
+
+    #[test]
+    fn test_stitch() {
+        let mut stitcher = Stitcher::new();
+        // 1 is cut by the boundary x = 1 of the sheets a and b
+        stitcher.insert(key("1"), "b.xml", rect(1.0, 3.0, 0.0), "b1");
+        stitcher.insert(key("1"), "a.xml", rect(0.0, 1.0, 0.0), "a1");
+        // 2 only touches at a corner, 3 is in the same file
+        stitcher.insert(key("2"), "a.xml", rect(0.0, 1.0, 5.0), "a2");
+        stitcher.insert(key("2"), "b.xml", rect(1.0, 2.0, 6.0), "b2");
+        stitcher.insert(key("3"), "a.xml", rect(0.0, 1.0, 9.0), "a3");
+        stitcher.insert(key("3"), "a.xml", rect(1.0, 2.0, 9.0), "a3'");
+        stitcher.insert(None, "a.xml", rect(0.0, 1.0, 0.0), "a-");
+
+        let (stitched, merged) = stitcher.finish();
+        assert_eq!(merged, 1);
+        let mut items: Vec<_> = stitched
+            .iter()
+            .map(|(polygon, item)| (*item, polygon.unsigned_area()))
+            .collect();
+        items.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(
+            items,
+            [
+                ("a-", 1.0),
+                ("a1", 3.0),
+                ("a2", 1.0),
+                ("a3", 1.0),
+                ("a3'", 1.0),
+                ("b2", 1.0)
+            ]
+        );
+    }
+}
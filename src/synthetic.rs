@@ -0,0 +1,175 @@
+//! Synthetic 地図XML, for benchmarks and tests.
+//!
+//! [`SyntheticMap`] generates a valid file of any size without the registry
+//! data: a grid of square 筆 in 公共座標9系, whose corners are shared
+//! `GM_Point`s referenced by the `GM_Curve`s, like in the real files.
+
+use std::fmt::Write;
+
+/// A grid of `rows` × `columns` square 筆.
+#[derive(Clone, Debug)]
+pub struct SyntheticMap {
+    rows: usize,
+    columns: usize,
+    cell_size: f64,
+    origin: [f64; 2],
+    municipality_code: String,
+}
+
+impl SyntheticMap {
+    pub fn new(rows: usize, columns: usize) -> Self {
+        Self {
+            rows,
+            columns,
+            cell_size: 20.0,
+            // near Tokyo Station
+            origin: [-35000.0, -6000.0],
+            municipality_code: "13101".to_string(),
+        }
+    }
+
+    /// The side of the 筆, in metres (20 by default).
+    pub fn with_cell_size(mut self, metres: f64) -> Self {
+        self.cell_size = metres;
+        self
+    }
+
+    /// The 市区町村コード of the file ("13101" by default).
+    pub fn with_municipality_code(mut self, code: &str) -> Self {
+        self.municipality_code = code.to_string();
+        self
+    }
+
+    /// The number of 筆.
+    pub fn fude_count(&self) -> usize {
+        self.rows * self.columns
+    }
+
+    /// Generate the XML document.
+    pub fn to_xml(&self) -> String {
+        let (rows, columns) = (self.rows, self.columns);
+        // about 2.4 kB per 筆
+        let mut xml = String::with_capacity(2500 * self.fude_count() + 1000);
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<地図 xmlns=\"http://www.moj.go.jp/MINJI/tizuxml\" xmlns:zmn=\"http://www.moj.go.jp/MINJI/tizuzumen\">\n");
+        xml.push_str("<地図名>synthetic</地図名>\n");
+        let _ = writeln!(
+            xml,
+            "<市区町村コード>{}</市区町村コード>",
+            self.municipality_code
+        );
+        xml.push_str("<市区町村名>合成市</市区町村名>\n");
+        xml.push_str("<座標系>公共座標9系</座標系>\n");
+        xml.push_str("<測地系判別>測地系2000</測地系判別>\n");
+
+        xml.push_str("<空間属性>\n");
+        // the corners, row by row from the south-west
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (x, y) = (
+                    self.origin[0] + row as f64 * self.cell_size,
+                    self.origin[1] + column as f64 * self.cell_size,
+                );
+                let _ = writeln!(
+                    xml,
+                    "<zmn:GM_Point id=\"P{}\"><zmn:GM_Point.position><zmn:DirectPosition><zmn:X>{:.2}</zmn:X><zmn:Y>{:.2}</zmn:Y></zmn:DirectPosition></zmn:GM_Point.position></zmn:GM_Point>",
+                    row * (columns + 1) + column + 1,
+                    x,
+                    y
+                );
+            }
+        }
+        for cell in 0..self.fude_count() {
+            let (row, column) = (cell / columns, cell % columns);
+            let corner = |dr: usize, dc: usize| (row + dr) * (columns + 1) + column + dc + 1;
+            // counterclockwise
+            let corners = [corner(0, 0), corner(0, 1), corner(1, 1), corner(1, 0)];
+            for side in 0..4 {
+                let _ = write!(
+                    xml,
+                    "<zmn:GM_Curve id=\"C{}\"><zmn:GM_Curve.segment><zmn:GM_LineString><zmn:GM_LineString.controlPoint><zmn:GM_PointArray>",
+                    4 * cell + side + 1
+                );
+                for point in [corners[side], corners[(side + 1) % 4]] {
+                    let _ = write!(
+                        xml,
+                        "<zmn:GM_PointArray.column><zmn:GM_Position.indirect><zmn:GM_PointRef.point idref=\"P{}\"/></zmn:GM_Position.indirect></zmn:GM_PointArray.column>",
+                        point
+                    );
+                }
+                xml.push_str("</zmn:GM_PointArray></zmn:GM_LineString.controlPoint></zmn:GM_LineString></zmn:GM_Curve.segment></zmn:GM_Curve>\n");
+            }
+            let _ = write!(
+                xml,
+                "<zmn:GM_Surface id=\"S{}\"><zmn:GM_Surface.patch><zmn:GM_Polygon><zmn:GM_Polygon.boundary><zmn:GM_SurfaceBoundary><zmn:GM_SurfaceBoundary.exterior><zmn:GM_Ring>",
+                cell + 1
+            );
+            for side in 0..4 {
+                let _ = write!(
+                    xml,
+                    "<zmn:GM_CompositeCurve.generator idref=\"C{}\"/>",
+                    4 * cell + side + 1
+                );
+            }
+            xml.push_str("</zmn:GM_Ring></zmn:GM_SurfaceBoundary.exterior></zmn:GM_SurfaceBoundary></zmn:GM_Polygon.boundary></zmn:GM_Polygon></zmn:GM_Surface.patch></zmn:GM_Surface>\n");
+        }
+        xml.push_str("</空間属性>\n");
+
+        xml.push_str("<主題属性>\n");
+        for cell in 0..self.fude_count() {
+            // a 大字 every 10 rows
+            let oaza = cell / columns / 10 + 1;
+            let _ = writeln!(
+                xml,
+                "<筆 id=\"H{}\"><大字コード>{:03}</大字コード><大字名>大字{}</大字名><地番>{}</地番><精度区分>甲二</精度区分><座標値種別>測量成果</座標値種別><形状 idref=\"S{}\"/></筆>",
+                cell + 1,
+                oaza,
+                oaza,
+                cell + 1,
+                cell + 1
+            );
+        }
+        xml.push_str("</主題属性>\n");
+        xml.push_str("</地図>\n");
+        xml
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+    use test_log::test;
+
+    #[test]
+    fn test_synthetic_map() {
+        let xml = SyntheticMap::new(3, 4).with_cell_size(10.0).to_xml();
+        let projections = jpr_projections();
+        let data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .with_options(ParseOptions {
+                keep_source_coordinates: true,
+                ..Default::default()
+            })
+            .parse()
+            .unwrap();
+        assert_eq!(data.metadata.municipality_code.as_deref(), Some("13101"));
+        assert_eq!(data.points.len(), 4 * 5);
+        assert_eq!(data.segments.len(), 4 * 12);
+        assert_eq!(data.surfaces.len(), 12);
+        assert_eq!(data.fudes.len(), 12);
+
+        let fude = &data.fudes["H6"];
+        assert_eq!(fude.attributes.chiban.as_deref(), Some("6"));
+        assert_eq!(fude.attributes.oaza_code.as_deref(), Some("001"));
+        let rings = data.resolve_surface(fude.surface_id).unwrap();
+        assert_eq!(
+            rings[0],
+            [
+                [-5990.0, -34990.0],
+                [-5980.0, -34990.0],
+                [-5980.0, -34980.0],
+                [-5990.0, -34980.0],
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Normalization of the attribute text, for the consumers of the outputs
+//! (often legacy software reading CSV or DBF) that don't handle some Unicode
+//! forms.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::data::FudeAttributes;
+
+/// The normalizations applied by [`normalize`](Self::normalize). The default
+/// leaves the text as is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextNormalization {
+    /// Normalize to the Unicode Normalization Form C (NFC). For Japanese
+    /// text, this composes the kana followed by a combining (semi-)voiced
+    /// sound mark into a single character (`か` + `◌゙` to `が`), as
+    /// decomposed by some software (e.g. in macOS file names), and replaces
+    /// the CJK compatibility ideographs by their unified forms (U+F91D `欄`
+    /// to U+6B04 `欄`).
+    pub nfc: bool,
+    /// Convert the full-width ASCII letters, digits and symbols, and the
+    /// ideographic space, to their half-width forms: `１２－３` to `12-3`.
+    pub halfwidth: bool,
+}
+
+impl TextNormalization {
+    /// Normalize the text attributes of a 筆: the codes, names, 地番,
+    /// 精度区分, ..., the 市区町村名 and the [`extra`](FudeAttributes::extra)
+    /// attributes.
+    pub fn normalize(&self, attributes: &mut FudeAttributes) {
+        if *self == Self::default() {
+            return;
+        }
+        for value in [
+            &mut attributes.oaza_code,
+            &mut attributes.chome_code,
+            &mut attributes.koaza_code,
+            &mut attributes.yobi_code,
+            &mut attributes.oaza,
+            &mut attributes.chome,
+            &mut attributes.koaza,
+            &mut attributes.yobi,
+            &mut attributes.chiban,
+            &mut attributes.hikkai_mitei,
+            &mut attributes.accuracy_class,
+            &mut attributes.coord_class,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let Cow::Owned(normalized) = self.apply(value) {
+                *value = normalized.into();
+            }
+        }
+        if let Some(name) = &mut attributes.municipality_name
+            && let Cow::Owned(normalized) = self.apply(name)
+        {
+            *name = Arc::from(normalized);
+        }
+        for (_, value) in &mut attributes.extra {
+            if let Cow::Owned(normalized) = self.apply(value) {
+                *value = normalized;
+            }
+        }
+    }
+
+    /// Normalize a text, borrowing it when it's unchanged.
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let text = if self.nfc && !unicode_normalization::is_nfc(text) {
+            Cow::Owned(text.nfc().collect())
+        } else {
+            Cow::Borrowed(text)
+        };
+        if !self.halfwidth || !text.chars().any(|c| halfwidth(c).is_some()) {
+            return text;
+        }
+        Cow::Owned(text.chars().map(|c| halfwidth(c).unwrap_or(c)).collect())
+    }
+}
+
+/// The half-width form of a full-width ASCII character.
+fn halfwidth(c: char) -> Option<char> {
+    match c {
+        '！'..='～' => char::from_u32(c as u32 - 0xFEE0),
+        '\u{3000}' => Some(' '),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TextNormalization;
+    use crate::data::FudeAttributes;
+    use test_log::test;
+
+    #[test]
+    fn test_normalize() {
+        let both = TextNormalization {
+            nfc: true,
+            halfwidth: true,
+        };
+        assert_eq!(
+            both.apply("か\u{3099}ほ\u{309A}ウ\u{3099}ワ\u{3099}"),
+            "がぽヴヷ"
+        );
+        assert_eq!(both.apply("１２－３　Ａ"), "12-3 A");
+        assert!(matches!(
+            both.apply("丸の内12-3"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+        // a lone mark, or a mark after a kana that doesn't take it, is kept
+        assert_eq!(both.apply("\u{3099}あ\u{3099}"), "\u{3099}あ\u{3099}");
+        assert_eq!(both.apply("\u{F91D}e\u{301}"), "\u{6B04}\u{E9}");
+
+        let halfwidth = TextNormalization {
+            halfwidth: true,
+            ..Default::default()
+        };
+        let mut attributes = FudeAttributes {
+            oaza: Some("は\u{309A}１".into()),
+            chiban: Some("１２".into()),
+            extra: vec![("note".into(), "Ｘ".into())],
+            ..Default::default()
+        };
+        halfwidth.normalize(&mut attributes);
+        assert_eq!(attributes.oaza.as_deref(), Some("は\u{309A}1"));
+        assert_eq!(attributes.chiban.as_deref(), Some("12"));
+        assert_eq!(attributes.extra("note"), Some("X"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Reference and geometry checks of parsed data.
+
+use geo::line_intersection::{LineIntersection, line_intersection};
+use geo::{Area, Coord, Line, LineString, Polygon, Relate};
+use jprect::etmerc::ExtendedTransverseMercatorProjection;
+use smallvec::SmallVec;
+
+use crate::data::{Id, ParsedData, Point};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The feature can't be converted (or is converted into an invalid geometry)
+    Error,
+    /// The feature is converted, but should be checked
+    Warning,
+}
+
+/// Kinds of issues found by [`validate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IssueKind {
+    /// A surface, curve or point referenced by the feature doesn't exist
+    MissingReference,
+    /// A ring has fewer than 3 distinct points
+    TooFewPoints,
+    /// A curve of a ring doesn't end where the next one starts
+    RingNotClosed,
+    /// Two edges of the polygon cross or overlap
+    SelfIntersects,
+    /// The exterior ring has no area
+    ZeroArea,
+    /// An interior ring contains the exterior ring: the file lists the rings
+    /// in the wrong roles (see [`fix_ring_roles`])
+    RingRolesSwapped,
+    /// An interior ring isn't inside the exterior ring
+    HoleOutsideExterior,
+    /// Two interior rings overlap
+    HolesOverlap,
+}
+
+impl IssueKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            IssueKind::MissingReference => "missing_reference",
+            IssueKind::TooFewPoints => "too_few_points",
+            IssueKind::RingNotClosed => "ring_not_closed",
+            IssueKind::SelfIntersects => "self_intersects",
+            IssueKind::ZeroArea => "zero_area",
+            IssueKind::RingRolesSwapped => "ring_roles_swapped",
+            IssueKind::HoleOutsideExterior => "hole_outside_exterior",
+            IssueKind::HolesOverlap => "holes_overlap",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            IssueKind::MissingReference | IssueKind::TooFewPoints => Severity::Error,
+            IssueKind::RingNotClosed
+            | IssueKind::SelfIntersects
+            | IssueKind::ZeroArea
+            | IssueKind::RingRolesSwapped
+            | IssueKind::HoleOutsideExterior
+            | IssueKind::HolesOverlap => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// id of the 筆 (or 基準点, 筆界点, 図郭)
+    pub id: String,
+    pub message: String,
+}
+
+/// Check the 筆 and the other parsed features. The issues are sorted by id.
+pub fn validate(data: &ParsedData) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for fude in data.fudes.values() {
+        check_surface(data, &fude.attributes.id, fude.surface_id, &mut issues);
+    }
+    for feature in data
+        .kijunten
+        .iter()
+        .chain(&data.hikkaiten)
+        .chain(&data.zukaku)
+    {
+        if data.resolve_feature_geo(feature).is_none() {
+            issues.push(Issue {
+                kind: IssueKind::MissingReference,
+                id: feature.id.clone(),
+                message: format!(
+                    "Geometry id={} not found",
+                    data.ids.name(feature.geometry_id)
+                ),
+            });
+        }
+    }
+    issues.sort_by(|a, b| a.id.cmp(&b.id));
+    issues
+}
+
+/// Names of the quality flags set by [`set_qa_flags`], as
+/// [`extra`](crate::data::FudeAttributes::extra) attributes. Add them to the
+/// output with [`Schema::with_extra_column`](crate::writer::Schema::with_extra_column).
+pub const QA_FLAGS: [&str; 4] = [
+    "ring_not_closed",
+    "self_intersects",
+    "tiny_area",
+    "arbitrary_crs",
+];
+
+/// Set the quality flags of each 筆, `"true"` or `"false"`, so that
+/// consumers can filter on them without checking the geometries again:
+///
+/// - `ring_not_closed` and `self_intersects`: the issues of [`validate`]
+/// - `tiny_area`: an exterior ring without area, or an area below
+///   `min_area` m² (computed like [`ParsedData::set_computed_areas`], with
+///   the same `projections`)
+/// - `arbitrary_crs`: the file is in a 任意座標系
+pub fn set_qa_flags(
+    data: &mut ParsedData,
+    projections: Option<&[ExtendedTransverseMercatorProjection; 19]>,
+    min_area: f64,
+) {
+    let arbitrary_crs = data.metadata.crs.as_deref() == Some("任意座標系");
+    let flags: Vec<(String, [bool; 4])> = data
+        .planar_areas(projections)
+        .into_iter()
+        .map(|(key, area)| {
+            let fude = &data.fudes[&key];
+            let mut issues = Vec::new();
+            check_surface(data, &fude.attributes.id, fude.surface_id, &mut issues);
+            let has = |kind| issues.iter().any(|issue| issue.kind == kind);
+            let tiny_area = has(IssueKind::ZeroArea) || area.is_some_and(|area| area < min_area);
+            let flags = [
+                has(IssueKind::RingNotClosed),
+                has(IssueKind::SelfIntersects),
+                tiny_area,
+                arbitrary_crs,
+            ];
+            (key, flags)
+        })
+        .collect();
+    for (key, flags) in flags {
+        let attributes = &mut data.fudes.get_mut(&key).unwrap().attributes;
+        for (name, flag) in QA_FLAGS.into_iter().zip(flags) {
+            attributes.set_extra(name, if flag { "true" } else { "false" });
+        }
+    }
+}
+
+/// Swap the exterior ring of the surfaces with an interior ring containing
+/// it (the [`IssueKind::RingRolesSwapped`] issues), which renderers draw
+/// inverted. Returns the number of surfaces fixed.
+pub fn fix_ring_roles(data: &mut ParsedData) -> usize {
+    let swapped: Vec<(Id, usize)> = data
+        .surfaces
+        .iter()
+        .filter(|(_, surface)| surface.len() > 1)
+        .filter_map(|(id, surface)| {
+            let polygons = surface
+                .rings()
+                .map(|ring| {
+                    let curves = ring
+                        .iter()
+                        .map(|&curve_id| data.resolve_curve(curve_id))
+                        .collect::<Result<Curves, _>>()?;
+                    Ok(ring_polygon(&curves))
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .ok()?;
+            Some((id, containing_ring(&polygons)?))
+        })
+        .collect();
+    for &(id, exterior) in &swapped {
+        data.surfaces.set_exterior(id, exterior);
+    }
+    swapped.len()
+}
+
+/// The start and end points of the curves of a ring (a handful, mostly).
+type Curves = SmallVec<[[Point; 2]; 16]>;
+
+fn ring_polygon(curves: &Curves) -> Polygon {
+    Polygon::new(
+        LineString::from_iter(curves.iter().map(|[start, _]| coord(*start))),
+        vec![],
+    )
+}
+
+/// The interior ring containing the exterior ring (the first of the
+/// polygons), if any.
+fn containing_ring(polygons: &[Polygon]) -> Option<usize> {
+    if polygons[0].unsigned_area() == 0.0 {
+        return None;
+    }
+    (1..polygons.len()).find(|&i| polygons[i].relate(&polygons[0]).is_contains())
+}
+
+fn check_surface(data: &ParsedData, id: &str, surface_id: Id, issues: &mut Vec<Issue>) {
+    let mut issue = |kind, message| {
+        issues.push(Issue {
+            kind,
+            id: id.to_string(),
+            message,
+        })
+    };
+    let Some(surface) = data.surfaces.get(surface_id) else {
+        issue(
+            IssueKind::MissingReference,
+            format!("Surface id={} not found", data.ids.name(surface_id)),
+        );
+        return;
+    };
+
+    let mut rings: SmallVec<[Curves; 1]> = SmallVec::with_capacity(surface.len());
+    for (ring_idx, ring) in surface.rings().enumerate() {
+        let curves = match ring
+            .iter()
+            .map(|&curve_id| data.resolve_curve(curve_id))
+            .collect::<Result<Curves, _>>()
+        {
+            Ok(curves) => curves,
+            Err(e) => return issue(IssueKind::MissingReference, e),
+        };
+        let starts: SmallVec<[Point; 16]> = curves.iter().map(|[start, _]| *start).collect();
+        let distinct = (0..starts.len())
+            .filter(|&i| !starts[..i].contains(&starts[i]))
+            .count();
+        if distinct < 3 {
+            return issue(
+                IssueKind::TooFewPoints,
+                format!("Ring {} has {} distinct points", ring_idx, distinct),
+            );
+        }
+        if let Some(i) =
+            (0..curves.len()).find(|&i| curves[i][1] != curves[(i + 1) % curves.len()][0])
+        {
+            issue(
+                IssueKind::RingNotClosed,
+                format!(
+                    "Ring {}: curve id={} doesn't end where curve id={} starts",
+                    ring_idx,
+                    data.ids.name(ring[i]),
+                    data.ids.name(ring[(i + 1) % ring.len()])
+                ),
+            );
+        }
+        rings.push(curves);
+    }
+
+    let lines: Vec<(usize, Line)> = rings
+        .iter()
+        .enumerate()
+        .flat_map(|(ring_idx, curves)| curves.iter().map(move |curves| (ring_idx, curves)))
+        .map(|(ring_idx, [start, end])| (ring_idx, Line::new(coord(*start), coord(*end))))
+        .collect();
+    // the rings crossing themselves, whose roles can't be checked
+    let mut crossed: SmallVec<[bool; 1]> = SmallVec::from_elem(false, rings.len());
+    let mut self_intersects = false;
+    for (i, (ring_a, a)) in lines.iter().enumerate() {
+        for (ring_b, b) in &lines[i + 1..] {
+            let at = match line_intersection(*a, *b) {
+                Some(LineIntersection::SinglePoint {
+                    intersection,
+                    is_proper: true,
+                }) => intersection,
+                Some(LineIntersection::Collinear { intersection })
+                    if intersection.start != intersection.end =>
+                {
+                    intersection.start
+                }
+                _ => continue,
+            };
+            if !self_intersects {
+                issue(
+                    IssueKind::SelfIntersects,
+                    format!("Edges cross at ({}, {})", at.x, at.y),
+                );
+                self_intersects = true;
+            }
+            if ring_a == ring_b {
+                crossed[*ring_a] = true;
+            }
+        }
+    }
+
+    let polygons: SmallVec<[Polygon; 1]> = rings.iter().map(ring_polygon).collect();
+    if polygons[0].unsigned_area() == 0.0 {
+        return issue(
+            IssueKind::ZeroArea,
+            "The exterior ring has no area".to_string(),
+        );
+    }
+    if polygons.len() == 1 || crossed[0] {
+        return;
+    }
+    let exterior = match containing_ring(&polygons) {
+        Some(i) => {
+            issue(
+                IssueKind::RingRolesSwapped,
+                format!("Interior ring {} contains the exterior ring", i),
+            );
+            i
+        }
+        None => 0,
+    };
+    let holes: SmallVec<[usize; 4]> = (0..polygons.len())
+        .filter(|&i| i != exterior && !crossed[i] && polygons[i].unsigned_area() > 0.0)
+        .collect();
+    // after a swap, the ring listed as exterior is inside by definition
+    for &i in &holes {
+        if i != 0 && !polygons[exterior].relate(&polygons[i]).is_contains() {
+            issue(
+                IssueKind::HoleOutsideExterior,
+                format!("Interior ring {} isn't inside the exterior ring", i),
+            );
+        }
+    }
+    for (n, &i) in holes.iter().enumerate() {
+        for &j in &holes[n + 1..] {
+            let matrix = polygons[i].relate(&polygons[j]);
+            if matrix.is_intersects() && !matrix.is_touches() {
+                issue(
+                    IssueKind::HolesOverlap,
+                    format!("Interior rings {} and {} overlap", i, j),
+                );
+            }
+        }
+    }
+}
+
+fn coord([x, y]: Point) -> Coord {
+    Coord { x, y }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IssueKind, fix_ring_roles, set_qa_flags, validate};
+    use crate::data::{Fude, FudeAttributes, Id, Ids, ParsedData, PointRef, Surfaces};
+    use hashbrown::HashMap;
+    use test_log::test;
+
+    fn fude(id: &str, surface_id: Id) -> (String, Fude<'static>) {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: id.to_string().into(),
+                ..Default::default()
+            },
+            surface_id,
+        };
+        (id.to_string(), fude)
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut ids = Ids::default();
+        let curves = ["C0", "C1", "C2", "C3"].map(|id| ids.intern(id));
+        let [c9, s1, s2, s3] = ["C9", "S1", "S2", "S3"].map(|id| ids.intern(id));
+        // a bow tie: (0 0) -> (1 1) -> (1 0) -> (0 1)
+        let corners = [[0.0, 0.0], [1.0, 1.0], [1.0, 0.0], [0.0, 1.0]];
+        let segments = (0..4)
+            .map(|i| {
+                let curve = [
+                    PointRef::Direct(corners[i]),
+                    PointRef::Direct(corners[(i + 1) % 4]),
+                ];
+                (curves[i], curve)
+            })
+            .collect();
+        let data = ParsedData {
+            segments,
+            surfaces: Surfaces::from_iter([
+                (s1, vec![curves.to_vec()]),
+                (s2, vec![vec![curves[0], c9]]),
+            ]),
+            fudes: HashMap::from([fude("H1", s1), fude("H2", s2), fude("H3", s3)]),
+            ids,
+            ..Default::default()
+        };
+
+        let issues = validate(&data);
+        let kinds: Vec<_> = issues.iter().map(|i| (i.id.as_str(), i.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                ("H1", IssueKind::SelfIntersects),
+                // the two halves cancel out
+                ("H1", IssueKind::ZeroArea),
+                ("H2", IssueKind::MissingReference),
+                ("H3", IssueKind::MissingReference),
+            ]
+        );
+        assert_eq!(issues[0].message, "Edges cross at (0.5, 0.5)");
+        assert_eq!(issues[2].message, "Curve id=C9 not found");
+
+        let mut data = data;
+        set_qa_flags(&mut data, None, 1.0);
+        let attributes = &data.fudes["H1"].attributes;
+        assert_eq!(attributes.extra("self_intersects"), Some("true"));
+        assert_eq!(attributes.extra("tiny_area"), Some("true"));
+        assert_eq!(attributes.extra("ring_not_closed"), Some("false"));
+        assert_eq!(attributes.extra("arbitrary_crs"), Some("false"));
+    }
+
+    #[test]
+    fn test_ring_roles() {
+        let mut ids = Ids::default();
+        let mut segments = HashMap::new();
+        // the curves of a square ring, counter-clockwise
+        let mut square = |name: &str, [x, y]: [f64; 2], size: f64| {
+            let corners = [[x, y], [x + size, y], [x + size, y + size], [x, y + size]];
+            (0..4)
+                .map(|i| {
+                    let id = ids.intern(format!("{}{}", name, i));
+                    let curve = [
+                        PointRef::Direct(corners[i]),
+                        PointRef::Direct(corners[(i + 1) % 4]),
+                    ];
+                    segments.insert(id, curve);
+                    id
+                })
+                .collect::<Vec<_>>()
+        };
+        let small = square("A", [1.0, 1.0], 1.0);
+        let large = square("B", [0.0, 0.0], 4.0);
+        let outside = square("C", [5.0, 0.0], 1.0);
+        let overlapping = square("D", [1.5, 1.5], 1.0);
+        let [s1, s2, s3] = ["S1", "S2", "S3"].map(|id| ids.intern(id));
+        let mut data = ParsedData {
+            segments,
+            surfaces: Surfaces::from_iter([
+                (s1, vec![small.clone(), large.clone()]),
+                (s2, vec![large.clone(), outside]),
+                (s3, vec![large.clone(), small.clone(), overlapping]),
+            ]),
+            fudes: HashMap::from([fude("H1", s1), fude("H2", s2), fude("H3", s3)]),
+            ids,
+            ..Default::default()
+        };
+
+        let issues = validate(&data);
+        let kinds: Vec<_> = issues.iter().map(|i| (i.id.as_str(), i.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                ("H1", IssueKind::RingRolesSwapped),
+                ("H2", IssueKind::HoleOutsideExterior),
+                ("H3", IssueKind::SelfIntersects),
+                ("H3", IssueKind::HolesOverlap),
+            ]
+        );
+        assert_eq!(issues[3].message, "Interior rings 1 and 2 overlap");
+
+        assert_eq!(fix_ring_roles(&mut data), 1);
+        let surface = data.surfaces.get(s1).unwrap();
+        assert_eq!(surface.exterior(), large);
+        assert_eq!(surface.interiors().collect::<Vec<_>>(), [small]);
+        assert_eq!(data.surfaces.get(s2).unwrap().exterior(), large);
+        let issues = validate(&data);
+        assert!(issues.iter().all(|issue| issue.id != "H1"));
+    }
+}
@@ -0,0 +1,267 @@
+//! CSV / TSV attribute tables.
+
+use std::borrow::Cow;
+use std::io::Write;
+
+use super::{Error, FeatureSink, Schema, geo_rings, wkt};
+use crate::data::Fude;
+
+/// Character encoding of a CSV file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CsvEncoding {
+    #[default]
+    Utf8,
+    /// Shift_JIS (CP932), as expected by older versions of Excel and other
+    /// legacy software. Characters missing from it are written as numeric
+    /// character references (`&#12345;`).
+    ShiftJis,
+}
+
+impl CsvEncoding {
+    /// The encoded text, and whether some of its characters are missing from
+    /// the encoding.
+    fn encode<'a>(&self, s: &'a str) -> (Cow<'a, [u8]>, bool) {
+        match self {
+            CsvEncoding::Utf8 => (s.as_bytes().into(), false),
+            CsvEncoding::ShiftJis => {
+                let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(s);
+                (encoded, unmappable)
+            }
+        }
+    }
+}
+
+/// Writes one row per parcel with all the attribute columns, and optionally
+/// the geometry as a WKT column.
+pub struct CsvWriter<W: Write> {
+    out: W,
+    delimiter: u8,
+    encoding: CsvEncoding,
+    wkt: bool,
+    schema: Schema,
+    header_written: bool,
+    buf: String,
+    unmappable: usize,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Comma-separated values
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            delimiter: b',',
+            encoding: CsvEncoding::default(),
+            wkt: false,
+            schema: Schema::default(),
+            header_written: false,
+            buf: String::new(),
+            unmappable: 0,
+        }
+    }
+
+    /// Tab-separated values
+    pub fn new_tsv(out: W) -> Self {
+        Self {
+            delimiter: b'\t',
+            ..Self::new(out)
+        }
+    }
+
+    /// Append a `geometry` column in WKT.
+    pub fn with_wkt(mut self, wkt: bool) -> Self {
+        self.wkt = wkt;
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: CsvEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Write the header row before the first row (the default), e.g. not
+    /// when appending to a file that already has one.
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header_written = !header;
+        self
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        for (idx, column) in self.schema.columns().iter().enumerate() {
+            if idx > 0 {
+                self.out.write_all(&[self.delimiter])?;
+            }
+            let (name, unmappable) = self.encoding.encode(column.name());
+            self.unmappable += unmappable as usize;
+            write_field(&mut self.out, self.delimiter, &name)?;
+        }
+        if self.wkt {
+            self.out.write_all(&[self.delimiter])?;
+            self.out.write_all(b"geometry")?;
+        }
+        self.out.write_all(b"\r\n")?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        for (idx, column) in self.schema.columns().iter().enumerate() {
+            if idx > 0 {
+                self.out.write_all(&[self.delimiter])?;
+            }
+            if let Some(value) = column.get(&fude.attributes) {
+                let (value, unmappable) = self.encoding.encode(&value);
+                self.unmappable += unmappable as usize;
+                write_field(&mut self.out, self.delimiter, &value)?;
+            }
+        }
+        if self.wkt {
+            self.buf.clear();
+            wkt::write_polygon(&mut self.buf, geo_rings(polygon));
+            self.out.write_all(&[self.delimiter])?;
+            write_field(&mut self.out, self.delimiter, self.buf.as_bytes())?;
+        }
+        self.out.write_all(b"\r\n")?;
+        Ok(())
+    }
+
+    /// The number of fields written so far with characters missing from the
+    /// encoding, which were replaced by numeric character references.
+    pub fn unmappable(&self) -> usize {
+        self.unmappable
+    }
+
+    /// The underlying writer, e.g. to flush it.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Write an encoded field, quoting it if needed (RFC 4180). The delimiters,
+/// quote and line breaks are below the trail bytes of the Shift_JIS
+/// characters (0x40 and up), so they can be searched in the bytes.
+fn write_field(out: &mut impl Write, delimiter: u8, value: &[u8]) -> std::io::Result<()> {
+    if value
+        .iter()
+        .any(|&b| b == delimiter || b == b'"' || b == b'\r' || b == b'\n')
+    {
+        out.write_all(b"\"")?;
+        for chunk in value.split_inclusive(|&b| b == b'"') {
+            out.write_all(chunk)?;
+            if chunk.ends_with(b"\"") {
+                out.write_all(b"\"")?;
+            }
+        }
+        out.write_all(b"\"")
+    } else {
+        out.write_all(value)
+    }
+}
+
+impl<W: Write> FeatureSink for CsvWriter<W> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        CsvWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CsvEncoding, CsvWriter};
+    use crate::data::{Fude, FudeAttributes};
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_csv_wkt() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = CsvWriter::new(Vec::new()).with_wkt(true);
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.5, y: 35.0), (x: 139.5, y: 35.5)],
+            )
+            .unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "id,大字コード,丁目コード,小字コード,予備コード,大字名,丁目名,小字名,予備名,地番,精度区分,座標値種別,geometry"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            r#"H000000001,,,,,,,,,12-3,,,"POLYGON ((139 35, 139.5 35, 139.5 35.5, 139 35))""#
+        );
+    }
+
+    #[test]
+    fn test_csv_without_header() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H1".into(),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = CsvWriter::new(Vec::new()).with_header(false);
+        writer.write_fude(&fude, &polygon![]).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(out, "H1,,,,,,,,,,,\r\n");
+    }
+
+    #[test]
+    fn test_csv_shift_jis() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H1".into(),
+                oaza: Some(r#"大手町,"表""#.into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = CsvWriter::new(Vec::new()).with_encoding(CsvEncoding::ShiftJis);
+        writer.write_fude(&fude, &polygon![]).unwrap();
+        assert_eq!(writer.unmappable(), 0);
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H2".into(),
+                oaza: Some("𠮷野".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        writer.write_fude(&fude, &polygon![]).unwrap();
+        assert_eq!(writer.unmappable(), 1);
+        let out = writer.finish().unwrap();
+        // 表 is 0x95 0x5C
+        let (out, _, errors) = encoding_rs::SHIFT_JIS.decode(&out);
+        assert!(!errors);
+        assert_eq!(
+            out.lines().nth(1).unwrap(),
+            r#"H1,,,,,"大手町,""表""",,,,,,"#
+        );
+        assert_eq!(out.lines().nth(2).unwrap(), "H2,,,,,&#134071;野,,,,,,");
+    }
+}
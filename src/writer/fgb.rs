@@ -0,0 +1,265 @@
+//! FlatGeobuf output.
+
+use std::io::{Read, Seek, Write};
+
+use flatgeobuf::{
+    ColumnType, FallibleStreamingIterator, FgbCrs, FgbReader, FgbWriter, FgbWriterOptions,
+    GeometryType,
+};
+use geo::InteriorPoint;
+use geozero::{ColumnValue, FeatureProperties, PropertyProcessor, ToGeo};
+
+use super::schema::{self, Value};
+use super::{Error, FeatureSink, Layer, Schema};
+use crate::crs::Crs;
+use crate::data::{Feature, Fude};
+
+/// Writes parcels as polygons with the standard attribute columns.
+///
+/// Coordinates are expected to be JGD2011 longitude/latitude (EPSG:6668),
+/// unless another CRS is set with [`with_crs`](Self::with_crs).
+///
+/// Features are spooled to a temporary file (in [`std::env::temp_dir`]) as
+/// they are written, and only the nodes of the spatial index are kept in
+/// memory, so the output size is bounded by the disk rather than the RAM.
+pub struct FgbFudeWriter<'a> {
+    fgb: FgbWriter<'a>,
+    layer: Layer,
+    schema: Schema,
+    metadata: Option<String>,
+    crs: Crs,
+    /// Whether the parcels are written as points (see [`new_points_with_schema`](Self::new_points_with_schema))
+    points: bool,
+}
+
+impl<'a> FgbFudeWriter<'a> {
+    pub fn new() -> Result<Self, Error> {
+        Self::new_layer(Layer::Fude)
+    }
+
+    /// A parcel writer with the given attribute columns.
+    pub fn new_with_schema(schema: Schema) -> Result<Self, Error> {
+        Self::create(Layer::Fude, schema, None, Crs::default(), false)
+    }
+
+    /// A writer of one point per parcel, at a point on its surface (always
+    /// inside it, unlike the centroid), with the given attribute columns,
+    /// e.g. to place 地番 labels.
+    pub fn new_points_with_schema(schema: Schema) -> Result<Self, Error> {
+        Self::create(Layer::Fude, schema, None, Crs::default(), true)
+    }
+
+    /// A writer for one of the other layers, written with [`write_feature`](Self::write_feature).
+    pub fn new_layer(layer: Layer) -> Result<Self, Error> {
+        Self::create(layer, Schema::default(), None, Crs::default(), false)
+    }
+
+    /// Set the `metadata` of the header, e.g. [`Metadata::to_json`](super::Metadata::to_json).
+    ///
+    /// The features written so far are discarded, so call this first.
+    pub fn with_metadata(self, metadata: String) -> Result<Self, Error> {
+        Self::create(
+            self.layer,
+            self.schema,
+            Some(metadata),
+            self.crs,
+            self.points,
+        )
+    }
+
+    /// Set the CRS of the header.
+    ///
+    /// The features written so far are discarded, so call this first.
+    pub fn with_crs(self, crs: Crs) -> Result<Self, Error> {
+        Self::create(self.layer, self.schema, self.metadata, crs, self.points)
+    }
+
+    fn create(
+        layer: Layer,
+        schema: Schema,
+        metadata: Option<String>,
+        crs: Crs,
+        points: bool,
+    ) -> Result<Self, Error> {
+        let fgb = FgbWriter::create_with_options(
+            if layer == Layer::Fude {
+                "mojxml"
+            } else {
+                layer.name()
+            },
+            if layer.is_point() || points {
+                GeometryType::Point
+            } else {
+                GeometryType::Polygon
+            },
+            FgbWriterOptions {
+                crs: FgbCrs {
+                    code: crs.epsg(),
+                    ..Default::default()
+                },
+                metadata: metadata.as_deref(),
+                ..Default::default()
+            },
+        )?;
+        let mut writer = Self {
+            fgb,
+            layer,
+            schema,
+            metadata,
+            crs,
+            points,
+        };
+        for (name, column_type) in writer.column_names().into_iter().zip(writer.column_types()) {
+            let column_type = match column_type {
+                schema::ColumnType::Text => ColumnType::String,
+                schema::ColumnType::Real => ColumnType::Double,
+            };
+            writer.fgb.add_column(&name, column_type, |_fbb, _col| {});
+        }
+        Ok(writer)
+    }
+
+    fn column_types(&self) -> Vec<schema::ColumnType> {
+        match self.layer {
+            Layer::Fude => self
+                .schema
+                .columns()
+                .iter()
+                .map(|column| column.column_type())
+                .collect(),
+            _ => vec![schema::ColumnType::Text; self.column_names().len()],
+        }
+    }
+
+    fn column_names(&self) -> Vec<String> {
+        match self.layer {
+            Layer::Fude => self
+                .schema
+                .columns()
+                .iter()
+                .map(|column| column.name().to_string())
+                .collect(),
+            layer => std::iter::once("id")
+                .chain(layer.feature_columns().iter().copied())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// Copy the features of an existing FlatGeobuf file (e.g. a previous
+    /// output, to append to it). Properties are matched by column name, and
+    /// the others are dropped. Returns the number of features copied.
+    pub fn append_from(&mut self, reader: impl Read + Seek) -> Result<usize, Error> {
+        let names = self.column_names();
+        let types = self.column_types();
+        let mut features = FgbReader::open(reader)?.select_all()?;
+        let mut count = 0;
+        while let Some(feature) = features.next()? {
+            let properties = feature.properties()?;
+            let mut result = Ok(());
+            self.fgb.add_feature_geom(feature.to_geo()?, |feat| {
+                for (idx, (name, column_type)) in names.iter().zip(&types).enumerate() {
+                    let Some(value) = properties.get(name) else {
+                        continue;
+                    };
+                    let value = match column_type {
+                        schema::ColumnType::Text => ColumnValue::String(value),
+                        schema::ColumnType::Real => match value.parse() {
+                            Ok(value) => ColumnValue::Double(value),
+                            Err(_) => continue,
+                        },
+                    };
+                    if let Err(e) = feat.property(idx, name, &value) {
+                        result = Err(e);
+                        return;
+                    }
+                }
+            })?;
+            result?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Write a parcel, as a point with [`new_points_with_schema`](Self::new_points_with_schema)
+    /// (nothing for an empty polygon).
+    pub fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        let geometry = match self.points {
+            true => match polygon.interior_point() {
+                Some(point) => geo::Geometry::Point(point),
+                None => return Ok(()),
+            },
+            false => geo::Geometry::Polygon(polygon),
+        };
+        let mut result = Ok(());
+        let schema = &self.schema;
+        self.fgb.add_feature_geom(geometry, |feat| {
+            for (idx, column) in schema.columns().iter().enumerate() {
+                let value = match column.value(&fude.attributes) {
+                    Some(Value::Text(value)) => ColumnValue::String(value),
+                    Some(Value::Real(value)) => ColumnValue::Double(value),
+                    None => continue,
+                };
+                if let Err(e) = feat.property(idx, column.name(), &value) {
+                    result = Err(e);
+                    return;
+                }
+            }
+        })?;
+        Ok(result?)
+    }
+
+    pub fn write_feature(
+        &mut self,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        let layer = self.layer;
+        let mut result = Ok(());
+        self.fgb.add_feature_geom(geometry, |feat| {
+            let values = std::iter::once(("id", Some(feature.id.as_str()))).chain(
+                layer
+                    .feature_columns()
+                    .iter()
+                    .map(|name| (*name, feature.attribute(name))),
+            );
+            for (idx, (name, value)) in values.enumerate() {
+                if let Some(value) = value
+                    && let Err(e) = feat.property(idx, name, &ColumnValue::String(value))
+                {
+                    result = Err(e);
+                    return;
+                }
+            }
+        })?;
+        Ok(result?)
+    }
+
+    /// Write out the file, including the spatial index.
+    pub fn finish(self, out: impl Write) -> Result<(), Error> {
+        self.fgb.write(out)?;
+        Ok(())
+    }
+}
+
+/// Writes the features of its own layer, ignoring the others.
+impl FeatureSink for FgbFudeWriter<'_> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        if self.layer != Layer::Fude {
+            return Ok(());
+        }
+        FgbFudeWriter::write_fude(self, fude, polygon)
+    }
+
+    fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        if layer != self.layer {
+            return Ok(());
+        }
+        FgbFudeWriter::write_feature(self, feature, geometry)
+    }
+}
@@ -0,0 +1,166 @@
+//! GeoJSON and newline-delimited GeoJSON (GeoJSONSeq) output.
+
+use std::io::Write;
+
+use super::schema::Value;
+use super::{Error, FeatureSink, Schema, write_json_string};
+use crate::data::Fude;
+
+/// Writes parcels as GeoJSON features.
+///
+/// In the sequence mode, each feature is written on its own line as soon as
+/// it is added, which suits tools like tippecanoe or jq.
+pub struct GeoJsonWriter<W: Write> {
+    out: W,
+    seq: bool,
+    count: usize,
+    schema: Schema,
+}
+
+impl<W: Write> GeoJsonWriter<W> {
+    /// Write a single FeatureCollection.
+    pub fn new(mut out: W) -> Result<Self, Error> {
+        out.write_all(br#"{"type":"FeatureCollection","features":["#)?;
+        Ok(Self {
+            out,
+            seq: false,
+            count: 0,
+            schema: Schema::default(),
+        })
+    }
+
+    /// Write one Feature per line.
+    pub fn new_seq(out: W) -> Self {
+        Self {
+            out,
+            seq: true,
+            count: 0,
+            schema: Schema::default(),
+        }
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        if !self.seq && self.count > 0 {
+            self.out.write_all(b",")?;
+        }
+        self.out.write_all(br#"{"type":"Feature","properties":{"#)?;
+        for (idx, column) in self.schema.columns().iter().enumerate() {
+            if idx > 0 {
+                self.out.write_all(b",")?;
+            }
+            write_json_string(&mut self.out, column.name())?;
+            self.out.write_all(b":")?;
+            match column.value(&fude.attributes) {
+                Some(Value::Text(value)) => write_json_string(&mut self.out, value)?,
+                Some(value @ Value::Real(number)) if number.is_finite() => {
+                    write!(self.out, "{}", value)?
+                }
+                _ => self.out.write_all(b"null")?,
+            }
+        }
+        self.out
+            .write_all(br#"},"geometry":{"type":"Polygon","coordinates":["#)?;
+        for (idx, ring) in std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .enumerate()
+        {
+            if idx > 0 {
+                self.out.write_all(b",")?;
+            }
+            self.out.write_all(b"[")?;
+            for (idx, coord) in ring.coords().enumerate() {
+                if idx > 0 {
+                    self.out.write_all(b",")?;
+                }
+                write!(self.out, "[{},{}]", coord.x, coord.y)?;
+            }
+            self.out.write_all(b"]")?;
+        }
+        self.out.write_all(b"]}}")?;
+        if self.seq {
+            self.out.write_all(b"\n")?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// The underlying writer, e.g. to flush it.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Close the collection (if any) and flush.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if !self.seq {
+            self.out.write_all(b"]}\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> FeatureSink for GeoJsonWriter<W> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        GeoJsonWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GeoJsonWriter;
+    use crate::data::{Fude, FudeAttributes};
+    use crate::writer::SharedFeatureSink;
+    use geo::polygon;
+    use std::sync::Mutex;
+    use test_log::test;
+
+    #[test]
+    fn test_geojson_seq() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("1-2\"3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let polygon = polygon![(x: 0., y: 0.), (x: 1., y: 0.), (x: 1., y: 1.)];
+        let mut writer = GeoJsonWriter::new_seq(Vec::new());
+        writer.write_fude(&fude, &polygon).unwrap();
+        writer.write_fude(&fude, &polygon).unwrap();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"type":"Feature","properties":{"id":"H000000001","#));
+        assert!(lines[0].contains(r#""地番":"1-2\"3""#));
+        assert!(lines[0].ends_with(r#""coordinates":[[[0,0],[1,0],[1,1],[0,0]]]}}"#));
+    }
+
+    #[test]
+    fn test_shared() {
+        let fude = Fude {
+            attributes: FudeAttributes::default(),
+            surface_id: Default::default(),
+        };
+        let polygon = polygon![(x: 0., y: 0.), (x: 1., y: 0.), (x: 1., y: 1.)];
+        let writer = Mutex::new(GeoJsonWriter::new(Vec::new()).unwrap());
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        writer.add(&fude, polygon.clone()).unwrap();
+                    }
+                });
+            }
+        });
+        let out = writer.into_inner().unwrap().finish().unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert_eq!(json.matches(r#"{"type":"Feature""#).count(), 400);
+        assert_eq!(json.matches("}},{").count(), 399);
+    }
+}
@@ -0,0 +1,169 @@
+//! GeoParquet output.
+
+use std::io::Write;
+
+use geo::BoundingRect;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+
+use super::{Error, FeatureSink, Metadata, Schema, geo_rings, wkb};
+use crate::arrow::FudeBatchBuilder;
+use crate::crs::Crs;
+use crate::data::Fude;
+
+/// Number of rows buffered before they are handed to the parquet writer.
+const BATCH_SIZE: usize = 65536;
+
+/// Writes parcels as GeoParquet (WKB-encoded `geometry` column, one column
+/// per attribute; see [`fude_schema`](crate::arrow::fude_schema)).
+pub struct GeoParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    batch: FudeBatchBuilder,
+    bbox: Option<geo::Rect<f64>>,
+    metadata: Option<Metadata>,
+    crs: Crs,
+}
+
+impl<W: Write + Send> GeoParquetWriter<W> {
+    pub fn new(out: W) -> Result<Self, Error> {
+        Self::new_with_schema(out, Schema::default())
+    }
+
+    /// A writer with the given attribute columns.
+    pub fn new_with_schema(out: W, schema: Schema) -> Result<Self, Error> {
+        let batch = FudeBatchBuilder::new_with_schema(schema);
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(out, batch.schema(), Some(props))?;
+        Ok(Self {
+            writer,
+            batch,
+            bbox: None,
+            metadata: None,
+            crs: Crs::default(),
+        })
+    }
+
+    /// Record the metadata of the dataset, as JSON under the `mojxml` key.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// The CRS recorded in the `geo` metadata (JGD2011 by default).
+    pub fn with_crs(mut self, crs: Crs) -> Self {
+        self.crs = crs;
+        self
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let mut geom = Vec::with_capacity(9 + polygon.exterior().0.len() * 16);
+        wkb::write_polygon(&mut geom, geo_rings(polygon));
+        self.batch.push(fude, Some(&geom));
+
+        if let Some(rect) = polygon.bounding_rect() {
+            self.bbox = Some(match self.bbox {
+                Some(bbox) => geo::Rect::new(
+                    geo::coord! { x: bbox.min().x.min(rect.min().x), y: bbox.min().y.min(rect.min().y) },
+                    geo::coord! { x: bbox.max().x.max(rect.max().x), y: bbox.max().y.max(rect.max().y) },
+                ),
+                None => rect,
+            });
+        }
+
+        if self.batch.len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<(), Error> {
+        let batch = self.batch.finish()?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+
+    /// Write the remaining rows, the `geo` metadata and the footer.
+    pub fn finish(mut self) -> Result<(), Error> {
+        if !self.batch.is_empty() {
+            self.write_batch()?;
+        }
+        let bbox = match self.bbox {
+            Some(b) => format!(
+                r#","bbox":[{},{},{},{}]"#,
+                b.min().x,
+                b.min().y,
+                b.max().x,
+                b.max().y
+            ),
+            None => String::new(),
+        };
+        let crs = self.crs.projjson();
+        let metadata = format!(
+            r#"{{"version":"1.1.0","primary_column":"geometry","columns":{{"geometry":{{"encoding":"WKB","geometry_types":["Polygon"],"crs":{crs}{bbox}}}}}}}"#
+        );
+        self.writer
+            .append_key_value_metadata(KeyValue::new("geo".to_string(), metadata));
+        if let Some(metadata) = &self.metadata {
+            self.writer
+                .append_key_value_metadata(KeyValue::new("mojxml".to_string(), metadata.to_json()));
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> FeatureSink for GeoParquetWriter<W> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        GeoParquetWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GeoParquetWriter;
+    use crate::data::{Fude, FudeAttributes};
+    use geo::polygon;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use test_log::test;
+
+    #[test]
+    fn test_geoparquet() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let dir = std::env::temp_dir().join(format!("mojxml-test-pq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.parquet");
+
+        let mut writer = GeoParquetWriter::new(std::fs::File::create(&path).unwrap()).unwrap();
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 1);
+        let geo = metadata
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == "geo")
+            .and_then(|kv| kv.value.as_deref())
+            .unwrap();
+        assert!(geo.contains(r#""bbox":[139,35,139.1,35.1]"#));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
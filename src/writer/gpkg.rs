@@ -0,0 +1,526 @@
+//! GeoPackage output.
+
+use std::path::Path;
+
+use geo::BoundingRect;
+use hashbrown::HashMap;
+use rusqlite::{Connection, OpenFlags};
+
+use super::schema::{ColumnType, Value};
+use super::{Error, FeatureSink, Layer, Metadata, Schema, geo_rings, wkb};
+use crate::crs::Crs;
+use crate::data::{Feature, Fude};
+
+/// Writes parcels into the `fude` layer of a new GeoPackage, with an R-tree
+/// spatial index. The other [`Layer`]s can be added with [`add_layer`](Self::add_layer).
+///
+/// Everything is written in a single transaction, committed by [`finish`](Self::finish).
+pub struct GpkgFudeWriter {
+    conn: Connection,
+    schema: Schema,
+    metadata: Option<Metadata>,
+    extents: HashMap<Layer, geo::Rect<f64>>,
+    crs: Crs,
+}
+
+impl GpkgFudeWriter {
+    /// Create a GeoPackage, replacing the file if it exists.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::create_with_schema(path, Schema::default())
+    }
+
+    /// Create a GeoPackage with the given attribute columns for parcels.
+    pub fn create_with_schema(path: impl AsRef<Path>, schema: Schema) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA application_id = 1196444487;
+             PRAGMA user_version = 10400;
+             PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             BEGIN;
+             CREATE TABLE gpkg_spatial_ref_sys (
+                 srs_name TEXT NOT NULL,
+                 srs_id INTEGER PRIMARY KEY,
+                 organization TEXT NOT NULL,
+                 organization_coordsys_id INTEGER NOT NULL,
+                 definition TEXT NOT NULL,
+                 description TEXT
+             );
+             INSERT INTO gpkg_spatial_ref_sys VALUES
+                 ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', NULL),
+                 ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', NULL);
+             CREATE TABLE gpkg_contents (
+                 table_name TEXT NOT NULL PRIMARY KEY,
+                 data_type TEXT NOT NULL,
+                 identifier TEXT UNIQUE,
+                 description TEXT DEFAULT '',
+                 last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+                 min_x DOUBLE,
+                 min_y DOUBLE,
+                 max_x DOUBLE,
+                 max_y DOUBLE,
+                 srs_id INTEGER,
+                 CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+             );
+             CREATE TABLE gpkg_geometry_columns (
+                 table_name TEXT NOT NULL,
+                 column_name TEXT NOT NULL,
+                 geometry_type_name TEXT NOT NULL,
+                 srs_id INTEGER NOT NULL,
+                 z TINYINT NOT NULL,
+                 m TINYINT NOT NULL,
+                 CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+                 CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+                 CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+             );
+             CREATE TABLE gpkg_extensions (
+                 table_name TEXT,
+                 column_name TEXT,
+                 extension_name TEXT NOT NULL,
+                 definition TEXT NOT NULL,
+                 scope TEXT NOT NULL,
+                 CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name)
+             );"
+        )?;
+
+        let mut writer = Self {
+            conn,
+            schema,
+            metadata: None,
+            extents: HashMap::new(),
+            crs: Crs::default(),
+        };
+        writer.add_srs()?;
+        writer.create_table(Layer::Fude)?;
+        Ok(writer)
+    }
+
+    /// Open an existing GeoPackage to append to it. The parcels are added
+    /// to its `fude` table, which must have the columns of the schema.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_with_schema(path, Schema::default())
+    }
+
+    /// [`open`](Self::open) with the given attribute columns for parcels.
+    pub fn open_with_schema(path: impl AsRef<Path>, schema: Schema) -> Result<Self, Error> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        // keep the journal, so that a failure doesn't corrupt the existing data
+        conn.execute_batch(
+            "PRAGMA synchronous = OFF;
+             BEGIN;",
+        )?;
+        let mut writer = Self {
+            conn,
+            schema,
+            metadata: None,
+            extents: HashMap::new(),
+            crs: Crs::default(),
+        };
+        writer.add_srs()?;
+        if !writer.has_table(Layer::Fude)? {
+            writer.create_table(Layer::Fude)?;
+        }
+        Ok(writer)
+    }
+
+    fn has_table(&self, layer: Layer) -> Result<bool, Error> {
+        Ok(self.conn.query_row(
+            "SELECT count(*) FROM gpkg_contents WHERE table_name = ?",
+            [layer.name()],
+            |row| row.get::<_, i64>(0),
+        )? > 0)
+    }
+
+    /// Record the metadata of the dataset (metadata extension), written by
+    /// [`finish`](Self::finish).
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Write the geometries in another CRS than JGD2011.
+    ///
+    /// The existing tables are switched to it, unless they already have
+    /// features in another CRS.
+    pub fn with_crs(mut self, crs: Crs) -> Result<Self, Error> {
+        self.crs = crs;
+        self.add_srs()?;
+        let tables: Vec<(String, i32)> = self
+            .conn
+            .prepare("SELECT table_name, srs_id FROM gpkg_geometry_columns")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (table, srs_id) in tables {
+            if srs_id == crs.epsg() {
+                continue;
+            }
+            let count: i64 = self.conn.query_row(
+                &format!("SELECT count(*) FROM \"{}\"", table.replace('"', "\"\"")),
+                [],
+                |row| row.get(0),
+            )?;
+            if count > 0 {
+                return Err(Error::CrsMismatch(format!(
+                    "table {} is in EPSG:{}, not {}",
+                    table, srs_id, crs
+                )));
+            }
+            self.conn.execute(
+                "UPDATE gpkg_contents SET srs_id = ?1 WHERE table_name = ?2",
+                (crs.epsg(), &table),
+            )?;
+            self.conn.execute(
+                "UPDATE gpkg_geometry_columns SET srs_id = ?1 WHERE table_name = ?2",
+                (crs.epsg(), &table),
+            )?;
+        }
+        Ok(self)
+    }
+
+    /// Register the CRS in gpkg_spatial_ref_sys, unless it exists.
+    fn add_srs(&self) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO gpkg_spatial_ref_sys VALUES (?1, ?2, 'EPSG', ?2, ?3, NULL)",
+            (self.crs.name(), self.crs.epsg(), self.crs.wkt()),
+        )?;
+        Ok(())
+    }
+
+    /// Add a table for the layer, unless it exists. (The `fude` table always
+    /// exists.)
+    pub fn add_layer(&mut self, layer: Layer) -> Result<(), Error> {
+        if !self.has_table(layer)? {
+            self.create_table(layer)?;
+        }
+        Ok(())
+    }
+
+    fn create_table(&mut self, layer: Layer) -> Result<(), Error> {
+        let table = layer.name();
+        let srs_id = self.crs.epsg();
+        let geometry_type = if layer.is_point() { "POINT" } else { "POLYGON" };
+        let types: Vec<ColumnType> = match layer {
+            Layer::Fude => self
+                .schema
+                .columns()
+                .iter()
+                .map(|c| c.column_type())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let columns = self
+            .column_names(layer)
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let column_type = types.get(i).copied().unwrap_or(ColumnType::Text);
+                format!(", {} {}", name, column_type.sql_type())
+            })
+            .collect::<String>();
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE \"{table}\" (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom {geometry_type}{columns});
+             INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id)
+                 VALUES ('{table}', 'features', '{table}', {srs_id});
+             INSERT INTO gpkg_geometry_columns VALUES ('{table}', 'geom', '{geometry_type}', {srs_id}, 0, 0);
+             CREATE VIRTUAL TABLE \"rtree_{table}_geom\" USING rtree(id, minx, maxx, miny, maxy);
+             INSERT INTO gpkg_extensions VALUES
+                 ('{table}', 'geom', 'gpkg_rtree_index', 'http://www.geopackage.org/spec120/#extension_rtree', 'write-only');"
+        ))?;
+        Ok(())
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let Some(bbox) = polygon.bounding_rect() else {
+            return Ok(());
+        };
+        let mut geom = Vec::with_capacity(40 + 9 + polygon.exterior().0.len() * 16);
+        write_header(&mut geom, self.crs.epsg(), bbox);
+        wkb::write_polygon(&mut geom, geo_rings(polygon));
+        let values: Vec<_> = self
+            .schema
+            .columns()
+            .iter()
+            .map(|column| column.value(&fude.attributes))
+            .collect();
+        self.insert(Layer::Fude, &geom, bbox, &values)
+    }
+
+    /// Write a feature of a layer added by [`add_layer`](Self::add_layer).
+    ///
+    /// Geometries other than points and polygons are ignored.
+    pub fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: &geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        let Some(bbox) = geometry.bounding_rect() else {
+            return Ok(());
+        };
+        let mut geom = Vec::with_capacity(40 + 21);
+        write_header(&mut geom, self.crs.epsg(), bbox);
+        match geometry {
+            geo::Geometry::Point(p) => wkb::write_point(&mut geom, [p.x(), p.y()]),
+            geo::Geometry::Polygon(polygon) => wkb::write_polygon(&mut geom, geo_rings(polygon)),
+            _ => return Ok(()),
+        }
+        let values: Vec<_> = std::iter::once(Some(feature.id.as_str()))
+            .chain(
+                layer
+                    .feature_columns()
+                    .iter()
+                    .map(|name| feature.attribute(name)),
+            )
+            .map(|value| value.map(Value::Text))
+            .collect();
+        self.insert(layer, &geom, bbox, &values)
+    }
+
+    /// Quoted names of the attribute columns of a layer.
+    fn column_names(&self, layer: Layer) -> Vec<String> {
+        let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+        match layer {
+            Layer::Fude => self
+                .schema
+                .columns()
+                .iter()
+                .map(|column| quote(column.name()))
+                .collect(),
+            _ => std::iter::once("id")
+                .chain(layer.feature_columns().iter().copied())
+                .map(quote)
+                .collect(),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        layer: Layer,
+        geom: &[u8],
+        bbox: geo::Rect<f64>,
+        values: &[Option<Value>],
+    ) -> Result<(), Error> {
+        let table = layer.name();
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "INSERT INTO \"{table}\" (geom, {}) VALUES (?{})",
+            self.column_names(layer).join(", "),
+            ", ?".repeat(values.len())
+        ))?;
+        stmt.raw_bind_parameter(1, geom)?;
+        for (i, value) in values.iter().enumerate() {
+            stmt.raw_bind_parameter(i + 2, value)?;
+        }
+        stmt.raw_execute()?;
+        let fid = self.conn.last_insert_rowid();
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "INSERT INTO \"rtree_{table}_geom\" VALUES (?, ?, ?, ?, ?)"
+        ))?;
+        stmt.execute((fid, bbox.min().x, bbox.max().x, bbox.min().y, bbox.max().y))?;
+
+        self.extents
+            .entry(layer)
+            .and_modify(|extent| {
+                *extent = geo::Rect::new(
+                    geo::coord! { x: extent.min().x.min(bbox.min().x), y: extent.min().y.min(bbox.min().y) },
+                    geo::coord! { x: extent.max().x.max(bbox.max().x), y: extent.max().y.max(bbox.max().y) },
+                )
+            })
+            .or_insert(bbox);
+        Ok(())
+    }
+
+    /// Record (extend) the extents of the layers and commit.
+    pub fn finish(self) -> Result<(), Error> {
+        for (layer, extent) in &self.extents {
+            self.conn.execute(
+                "UPDATE gpkg_contents SET
+                     min_x = min(coalesce(min_x, ?1), ?1),
+                     min_y = min(coalesce(min_y, ?2), ?2),
+                     max_x = max(coalesce(max_x, ?3), ?3),
+                     max_y = max(coalesce(max_y, ?4), ?4)
+                 WHERE table_name = ?5",
+                (
+                    extent.min().x,
+                    extent.min().y,
+                    extent.max().x,
+                    extent.max().y,
+                    layer.name(),
+                ),
+            )?;
+        }
+        if let Some(metadata) = &self.metadata {
+            // the time of the conversion, rather than of the creation of the tables
+            self.conn.execute(
+                "UPDATE gpkg_contents SET last_change = strftime('%Y-%m-%dT%H:%M:%fZ', ?1)",
+                [&metadata.created],
+            )?;
+            self.conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS gpkg_metadata (
+                     id INTEGER CONSTRAINT m_pk PRIMARY KEY ASC NOT NULL,
+                     md_scope TEXT NOT NULL DEFAULT 'dataset',
+                     md_standard_uri TEXT NOT NULL,
+                     mime_type TEXT NOT NULL DEFAULT 'text/xml',
+                     metadata TEXT NOT NULL DEFAULT ''
+                 );
+                 CREATE TABLE IF NOT EXISTS gpkg_metadata_reference (
+                     reference_scope TEXT NOT NULL,
+                     table_name TEXT,
+                     column_name TEXT,
+                     row_id_value INTEGER,
+                     timestamp DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+                     md_file_id INTEGER NOT NULL,
+                     md_parent_id INTEGER,
+                     CONSTRAINT crmr_mfi_fk FOREIGN KEY (md_file_id) REFERENCES gpkg_metadata(id),
+                     CONSTRAINT crmr_mpi_fk FOREIGN KEY (md_parent_id) REFERENCES gpkg_metadata(id)
+                 );
+                 INSERT OR IGNORE INTO gpkg_extensions VALUES
+                     ('gpkg_metadata', NULL, 'gpkg_metadata', 'http://www.geopackage.org/spec120/#extension_metadata', 'read-write'),
+                     ('gpkg_metadata_reference', NULL, 'gpkg_metadata', 'http://www.geopackage.org/spec120/#extension_metadata', 'read-write');",
+            )?;
+            self.conn.execute(
+                "INSERT INTO gpkg_metadata VALUES (NULL, 'dataset', 'urn:mojxml:metadata', 'application/json', ?)",
+                [metadata.to_json()],
+            )?;
+            self.conn.execute(
+                "INSERT INTO gpkg_metadata_reference (reference_scope, timestamp, md_file_id)
+                 VALUES ('geopackage', strftime('%Y-%m-%dT%H:%M:%fZ', ?1), ?2)",
+                (&metadata.created, self.conn.last_insert_rowid()),
+            )?;
+        }
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+/// GeoPackageBinary header, with an xy envelope.
+fn write_header(out: &mut Vec<u8>, srs_id: i32, bbox: geo::Rect<f64>) {
+    out.extend_from_slice(b"GP");
+    out.push(0); // version
+    out.push(0b0000_0011); // little endian, with an xy envelope
+    out.extend_from_slice(&srs_id.to_le_bytes());
+    for v in [bbox.min().x, bbox.max().x, bbox.min().y, bbox.max().y] {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+impl FeatureSink for GpkgFudeWriter {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        GpkgFudeWriter::write_fude(self, fude, &polygon)
+    }
+
+    /// The layer must have been added by [`add_layer`](GpkgFudeWriter::add_layer).
+    fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        GpkgFudeWriter::write_feature(self, layer, feature, &geometry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GpkgFudeWriter;
+    use crate::data::{Fude, FudeAttributes, MapMetadata};
+    use crate::writer::Metadata;
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_gpkg() {
+        let dir = std::env::temp_dir().join(format!("mojxml-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.gpkg");
+
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = GpkgFudeWriter::create(&path)
+            .unwrap()
+            .with_metadata(Metadata::new(
+                Some("test.zip".to_string()),
+                &MapMetadata {
+                    municipality_code: Some("15222".to_string()),
+                    ..Default::default()
+                },
+            ));
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let chiban: String = conn
+            .query_row("SELECT \"地番\" FROM fude", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(chiban, "12-3");
+        let max_x: f64 = conn
+            .query_row("SELECT maxx FROM rtree_fude_geom", [], |row| row.get(0))
+            .unwrap();
+        // r-tree coordinates are stored as float32, rounded outwards
+        assert!(max_x >= 139.1 && max_x - 139.1 < 1e-4);
+        let metadata: String = conn
+            .query_row("SELECT metadata FROM gpkg_metadata", [], |row| row.get(0))
+            .unwrap();
+        assert!(metadata.starts_with(r#"{"source":"test.zip","municipality_code":"15222","#));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gpkg_append() {
+        let dir = std::env::temp_dir().join(format!("mojxml-test-append-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.gpkg");
+
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        for (append, x) in [(false, 139.0), (true, 140.0)] {
+            let mut writer = if append {
+                GpkgFudeWriter::open(&path).unwrap()
+            } else {
+                GpkgFudeWriter::create(&path).unwrap()
+            };
+            writer
+                .write_fude(
+                    &fude,
+                    &polygon![(x: x, y: 35.0), (x: x + 0.1, y: 35.0), (x: x + 0.1, y: 35.1)],
+                )
+                .unwrap();
+            writer.finish().unwrap();
+        }
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM rtree_fude_geom", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let (min_x, max_x): (f64, f64) = conn
+            .query_row(
+                "SELECT min_x, max_x FROM gpkg_contents WHERE table_name = 'fude'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((min_x, max_x), (139.0, 140.1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! KML / KMZ output, for Google Earth and field survey apps.
+
+use std::io::{Seek, Write};
+
+use quick_xml::escape::escape;
+
+use super::{Error, FeatureSink, Schema};
+use crate::data::Fude;
+
+/// Writes parcels as placemarks named 大字+地番, with all the attributes
+/// shown in the balloon.
+pub struct KmlWriter<W: Write> {
+    out: W,
+    schema: Schema,
+}
+
+impl<W: Write> KmlWriter<W> {
+    pub fn new(mut out: W) -> Result<Self, Error> {
+        out.write_all(
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+<Document>
+<Style id="fude"><LineStyle><color>ff0000ff</color><width>1</width></LineStyle><PolyStyle><fill>0</fill></PolyStyle></Style>
+"#,
+        )?;
+        Ok(Self {
+            out,
+            schema: Schema::default(),
+        })
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let attrs = &fude.attributes;
+        let name = [attrs.oaza.as_deref(), attrs.chiban.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<String>();
+        write!(
+            self.out,
+            "<Placemark id=\"{}\"><name>{}</name><styleUrl>#fude</styleUrl><ExtendedData>",
+            escape(attrs.id.as_ref()),
+            escape(name.as_str())
+        )?;
+        for column in self.schema.columns() {
+            if let Some(value) = column.get(attrs) {
+                write!(
+                    self.out,
+                    "<Data name=\"{}\"><value>{}</value></Data>",
+                    escape(column.name()),
+                    escape(&*value)
+                )?;
+            }
+        }
+        self.out.write_all(b"</ExtendedData><Polygon>")?;
+        for (idx, ring) in std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .enumerate()
+        {
+            self.out.write_all(if idx == 0 {
+                b"<outerBoundaryIs><LinearRing><coordinates>"
+            } else {
+                b"<innerBoundaryIs><LinearRing><coordinates>"
+            })?;
+            for (idx, coord) in ring.coords().enumerate() {
+                if idx > 0 {
+                    self.out.write_all(b" ")?;
+                }
+                write!(self.out, "{},{}", coord.x, coord.y)?;
+            }
+            self.out.write_all(if idx == 0 {
+                b"</coordinates></LinearRing></outerBoundaryIs>"
+            } else {
+                b"</coordinates></LinearRing></innerBoundaryIs>"
+            })?;
+        }
+        self.out.write_all(b"</Polygon></Placemark>\n")?;
+        Ok(())
+    }
+
+    /// Close the document, flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.out.write_all(b"</Document>\n</kml>\n")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A [`KmlWriter`] writing into a KMZ archive.
+pub type KmzWriter<W> = KmlWriter<::zip::ZipWriter<W>>;
+
+impl<W: Write + Seek> KmzWriter<W> {
+    /// Write a KMZ archive (`doc.kml` in a zip file).
+    pub fn new_kmz(out: W) -> Result<Self, Error> {
+        let mut zip = ::zip::ZipWriter::new(out);
+        zip.start_file(
+            "doc.kml",
+            ::zip::write::SimpleFileOptions::default()
+                .compression_method(::zip::CompressionMethod::Deflated),
+        )?;
+        Self::new(zip)
+    }
+
+    /// Close the document and the archive.
+    pub fn finish_kmz(self) -> Result<W, Error> {
+        Ok(self.finish()?.finish()?)
+    }
+}
+
+impl<W: Write> FeatureSink for KmlWriter<W> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        KmlWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KmlWriter;
+    use crate::data::{Fude, FudeAttributes};
+    use geo::polygon;
+    use std::io::{Cursor, Read};
+    use test_log::test;
+
+    #[test]
+    fn test_kmz() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                oaza: Some("大字<A>".into()),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = KmlWriter::new_kmz(Cursor::new(Vec::new())).unwrap();
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        let out = writer.finish_kmz().unwrap();
+
+        let mut zip = ::zip::ZipArchive::new(out).unwrap();
+        let mut kml = String::new();
+        zip.by_name("doc.kml")
+            .unwrap()
+            .read_to_string(&mut kml)
+            .unwrap();
+        assert!(kml.contains("<name>大字&lt;A&gt;12-3</name>"));
+        assert!(kml.contains("<Data name=\"地番\"><value>12-3</value></Data>"));
+        assert!(kml.contains("<coordinates>139,35 139.1,35 139.1,35.1 139,35</coordinates>"));
+        assert!(kml.ends_with("</kml>\n"));
+    }
+}
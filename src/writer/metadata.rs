@@ -0,0 +1,125 @@
+//! Dataset-level metadata, so that outputs can be traced back to their
+//! source.
+
+use super::write_json_string;
+use crate::data::MapMetadata;
+
+/// Metadata written into the outputs that support it.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    /// Name of the source file
+    pub source: Option<String>,
+    /// 市区町村コード
+    pub municipality_code: Option<String>,
+    /// 市区町村名
+    pub municipality_name: Option<String>,
+    /// 測地系判別
+    pub datum: Option<String>,
+    /// 座標系 of the source
+    pub crs: Option<String>,
+    /// Time of the conversion (UTC, RFC 3339)
+    pub created: String,
+}
+
+impl Metadata {
+    /// Metadata of a conversion happening now.
+    pub fn new(source: Option<String>, map: &MapMetadata) -> Self {
+        Self {
+            source,
+            municipality_code: map.municipality_code.clone(),
+            municipality_name: map.municipality_name.clone(),
+            datum: map.datum.clone(),
+            crs: map.crs.clone(),
+            created: String::new(),
+        }
+        .with_created_at(unix_now())
+    }
+
+    /// Set the time of the conversion, in seconds since the Unix epoch (e.g.
+    /// a fixed `SOURCE_DATE_EPOCH`, for reproducible outputs).
+    pub fn with_created_at(mut self, unix_time: i64) -> Self {
+        let (year, month, day, secs) = utc_date(unix_time);
+        self.created = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        self
+    }
+
+    /// The metadata as key-value pairs, including the version of this crate.
+    pub fn entries(&self) -> Vec<(&'static str, &str)> {
+        let mut entries = Vec::new();
+        for (key, value) in [
+            ("source", &self.source),
+            ("municipality_code", &self.municipality_code),
+            ("municipality_name", &self.municipality_name),
+            ("datum", &self.datum),
+            ("crs", &self.crs),
+        ] {
+            if let Some(value) = value {
+                entries.push((key, value.as_str()));
+            }
+        }
+        entries.push(("created", self.created.as_str()));
+        entries.push(("mojxml_version", env!("CARGO_PKG_VERSION")));
+        entries
+    }
+
+    /// The [`entries`](Self::entries) as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut out = Vec::new();
+        out.push(b'{');
+        for (idx, (key, value)) in self.entries().into_iter().enumerate() {
+            if idx > 0 {
+                out.push(b',');
+            }
+            // writing into a Vec doesn't fail
+            let _ = write_json_string(&mut out, key);
+            out.push(b':');
+            let _ = write_json_string(&mut out, value);
+        }
+        out.push(b'}');
+        String::from_utf8(out).expect("JSON is valid UTF-8")
+    }
+}
+
+/// Current time, in seconds since the Unix epoch.
+pub(crate) fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// UTC date of a Unix time, and seconds of the day.
+pub(crate) fn utc_date(secs: i64) -> (i64, u8, u8, i64) {
+    // Howard Hinnant's civil_from_days
+    let z = secs.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, secs.rem_euclid(86400))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metadata;
+    use test_log::test;
+
+    #[test]
+    fn test_created_at() {
+        let metadata = Metadata::default().with_created_at(0);
+        assert_eq!(metadata.created, "1970-01-01T00:00:00Z");
+        let metadata = metadata.with_created_at(951_782_400 + 3661);
+        assert_eq!(metadata.created, "2000-02-29T01:01:01Z");
+    }
+}
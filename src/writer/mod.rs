@@ -0,0 +1,333 @@
+//! Writers for the parsed parcels (筆).
+
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "fgb")]
+pub mod fgb;
+#[cfg(feature = "geojson")]
+pub mod geojson;
+#[cfg(feature = "geoparquet")]
+pub mod geoparquet;
+#[cfg(feature = "gpkg")]
+pub mod gpkg;
+#[cfg(feature = "kml")]
+pub mod kml;
+pub mod metadata;
+#[cfg(feature = "pmtiles")]
+mod mvt;
+#[cfg(feature = "pmtiles")]
+pub mod pmtiles;
+pub mod schema;
+#[cfg(feature = "shapefile")]
+pub mod shapefile;
+#[cfg(feature = "spatialite")]
+pub mod spatialite;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod topology;
+pub mod vrt;
+pub(crate) mod wkb;
+pub(crate) mod wkt;
+pub mod xml;
+
+use std::io::Write;
+#[cfg(feature = "geo")]
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+pub use metadata::Metadata;
+pub use schema::Schema;
+
+use crate::data::FudeAttributes;
+#[cfg(feature = "geo")]
+use crate::data::{Feature, Fude, MapMetadata};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "fgb")]
+    #[error(transparent)]
+    Fgb(#[from] flatgeobuf::Error),
+    #[cfg(feature = "fgb")]
+    #[error(transparent)]
+    Geozero(#[from] geozero::error::GeozeroError),
+    #[cfg(any(feature = "gpkg", feature = "spatialite"))]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "geoparquet")]
+    #[error(transparent)]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "geoparquet")]
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "kml")]
+    #[error(transparent)]
+    Zip(#[from] ::zip::result::ZipError),
+    #[cfg(feature = "gpkg")]
+    #[error("CRS mismatch: {0}")]
+    CrsMismatch(String),
+    #[error("unknown column: {0}")]
+    UnknownColumn(String),
+    #[cfg(feature = "shapefile")]
+    #[error("invalid DBF field name: {0}")]
+    FieldName(String),
+}
+
+/// Output layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    /// 筆 (polygons)
+    Fude,
+    /// 基準点 (points)
+    Kijunten,
+    /// 筆界点 (points)
+    Hikkaiten,
+    /// 図郭 (polygons)
+    Zukaku,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 4] = [
+        Layer::Fude,
+        Layer::Kijunten,
+        Layer::Hikkaiten,
+        Layer::Zukaku,
+    ];
+
+    /// Name of the layer (table, file suffix, ...).
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Fude => "fude",
+            Layer::Kijunten => "kijunten",
+            Layer::Hikkaiten => "hikkaiten",
+            Layer::Zukaku => "zukaku",
+        }
+    }
+
+    pub fn is_point(self) -> bool {
+        matches!(self, Layer::Kijunten | Layer::Hikkaiten)
+    }
+
+    /// Attribute columns of the layer, besides `id`, for [`Feature`](crate::data::Feature)s.
+    /// (The 筆 layer uses the columns of [`FudeAttributes`].)
+    pub fn feature_columns(self) -> &'static [&'static str] {
+        match self {
+            Layer::Fude => &[],
+            Layer::Kijunten => &["名称", "種別", "座標値種別", "精度区分"],
+            Layer::Hikkaiten => &["点番名", "座標値種別", "精度区分"],
+            Layer::Zukaku => &[
+                "地図番号",
+                "縮尺分母",
+                "方位",
+                "座標値種別",
+                "精度区分",
+                "地図種類",
+                "地図分類",
+                "地図材質",
+                "作成年月日",
+                "備付年月日",
+                "補正年月日",
+                "電子化年月日",
+            ],
+        }
+    }
+}
+
+/// Destination of the features of a conversion (see [`crate::convert()`]).
+///
+/// Implemented by the writers, and by `&mut` references to them so that the
+/// writer can be finished after the conversion.
+#[cfg(feature = "geo")]
+pub trait FeatureSink {
+    /// Called once for each XML file, before its features are written.
+    fn begin_file(&mut self, _name: &str, _metadata: &MapMetadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error>;
+
+    /// Write a feature of a layer other than [`Layer::Fude`]. Ignored by default.
+    fn write_feature(
+        &mut self,
+        _layer: Layer,
+        _feature: &Feature,
+        _geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Write the features of an XML file, after [`begin_file`](Self::begin_file).
+    /// Override it to keep the features instead of copying them.
+    fn write_file(&mut self, batch: FileBatch) -> Result<(), Error> {
+        self.begin_file(batch.name, batch.metadata)?;
+        for (fude, polygon) in batch.fudes {
+            self.write_fude(&fude, polygon)?;
+        }
+        for (layer, feature, geometry) in batch.features {
+            self.write_feature(layer, &feature, geometry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "geo")]
+impl<S: FeatureSink + ?Sized> FeatureSink for &mut S {
+    fn begin_file(&mut self, name: &str, metadata: &MapMetadata) -> Result<(), Error> {
+        (**self).begin_file(name, metadata)
+    }
+
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        (**self).write_fude(fude, polygon)
+    }
+
+    fn write_feature(
+        &mut self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        (**self).write_feature(layer, feature, geometry)
+    }
+
+    fn write_file(&mut self, batch: FileBatch) -> Result<(), Error> {
+        (**self).write_file(batch)
+    }
+}
+
+/// The features of an XML file, written together (see
+/// [`FeatureSink::write_file`] and [`SharedFeatureSink::add_file`]).
+#[cfg(feature = "geo")]
+pub struct FileBatch<'a> {
+    /// Name of the XML file
+    pub name: &'a str,
+    pub metadata: &'a MapMetadata,
+    pub fudes: Vec<(Fude<'static>, geo::Polygon<f64>)>,
+    /// Features of the other layers
+    pub features: Vec<(Layer, Feature, geo::Geometry<f64>)>,
+}
+
+/// A sink which several threads can write to at the same time, e.g. workers
+/// resolving the geometries of different files in parallel.
+///
+/// Implemented by `Mutex<S>` for any [`FeatureSink`] (FlatGeobuf, GeoJSON,
+/// CSV, ...): the lock is held for one feature, or for the whole file with
+/// [`add_file`](Self::add_file). References to shared sinks are shared sinks.
+#[cfg(feature = "geo")]
+pub trait SharedFeatureSink: Sync {
+    fn add(&self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error>;
+
+    /// Write a feature of a layer other than [`Layer::Fude`].
+    fn add_feature(
+        &self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error>;
+
+    /// Write the features of an XML file, without interleaving them with
+    /// those of other files.
+    fn add_file(&self, batch: FileBatch) -> Result<(), Error>;
+}
+
+#[cfg(feature = "geo")]
+impl<S: FeatureSink + Send> SharedFeatureSink for Mutex<S> {
+    fn add(&self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        self.lock().unwrap().write_fude(fude, polygon)
+    }
+
+    fn add_feature(
+        &self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        self.lock().unwrap().write_feature(layer, feature, geometry)
+    }
+
+    fn add_file(&self, batch: FileBatch) -> Result<(), Error> {
+        self.lock().unwrap().write_file(batch)
+    }
+}
+
+#[cfg(feature = "geo")]
+impl<S: SharedFeatureSink + ?Sized> SharedFeatureSink for &S {
+    fn add(&self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        (**self).add(fude, polygon)
+    }
+
+    fn add_feature(
+        &self,
+        layer: Layer,
+        feature: &Feature,
+        geometry: geo::Geometry<f64>,
+    ) -> Result<(), Error> {
+        (**self).add_feature(layer, feature, geometry)
+    }
+
+    fn add_file(&self, batch: FileBatch) -> Result<(), Error> {
+        (**self).add_file(batch)
+    }
+}
+
+/// Accessor of an attribute column.
+pub(crate) type Getter = for<'a, 'b> fn(&'a FudeAttributes<'b>) -> Option<&'a str>;
+
+/// The standard attribute columns of a parcel.
+pub(crate) const FUDE_COLUMNS: [(&str, Getter); 12] = [
+    ("id", |a| Some(&a.id)),
+    ("大字コード", |a| a.oaza_code.as_deref()),
+    ("丁目コード", |a| a.chome_code.as_deref()),
+    ("小字コード", |a| a.koaza_code.as_deref()),
+    ("予備コード", |a| a.yobi_code.as_deref()),
+    ("大字名", |a| a.oaza.as_deref()),
+    ("丁目名", |a| a.chome.as_deref()),
+    ("小字名", |a| a.koaza.as_deref()),
+    ("予備名", |a| a.yobi.as_deref()),
+    ("地番", |a| a.chiban.as_deref()),
+    ("精度区分", |a| a.accuracy_class.as_deref()),
+    ("座標値種別", |a| a.coord_class.as_deref()),
+];
+
+/// The rings (exterior first) of a polygon, as coordinate arrays.
+#[cfg(any(
+    feature = "gpkg",
+    feature = "geoparquet",
+    feature = "csv",
+    feature = "spatialite"
+))]
+pub(crate) fn geo_rings(
+    polygon: &geo::Polygon<f64>,
+) -> impl Iterator<Item = impl Iterator<Item = [f64; 2]> + '_> {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| ring.coords().map(|c| [c.x, c.y]))
+}
+
+/// Write a JSON string literal.
+pub(crate) fn write_json_string(out: &mut impl Write, s: &str) -> std::io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (idx, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if c < ' ' => {
+                out.write_all(&s.as_bytes()[start..idx])?;
+                write!(out, "\\u{:04x}", c as u32)?;
+                start = idx + 1;
+                continue;
+            }
+            _ => continue,
+        };
+        out.write_all(&s.as_bytes()[start..idx])?;
+        out.write_all(escaped.as_bytes())?;
+        start = idx + 1;
+    }
+    out.write_all(&s.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
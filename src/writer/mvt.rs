@@ -0,0 +1,237 @@
+//! Mapbox Vector Tile encoding, and the geometry operations needed to cut
+//! polygons into tiles.
+
+use hashbrown::HashMap;
+
+use super::schema::Value;
+
+/// Tile coordinate extent
+pub(crate) const EXTENT: u32 = 4096;
+
+/// A ring in tile (or world) coordinates, without the closing point.
+pub(crate) type Ring = Vec<[f64; 2]>;
+
+/// Web Mercator position of a lon/lat, normalized to [0, 1].
+pub(crate) fn lonlat_to_world(lon: f64, lat: f64) -> [f64; 2] {
+    let x = (lon + 180.0) / 360.0;
+    let lat = lat.to_radians();
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0;
+    [x, y]
+}
+
+/// Clip a ring to an axis-aligned rectangle (Sutherland-Hodgman).
+pub(crate) fn clip_ring(ring: &[[f64; 2]], min: f64, max: f64) -> Ring {
+    let mut output = ring.to_vec();
+    // (axis, bound, keep when below the bound)
+    for (axis, bound, below) in [
+        (0, min, false),
+        (0, max, true),
+        (1, min, false),
+        (1, max, true),
+    ] {
+        let input = std::mem::take(&mut output);
+        let inside = |p: &[f64; 2]| (p[axis] <= bound) == below || p[axis] == bound;
+        let Some(mut prev) = input.last() else {
+            break;
+        };
+        for p in &input {
+            if inside(p) {
+                if !inside(prev) {
+                    output.push(intersect(prev, p, axis, bound));
+                }
+                output.push(*p);
+            } else if inside(prev) {
+                output.push(intersect(prev, p, axis, bound));
+            }
+            prev = p;
+        }
+    }
+    output
+}
+
+fn intersect(a: &[f64; 2], b: &[f64; 2], axis: usize, bound: f64) -> [f64; 2] {
+    let t = (bound - a[axis]) / (b[axis] - a[axis]);
+    let mut p = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+    p[axis] = bound;
+    p
+}
+
+/// Twice the signed area of an integer ring (positive when clockwise in
+/// tile coordinates, where y points down).
+fn signed_area(ring: &[[i32; 2]]) -> i64 {
+    let mut sum = 0i64;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        sum += a[0] as i64 * b[1] as i64 - b[0] as i64 * a[1] as i64;
+    }
+    sum
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    (id & 0x7) | ((count as u32) << 3)
+}
+
+/// Builds one layer of a vector tile.
+pub(crate) struct LayerBuilder {
+    name: String,
+    keys: Vec<String>,
+    /// Encoded `Value` messages
+    values: Vec<Vec<u8>>,
+    value_index: HashMap<Vec<u8>, u32>,
+    features: Vec<u8>,
+    num_features: usize,
+}
+
+impl LayerBuilder {
+    pub(crate) fn new(name: &str, keys: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            values: Vec::new(),
+            value_index: HashMap::new(),
+            features: Vec::new(),
+            num_features: 0,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.num_features == 0
+    }
+
+    /// Add a polygon given as rings in tile coordinates (exterior first).
+    ///
+    /// The rings are rounded to integers, and re-oriented as MVT requires.
+    /// `properties` are indexed like the keys of the layer, encoded with
+    /// [`encode_value`].
+    pub(crate) fn add_polygon(&mut self, id: u64, rings: &[Ring], properties: &[Option<&[u8]>]) {
+        let mut geometry = Vec::new();
+        let mut cursor = [0i32; 2];
+        for (idx, ring) in rings.iter().enumerate() {
+            let mut points: Vec<[i32; 2]> = Vec::with_capacity(ring.len());
+            for p in ring {
+                let p = [p[0].round() as i32, p[1].round() as i32];
+                if points.last() != Some(&p) {
+                    points.push(p);
+                }
+            }
+            while points.len() > 1 && points.first() == points.last() {
+                points.pop();
+            }
+            if points.len() < 3 {
+                if idx == 0 {
+                    return;
+                }
+                continue;
+            }
+            let area = signed_area(&points);
+            if area == 0 {
+                if idx == 0 {
+                    return;
+                }
+                continue;
+            }
+            // exterior rings are clockwise, interior rings counter-clockwise
+            if (area > 0) != (idx == 0) {
+                points.reverse();
+            }
+
+            geometry.push(command(1, 1));
+            geometry.push(zigzag(points[0][0] - cursor[0]));
+            geometry.push(zigzag(points[0][1] - cursor[1]));
+            geometry.push(command(2, points.len() - 1));
+            for w in points.windows(2) {
+                geometry.push(zigzag(w[1][0] - w[0][0]));
+                geometry.push(zigzag(w[1][1] - w[0][1]));
+            }
+            geometry.push(command(7, 1));
+            cursor = *points.last().unwrap();
+        }
+
+        let mut tags = Vec::new();
+        for (key, value) in properties.iter().enumerate() {
+            let Some(value) = value else { continue };
+            let index = match self.value_index.get(*value) {
+                Some(&index) => index,
+                None => {
+                    let index = self.values.len() as u32;
+                    self.values.push(value.to_vec());
+                    self.value_index.insert(value.to_vec(), index);
+                    index
+                }
+            };
+            tags.push(key as u32);
+            tags.push(index);
+        }
+
+        let mut feature = Vec::new();
+        write_varint_field(&mut feature, 1, id);
+        write_packed(&mut feature, 2, &tags);
+        write_varint_field(&mut feature, 3, 3); // POLYGON
+        write_packed(&mut feature, 4, &geometry);
+        write_bytes_field(&mut self.features, 2, &feature);
+        self.num_features += 1;
+    }
+
+    /// Encode the layer as a complete tile.
+    pub(crate) fn into_tile(self) -> Vec<u8> {
+        let mut layer = Vec::new();
+        write_varint_field(&mut layer, 15, 2); // version
+        write_bytes_field(&mut layer, 1, self.name.as_bytes());
+        layer.extend_from_slice(&self.features);
+        for key in &self.keys {
+            write_bytes_field(&mut layer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            write_bytes_field(&mut layer, 4, value);
+        }
+        write_varint_field(&mut layer, 5, EXTENT as u64);
+
+        let mut tile = Vec::new();
+        write_bytes_field(&mut tile, 3, &layer);
+        tile
+    }
+}
+
+/// Encode a property value as an MVT `Value` message: a string, or a double.
+pub(crate) fn encode_value(value: Value) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    match value {
+        Value::Text(value) => write_bytes_field(&mut encoded, 1, value.as_bytes()),
+        Value::Real(value) => {
+            write_varint(&mut encoded, (3 << 3) | 1);
+            encoded.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    encoded
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field: u32, v: u64) {
+    write_varint(out, (field as u64) << 3);
+    write_varint(out, v);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(out, ((field as u64) << 3) | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len());
+    for &v in values {
+        write_varint(&mut packed, v as u64);
+    }
+    write_bytes_field(out, field, &packed);
+}
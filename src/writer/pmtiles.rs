@@ -0,0 +1,520 @@
+//! Vector tiles (MVT) in a PMTiles v3 archive.
+
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use geo::{BoundingRect, Simplify};
+use hashbrown::HashMap;
+
+use super::mvt::{self, EXTENT, LayerBuilder, Ring};
+use super::{Error, FeatureSink, Schema};
+use crate::data::{Fude, FudeAttributes};
+
+const LAYER_NAME: &str = "fude";
+const HEADER_LEN: usize = 127;
+/// The header and the root directory must fit in the first 16 KiB.
+const MAX_ROOT_LEN: usize = 16384 - HEADER_LEN;
+/// Buffer around each tile, in tile units
+const BUFFER: f64 = 64.0;
+
+const COMPRESSION_GZIP: u8 = 2;
+const TILE_TYPE_MVT: u8 = 1;
+
+struct Feature {
+    /// Rings in normalized Web Mercator coordinates
+    rings: Vec<Ring>,
+    /// (min, max) of the rings
+    bbox: [[f64; 2]; 2],
+    /// Encoded with [`mvt::encode_value`]
+    properties: Vec<Option<Vec<u8>>>,
+}
+
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    run_length: u32,
+}
+
+/// Cuts parcels into Mapbox Vector Tiles over a range of zoom levels and
+/// writes them into a PMTiles archive.
+///
+/// The parcels are kept in memory until [`finish`](Self::finish), where the
+/// tiles are generated zoom by zoom. Geometries are simplified per zoom level
+/// with a tolerance in tile units, and parcels smaller than a tile unit are
+/// dropped below the maximum zoom.
+pub struct PmtilesWriter<W: Write> {
+    out: W,
+    min_zoom: u8,
+    max_zoom: u8,
+    tolerance: f64,
+    schema: Schema,
+    features: Vec<Feature>,
+    /// Extent of the parcels in lon/lat
+    bounds: Option<geo::Rect<f64>>,
+}
+
+impl<W: Write> PmtilesWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            min_zoom: 14,
+            max_zoom: 16,
+            tolerance: 1.0,
+            schema: Schema::default(),
+            features: Vec::new(),
+            bounds: None,
+        }
+    }
+
+    /// Set the zoom levels to generate (default: 14 to 16).
+    pub fn with_zoom_range(mut self, min_zoom: u8, max_zoom: u8) -> Self {
+        self.min_zoom = min_zoom.min(max_zoom);
+        self.max_zoom = max_zoom.min(24);
+        self
+    }
+
+    /// Set the simplification tolerance, in tile units (of 4096 per tile).
+    pub fn with_simplification(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let Some(rect) = polygon.bounding_rect() else {
+            return Ok(());
+        };
+        let rings = world_rings(polygon);
+        let mut bbox = [[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]];
+        for p in &rings[0] {
+            bbox[0] = [bbox[0][0].min(p[0]), bbox[0][1].min(p[1])];
+            bbox[1] = [bbox[1][0].max(p[0]), bbox[1][1].max(p[1])];
+        }
+        self.features.push(Feature {
+            rings,
+            bbox,
+            properties: self
+                .schema
+                .columns()
+                .iter()
+                .map(|column| column.value(&fude.attributes).map(mvt::encode_value))
+                .collect(),
+        });
+        self.bounds = Some(match self.bounds {
+            Some(b) => geo::Rect::new(
+                geo::coord! { x: b.min().x.min(rect.min().x), y: b.min().y.min(rect.min().y) },
+                geo::coord! { x: b.max().x.max(rect.max().x), y: b.max().y.max(rect.max().y) },
+            ),
+            None => rect,
+        });
+        Ok(())
+    }
+
+    /// Generate the tiles and write the archive.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let mut tiles = Vec::new();
+        for z in self.min_zoom..=self.max_zoom {
+            self.generate_zoom(z, &mut tiles)?;
+        }
+        tiles.sort_unstable_by_key(|(tile_id, _)| *tile_id);
+
+        let mut entries = Vec::with_capacity(tiles.len());
+        let mut offset = 0u64;
+        for (tile_id, data) in &tiles {
+            entries.push(Entry {
+                tile_id: *tile_id,
+                offset,
+                length: data.len() as u32,
+                run_length: 1,
+            });
+            offset += data.len() as u64;
+        }
+        let tile_data_len = offset;
+        let (root, leaves) = build_directories(&entries)?;
+        let metadata = gzip(self.metadata().as_bytes())?;
+
+        let root_offset = HEADER_LEN as u64;
+        let metadata_offset = root_offset + root.len() as u64;
+        let leaves_offset = metadata_offset + metadata.len() as u64;
+        let tile_data_offset = leaves_offset + leaves.len() as u64;
+
+        let bounds = self.bounds.unwrap_or(geo::Rect::new(
+            geo::coord! { x: 0., y: 0. },
+            geo::coord! { x: 0., y: 0. },
+        ));
+        let e7 = |v: f64| ((v * 1e7).round() as i32).to_le_bytes();
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(b"PMTiles");
+        header.push(3);
+        for v in [
+            root_offset,
+            root.len() as u64,
+            metadata_offset,
+            metadata.len() as u64,
+            leaves_offset,
+            leaves.len() as u64,
+            tile_data_offset,
+            tile_data_len,
+            entries.len() as u64, // addressed tiles
+            entries.len() as u64, // tile entries
+            entries.len() as u64, // tile contents
+        ] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        header.extend_from_slice(&[
+            1, // clustered
+            COMPRESSION_GZIP,
+            COMPRESSION_GZIP,
+            TILE_TYPE_MVT,
+            self.min_zoom,
+            self.max_zoom,
+        ]);
+        header.extend_from_slice(&e7(bounds.min().x));
+        header.extend_from_slice(&e7(bounds.min().y));
+        header.extend_from_slice(&e7(bounds.max().x));
+        header.extend_from_slice(&e7(bounds.max().y));
+        header.push(self.min_zoom);
+        header.extend_from_slice(&e7(bounds.center().x));
+        header.extend_from_slice(&e7(bounds.center().y));
+        debug_assert_eq!(header.len(), HEADER_LEN);
+
+        self.out.write_all(&header)?;
+        self.out.write_all(&root)?;
+        self.out.write_all(&metadata)?;
+        self.out.write_all(&leaves)?;
+        for (_, data) in &tiles {
+            self.out.write_all(data)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn generate_zoom(&self, z: u8, tiles: &mut Vec<(u64, Vec<u8>)>) -> Result<(), Error> {
+        let num_tiles = 1u32 << z;
+        let scale = EXTENT as f64 * num_tiles as f64;
+        let keys: Vec<&str> = self.schema.columns().iter().map(|c| c.name()).collect();
+        let mut layers: HashMap<(u32, u32), LayerBuilder> = HashMap::new();
+
+        for (idx, feature) in self.features.iter().enumerate() {
+            let [min, max] = feature.bbox;
+            if z < self.max_zoom
+                && (max[0] - min[0]) * scale < 1.0
+                && (max[1] - min[1]) * scale < 1.0
+            {
+                continue;
+            }
+            let rings = scale_rings(&feature.rings, scale, self.tolerance);
+            let properties: Vec<Option<&[u8]>> =
+                feature.properties.iter().map(|v| v.as_deref()).collect();
+
+            let tile_range = |min: f64, max: f64| {
+                let first = ((min * scale - BUFFER) / EXTENT as f64).floor().max(0.0) as u32;
+                let last = ((max * scale + BUFFER) / EXTENT as f64).floor().max(0.0) as u32;
+                first..=last.min(num_tiles - 1)
+            };
+            for ty in tile_range(min[1], max[1]) {
+                for tx in tile_range(min[0], max[0]) {
+                    let clipped = clip_to_tile(&rings, tx, ty);
+                    if clipped.is_empty() {
+                        continue;
+                    }
+                    layers
+                        .entry((tx, ty))
+                        .or_insert_with(|| LayerBuilder::new(LAYER_NAME, &keys))
+                        .add_polygon(idx as u64 + 1, &clipped, &properties);
+                }
+            }
+        }
+
+        for ((x, y), layer) in layers {
+            if layer.is_empty() {
+                continue;
+            }
+            tiles.push((tile_id(z, x, y), gzip(&layer.into_tile())?));
+        }
+        Ok(())
+    }
+
+    fn metadata(&self) -> String {
+        let fields = self
+            .schema
+            .columns()
+            .iter()
+            .map(|column| format!(r#""{}":"String""#, column.name()))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            r#"{{"name":"{LAYER_NAME}","format":"pbf","vector_layers":[{{"id":"{LAYER_NAME}","fields":{{{fields}}},"minzoom":{},"maxzoom":{}}}]}}"#,
+            self.min_zoom, self.max_zoom
+        )
+    }
+}
+
+/// Rings of a polygon in lon/lat, in normalized Web Mercator coordinates.
+fn world_rings(polygon: &geo::Polygon<f64>) -> Vec<Ring> {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| {
+            ring.coords()
+                .map(|c| mvt::lonlat_to_world(c.x, c.y))
+                .collect()
+        })
+        .collect()
+}
+
+/// Scale rings in world coordinates to the tile units of a zoom level, and
+/// simplify them.
+fn scale_rings(rings: &[Ring], scale: f64, tolerance: f64) -> Vec<Ring> {
+    rings
+        .iter()
+        .map(|ring| {
+            let line: geo::LineString<f64> =
+                ring.iter().map(|p| (p[0] * scale, p[1] * scale)).collect();
+            line.simplify(&tolerance)
+                .coords()
+                .map(|c| [c.x, c.y])
+                .collect()
+        })
+        .collect()
+}
+
+/// Clip scaled rings to a tile and its buffer, in the coordinates of the
+/// tile. Empty when the exterior is outside of it.
+fn clip_to_tile(rings: &[Ring], tx: u32, ty: u32) -> Vec<Ring> {
+    let origin = [tx as f64 * EXTENT as f64, ty as f64 * EXTENT as f64];
+    let mut clipped = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let local: Ring = ring
+            .iter()
+            .map(|p| [p[0] - origin[0], p[1] - origin[1]])
+            .collect();
+        let ring = mvt::clip_ring(&local, -BUFFER, EXTENT as f64 + BUFFER);
+        // holes may fall outside of the tile, but the exterior must not
+        if !ring.is_empty() {
+            clipped.push(ring);
+        } else if clipped.is_empty() {
+            break;
+        }
+    }
+    clipped
+}
+
+/// Extent in longitude/latitude of a tile and its buffer: the parcels to
+/// give to [`encode_tile`].
+pub fn tile_bounds(z: u8, x: u32, y: u32) -> geo::Rect<f64> {
+    let scale = EXTENT as f64 * (1u64 << z) as f64;
+    let lonlat = |px: f64, py: f64| {
+        let lat = (std::f64::consts::PI * (1.0 - 2.0 * py / scale))
+            .sinh()
+            .atan();
+        geo::coord! { x: px / scale * 360.0 - 180.0, y: lat.to_degrees() }
+    };
+    let [x0, y0] = [
+        x as f64 * EXTENT as f64 - BUFFER,
+        y as f64 * EXTENT as f64 - BUFFER,
+    ];
+    let [x1, y1] = [
+        x0 + EXTENT as f64 + 2.0 * BUFFER,
+        y0 + EXTENT as f64 + 2.0 * BUFFER,
+    ];
+    geo::Rect::new(lonlat(x0, y0), lonlat(x1, y1))
+}
+
+/// Encode one vector tile (uncompressed MVT, with a `fude` layer like the
+/// tiles of [`PmtilesWriter`]) of parcels in lon/lat, e.g. to serve the
+/// tiles of a [`Dataset`](crate::dataset::Dataset) on demand. The parcels
+/// are simplified with `tolerance`, in tile units. Empty when no parcel is
+/// in the tile.
+pub fn encode_tile<'a>(
+    z: u8,
+    x: u32,
+    y: u32,
+    parcels: impl IntoIterator<Item = (&'a FudeAttributes<'a>, &'a geo::Polygon<f64>)>,
+    schema: &Schema,
+    tolerance: f64,
+) -> Vec<u8> {
+    let scale = EXTENT as f64 * (1u64 << z) as f64;
+    let keys: Vec<&str> = schema.columns().iter().map(|c| c.name()).collect();
+    let mut layer = LayerBuilder::new(LAYER_NAME, &keys);
+    for (idx, (attributes, polygon)) in parcels.into_iter().enumerate() {
+        let rings = scale_rings(&world_rings(polygon), scale, tolerance);
+        let clipped = clip_to_tile(&rings, x, y);
+        if clipped.is_empty() {
+            continue;
+        }
+        let properties: Vec<Option<Vec<u8>>> = schema
+            .columns()
+            .iter()
+            .map(|column| column.value(attributes).map(mvt::encode_value))
+            .collect();
+        let properties: Vec<Option<&[u8]>> = properties.iter().map(|v| v.as_deref()).collect();
+        layer.add_polygon(idx as u64 + 1, &clipped, &properties);
+    }
+    match layer.is_empty() {
+        true => Vec::new(),
+        false => layer.into_tile(),
+    }
+}
+
+/// Tile ID of a tile: the position on the Hilbert curve of its zoom level,
+/// after all the tiles of the lower zoom levels.
+fn tile_id(z: u8, x: u32, y: u32) -> u64 {
+    let base = ((1u64 << (2 * z as u64)) - 1) / 3;
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    base + d
+}
+
+fn serialize_directory(entries: &[Entry]) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    mvt::write_varint(&mut buf, entries.len() as u64);
+    let mut last_id = 0;
+    for e in entries {
+        mvt::write_varint(&mut buf, e.tile_id - last_id);
+        last_id = e.tile_id;
+    }
+    for e in entries {
+        mvt::write_varint(&mut buf, e.run_length as u64);
+    }
+    for e in entries {
+        mvt::write_varint(&mut buf, e.length as u64);
+    }
+    for (i, e) in entries.iter().enumerate() {
+        if i > 0 && e.offset == entries[i - 1].offset + entries[i - 1].length as u64 {
+            mvt::write_varint(&mut buf, 0);
+        } else {
+            mvt::write_varint(&mut buf, e.offset + 1);
+        }
+    }
+    gzip(&buf)
+}
+
+/// Build the root directory, and the leaf directories if the entries don't
+/// fit in the root.
+fn build_directories(entries: &[Entry]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let root = serialize_directory(entries)?;
+    if root.len() <= MAX_ROOT_LEN {
+        return Ok((root, Vec::new()));
+    }
+    let mut leaf_size = 4096;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = serialize_directory(chunk)?;
+            root_entries.push(Entry {
+                tile_id: chunk[0].tile_id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u32,
+                run_length: 0,
+            });
+            leaves.extend_from_slice(&leaf);
+        }
+        let root = serialize_directory(&root_entries)?;
+        if root.len() <= MAX_ROOT_LEN {
+            return Ok((root, leaves));
+        }
+        leaf_size *= 2;
+    }
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+impl<W: Write> FeatureSink for PmtilesWriter<W> {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        PmtilesWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PmtilesWriter, encode_tile, tile_bounds, tile_id};
+    use crate::data::{Fude, FudeAttributes};
+    use crate::writer::Schema;
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_tile_id() {
+        assert_eq!(tile_id(0, 0, 0), 0);
+        assert_eq!(tile_id(1, 0, 0), 1);
+        assert_eq!(tile_id(1, 0, 1), 2);
+        assert_eq!(tile_id(1, 1, 1), 3);
+        assert_eq!(tile_id(1, 1, 0), 4);
+        assert_eq!(tile_id(2, 0, 0), 5);
+    }
+
+    #[test]
+    fn test_pmtiles() {
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut buf = Vec::new();
+        let mut writer = PmtilesWriter::new(&mut buf).with_zoom_range(15, 16);
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.7000, y: 35.6800), (x: 139.7010, y: 35.6800), (x: 139.7010, y: 35.6810)],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(&buf[..8], b"PMTiles\x03");
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        let tile_data_offset = u64_at(56);
+        let tile_data_len = u64_at(64);
+        assert_eq!(tile_data_offset + tile_data_len, buf.len() as u64);
+        // one tile per zoom level
+        assert_eq!(u64_at(72), 2);
+        assert_eq!(&buf[100..102], &[15, 16]);
+    }
+
+    #[test]
+    fn test_encode_tile() {
+        let attributes = FudeAttributes {
+            id: "H000000001".into(),
+            ..Default::default()
+        };
+        let polygon = polygon![(x: 139.7000, y: 35.6800), (x: 139.7010, y: 35.6800), (x: 139.7010, y: 35.6810)];
+        // the tile of z16 containing the parcel
+        let (x, y) = (58199, 25806);
+        let bounds = tile_bounds(16, x, y);
+        assert!(bounds.min().x < 139.7 && bounds.max().x > 139.701);
+        assert!(bounds.min().y < 35.68 && bounds.max().y > 35.681);
+        let schema = Schema::default();
+        let tile = encode_tile(16, x, y, [(&attributes, &polygon)], &schema, 1.0);
+        assert!(!tile.is_empty());
+        let tile = encode_tile(16, x + 2, y, [(&attributes, &polygon)], &schema, 1.0);
+        assert!(tile.is_empty());
+    }
+}
@@ -0,0 +1,500 @@
+//! Attribute schema: which columns are written, in which order, and under
+//! which names.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use super::{Error, FUDE_COLUMNS, Getter};
+use crate::codes::{AccuracyClass, CoordClass};
+use crate::data::FudeAttributes;
+
+/// English names of [`FUDE_COLUMNS`], in the same order.
+const ENGLISH_NAMES: [&str; 12] = [
+    "id",
+    "oaza_code",
+    "chome_code",
+    "koaza_code",
+    "yobi_code",
+    "oaza",
+    "chome",
+    "koaza",
+    "yobi",
+    "chiban",
+    "accuracy_class",
+    "coord_class",
+];
+
+/// Name of the [`Schema::with_stable_id`] column, in both schemas.
+const STABLE_ID: &str = "stable_id";
+
+/// Names of the [`Schema::with_provenance`] columns, in both schemas.
+const PROVENANCE: [(&str, Getter); 3] = [
+    ("source_file", |a| a.source_file.as_deref()),
+    ("map_number", |a| a.map_number.as_deref()),
+    ("run_id", |a| a.run_id.as_deref()),
+];
+
+/// Japanese and English names of the [`Schema::with_computed_area`] column.
+const COMPUTED_AREA: (&str, &str) = ("計算地積", "computed_area");
+
+/// Japanese and English names of the [`Schema::with_municipality`] columns.
+const MUNICIPALITY: [(&str, &str, Getter); 2] = [
+    ("市区町村コード", "municipality_code", |a| {
+        a.municipality_code.as_deref()
+    }),
+    ("市区町村名", "municipality_name", |a| {
+        a.municipality_name.as_deref()
+    }),
+];
+
+/// Japanese and English names of the [`Schema::with_code_labels`] columns,
+/// with their Japanese and English values.
+const CODE_LABELS: [(&str, &str, Getter, Getter); 3] = [
+    (
+        "精度区分説明",
+        "accuracy_label",
+        |a| accuracy_class(a).map(AccuracyClass::label),
+        |a| accuracy_class(a).map(AccuracyClass::english_label),
+    ),
+    (
+        "公差",
+        "accuracy_tolerance",
+        |a| accuracy_class(a).map(AccuracyClass::tolerance_text),
+        |a| accuracy_class(a).map(AccuracyClass::tolerance_text),
+    ),
+    (
+        "座標値種別説明",
+        "coord_class_label",
+        |a| coord_class(a).map(CoordClass::label),
+        |a| coord_class(a).map(CoordClass::english_label),
+    ),
+];
+
+fn accuracy_class(attributes: &FudeAttributes) -> Option<AccuracyClass> {
+    AccuracyClass::parse(attributes.accuracy_class.as_deref()?)
+}
+
+fn coord_class(attributes: &FudeAttributes) -> Option<CoordClass> {
+    CoordClass::parse(attributes.coord_class.as_deref()?)
+}
+
+/// An output column of parcels.
+#[derive(Clone, Debug)]
+pub struct Column {
+    name: String,
+    key: Cow<'static, str>,
+    english_name: Cow<'static, str>,
+    source: Source,
+}
+
+/// Where the value of a column comes from.
+#[derive(Clone, Debug)]
+enum Source {
+    Field(Getter),
+    /// A number, e.g. [`FudeAttributes::computed_area`]
+    Real(fn(&FudeAttributes) -> Option<f64>),
+    /// [`FudeAttributes::extra`], by the key of the column
+    Extra,
+}
+
+/// Type of the values of a column, for the formats with typed fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    Real,
+}
+
+/// A value of a column.
+///
+/// Displayed as text by the text formats, with 2 decimals for numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Text(&'a str),
+    Real(f64),
+}
+
+#[cfg(any(feature = "gpkg", feature = "spatialite"))]
+impl ColumnType {
+    /// The type of the column in SQLite.
+    pub(crate) fn sql_type(self) -> &'static str {
+        match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Real => "REAL",
+        }
+    }
+}
+
+#[cfg(any(feature = "gpkg", feature = "spatialite"))]
+impl rusqlite::ToSql for Value<'_> {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(match *self {
+            Value::Text(value) => value.into(),
+            Value::Real(value) => value.into(),
+        })
+    }
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(value) => f.write_str(value),
+            Value::Real(value) => write!(f, "{:.2}", value),
+        }
+    }
+}
+
+impl Column {
+    /// Name of the column in the output.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The standard (Japanese) name of the attribute.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The English name of the attribute.
+    pub fn english_name(&self) -> &str {
+        &self.english_name
+    }
+
+    /// Whether the column was given a name other than its standard Japanese
+    /// or English one.
+    pub fn is_renamed(&self) -> bool {
+        self.name != self.key() && self.name != self.english_name()
+    }
+
+    pub fn column_type(&self) -> ColumnType {
+        match self.source {
+            Source::Real(_) => ColumnType::Real,
+            Source::Field(_) | Source::Extra => ColumnType::Text,
+        }
+    }
+
+    /// The value of the column, typed.
+    pub fn value<'a>(&self, attributes: &'a FudeAttributes) -> Option<Value<'a>> {
+        match self.source {
+            Source::Field(get) => get(attributes).map(Value::Text),
+            Source::Real(get) => get(attributes).map(Value::Real),
+            Source::Extra => attributes.extra(&self.key).map(Value::Text),
+        }
+    }
+
+    /// The value of the column as text (see [`Value`]).
+    pub fn get<'a>(&self, attributes: &'a FudeAttributes) -> Option<Cow<'a, str>> {
+        match self.value(attributes)? {
+            Value::Text(value) => Some(Cow::Borrowed(value)),
+            value => Some(Cow::Owned(value.to_string())),
+        }
+    }
+}
+
+/// The columns of parcels written by the writers.
+///
+/// Columns are referred to by their Japanese name (`地番`), their English
+/// name (`chiban`) or their current name.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    columns: Vec<Column>,
+    /// Whether the columns added later get their English names
+    english: bool,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self::japanese()
+    }
+}
+
+impl Schema {
+    /// The standard Japanese column names (`大字コード`, `地番`, ...).
+    pub fn japanese() -> Self {
+        Self::with_names(FUDE_COLUMNS.map(|(name, _)| name), false)
+    }
+
+    /// ASCII column names (`oaza_code`, `chiban`, ...), for tools that
+    /// mangle non-ASCII field names.
+    pub fn english() -> Self {
+        Self::with_names(ENGLISH_NAMES, true)
+    }
+
+    fn with_names(names: [&str; 12], english: bool) -> Self {
+        Self {
+            columns: names
+                .iter()
+                .zip(FUDE_COLUMNS.iter().zip(ENGLISH_NAMES))
+                .map(|(name, (&(key, get), english_name))| Column {
+                    name: name.to_string(),
+                    key: key.into(),
+                    english_name: english_name.into(),
+                    source: Source::Field(get),
+                })
+                .collect(),
+            english,
+        }
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    fn position(&self, column: &str) -> Result<usize, Error> {
+        self.columns
+            .iter()
+            .position(|c| c.name == column || c.key() == column || c.english_name() == column)
+            .ok_or_else(|| Error::UnknownColumn(column.to_string()))
+    }
+
+    /// Rename a column.
+    pub fn rename(mut self, column: &str, name: impl Into<String>) -> Result<Self, Error> {
+        let pos = self.position(column)?;
+        self.columns[pos].name = name.into();
+        Ok(self)
+    }
+
+    /// Move the given columns to the front, in this order.
+    pub fn reorder<S: AsRef<str>>(
+        mut self,
+        columns: impl IntoIterator<Item = S>,
+    ) -> Result<Self, Error> {
+        let mut front = Vec::new();
+        for column in columns {
+            let pos = self.position(column.as_ref())?;
+            front.push(self.columns.remove(pos));
+        }
+        front.append(&mut self.columns);
+        self.columns = front;
+        Ok(self)
+    }
+
+    /// Add a `stable_id` column in front, with
+    /// [`FudeAttributes::stable_id`] (see [`ParseOptions::stable_ids`](crate::parser::ParseOptions)).
+    pub fn with_stable_id(mut self) -> Self {
+        if !self.columns.iter().any(|c| c.key == STABLE_ID) {
+            let column = Column {
+                name: STABLE_ID.to_string(),
+                key: STABLE_ID.into(),
+                english_name: STABLE_ID.into(),
+                source: Source::Field(|a| a.stable_id.as_deref()),
+            };
+            self.columns.insert(0, column);
+        }
+        self
+    }
+
+    /// Add `source_file`, `map_number` and `run_id` columns at the end, with
+    /// the attributes filled by [`ParsedData::set_provenance`](crate::data::ParsedData).
+    pub fn with_provenance(mut self) -> Self {
+        for (key, get) in PROVENANCE {
+            if !self.columns.iter().any(|c| c.key == key) {
+                self.columns.push(Column {
+                    name: key.to_string(),
+                    key: key.into(),
+                    english_name: key.into(),
+                    source: Source::Field(get),
+                });
+            }
+        }
+        self
+    }
+
+    /// Add columns describing the 精度区分 (label with the allowed errors, and
+    /// 公差 in meters) and the 座標値種別 at the end (see [`crate::codes`]),
+    /// in English with [`Schema::english`].
+    pub fn with_code_labels(mut self) -> Self {
+        for (key, english_name, japanese_get, english_get) in CODE_LABELS {
+            if !self.columns.iter().any(|c| c.key == key) {
+                let (name, get) = match self.english {
+                    true => (english_name, english_get),
+                    false => (key, japanese_get),
+                };
+                self.columns.push(Column {
+                    name: name.to_string(),
+                    key: key.into(),
+                    english_name: english_name.into(),
+                    source: Source::Field(get),
+                });
+            }
+        }
+        self
+    }
+
+    /// Add 市区町村コード and 市区町村名 columns in front, with the attributes
+    /// filled by [`ParsedData::set_municipality`](crate::data::ParsedData::set_municipality).
+    pub fn with_municipality(mut self) -> Self {
+        for (i, (key, english_name, get)) in MUNICIPALITY.into_iter().enumerate() {
+            if !self.columns.iter().any(|c| c.key == key) {
+                let name = match self.english {
+                    true => english_name,
+                    false => key,
+                };
+                let column = Column {
+                    name: name.to_string(),
+                    key: key.into(),
+                    english_name: english_name.into(),
+                    source: Source::Field(get),
+                };
+                self.columns.insert(i, column);
+            }
+        }
+        self
+    }
+
+    /// Add a `計算地積` column at the end, with the area filled by
+    /// [`ParsedData::set_computed_areas`](crate::data::ParsedData::set_computed_areas).
+    pub fn with_computed_area(mut self) -> Self {
+        let (key, english_name) = COMPUTED_AREA;
+        if !self.columns.iter().any(|c| c.key == key) {
+            let name = match self.english {
+                true => english_name,
+                false => key,
+            };
+            self.columns.push(Column {
+                name: name.to_string(),
+                key: key.into(),
+                english_name: english_name.into(),
+                source: Source::Real(|a| a.computed_area),
+            });
+        }
+        self
+    }
+
+    /// Add a column at the end, with the [`extra`](FudeAttributes::extra)
+    /// attribute of this name (e.g. set by a conversion transform).
+    pub fn with_extra_column(mut self, name: &str) -> Self {
+        if !self.columns.iter().any(|c| c.key == name) {
+            self.columns.push(Column {
+                name: name.to_string(),
+                key: name.to_string().into(),
+                english_name: name.to_string().into(),
+                source: Source::Extra,
+            });
+        }
+        self
+    }
+
+    /// Keep only the given columns, in this order.
+    pub fn select<S: AsRef<str>>(
+        self,
+        columns: impl IntoIterator<Item = S>,
+    ) -> Result<Self, Error> {
+        let mut selected = Vec::new();
+        for column in columns {
+            let column = &self.columns[self.position(column.as_ref())?];
+            if !selected.iter().any(|c: &Column| c.key == column.key) {
+                selected.push(column.clone());
+            }
+        }
+        Ok(Self {
+            columns: selected,
+            english: self.english,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ColumnType, Schema, Value};
+    use crate::data::FudeAttributes;
+    use test_log::test;
+
+    #[test]
+    fn test_schema() {
+        let schema = Schema::english()
+            .rename("地番", "chiban_no")
+            .unwrap()
+            .reorder(["chiban_no", "oaza"])
+            .unwrap();
+        let names: Vec<_> = schema.columns().iter().map(|c| c.name()).collect();
+        assert_eq!(&names[..3], ["chiban_no", "oaza", "id"]);
+        assert_eq!(schema.len(), 12);
+
+        let attributes = FudeAttributes {
+            chiban: Some("12-3".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            schema.columns()[0].get(&attributes).as_deref(),
+            Some("12-3")
+        );
+        assert!(schema.columns()[0].is_renamed());
+        assert!(!schema.columns()[1].is_renamed());
+        assert!(Schema::japanese().rename("foo", "bar").is_err());
+
+        let schema = Schema::japanese().select(["chiban", "id", "地番"]).unwrap();
+        let names: Vec<_> = schema.columns().iter().map(|c| c.name()).collect();
+        assert_eq!(names, ["地番", "id"]);
+
+        let schema = Schema::english().with_stable_id();
+        assert_eq!(schema.columns()[0].name(), "stable_id");
+        assert_eq!(schema.len(), 13);
+        let schema = schema.with_provenance().with_provenance();
+        let names: Vec<_> = schema.columns().iter().map(|c| c.name()).collect();
+        assert_eq!(names[13..], ["source_file", "map_number", "run_id"]);
+
+        let schema = Schema::english().with_stable_id().with_municipality();
+        let names: Vec<_> = schema.columns().iter().map(|c| c.name()).collect();
+        assert_eq!(
+            names[..3],
+            ["municipality_code", "municipality_name", "stable_id"]
+        );
+
+        let schema = Schema::japanese()
+            .with_extra_column("area")
+            .rename("area", "面積")
+            .unwrap();
+        let mut attributes = FudeAttributes::default();
+        attributes.set_extra("area", "1.5");
+        assert_eq!(schema.columns()[12].name(), "面積");
+        assert_eq!(
+            schema.columns()[12].get(&attributes).as_deref(),
+            Some("1.5")
+        );
+
+        let attributes = FudeAttributes {
+            computed_area: Some(123.456),
+            ..Default::default()
+        };
+        let schema = Schema::english().with_computed_area();
+        let column = &schema.columns()[12];
+        assert_eq!(column.column_type(), ColumnType::Real);
+        assert_eq!(column.value(&attributes), Some(Value::Real(123.456)));
+        assert_eq!(column.get(&attributes).as_deref(), Some("123.46"));
+
+        let attributes = FudeAttributes {
+            accuracy_class: Some("乙一".into()),
+            coord_class: Some("図上測量".into()),
+            ..Default::default()
+        };
+        let schema = Schema::english().with_code_labels();
+        let values: Vec<_> = schema.columns()[12..]
+            .iter()
+            .map(|c| (c.name(), c.get(&attributes).unwrap().into_owned()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                (
+                    "accuracy_label",
+                    "Otsu 1 (RMSE 25 cm, tolerance 75 cm)".to_string()
+                ),
+                ("accuracy_tolerance", "0.75".to_string()),
+                (
+                    "coord_class_label",
+                    "digitized from a paper map".to_string()
+                )
+            ]
+        );
+        let schema = Schema::japanese().with_code_labels();
+        assert_eq!(schema.columns()[13].name(), "公差");
+    }
+}
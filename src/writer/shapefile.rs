@@ -0,0 +1,464 @@
+//! ESRI Shapefile output.
+//!
+//! DBF field names are limited to 10 bytes, so the Japanese column names are
+//! mapped to romanized ones (see [`DEFAULT_FIELD_NAMES`]).
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use geo::BoundingRect;
+use geo::orient::{Direction, Orient};
+use hashbrown::HashMap;
+
+use super::schema::{Column, ColumnType};
+use super::{Error, FeatureSink, Schema};
+use crate::crs::Crs;
+use crate::data::Fude;
+
+const SHAPE_TYPE_POLYGON: i32 = 5;
+const MAX_FIELD_NAME_LEN: usize = 10;
+const MAX_FIELD_LEN: usize = 254;
+
+/// Default mapping from the column names to DBF field names.
+pub const DEFAULT_FIELD_NAMES: [(&str, &str); 16] = [
+    ("stable_id", "STABLE_ID"),
+    ("id", "ID"),
+    ("大字コード", "OAZA_CD"),
+    ("丁目コード", "CHOME_CD"),
+    ("小字コード", "KOAZA_CD"),
+    ("予備コード", "YOBI_CD"),
+    ("大字名", "OAZA"),
+    ("丁目名", "CHOME"),
+    ("小字名", "KOAZA"),
+    ("予備名", "YOBI"),
+    ("地番", "CHIBAN"),
+    ("精度区分", "SEIDO"),
+    ("座標値種別", "ZAHYO_SBT"),
+    ("source_file", "SRC_FILE"),
+    ("map_number", "MAP_NO"),
+    ("run_id", "RUN_ID"),
+];
+
+/// Character encoding of the DBF attributes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DbfEncoding {
+    #[default]
+    Utf8,
+    /// Shift_JIS (CP932), as expected by many legacy GIS. Characters missing
+    /// from it are written as numeric character references (`&#12345;`).
+    ShiftJis,
+}
+
+impl DbfEncoding {
+    /// The encoded text, and whether some of its characters are missing from
+    /// the encoding.
+    fn encode<'a>(&self, s: &'a str) -> (Cow<'a, [u8]>, bool) {
+        match self {
+            DbfEncoding::Utf8 => (s.as_bytes().into(), false),
+            DbfEncoding::ShiftJis => {
+                let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(s);
+                (encoded, unmappable)
+            }
+        }
+    }
+
+    /// Content of the .cpg file
+    fn code_page(&self) -> &'static str {
+        match self {
+            DbfEncoding::Utf8 => "UTF-8",
+            DbfEncoding::ShiftJis => "CP932",
+        }
+    }
+
+    /// Language driver ID of the DBF header
+    fn language_driver(&self) -> u8 {
+        match self {
+            DbfEncoding::Utf8 => 0x00,
+            DbfEncoding::ShiftJis => 0x13,
+        }
+    }
+}
+
+/// Writes parcels as a Shapefile (.shp, .shx, .dbf, .prj and .cpg).
+///
+/// Geometries are streamed to the .shp file. The encoded attributes are kept in
+/// memory until [`finish`](Self::finish), since the DBF field widths must be
+/// known before the first record.
+pub struct ShapefileWriter {
+    path: PathBuf,
+    shp: BufWriter<File>,
+    shx: BufWriter<File>,
+    /// Current length of the .shp file in 16-bit words
+    shp_len: i32,
+    num_records: i32,
+    bbox: Option<geo::Rect<f64>>,
+    records: Vec<Vec<Vec<u8>>>,
+    encoding: DbfEncoding,
+    unmappable: usize,
+    schema: Schema,
+    field_names: HashMap<String, String>,
+    crs: Crs,
+    /// Unix time of the date of the DBF header (default: now)
+    date: Option<i64>,
+}
+
+impl ShapefileWriter {
+    /// Create a Shapefile. `path` is the .shp file; the other files are
+    /// placed next to it.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().with_extension("shp");
+        let mut shp = BufWriter::new(File::create(&path)?);
+        let mut shx = BufWriter::new(File::create(path.with_extension("shx"))?);
+        // headers are rewritten in finish()
+        shp.write_all(&[0; 100])?;
+        shx.write_all(&[0; 100])?;
+        Ok(Self {
+            path,
+            shp,
+            shx,
+            shp_len: 50,
+            num_records: 0,
+            bbox: None,
+            records: Vec::new(),
+            encoding: DbfEncoding::default(),
+            unmappable: 0,
+            schema: Schema::default(),
+            field_names: HashMap::new(),
+            crs: Crs::default(),
+            date: None,
+        })
+    }
+
+    /// Set the date of the DBF header, in seconds since the Unix epoch,
+    /// instead of today (e.g. for reproducible outputs).
+    pub fn with_date(mut self, unix_time: i64) -> Self {
+        self.date = Some(unix_time);
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: DbfEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// The CRS written to the .prj file (JGD2011 by default).
+    pub fn with_crs(mut self, crs: Crs) -> Self {
+        self.crs = crs;
+        self
+    }
+
+    /// Columns renamed by the schema use their new name (truncated to 10
+    /// bytes) as the DBF field name; the others use [`DEFAULT_FIELD_NAMES`].
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Override the DBF field names of some columns, given by their schema
+    /// or standard name.
+    pub fn with_field_names(mut self, mapping: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.field_names.extend(mapping);
+        self
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let Some(rect) = polygon.bounding_rect() else {
+            return Ok(());
+        };
+        // exterior rings are clockwise in Shapefiles
+        let polygon = polygon.orient(Direction::Reversed);
+        let rings: Vec<_> = std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .collect();
+        let num_points: usize = rings.iter().map(|r| r.0.len()).sum();
+
+        // content length in 16-bit words
+        let content_len = (44 + 4 * rings.len() + 16 * num_points) as i32 / 2;
+        self.num_records += 1;
+        self.shx.write_all(&self.shp_len.to_be_bytes())?;
+        self.shx.write_all(&content_len.to_be_bytes())?;
+
+        self.shp.write_all(&self.num_records.to_be_bytes())?;
+        self.shp.write_all(&content_len.to_be_bytes())?;
+        self.shp.write_all(&SHAPE_TYPE_POLYGON.to_le_bytes())?;
+        write_bbox(&mut self.shp, &rect)?;
+        self.shp.write_all(&(rings.len() as i32).to_le_bytes())?;
+        self.shp.write_all(&(num_points as i32).to_le_bytes())?;
+        let mut start = 0i32;
+        for ring in &rings {
+            self.shp.write_all(&start.to_le_bytes())?;
+            start += ring.0.len() as i32;
+        }
+        for coord in rings.iter().flat_map(|r| r.coords()) {
+            self.shp.write_all(&coord.x.to_le_bytes())?;
+            self.shp.write_all(&coord.y.to_le_bytes())?;
+        }
+        self.shp_len += 4 + content_len;
+
+        self.bbox = Some(match self.bbox {
+            Some(bbox) => geo::Rect::new(
+                geo::coord! { x: bbox.min().x.min(rect.min().x), y: bbox.min().y.min(rect.min().y) },
+                geo::coord! { x: bbox.max().x.max(rect.max().x), y: bbox.max().y.max(rect.max().y) },
+            ),
+            None => rect,
+        });
+
+        let mut record = Vec::with_capacity(self.schema.columns().len());
+        for column in self.schema.columns() {
+            let value = column.get(&fude.attributes).unwrap_or_default();
+            let (encoded, unmappable) = self.encode_value(&value);
+            self.unmappable += unmappable as usize;
+            record.push(encoded);
+        }
+        self.records.push(record);
+        Ok(())
+    }
+
+    /// The number of attribute values written so far with characters missing
+    /// from the encoding, which were replaced by numeric character references.
+    pub fn unmappable(&self) -> usize {
+        self.unmappable
+    }
+
+    /// Write the file headers and the .dbf, .prj and .cpg files.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let field_names = self
+            .schema
+            .columns()
+            .iter()
+            .map(|column| self.field_name(column))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shx_len = 50 + 4 * self.num_records;
+        let bbox = self.bbox.unwrap_or(geo::Rect::new(
+            geo::coord! { x: 0., y: 0. },
+            geo::coord! { x: 0., y: 0. },
+        ));
+        for (out, len) in [(&mut self.shp, self.shp_len), (&mut self.shx, shx_len)] {
+            out.seek(SeekFrom::Start(0))?;
+            write_header(out, len, &bbox)?;
+            out.flush()?;
+        }
+
+        self.write_dbf(&field_names)?;
+        std::fs::write(self.path.with_extension("prj"), self.crs.wkt())?;
+        std::fs::write(self.path.with_extension("cpg"), self.encoding.code_page())?;
+        Ok(())
+    }
+
+    fn field_name(&self, column: &Column) -> Result<Vec<u8>, Error> {
+        let default = || {
+            DEFAULT_FIELD_NAMES
+                .iter()
+                .find(|(key, _)| *key == column.key())
+                .map_or(column.key(), |(_, name)| name)
+        };
+        let name = match self
+            .field_names
+            .get(column.name())
+            .or_else(|| self.field_names.get(column.key()))
+        {
+            Some(name) => name.as_str(),
+            None if column.is_renamed() => column.name(),
+            None => default(),
+        };
+        let mut encoded = self.encoding.encode(name).0.into_owned();
+        if encoded.len() > MAX_FIELD_NAME_LEN {
+            // truncate on a character boundary
+            let mut end = 0;
+            for (i, c) in name.char_indices() {
+                if self.encoding.encode(&name[..i + c.len_utf8()]).0.len() > MAX_FIELD_NAME_LEN {
+                    break;
+                }
+                end = i + c.len_utf8();
+            }
+            encoded = self.encoding.encode(&name[..end]).0.into_owned();
+        }
+        if encoded.is_empty() {
+            return Err(Error::FieldName(name.to_string()));
+        }
+        Ok(encoded)
+    }
+
+    fn write_dbf(&self, field_names: &[Vec<u8>]) -> Result<(), Error> {
+        for (i, name) in field_names.iter().enumerate() {
+            if field_names[..i].contains(name) {
+                return Err(Error::FieldName(String::from_utf8_lossy(name).into_owned()));
+            }
+        }
+
+        let records = &self.records;
+        let widths = (0..field_names.len())
+            .map(|i| records.iter().map(|r| r[i].len()).max().unwrap_or(0).max(1))
+            .collect::<Vec<_>>();
+
+        // numeric columns are right-aligned, with 2 decimals
+        let numeric: Vec<bool> = self
+            .schema
+            .columns()
+            .iter()
+            .map(|column| column.column_type() == ColumnType::Real)
+            .collect();
+
+        let mut dbf = BufWriter::new(File::create(self.path.with_extension("dbf"))?);
+        let header_len = 32 + 32 * field_names.len() + 1;
+        let record_len = 1 + widths.iter().sum::<usize>();
+        let date = self.date.unwrap_or_else(super::metadata::unix_now);
+        let (year, month, day, _) = super::metadata::utc_date(date);
+        dbf.write_all(&[0x03, (year - 1900) as u8, month, day])?;
+        dbf.write_all(&(records.len() as u32).to_le_bytes())?;
+        dbf.write_all(&(header_len as u16).to_le_bytes())?;
+        dbf.write_all(&(record_len as u16).to_le_bytes())?;
+        let mut reserved = [0u8; 20];
+        reserved[9] = self.encoding.language_driver();
+        dbf.write_all(&reserved)?;
+        for ((name, width), &numeric) in field_names.iter().zip(&widths).zip(&numeric) {
+            let mut descriptor = [0u8; 32];
+            descriptor[..name.len()].copy_from_slice(name);
+            descriptor[11] = if numeric { b'N' } else { b'C' };
+            descriptor[16] = *width as u8;
+            descriptor[17] = if numeric { 2 } else { 0 };
+            dbf.write_all(&descriptor)?;
+        }
+        dbf.write_all(&[0x0d])?;
+
+        for record in records {
+            dbf.write_all(b" ")?;
+            for ((value, width), &numeric) in record.iter().zip(&widths).zip(&numeric) {
+                let padding = b" ".repeat(width - value.len());
+                if numeric {
+                    dbf.write_all(&padding)?;
+                    dbf.write_all(value)?;
+                } else {
+                    dbf.write_all(value)?;
+                    dbf.write_all(&padding)?;
+                }
+            }
+        }
+        dbf.write_all(&[0x1a])?;
+        dbf.flush()?;
+        Ok(())
+    }
+
+    /// The encoded value, truncated to the maximum field length, and whether
+    /// some of its characters are missing from the encoding.
+    fn encode_value(&self, value: &str) -> (Vec<u8>, bool) {
+        let (encoded, unmappable) = self.encoding.encode(value);
+        if encoded.len() <= MAX_FIELD_LEN {
+            return (encoded.into_owned(), unmappable);
+        }
+        let mut value = value;
+        while self.encoding.encode(value).0.len() > MAX_FIELD_LEN {
+            let mut chars = value.chars();
+            chars.next_back();
+            value = chars.as_str();
+        }
+        (self.encoding.encode(value).0.into_owned(), unmappable)
+    }
+}
+
+fn write_header(out: &mut impl Write, len: i32, bbox: &geo::Rect<f64>) -> std::io::Result<()> {
+    out.write_all(&9994i32.to_be_bytes())?;
+    out.write_all(&[0; 20])?;
+    out.write_all(&len.to_be_bytes())?;
+    out.write_all(&1000i32.to_le_bytes())?;
+    out.write_all(&SHAPE_TYPE_POLYGON.to_le_bytes())?;
+    write_bbox(out, bbox)?;
+    // z and m ranges
+    out.write_all(&[0; 32])
+}
+
+fn write_bbox(out: &mut impl Write, bbox: &geo::Rect<f64>) -> std::io::Result<()> {
+    for v in [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y] {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+impl FeatureSink for ShapefileWriter {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        ShapefileWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DbfEncoding, ShapefileWriter};
+    use crate::data::{Fude, FudeAttributes};
+    use crate::writer::Schema;
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_shapefile() {
+        let dir = std::env::temp_dir().join(format!("mojxml-test-shp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.shp");
+
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                oaza: Some("大手町".into()),
+                chiban: Some("12-3".into()),
+                computed_area: Some(1234.5),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let unmappable = Fude {
+            attributes: FudeAttributes {
+                id: "H000000002".into(),
+                oaza: Some("𠮷野".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = ShapefileWriter::create(&path)
+            .unwrap()
+            .with_encoding(DbfEncoding::ShiftJis)
+            .with_schema(Schema::japanese().with_computed_area())
+            .with_field_names([("地番".to_string(), "CHIBAN_NO".to_string())]);
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        assert_eq!(writer.unmappable(), 0);
+        writer
+            .write_fude(
+                &unmappable,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        assert_eq!(writer.unmappable(), 1);
+        writer.finish().unwrap();
+
+        // header (100) + 2 * (record header (8) + content (44 + 4 + 4 * 16))
+        let shp = std::fs::read(&path).unwrap();
+        assert_eq!(shp.len(), 100 + 2 * (8 + 44 + 4 + 64));
+        assert_eq!(
+            i32::from_be_bytes(shp[24..28].try_into().unwrap()) * 2,
+            shp.len() as i32
+        );
+        assert_eq!(std::fs::read(dir.join("test.shx")).unwrap().len(), 116);
+
+        let dbf = std::fs::read(dir.join("test.dbf")).unwrap();
+        assert_eq!(u32::from_le_bytes(dbf[4..8].try_into().unwrap()), 2);
+        assert!(dbf.windows(9).any(|w| w == b"CHIBAN_NO"));
+        let (oaza, _, _) = encoding_rs::SHIFT_JIS.encode("大手町");
+        assert!(dbf.windows(oaza.len()).any(|w| w == &oaza[..]));
+        // the area is a numeric field with 2 decimals
+        let descriptor = &dbf[32 + 32 * 12..32 + 32 * 13];
+        assert_eq!((descriptor[11], descriptor[17]), (b'N', 2));
+        assert!(dbf.windows(7).any(|w| w == b"1234.50"));
+        assert!(dbf.windows(9).any(|w| w == b"&#134071;"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("test.cpg")).unwrap(),
+            "CP932"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
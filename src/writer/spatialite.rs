@@ -0,0 +1,206 @@
+//! SpatiaLite (SQLite) output.
+
+use std::path::Path;
+
+use geo::BoundingRect;
+use rusqlite::Connection;
+
+use super::{Error, FeatureSink, Schema, geo_rings, wkb};
+use crate::crs::Crs;
+use crate::data::Fude;
+const FUDE_TABLE: &str = "fude";
+/// `geometry_type` of geometry_columns for a 2D POLYGON
+const GEOMETRY_TYPE_POLYGON: i32 = 3;
+
+/// Writes parcels into the `fude` table of a new SpatiaLite database, with an
+/// R-tree spatial index.
+///
+/// The metadata tables are created directly, so the SpatiaLite extension
+/// isn't needed for writing. Everything is written in a single transaction,
+/// committed by [`finish`](Self::finish).
+pub struct SpatialiteWriter {
+    conn: Connection,
+    schema: Schema,
+    crs: Crs,
+}
+
+impl SpatialiteWriter {
+    /// Create a database, replacing the file if it exists.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::create_with_schema(path, Schema::default())
+    }
+
+    /// Create a database with the given attribute columns.
+    pub fn create_with_schema(path: impl AsRef<Path>, schema: Schema) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let conn = Connection::open(path)?;
+        let columns = schema
+            .columns()
+            .iter()
+            .map(|column| {
+                let name = column.name().replace('"', "\"\"");
+                format!(", \"{}\" {}", name, column.column_type().sql_type())
+            })
+            .collect::<String>();
+        conn.execute_batch(&format!(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             BEGIN;
+             CREATE TABLE spatial_ref_sys (
+                 srid INTEGER NOT NULL PRIMARY KEY,
+                 auth_name TEXT NOT NULL,
+                 auth_srid INTEGER NOT NULL,
+                 ref_sys_name TEXT NOT NULL DEFAULT 'Unknown',
+                 proj4text TEXT NOT NULL,
+                 srtext TEXT NOT NULL DEFAULT 'Undefined'
+             );
+             CREATE TABLE geometry_columns (
+                 f_table_name TEXT NOT NULL,
+                 f_geometry_column TEXT NOT NULL,
+                 geometry_type INTEGER NOT NULL,
+                 coord_dimension INTEGER NOT NULL,
+                 srid INTEGER NOT NULL,
+                 spatial_index_enabled INTEGER NOT NULL,
+                 CONSTRAINT pk_geom_cols PRIMARY KEY (f_table_name, f_geometry_column),
+                 CONSTRAINT fk_gc_srs FOREIGN KEY (srid) REFERENCES spatial_ref_sys (srid)
+             );
+             CREATE TABLE \"{FUDE_TABLE}\" (pk_uid INTEGER PRIMARY KEY AUTOINCREMENT, geometry POLYGON{columns});
+             CREATE VIRTUAL TABLE \"idx_{FUDE_TABLE}_geometry\" USING rtree(pkid, xmin, xmax, ymin, ymax);"
+        ))?;
+        let writer = Self {
+            conn,
+            schema,
+            crs: Crs::default(),
+        };
+        writer.add_srs()?;
+        writer.conn.execute(
+            "INSERT INTO geometry_columns VALUES (?, 'geometry', ?, 2, ?, 1)",
+            (FUDE_TABLE, GEOMETRY_TYPE_POLYGON, writer.crs.epsg()),
+        )?;
+        Ok(writer)
+    }
+
+    /// Write the geometries in another CRS than JGD2011. Call this before
+    /// writing.
+    pub fn with_crs(mut self, crs: Crs) -> Result<Self, Error> {
+        self.crs = crs;
+        self.add_srs()?;
+        self.conn.execute(
+            "UPDATE geometry_columns SET srid = ? WHERE f_table_name = ?",
+            (crs.epsg(), FUDE_TABLE),
+        )?;
+        Ok(self)
+    }
+
+    /// Register the CRS in spatial_ref_sys, unless it exists.
+    fn add_srs(&self) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO spatial_ref_sys VALUES (?1, 'epsg', ?1, ?2, ?3, ?4)",
+            (
+                self.crs.epsg(),
+                self.crs.name(),
+                self.crs.proj4(),
+                self.crs.wkt(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        let Some(bbox) = polygon.bounding_rect() else {
+            return Ok(());
+        };
+
+        // SpatiaLite BLOB-Geometry
+        let mut geom = Vec::with_capacity(39 + 4 + 4 + 4 + polygon.exterior().0.len() * 16 + 1);
+        geom.push(0x00);
+        geom.push(0x01); // little endian
+        geom.extend_from_slice(&self.crs.epsg().to_le_bytes());
+        for v in [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y] {
+            geom.extend_from_slice(&v.to_le_bytes());
+        }
+        geom.push(0x7c);
+        geom.extend_from_slice(&GEOMETRY_TYPE_POLYGON.to_le_bytes());
+        wkb::write_polygon_body(&mut geom, geo_rings(polygon));
+        geom.push(0xfe);
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "INSERT INTO \"{FUDE_TABLE}\" VALUES (NULL, ?{})",
+            ", ?".repeat(self.schema.len())
+        ))?;
+        stmt.raw_bind_parameter(1, &geom)?;
+        for (i, column) in self.schema.columns().iter().enumerate() {
+            stmt.raw_bind_parameter(i + 2, column.value(&fude.attributes))?;
+        }
+        stmt.raw_execute()?;
+        let pk = self.conn.last_insert_rowid();
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "INSERT INTO \"idx_{FUDE_TABLE}_geometry\" VALUES (?, ?, ?, ?, ?)"
+        ))?;
+        stmt.execute((pk, bbox.min().x, bbox.max().x, bbox.min().y, bbox.max().y))?;
+        Ok(())
+    }
+
+    /// Commit.
+    pub fn finish(self) -> Result<(), Error> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+impl FeatureSink for SpatialiteWriter {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        SpatialiteWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpatialiteWriter;
+    use crate::data::{Fude, FudeAttributes};
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_spatialite() {
+        let dir = std::env::temp_dir().join(format!("mojxml-test-sl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.sqlite");
+
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H000000001".into(),
+                chiban: Some("12-3".into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = SpatialiteWriter::create(&path).unwrap();
+        writer
+            .write_fude(
+                &fude,
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let (chiban, geom): (String, Vec<u8>) = conn
+            .query_row("SELECT \"地番\", geometry FROM fude", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(chiban, "12-3");
+        // header (39) + class (4) + rings (4) + points (4 + 4 * 16) + end (1)
+        assert_eq!(geom.len(), 39 + 4 + 4 + 4 + 64 + 1);
+        assert_eq!(
+            (geom[0], geom[38], *geom.last().unwrap()),
+            (0x00, 0x7c, 0xfe)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
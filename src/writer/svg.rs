@@ -0,0 +1,211 @@
+//! SVG rendering of parcel outlines, for quick visual checks and printable
+//! sheet previews.
+
+use std::io::Write;
+
+use geo::{BoundingRect, InteriorPoint, Intersects};
+use quick_xml::escape::escape;
+
+use super::{Error, FeatureSink};
+use crate::data::Fude;
+
+const DEFAULT_WIDTH: f64 = 1000.0;
+
+/// Renders parcels into a single SVG image.
+///
+/// The parcels are kept in memory, since the extent of the image is known
+/// only at the end. Longitudes are scaled by the cosine of the latitude, so
+/// that shapes aren't stretched.
+pub struct SvgWriter {
+    bbox: Option<geo::Rect<f64>>,
+    width: f64,
+    labels: bool,
+    parcels: Vec<(Option<String>, geo::Polygon<f64>)>,
+}
+
+impl Default for SvgWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SvgWriter {
+    pub fn new() -> Self {
+        Self {
+            bbox: None,
+            width: DEFAULT_WIDTH,
+            labels: false,
+            parcels: Vec::new(),
+        }
+    }
+
+    /// Only draw this extent (e.g. a 図郭), instead of all the parcels.
+    pub fn with_bbox(mut self, bbox: geo::Rect<f64>) -> Self {
+        self.bbox = Some(bbox);
+        self
+    }
+
+    /// Width of the image in pixels (default: 1000)
+    pub fn with_width(mut self, width: f64) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Label the parcels with their 地番.
+    pub fn with_labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Set the extent after creation, see [`with_bbox`](Self::with_bbox).
+    pub fn set_bbox(&mut self, bbox: geo::Rect<f64>) {
+        self.bbox = Some(bbox);
+    }
+
+    pub fn bbox(&self) -> Option<geo::Rect<f64>> {
+        self.bbox
+    }
+
+    pub fn write_fude(&mut self, fude: &Fude, polygon: &geo::Polygon<f64>) -> Result<(), Error> {
+        if let Some(bbox) = self.bbox
+            && !bbox.intersects(polygon)
+        {
+            return Ok(());
+        }
+        self.parcels.push((
+            fude.attributes.chiban.as_deref().map(str::to_string),
+            polygon.clone(),
+        ));
+        Ok(())
+    }
+
+    /// Render the image.
+    pub fn finish(mut self, mut out: impl Write) -> Result<(), Error> {
+        if let Some(bbox) = self.bbox {
+            // the extent may have been set after some parcels were written
+            self.parcels.retain(|(_, polygon)| bbox.intersects(polygon));
+        }
+        let bbox = self.bbox.or_else(|| {
+            self.parcels
+                .iter()
+                .filter_map(|(_, polygon)| polygon.bounding_rect())
+                .reduce(|a, b| {
+                    geo::Rect::new(
+                        geo::coord! { x: a.min().x.min(b.min().x), y: a.min().y.min(b.min().y) },
+                        geo::coord! { x: a.max().x.max(b.max().x), y: a.max().y.max(b.max().y) },
+                    )
+                })
+        });
+        let Some(bbox) = bbox else {
+            writeln!(
+                out,
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}"/>"#,
+                self.width
+            )?;
+            return Ok(());
+        };
+        let kx = bbox.center().y.to_radians().cos();
+        let scale = self.width / (bbox.width() * kx).max(f64::EPSILON);
+        let height = (bbox.height() * scale).ceil();
+        let project = |c: geo::Coord<f64>| {
+            (
+                (c.x - bbox.min().x) * kx * scale,
+                (bbox.max().y - c.y) * scale,
+            )
+        };
+
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            self.width, height, self.width, height
+        )?;
+        writeln!(
+            out,
+            r#"<g fill="none" stroke="black" stroke-width="0.5" fill-rule="evenodd">"#
+        )?;
+        for (_, polygon) in &self.parcels {
+            out.write_all(b"<path d=\"")?;
+            for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+                for (idx, coord) in ring.coords().enumerate() {
+                    let (x, y) = project(*coord);
+                    write!(out, "{}{:.1} {:.1}", if idx == 0 { "M" } else { "L" }, x, y)?;
+                }
+                out.write_all(b"Z")?;
+            }
+            out.write_all(b"\"/>\n")?;
+        }
+        out.write_all(b"</g>\n")?;
+
+        if self.labels {
+            writeln!(
+                out,
+                r#"<g font-family="sans-serif" font-size="8" text-anchor="middle" dominant-baseline="middle">"#
+            )?;
+            for (chiban, polygon) in &self.parcels {
+                let (Some(chiban), Some(point)) = (chiban, polygon.interior_point()) else {
+                    continue;
+                };
+                let (x, y) = project(point.0);
+                writeln!(
+                    out,
+                    "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                    x,
+                    y,
+                    escape(chiban.as_str())
+                )?;
+            }
+            out.write_all(b"</g>\n")?;
+        }
+        out.write_all(b"</svg>\n")?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl FeatureSink for SvgWriter {
+    fn write_fude(&mut self, fude: &Fude, polygon: geo::Polygon<f64>) -> Result<(), Error> {
+        SvgWriter::write_fude(self, fude, &polygon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SvgWriter;
+    use crate::data::{Fude, FudeAttributes};
+    use geo::polygon;
+    use test_log::test;
+
+    #[test]
+    fn test_svg_bbox() {
+        let fude = |id: &'static str, chiban: &'static str| Fude {
+            attributes: FudeAttributes {
+                id: id.into(),
+                chiban: Some(chiban.into()),
+                ..Default::default()
+            },
+            surface_id: Default::default(),
+        };
+        let mut writer = SvgWriter::new()
+            .with_labels(true)
+            .with_bbox(geo::Rect::new((139.0, 35.0), (139.1, 35.1)));
+        writer
+            .write_fude(
+                &fude("H1", "1"),
+                &polygon![(x: 139.0, y: 35.0), (x: 139.1, y: 35.0), (x: 139.1, y: 35.1)],
+            )
+            .unwrap();
+        writer
+            .write_fude(
+                &fude("H2", "2"),
+                &polygon![(x: 140.0, y: 35.0), (x: 140.1, y: 35.0), (x: 140.1, y: 35.1)],
+            )
+            .unwrap();
+        let mut out = Vec::new();
+        writer.finish(&mut out).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert_eq!(svg.matches("<path ").count(), 1);
+        assert!(svg.contains(">1</text>"));
+        assert!(!svg.contains(">2</text>"));
+        assert!(svg.contains("M0.0 "));
+    }
+}
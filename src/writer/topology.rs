@@ -0,0 +1,296 @@
+//! Export of the topology of the geometries as a graph: which surface each
+//! 筆 has, which curves each surface uses (by ring) and which points each
+//! curve uses, for analyses of the network of boundaries.
+
+use std::io::Write;
+
+use quick_xml::escape::escape;
+
+use super::Error;
+use crate::data::{Id, ParsedData, PointRef};
+
+/// Format of a [`TopologyWriter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TopologyFormat {
+    /// An edge list, with the coordinates of the points:
+    /// `source,source_kind,target,target_kind,ring,index,x,y`
+    #[default]
+    Csv,
+    /// [GraphML](http://graphml.graphdrawing.org/), with `kind`, `x` and `y`
+    /// node attributes and `ring` and `index` edge attributes
+    GraphMl,
+}
+
+const CSV_HEADER: &str = "source,source_kind,target,target_kind,ring,index,x,y\r\n";
+
+const GRAPHML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+<key id="kind" for="node" attr.name="kind" attr.type="string"/>
+<key id="x" for="node" attr.name="x" attr.type="double"/>
+<key id="y" for="node" attr.name="y" attr.type="double"/>
+<key id="ring" for="edge" attr.name="ring" attr.type="int"/>
+<key id="index" for="edge" attr.name="index" attr.type="int"/>
+<graph edgedefault="directed">
+"#;
+
+/// Writes the reference graph of the 筆, surfaces, curves and points of one
+/// or more files. The edges go from the referencing to the referenced
+/// geometry: 筆 to surface, surface to curve (with the index of the ring,
+/// 0 being the exterior, and of the curve in the ring) and curve to point
+/// (with the index of the point in the curve).
+///
+/// The nodes are named by the ids of the file, and the points given by
+/// their coordinates in a curve by the id of the curve and their index
+/// (`C1#0`). References to missing geometries are left out.
+pub struct TopologyWriter<W: Write> {
+    out: W,
+    format: TopologyFormat,
+}
+
+impl<W: Write> TopologyWriter<W> {
+    pub fn new(mut out: W, format: TopologyFormat) -> Result<Self, Error> {
+        out.write_all(
+            match format {
+                TopologyFormat::Csv => CSV_HEADER,
+                TopologyFormat::GraphMl => GRAPHML_HEADER,
+            }
+            .as_bytes(),
+        )?;
+        Ok(Self { out, format })
+    }
+
+    /// Write the graph of a file, with `prefix` prepended to the names of its
+    /// nodes (e.g. the name of the file, as the ids are only unique in a file).
+    /// The nodes and edges are written in the order of their names.
+    pub fn write_data(&mut self, data: &ParsedData, prefix: &str) -> Result<(), Error> {
+        let name = |id: Id| format!("{}{}", prefix, data.ids.name(id));
+        let sort = |ids: &mut Vec<Id>| {
+            ids.sort_by(|a, b| data.ids.name(*a).cmp(data.ids.name(*b)));
+        };
+
+        let mut points: Vec<Id> = data.points.keys().copied().collect();
+        sort(&mut points);
+        for &id in &points {
+            let [x, y] = data.points[&id];
+            self.write_node(&name(id), "point", Some([x, y]))?;
+        }
+
+        let mut curves: Vec<Id> = data.segments.keys().copied().collect();
+        sort(&mut curves);
+        for &id in &curves {
+            let curve = name(id);
+            self.write_node(&curve, "curve", None)?;
+            for (index, point_ref) in data.segments[&id].iter().enumerate() {
+                match *point_ref {
+                    PointRef::Direct(point) => {
+                        let point_name = format!("{}#{}", curve, index);
+                        self.write_node(&point_name, "point", Some(point))?;
+                        self.write_edge(
+                            [&curve, "curve"],
+                            [&point_name, "point"],
+                            None,
+                            index,
+                            Some(point),
+                        )?;
+                    }
+                    PointRef::Indirect(point_id) => {
+                        if let Some(&point) = data.points.get(&point_id) {
+                            let target = name(point_id);
+                            self.write_edge(
+                                [&curve, "curve"],
+                                [&target, "point"],
+                                None,
+                                index,
+                                Some(point),
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut surfaces: Vec<_> = data.surfaces.iter().collect();
+        surfaces.sort_by(|(a, _), (b, _)| data.ids.name(*a).cmp(data.ids.name(*b)));
+        for (id, surface_rings) in surfaces {
+            let surface = name(id);
+            self.write_node(&surface, "surface", None)?;
+            for (ring, curve_ids) in surface_rings.rings().enumerate() {
+                for (index, &curve_id) in curve_ids.iter().enumerate() {
+                    if data.segments.contains_key(&curve_id) {
+                        let target = name(curve_id);
+                        self.write_edge(
+                            [&surface, "surface"],
+                            [&target, "curve"],
+                            Some(ring),
+                            index,
+                            None,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        let mut fudes: Vec<_> = data.fudes.values().collect();
+        fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+        for fude in fudes {
+            let node = format!("{}{}", prefix, fude.attributes.id);
+            self.write_node(&node, "fude", None)?;
+            if data.surfaces.contains(fude.surface_id) {
+                let target = name(fude.surface_id);
+                self.write_edge([&node, "fude"], [&target, "surface"], None, 0, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, name: &str, kind: &str, point: Option<[f64; 2]>) -> Result<(), Error> {
+        if self.format != TopologyFormat::GraphMl {
+            // the CSV only has the edges
+            return Ok(());
+        }
+        write!(
+            self.out,
+            "<node id=\"{}\"><data key=\"kind\">{}</data>",
+            escape(name),
+            kind
+        )?;
+        if let Some([x, y]) = point {
+            write!(
+                self.out,
+                "<data key=\"x\">{}</data><data key=\"y\">{}</data>",
+                x, y
+            )?;
+        }
+        self.out.write_all(b"</node>\n")?;
+        Ok(())
+    }
+
+    fn write_edge(
+        &mut self,
+        [source, source_kind]: [&str; 2],
+        [target, target_kind]: [&str; 2],
+        ring: Option<usize>,
+        index: usize,
+        point: Option<[f64; 2]>,
+    ) -> Result<(), Error> {
+        match self.format {
+            TopologyFormat::Csv => {
+                let ring = ring.map(|ring| ring.to_string()).unwrap_or_default();
+                let [x, y] = point.map_or([String::new(), String::new()], |[x, y]| {
+                    [x.to_string(), y.to_string()]
+                });
+                write!(
+                    self.out,
+                    "{},{},{},{},{},{},{},{}\r\n",
+                    csv_field(source),
+                    source_kind,
+                    csv_field(target),
+                    target_kind,
+                    ring,
+                    index,
+                    x,
+                    y
+                )?;
+            }
+            TopologyFormat::GraphMl => {
+                write!(
+                    self.out,
+                    "<edge source=\"{}\" target=\"{}\">",
+                    escape(source),
+                    escape(target)
+                )?;
+                if let Some(ring) = ring {
+                    write!(self.out, "<data key=\"ring\">{}</data>", ring)?;
+                }
+                writeln!(self.out, "<data key=\"index\">{}</data></edge>", index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Complete the graph, flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.format == TopologyFormat::GraphMl {
+            self.out.write_all(b"</graph>\n</graphml>\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Quote a CSV field if needed (RFC 4180).
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{Fude, FudeAttributes, Ids, Surfaces};
+    use hashbrown::HashMap;
+    use test_log::test;
+
+    fn data() -> ParsedData<'static> {
+        let mut ids = Ids::default();
+        let [p1, p2, c1, c2, s1] = ["P1", "P2", "C1", "C2", "S1"].map(|id| ids.intern(id));
+        let fude = Fude {
+            attributes: FudeAttributes {
+                id: "H1".into(),
+                ..Default::default()
+            },
+            surface_id: s1,
+        };
+        ParsedData {
+            points: HashMap::from([(p1, [0.0, 0.0]), (p2, [1.0, 0.0])]),
+            segments: HashMap::from([
+                (c1, [PointRef::Indirect(p1), PointRef::Indirect(p2)]),
+                (c2, [PointRef::Indirect(p2), PointRef::Direct([0.5, 1.0])]),
+            ]),
+            surfaces: Surfaces::from_iter([(s1, vec![vec![c1, c2]])]),
+            fudes: HashMap::from([("H1".to_string(), fude)]),
+            ids,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut writer = TopologyWriter::new(Vec::new(), TopologyFormat::Csv).unwrap();
+        writer.write_data(&data(), "a:").unwrap();
+        let csv = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "source,source_kind,target,target_kind,ring,index,x,y",
+                "a:C1,curve,a:P1,point,,0,0,0",
+                "a:C1,curve,a:P2,point,,1,1,0",
+                "a:C2,curve,a:P2,point,,0,1,0",
+                "a:C2,curve,a:C2#1,point,,1,0.5,1",
+                "a:S1,surface,a:C1,curve,0,0,,",
+                "a:S1,surface,a:C2,curve,0,1,,",
+                "a:H1,fude,a:S1,surface,,0,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graphml() {
+        let mut writer = TopologyWriter::new(Vec::new(), TopologyFormat::GraphMl).unwrap();
+        writer.write_data(&data(), "").unwrap();
+        let xml = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(xml.matches("<node ").count(), 7);
+        assert_eq!(xml.matches("<edge ").count(), 7);
+        assert!(xml.contains(
+            r#"<node id="C2#1"><data key="kind">point</data><data key="x">0.5</data><data key="y">1</data></node>"#
+        ));
+        assert!(xml.contains(
+            r#"<edge source="S1" target="C2"><data key="ring">0</data><data key="index">1</data></edge>"#
+        ));
+        assert!(xml.ends_with("</graph>\n</graphml>\n"));
+    }
+}
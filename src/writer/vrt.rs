@@ -0,0 +1,75 @@
+//! OGR VRT output: a small XML file that opens the layers written to
+//! separate files (e.g. `output.fgb`, `output_kijunten.fgb`) as one GDAL
+//! datasource, for `ogrinfo` and `ogr2ogr`.
+
+use std::io::Write;
+
+use quick_xml::escape::escape;
+
+use super::{Error, Layer};
+use crate::crs::Crs;
+
+/// Lists the layers of a conversion and the files they were written to.
+pub struct VrtWriter {
+    crs: Crs,
+    layers: Vec<(Layer, String)>,
+}
+
+impl VrtWriter {
+    pub fn new(crs: Crs) -> Self {
+        Self {
+            crs,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add a layer read from `source`, a single-layer file (e.g. FlatGeobuf)
+    /// whose path is relative to the VRT file.
+    pub fn add_layer(&mut self, layer: Layer, source: &str) {
+        self.layers.push((layer, source.to_string()));
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        out.write_all(b"<OGRVRTDataSource>\n")?;
+        for (layer, source) in &self.layers {
+            writeln!(
+                out,
+                "  <OGRVRTLayer name=\"{}\">\n    <SrcDataSource relativeToVRT=\"1\">{}</SrcDataSource>\n    <GeometryType>{}</GeometryType>\n    <LayerSRS>EPSG:{}</LayerSRS>\n  </OGRVRTLayer>",
+                layer.name(),
+                escape(source.as_str()),
+                if layer.is_point() {
+                    "wkbPoint"
+                } else {
+                    "wkbPolygon"
+                },
+                self.crs.epsg()
+            )?;
+        }
+        out.write_all(b"</OGRVRTDataSource>\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_write() {
+        let mut vrt = VrtWriter::new(Crs::Jpr(9));
+        vrt.add_layer(Layer::Fude, "a&b.fgb");
+        vrt.add_layer(Layer::Kijunten, "a&b_kijunten.fgb");
+        let mut out = Vec::new();
+        vrt.write(&mut out).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert_eq!(xml.matches("<OGRVRTLayer ").count(), 2);
+        assert!(xml.contains(
+            r#"<OGRVRTLayer name="fude">
+    <SrcDataSource relativeToVRT="1">a&amp;b.fgb</SrcDataSource>
+    <GeometryType>wkbPolygon</GeometryType>
+    <LayerSRS>EPSG:6677</LayerSRS>"#
+        ));
+        assert!(xml.contains("<GeometryType>wkbPoint</GeometryType>"));
+    }
+}
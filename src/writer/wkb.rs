@@ -0,0 +1,49 @@
+//! Minimal (little-endian, 2D) WKB encoding.
+
+#[cfg(feature = "gpkg")]
+const WKB_POINT: u32 = 1;
+const WKB_POLYGON: u32 = 3;
+
+/// Append the WKB of a point.
+#[cfg(feature = "gpkg")]
+pub(crate) fn write_point(out: &mut Vec<u8>, [x, y]: [f64; 2]) {
+    out.push(1); // little endian
+    out.extend_from_slice(&WKB_POINT.to_le_bytes());
+    out.extend_from_slice(&x.to_le_bytes());
+    out.extend_from_slice(&y.to_le_bytes());
+}
+
+/// Append the WKB of a polygon given as rings of coordinates.
+pub(crate) fn write_polygon<I, C>(out: &mut Vec<u8>, rings: I)
+where
+    I: IntoIterator<Item = C>,
+    C: IntoIterator<Item = [f64; 2]>,
+{
+    out.push(1); // little endian
+    out.extend_from_slice(&WKB_POLYGON.to_le_bytes());
+    write_polygon_body(out, rings);
+}
+
+/// Append the rings of a polygon (the part of WKB after the type).
+pub(crate) fn write_polygon_body<I, C>(out: &mut Vec<u8>, rings: I)
+where
+    I: IntoIterator<Item = C>,
+    C: IntoIterator<Item = [f64; 2]>,
+{
+    let num_rings_pos = out.len();
+    out.extend_from_slice(&0u32.to_le_bytes());
+    let mut num_rings = 0u32;
+    for ring in rings {
+        let num_points_pos = out.len();
+        out.extend_from_slice(&0u32.to_le_bytes());
+        let mut num_points = 0u32;
+        for [x, y] in ring {
+            out.extend_from_slice(&x.to_le_bytes());
+            out.extend_from_slice(&y.to_le_bytes());
+            num_points += 1;
+        }
+        out[num_points_pos..num_points_pos + 4].copy_from_slice(&num_points.to_le_bytes());
+        num_rings += 1;
+    }
+    out[num_rings_pos..num_rings_pos + 4].copy_from_slice(&num_rings.to_le_bytes());
+}
@@ -0,0 +1,26 @@
+//! Minimal (2D) WKT encoding.
+
+use std::fmt::Write;
+
+/// Append the WKT of a polygon given as rings of coordinates.
+pub(crate) fn write_polygon<I, C>(out: &mut String, rings: I)
+where
+    I: IntoIterator<Item = C>,
+    C: IntoIterator<Item = [f64; 2]>,
+{
+    out.push_str("POLYGON (");
+    for (idx, ring) in rings.into_iter().enumerate() {
+        if idx > 0 {
+            out.push_str(", ");
+        }
+        out.push('(');
+        for (idx, [x, y]) in ring.into_iter().enumerate() {
+            if idx > 0 {
+                out.push_str(", ");
+            }
+            let _ = write!(out, "{} {}", x, y);
+        }
+        out.push(')');
+    }
+    out.push(')');
+}
@@ -0,0 +1,289 @@
+//! 地図XML output: [`ParsedData`] written back in the format of the registry,
+//! e.g. after removing or editing some 筆.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use quick_xml::escape::escape;
+
+use super::Error;
+use crate::data::{Feature, Id, ParsedData, Point, PointRef};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<地図 xmlns="http://www.moj.go.jp/MINJI/tizuxml" xmlns:zmn="http://www.moj.go.jp/MINJI/tizuzumen" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.moj.go.jp/MINJI/tizuxml 地図XML.xsd">
+<version>1.0</version>
+"#;
+
+/// Writes a 地図XML document.
+///
+/// The coordinates must be those of the source, i.e. the data must have been
+/// parsed with [`keep_source_coordinates`](crate::parser::ParseOptions::keep_source_coordinates).
+/// Only the geometries referenced by the 筆, 基準点, 筆界点 and 図郭 are
+/// written, so the ones of removed 筆 are dropped as well. The 筆 are
+/// written in the order of their ids, without the 筆界未定構成筆 (which the
+/// parser skips).
+pub struct XmlWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Write the document, flush and return the underlying writer.
+    pub fn write_map(mut self, data: &ParsedData) -> Result<W, Error> {
+        let metadata = &data.metadata;
+        self.out.write_all(HEADER.as_bytes())?;
+        for (name, value) in [
+            ("地図名", &metadata.map_name),
+            ("市区町村コード", &metadata.municipality_code),
+            ("市区町村名", &metadata.municipality_name),
+            ("座標系", &metadata.crs),
+            ("測地系判別", &metadata.datum),
+        ] {
+            if let Some(value) = value {
+                writeln!(self.out, "<{0}>{1}</{0}>", name, escape(value.as_str()))?;
+            }
+        }
+        for feature in &data.zukaku {
+            self.write_feature(data, "図郭", "図郭線", feature)?;
+        }
+
+        // The points of the plane rectangular coordinate systems are
+        // (easting, northing), X being the northing.
+        let swap = metadata
+            .crs
+            .as_deref()
+            .is_some_and(|crs| crs.starts_with("公共座標"));
+        let position = move |[x, y]: Point| if swap { [y, x] } else { [x, y] };
+
+        let used = Used::collect(data);
+        self.out.write_all("<空間属性>\n".as_bytes())?;
+        for &id in &used.points {
+            if let Some(&point) = data.points.get(&id) {
+                let [x, y] = position(point);
+                writeln!(
+                    self.out,
+                    "<zmn:GM_Point id=\"{}\"><zmn:GM_Point.position><zmn:DirectPosition><zmn:X>{}</zmn:X><zmn:Y>{}</zmn:Y></zmn:DirectPosition></zmn:GM_Point.position></zmn:GM_Point>",
+                    escape(data.ids.name(id)),
+                    x,
+                    y
+                )?;
+            }
+        }
+        for &id in &used.curves {
+            let Some(point_refs) = data.segments.get(&id) else {
+                continue;
+            };
+            write!(
+                self.out,
+                "<zmn:GM_Curve id=\"{}\"><zmn:GM_Curve.segment><zmn:GM_LineString><zmn:GM_LineString.controlPoint><zmn:GM_PointArray>",
+                escape(data.ids.name(id))
+            )?;
+            for point_ref in point_refs {
+                self.out.write_all(b"<zmn:GM_PointArray.column>")?;
+                match *point_ref {
+                    PointRef::Direct(point) => {
+                        let [x, y] = position(point);
+                        write!(
+                            self.out,
+                            "<zmn:GM_Position.direct><zmn:X>{}</zmn:X><zmn:Y>{}</zmn:Y></zmn:GM_Position.direct>",
+                            x, y
+                        )?;
+                    }
+                    PointRef::Indirect(point_id) => write!(
+                        self.out,
+                        "<zmn:GM_Position.indirect><zmn:GM_PointRef.point idref=\"{}\"/></zmn:GM_Position.indirect>",
+                        escape(data.ids.name(point_id))
+                    )?,
+                }
+                self.out.write_all(b"</zmn:GM_PointArray.column>")?;
+            }
+            self.out.write_all(b"</zmn:GM_PointArray></zmn:GM_LineString.controlPoint></zmn:GM_LineString></zmn:GM_Curve.segment></zmn:GM_Curve>\n")?;
+        }
+        for &id in &used.surfaces {
+            let Some(surface) = data.surfaces.get(id) else {
+                continue;
+            };
+            write!(
+                self.out,
+                "<zmn:GM_Surface id=\"{}\"><zmn:GM_Surface.patch><zmn:GM_Polygon><zmn:GM_Polygon.boundary><zmn:GM_SurfaceBoundary>",
+                escape(data.ids.name(id))
+            )?;
+            for (idx, ring) in surface.rings().enumerate() {
+                let boundary = if idx == 0 { "exterior" } else { "interior" };
+                write!(
+                    self.out,
+                    "<zmn:GM_SurfaceBoundary.{}><zmn:GM_Ring>",
+                    boundary
+                )?;
+                for &curve_id in ring {
+                    write!(
+                        self.out,
+                        "<zmn:GM_CompositeCurve.generator idref=\"{}\"/>",
+                        escape(data.ids.name(curve_id))
+                    )?;
+                }
+                write!(
+                    self.out,
+                    "</zmn:GM_Ring></zmn:GM_SurfaceBoundary.{}>",
+                    boundary
+                )?;
+            }
+            self.out.write_all(b"</zmn:GM_SurfaceBoundary></zmn:GM_Polygon.boundary></zmn:GM_Polygon></zmn:GM_Surface.patch></zmn:GM_Surface>\n")?;
+        }
+        self.out.write_all("</空間属性>\n".as_bytes())?;
+
+        self.out.write_all("<主題属性>\n".as_bytes())?;
+        let mut fudes: Vec<_> = data.fudes.values().collect();
+        fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+        for fude in fudes {
+            let attributes = &fude.attributes;
+            write!(self.out, "<筆 id=\"{}\">", escape(attributes.id.as_ref()))?;
+            // in the order of the schema
+            for (name, value) in [
+                ("大字コード", &attributes.oaza_code),
+                ("丁目コード", &attributes.chome_code),
+                ("小字コード", &attributes.koaza_code),
+                ("予備コード", &attributes.yobi_code),
+                ("大字名", &attributes.oaza),
+                ("丁目名", &attributes.chome),
+                ("小字名", &attributes.koaza),
+                ("予備名", &attributes.yobi),
+                ("地番", &attributes.chiban),
+                ("精度区分", &attributes.accuracy_class),
+                ("座標値種別", &attributes.coord_class),
+            ] {
+                if let Some(value) = value {
+                    write!(self.out, "<{0}>{1}</{0}>", name, escape(value.as_ref()))?;
+                }
+            }
+            writeln!(
+                self.out,
+                "<形状 idref=\"{}\"/></筆>",
+                escape(data.ids.name(fude.surface_id))
+            )?;
+        }
+        for feature in &data.kijunten {
+            self.write_feature(data, "基準点", "形状", feature)?;
+        }
+        for feature in &data.hikkaiten {
+            self.write_feature(data, "筆界点", "形状", feature)?;
+        }
+        self.out.write_all("</主題属性>\n</地図>\n".as_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_feature(
+        &mut self,
+        data: &ParsedData,
+        element: &str,
+        geometry_element: &str,
+        feature: &Feature,
+    ) -> Result<(), Error> {
+        write!(
+            self.out,
+            "<{} id=\"{}\">",
+            element,
+            escape(feature.id.as_str())
+        )?;
+        for (name, value) in &feature.attributes {
+            write!(self.out, "<{0}>{1}</{0}>", name, escape(value.as_str()))?;
+        }
+        writeln!(
+            self.out,
+            "<{} idref=\"{}\"/></{}>",
+            geometry_element,
+            escape(data.ids.name(feature.geometry_id)),
+            element
+        )?;
+        Ok(())
+    }
+}
+
+/// The geometries referenced by the features, in the order of their ids.
+struct Used {
+    points: BTreeSet<Id>,
+    curves: BTreeSet<Id>,
+    surfaces: BTreeSet<Id>,
+}
+
+impl Used {
+    fn collect(data: &ParsedData) -> Self {
+        let mut used = Used {
+            points: BTreeSet::new(),
+            curves: BTreeSet::new(),
+            surfaces: data.fudes.values().map(|fude| fude.surface_id).collect(),
+        };
+        for feature in data
+            .kijunten
+            .iter()
+            .chain(&data.hikkaiten)
+            .chain(&data.zukaku)
+        {
+            if data.points.contains_key(&feature.geometry_id) {
+                used.points.insert(feature.geometry_id);
+            } else {
+                used.surfaces.insert(feature.geometry_id);
+            }
+        }
+        for &id in &used.surfaces {
+            if let Some(surface) = data.surfaces.get(id) {
+                used.curves.extend(surface.rings().flatten());
+            }
+        }
+        for id in &used.curves {
+            for point_ref in data.segments.get(id).into_iter().flatten() {
+                if let PointRef::Indirect(point_id) = point_ref {
+                    used.points.insert(*point_id);
+                }
+            }
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::XmlWriter;
+    use crate::parser::{MojxmlParser, ParseOptions, jpr_projections};
+    use crate::synthetic::SyntheticMap;
+    use test_log::test;
+
+    #[test]
+    fn test_round_trip() {
+        let projections = jpr_projections();
+        let options = ParseOptions {
+            keep_source_coordinates: true,
+            ..Default::default()
+        };
+        let xml = SyntheticMap::new(2, 3).to_xml();
+        let mut data = MojxmlParser::from_slice(xml.as_bytes(), &projections)
+            .with_options(options.clone())
+            .parse()
+            .unwrap();
+        // the geometries of H1 (in the corner) go with it
+        data.fudes.remove("H1");
+        let out = XmlWriter::new(Vec::new()).write_map(&data).unwrap();
+
+        let written = MojxmlParser::from_slice(&out, &projections)
+            .with_options(options)
+            .parse()
+            .unwrap();
+        assert_eq!(written.metadata.crs.as_deref(), Some("公共座標9系"));
+        assert_eq!(written.fudes.len(), 5);
+        assert_eq!(written.surfaces.len(), 5);
+        assert_eq!(written.segments.len(), 4 * 5);
+        assert!(written.ids.get("P1").is_none());
+        assert_eq!(written.points.len(), 3 * 4 - 1);
+        for (id, fude) in &written.fudes {
+            assert_eq!(fude.attributes.chiban, data.fudes[id].attributes.chiban);
+            assert_eq!(
+                written.resolve_surface(fude.surface_id).unwrap(),
+                data.resolve_surface(data.fudes[id].surface_id).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Download of the packages of 登記所備付地図データ from the
+//! [G空間情報センター](https://www.geospatial.jp/), the open-data portal
+//! distributing them.
+//!
+//! The portal runs [CKAN](https://ckan.org/): [`Portal::resolve`] searches its
+//! API for the datasets of a prefecture and picks the packages (one zip per
+//! municipality) of a 市区町村コード, and [`Portal::download`] fetches them,
+//! resuming interrupted downloads and verifying them before use.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::to_hex;
+use crate::municipality::{is_valid_code, prefecture_name};
+
+/// The CKAN API of G空間情報センター.
+pub const DEFAULT_API: &str = "https://www.geospatial.jp/ckan/api/3/action";
+
+/// The search of the datasets, followed by the name of a prefecture.
+pub const DEFAULT_QUERY: &str = "登記所備付地図データ";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid 市区町村コード or prefecture code: {0}")]
+    InvalidCode(String),
+    #[error("no package found for {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    Http(#[from] ureq::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("unexpected response from the portal: {0}")]
+    Response(String),
+    #[error("{name}: expected {expected} bytes, downloaded {actual}")]
+    Size {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("{name}: SHA-256 mismatch (expected {expected}, got {actual})")]
+    Checksum {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    /// The archive is broken, e.g. an entry fails its CRC-32 check.
+    #[error("{name}: {source}")]
+    Archive {
+        name: String,
+        #[source]
+        source: zip::result::ZipError,
+    },
+}
+
+/// A package (zip) of the portal.
+#[derive(Clone, Debug)]
+pub struct Package {
+    /// 市区町村コード of the package, from its file name
+    pub municipality_code: String,
+    /// File name of the package, e.g. `15222-1107-2023.zip`
+    pub name: String,
+    pub url: String,
+    /// Title of the dataset publishing it
+    pub dataset: String,
+    /// Size in bytes, when published
+    pub size: Option<u64>,
+    /// SHA-256 of the package (hex), when published
+    pub sha256: Option<String>,
+}
+
+/// A client of the portal.
+pub struct Portal {
+    agent: ureq::Agent,
+    api: String,
+    query: String,
+}
+
+impl Default for Portal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Portal {
+    pub fn new() -> Self {
+        Self {
+            agent: ureq::Agent::new_with_defaults(),
+            api: DEFAULT_API.to_string(),
+            query: DEFAULT_QUERY.to_string(),
+        }
+    }
+
+    /// Use another CKAN API (e.g. a mirror), given by the URL of its
+    /// `action` endpoint.
+    pub fn with_api(mut self, api: &str) -> Self {
+        self.api = api.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Search the datasets with another query (followed by the name of the
+    /// prefecture).
+    pub fn with_query(mut self, query: &str) -> Self {
+        self.query = query.to_string();
+        self
+    }
+
+    /// The packages of a 市区町村コード (5 digits), or of all the
+    /// municipalities of a prefecture (2 digits), sorted by name. When
+    /// several datasets (years) publish a municipality, the packages of the
+    /// most recently modified one are picked.
+    pub fn resolve(&self, code: &str) -> Result<Vec<Package>, Error> {
+        let code = match code.len() {
+            2 if prefecture_name(code).is_some() => code,
+            5 | 6 if is_valid_code(code) => &code[..5],
+            _ => return Err(Error::InvalidCode(code.to_string())),
+        };
+        let prefecture = prefecture_name(code).unwrap_or_default();
+        let mut response = self
+            .agent
+            .get(format!("{}/package_search", self.api))
+            .query("q", format!("{} {}", self.query, prefecture))
+            .query("rows", "1000")
+            .call()?;
+        let json: Value = serde_json::from_str(&response.body_mut().read_to_string()?)
+            .map_err(|e| Error::Response(e.to_string()))?;
+        let Some(datasets) = json["result"]["results"].as_array() else {
+            return Err(Error::Response("no search results".to_string()));
+        };
+        let packages = pick_packages(datasets, code);
+        if packages.is_empty() {
+            return Err(Error::NotFound(code.to_string()));
+        }
+        Ok(packages)
+    }
+
+    /// Download a package into `dir`, and return its path. `progress` is
+    /// called with the number of bytes downloaded so far.
+    ///
+    /// The data goes to `<name>.part` first, and an interrupted download is
+    /// resumed from there with a range request. The package is then checked
+    /// against its published size and SHA-256 and the CRC-32 of its entries
+    /// before being renamed. A package already in `dir` is checked the same
+    /// way, and kept if valid.
+    pub fn download(
+        &self,
+        package: &Package,
+        dir: &Path,
+        mut progress: impl FnMut(u64),
+    ) -> Result<PathBuf, Error> {
+        let path = dir.join(&package.name);
+        if path.exists() {
+            if verify(&path, package).is_ok() {
+                return Ok(path);
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let part = dir.join(format!("{}.part", package.name));
+        let mut offset = std::fs::metadata(&part).map_or(0, |metadata| metadata.len());
+        if package.size.is_none_or(|size| offset > size) {
+            // only resume when the end is known
+            offset = 0;
+        }
+        if package.size != Some(offset) {
+            let mut request = self.agent.get(&package.url);
+            if offset > 0 {
+                request = request.header("Range", &format!("bytes={}-", offset));
+            }
+            let mut response = request.call()?;
+            let mut file = if response.status() == 206 {
+                let range = response
+                    .headers()
+                    .get("Content-Range")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                if range_start(range) != Some(offset) {
+                    // appending would corrupt the data
+                    std::fs::remove_file(&part).or_else(ignore_not_found)?;
+                    return Err(Error::Response(format!(
+                        "{}: requested the bytes from {}, got the range '{}'",
+                        package.name, offset, range
+                    )));
+                }
+                OpenOptions::new().append(true).open(&part)?
+            } else {
+                // the server ignored the range: start over
+                offset = 0;
+                File::create(&part)?
+            };
+            let mut reader = response.body_mut().as_reader();
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                file.write_all(&buf[..n])?;
+                offset += n as u64;
+                progress(offset);
+            }
+            file.sync_all()?;
+        }
+        if let Err(e) = verify(&part, package) {
+            // don't resume from broken data
+            std::fs::remove_file(&part)?;
+            return Err(e);
+        }
+        std::fs::rename(&part, &path)?;
+        Ok(path)
+    }
+}
+
+/// The first byte of a `Content-Range: bytes <first>-<last>/<length>` header.
+fn range_start(value: &str) -> Option<u64> {
+    value
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn ignore_not_found(e: std::io::Error) -> std::io::Result<()> {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
+
+/// The zip resources of the datasets whose file name starts with `code`,
+/// from the most recently modified dataset of each municipality.
+fn pick_packages(datasets: &[Value], code: &str) -> Vec<Package> {
+    let mut datasets: Vec<&Value> = datasets.iter().collect();
+    // ISO 8601 times, sorting as strings
+    fn modified(dataset: &Value) -> &str {
+        dataset["metadata_modified"].as_str().unwrap_or_default()
+    }
+    datasets.sort_by(|a, b| modified(b).cmp(modified(a)));
+    let mut picked: HashMap<String, usize> = HashMap::new();
+    let mut packages = Vec::new();
+    for (index, dataset) in datasets.iter().enumerate() {
+        let title = dataset["title"].as_str().unwrap_or_default();
+        for resource in dataset["resources"].as_array().into_iter().flatten() {
+            let Some(url) = resource["url"].as_str() else {
+                continue;
+            };
+            let name = url.rsplit('/').next().unwrap_or_default();
+            let Some(municipality_code) = name.get(..5) else {
+                continue;
+            };
+            if !name.to_ascii_lowercase().ends_with(".zip")
+                || !name.starts_with(code)
+                || !municipality_code.bytes().all(|b| b.is_ascii_digit())
+                || *picked.entry_ref(municipality_code).or_insert(index) != index
+            {
+                continue;
+            }
+            let size = match &resource["size"] {
+                Value::Number(size) => size.as_u64(),
+                Value::String(size) => size.parse().ok(),
+                _ => None,
+            };
+            let sha256 = resource["hash"]
+                .as_str()
+                .map(|hash| hash.trim_start_matches("sha256:").to_ascii_lowercase())
+                .filter(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()));
+            packages.push(Package {
+                municipality_code: municipality_code.to_string(),
+                name: name.to_string(),
+                url: url.to_string(),
+                dataset: title.to_string(),
+                size,
+                sha256,
+            });
+        }
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
+
+/// Check a downloaded file against the size and SHA-256 of a package, and
+/// the CRC-32 of the entries of its archive.
+pub fn verify(path: &Path, package: &Package) -> Result<(), Error> {
+    let actual = std::fs::metadata(path)?.len();
+    if let Some(expected) = package.size
+        && expected != actual
+    {
+        return Err(Error::Size {
+            name: package.name.clone(),
+            expected,
+            actual,
+        });
+    }
+    if let Some(expected) = &package.sha256 {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut File::open(path)?, &mut hasher)?;
+        let actual = to_hex(hasher);
+        if *expected != actual {
+            return Err(Error::Checksum {
+                name: package.name.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    let archive_error = |source| Error::Archive {
+        name: package.name.clone(),
+        source,
+    };
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(archive_error)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(archive_error)?;
+        // reading an entry to the end checks its CRC-32
+        std::io::copy(&mut entry, &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use test_log::test;
+
+    /// How the test server answers a range request.
+    #[derive(Clone, Copy)]
+    enum Ranges {
+        Honored,
+        Ignored,
+        /// 206 with the whole file
+        Misplaced,
+    }
+
+    /// Serve `data` over HTTP on a local port, and return its URL and the
+    /// requested ranges.
+    fn serve(data: Vec<u8>, ranges: Ranges) -> (String, Arc<Mutex<Vec<Option<usize>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/15222-1107-2024.zip",
+            listener.local_addr().unwrap()
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requested = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut start = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        start = range.trim_end_matches('-').parse::<usize>().ok();
+                    }
+                }
+                requested.lock().unwrap().push(start);
+                let (status, first) = match (start, ranges) {
+                    (Some(start), Ranges::Honored) => ("206 Partial Content", start),
+                    (Some(_), Ranges::Misplaced) => ("206 Partial Content", 0),
+                    _ => ("200 OK", 0),
+                };
+                let mut header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    status,
+                    data.len() - first
+                );
+                if status.starts_with("206") {
+                    header += &format!(
+                        "Content-Range: bytes {}-{}/{}\r\n",
+                        first,
+                        data.len() - 1,
+                        data.len()
+                    );
+                }
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(b"\r\n").unwrap();
+                stream.write_all(&data[first..]).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn package_data() -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer
+            .start_file(
+                "15222-1107-0001.xml",
+                zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
+            )
+            .unwrap();
+        writer.write_all(&[b'x'; 10_000]).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn package(url: &str, data: &[u8]) -> Package {
+        Package {
+            municipality_code: "15222".to_string(),
+            name: "15222-1107-2024.zip".to_string(),
+            url: url.to_string(),
+            dataset: String::new(),
+            size: Some(data.len() as u64),
+            sha256: Some(to_hex(Sha256::new_with_prefix(data))),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mojxml-test-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_download() {
+        let data = package_data();
+        let half = data.len() / 2;
+        let portal = Portal::new();
+
+        // resumed from the .part file
+        let dir = temp_dir("fetch-resume");
+        let (url, requests) = serve(data.clone(), Ranges::Honored);
+        let package = package(&url, &data);
+        std::fs::write(dir.join("15222-1107-2024.zip.part"), &data[..half]).unwrap();
+        let mut downloaded = Vec::new();
+        let path = portal
+            .download(&package, &dir, |bytes| downloaded.push(bytes))
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert_eq!(*requests.lock().unwrap(), [Some(half)]);
+        assert_eq!(downloaded.last(), Some(&(data.len() as u64)));
+        assert!(!dir.join("15222-1107-2024.zip.part").exists());
+        // already there
+        portal.download(&package, &dir, |_| ()).unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the server sends the whole file instead of the range
+        let dir = temp_dir("fetch-full");
+        let (url, requests) = serve(data.clone(), Ranges::Ignored);
+        let package = self::package(&url, &data);
+        std::fs::write(dir.join("15222-1107-2024.zip.part"), vec![0; half]).unwrap();
+        let path = portal.download(&package, &dir, |_| ()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert_eq!(*requests.lock().unwrap(), [Some(half)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the server sends another range
+        let dir = temp_dir("fetch-range");
+        let package = self::package(&serve(data.clone(), Ranges::Misplaced).0, &data);
+        std::fs::write(dir.join("15222-1107-2024.zip.part"), &data[..half]).unwrap();
+        let result = portal.download(&package, &dir, |_| ());
+        assert!(matches!(result, Err(Error::Response(_))), "{:?}", result);
+        assert!(!dir.join("15222-1107-2024.zip.part").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the data doesn't match the published SHA-256
+        let dir = temp_dir("fetch-checksum");
+        let mut package = self::package(&serve(data.clone(), Ranges::Honored).0, &data);
+        package.sha256 = Some("0".repeat(64));
+        let result = portal.download(&package, &dir, |_| ());
+        assert!(
+            matches!(result, Err(Error::Checksum { .. })),
+            "{:?}",
+            result
+        );
+        assert!(!dir.join("15222-1107-2024.zip.part").exists());
+        assert!(!dir.join("15222-1107-2024.zip").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_range_start() {
+        assert_eq!(range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(range_start("bytes 0-199/*"), Some(0));
+        assert_eq!(range_start("bytes */200"), None);
+        assert_eq!(range_start(""), None);
+    }
+
+    #[test]
+    fn test_pick_packages() {
+        let datasets: Value = serde_json::json!([
+            {
+                "title": "登記所備付地図データ 2023 新潟県",
+                "metadata_modified": "2023-04-01T00:00:00",
+                "resources": [
+                    {"url": "https://example.com/a/15222-1107-2023.zip", "size": 100},
+                    {"url": "https://example.com/a/15223-1107-2023.zip"},
+                ],
+            },
+            {
+                "title": "登記所備付地図データ 2024 新潟県",
+                "metadata_modified": "2024-04-01T00:00:00",
+                "resources": [
+                    {
+                        "url": "https://example.com/b/15222-1107-2024.zip",
+                        "size": "200",
+                        "hash": format!("sha256:{}", "AB".repeat(32)),
+                    },
+                    {"url": "https://example.com/b/README.pdf"},
+                ],
+            },
+        ]);
+        let datasets = datasets.as_array().unwrap();
+
+        let packages = pick_packages(datasets, "15222");
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].name, "15222-1107-2024.zip");
+        assert_eq!(packages[0].size, Some(200));
+        assert_eq!(packages[0].sha256, Some("ab".repeat(32)));
+
+        let names: Vec<_> = pick_packages(datasets, "15")
+            .into_iter()
+            .map(|package| package.name)
+            .collect();
+        assert_eq!(names, ["15222-1107-2024.zip", "15223-1107-2023.zip"]);
+    }
+}
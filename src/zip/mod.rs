@@ -1,190 +1,1372 @@
 //! Utilities for reading the nested-zip distribution.
 
+#[cfg(feature = "rayon")]
 mod cloneable_seekable_reader;
+#[cfg(feature = "remote")]
+pub mod fetch;
+#[cfg(feature = "remote")]
+pub mod remote;
+
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use hashbrown::HashSet;
+use jprect::etmerc::ExtendedTransverseMercatorProjection;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::data::ParsedData;
+use crate::parser::{MojxmlParser, ParseBuffers, ParseOptions};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An entry could not be read (e.g. unsupported compression or a broken
+    /// nested archive). The iterators skip it and continue with the next one.
+    #[error("{name}: {source}")]
+    Entry {
+        name: String,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    /// An XML file could not be parsed.
+    #[error("{name}: {source}")]
+    Parse {
+        name: String,
+        #[source]
+        source: crate::parser::Error,
+    },
+}
+
+impl Error {
+    /// Name of the entry the error is about.
+    pub fn entry_name(&self) -> &str {
+        match self {
+            Error::Entry { name, .. } | Error::Parse { name, .. } => name,
+        }
+    }
+
+    /// Whether the parser deliberately skipped the file (see [`crate::parser::Error::SkipAll`]).
+    pub fn is_skipped(&self) -> bool {
+        matches!(
+            self,
+            Error::Parse {
+                source: crate::parser::Error::SkipAll,
+                ..
+            }
+        )
+    }
+}
+
+/// An XML file read from the archive.
+#[derive(Clone, Debug)]
+pub struct EntryInfo {
+    /// Name of the XML file.
+    pub name: String,
+    /// Size of the XML file in bytes.
+    pub size: u64,
+    /// Time spent parsing the XML file.
+    pub parse_time: std::time::Duration,
+    /// SHA-256 of the XML file (64 hex digits), with `with_checksums`.
+    pub sha256: Option<String>,
+    /// The share of the memory budget held by the file (see
+    /// `ZipPackageParallelIter::with_memory_budget`), given back once the
+    /// info and its clones are dropped.
+    #[cfg(feature = "rayon")]
+    reservation: Option<Arc<parallel::Reservation>>,
+}
+
+/// Record the outcome of an XML file read and parsed by an iterator.
+#[cfg(feature = "tracing")]
+fn trace_parsed(result: &Result<(EntryInfo, ParsedData<'static>), Error>) {
+    match result {
+        Ok((info, data)) => tracing::debug!(
+            name = %info.name,
+            size = info.size,
+            parse_time = ?info.parse_time,
+            fudes = data.fudes.len(),
+            "parsed file"
+        ),
+        Err(e) if e.is_skipped() => tracing::debug!(name = e.entry_name(), "skipped file"),
+        Err(e) => tracing::warn!(error = %e, "failed to read file"),
+    }
+}
+
+/// Predicate on the names of the entries in the outer archive.
+pub type EntryFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Names of entries which should not be yielded (e.g. already processed ones).
+type SkipSet = Arc<HashSet<String>>;
+
+/// Name and CRC-32 of the XML files seen so far.
+///
+/// Share one set (it is cheaply cloneable) between the iterators over several
+/// archives so that an XML file contained in more than one of them is only
+/// yielded once.
+#[derive(Clone, Default)]
+pub struct DedupSet(Arc<Mutex<HashSet<(String, u32)>>>);
+
+impl DedupSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a file, returning `false` if it has been seen before.
+    fn insert(&self, name: &str, crc32: u32) -> bool {
+        self.0.lock().unwrap().insert((name.to_string(), crc32))
+    }
+}
+
+/// Default maximum number of nested archive levels below the outer archive.
+pub const DEFAULT_MAX_DEPTH: usize = 3;
+
+/// Options shared by the sequential and parallel iterators.
+#[derive(Clone)]
+struct WalkOptions {
+    filter: Option<EntryFilter>,
+    skip_set: Option<SkipSet>,
+    dedup: Option<DedupSet>,
+    max_depth: usize,
+    checksums: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            skip_set: None,
+            dedup: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            checksums: false,
+        }
+    }
+}
 
-use std::io::{Cursor, Read, Seek};
+impl WalkOptions {
+    /// Whether an outer entry may contain XML and passes the filter and the skip set.
+    fn is_selected(&self, name: &str) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(name)) && self.is_nested_selected(name)
+    }
+
+    /// Same as `is_selected`, for entries of nested archives (the filter doesn't apply).
+    fn is_nested_selected(&self, name: &str) -> bool {
+        (name.ends_with(".zip") || name.ends_with(".xml"))
+            && !self.skip_set.as_ref().is_some_and(|set| set.contains(name))
+    }
+
+    /// Same as `is_selected`, also leaving out the nested archives whose XML
+    /// file is in the skip set, for the totals.
+    ///
+    /// MOJ nested archives hold a single XML file named after the archive.
+    fn is_counted(&self, name: &str) -> bool {
+        self.is_selected(name)
+            && !name.strip_suffix(".zip").is_some_and(|stem| {
+                let stem = stem.rsplit('/').next().unwrap_or(stem);
+                let set = self.skip_set.as_ref();
+                set.is_some_and(|set| set.contains(&format!("{stem}.xml")))
+            })
+    }
+
+    /// Whether an XML file has already been seen, according to the dedup set.
+    fn is_duplicate<R: Read + Seek>(
+        &self,
+        zip: &mut zip::ZipArchive<R>,
+        index: usize,
+        name: &str,
+    ) -> bool {
+        let Some(dedup) = &self.dedup else {
+            return false;
+        };
+        if !name.ends_with(".xml") {
+            return false;
+        }
+        match zip.by_index_raw(index) {
+            Ok(file) => !dedup.insert(name, file.crc32()),
+            Err(_) => false,
+        }
+    }
+
+    /// Open an archive found at the given nesting level.
+    fn open_nested(
+        &self,
+        data: Vec<u8>,
+        depth: usize,
+    ) -> zip::result::ZipResult<zip::ZipArchive<Cursor<Vec<u8>>>> {
+        if depth > self.max_depth {
+            return Err(zip::result::ZipError::UnsupportedArchive(
+                "archives are nested too deeply",
+            ));
+        }
+        zip::ZipArchive::new(Cursor::new(data))
+    }
+}
+
+/// Names and uncompressed sizes of the outer entries, from the central directory.
+fn outer_entries<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+) -> zip::result::ZipResult<Vec<(String, u64)>> {
+    (0..zip.len())
+        .map(|idx| {
+            let file = zip.by_index_raw(idx)?;
+            Ok((file.name().to_string(), file.size()))
+        })
+        .collect()
+}
 
 pub struct ZipPackageIter<R: Read + Seek> {
     zip: zip::ZipArchive<R>,
-    inner_zip: Option<zip::ZipArchive<Cursor<Vec<u8>>>>,
+    entries: Vec<(String, u64)>,
+    /// Nested archives being read, with the index of their next entry.
+    stack: Vec<(zip::ZipArchive<Cursor<Vec<u8>>>, usize)>,
     index: usize,
-    inner_index: usize,
+    options: WalkOptions,
+    buffers: ParseBuffers,
 }
 
 impl<R: Read + Seek> ZipPackageIter<R> {
     pub fn new(reader: R) -> std::io::Result<Self> {
-        let zip = zip::ZipArchive::new(reader)?;
+        let mut zip = zip::ZipArchive::new(reader)?;
+        let entries = outer_entries(&mut zip)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(entries = entries.len(), "opened package");
         Ok(Self {
             zip,
-            inner_zip: None,
+            entries,
+            stack: Vec::new(),
             index: 0,
-            inner_index: 0,
+            options: WalkOptions::default(),
+            buffers: ParseBuffers::default(),
         })
     }
 
-    fn next_inner(&mut self) -> std::io::Result<Option<(String, Vec<u8>)>> {
-        loop {
-            if let Some(inner_zip) = &mut self.inner_zip {
-                if self.inner_index < inner_zip.len() {
-                    let mut inner_file = inner_zip.by_index(self.inner_index)?;
-                    let mut inner = Cursor::new(Vec::with_capacity(inner_file.size() as usize));
-                    std::io::copy(&mut inner_file, &mut inner)?;
-                    let name = inner_file.name().to_string();
-                    self.inner_index += 1;
-                    return Ok(Some((name, inner.into_inner())));
-                } else {
-                    self.inner_zip = None;
-                    self.inner_index = 0;
-                    self.index += 1;
-                }
-            }
+    /// Only read the outer entries whose name satisfies the predicate.
+    ///
+    /// Rejected entries are never decompressed (or downloaded, for remote archives).
+    pub fn with_entry_filter(
+        mut self,
+        filter: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Don't yield the entries whose name is in the set.
+    ///
+    /// The names are matched against both the outer entries and the XML files
+    /// inside nested archives, i.e. the names this iterator yields.
+    pub fn with_skip_set(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.options.skip_set = Some(Arc::new(names.into_iter().collect()));
+        self
+    }
+
+    /// Set how many levels of archives nested in the outer archive are read
+    /// (default: [`DEFAULT_MAX_DEPTH`]). Deeper archives are reported as errors.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    /// Skip the XML files already recorded in the set, and record the others.
+    pub fn with_dedup(mut self, dedup: DedupSet) -> Self {
+        self.options.dedup = Some(dedup);
+        self
+    }
+
+    /// Compute the SHA-256 of the parsed XML files, in [`EntryInfo::sha256`],
+    /// e.g. to prove which exact files produced an output.
+    pub fn with_checksums(mut self) -> Self {
+        self.options.checksums = true;
+        self
+    }
+
+    /// Number of outer entries which will be read.
+    ///
+    /// A nested archive counts as one entry, unless the skip set holds the XML
+    /// file named after it. MOJ packages have exactly one such XML file in each
+    /// nested archive, so this matches the number of items yielded.
+    pub fn total_entries(&self) -> usize {
+        self.selected_entries().count()
+    }
+
+    /// Sum of the uncompressed sizes of the outer entries counted by `total_entries`.
+    pub fn total_uncompressed_size(&self) -> u64 {
+        self.selected_entries().map(|(_, size)| size).sum()
+    }
 
-            if self.index >= self.zip.len() {
-                break;
+    /// Names and uncompressed sizes of the outer entries counted by `total_entries`.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.selected_entries()
+            .map(|(name, size)| (name.as_str(), *size))
+    }
+
+    fn selected_entries(&self) -> impl Iterator<Item = &(String, u64)> {
+        self.entries
+            .iter()
+            .filter(|(name, _)| self.options.is_counted(name))
+    }
+
+    /// Parse the next XML file as it is decompressed, instead of decompressing
+    /// it into memory first like [`next`](Iterator::next) does, which halves
+    /// the memory needed for each file.
+    ///
+    /// The rest of a parsed file is read after the end of the XML, so that
+    /// its CRC-32 is checked like with [`next`](Iterator::next).
+    pub fn next_parsed(
+        &mut self,
+        options: &ParseOptions,
+        projections: &[ExtendedTransverseMercatorProjection; 19],
+    ) -> Option<Result<(EntryInfo, ParsedData<'static>), Error>> {
+        let mut buffers = std::mem::take(&mut self.buffers);
+        let checksums = self.options.checksums;
+        let parsed = self.next_entry(&mut |name, file, size| {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("mojxml_file", name = %name, size).entered();
+            let start = Instant::now();
+            let mut reader = HashingReader {
+                inner: file,
+                hasher: checksums.then(Sha256::new),
+            };
+            let parsed = MojxmlParser::new(BufReader::new(&mut reader), projections)
+                .with_options(options.clone())
+                .parse_with(&mut buffers);
+            let parse_time = start.elapsed();
+            match parsed {
+                Ok(parsed) => match std::io::copy(&mut reader, &mut std::io::sink()) {
+                    Ok(_) => Ok((
+                        EntryInfo {
+                            name,
+                            size,
+                            parse_time,
+                            sha256: reader.hasher.map(to_hex),
+                            #[cfg(feature = "rayon")]
+                            reservation: None,
+                        },
+                        parsed,
+                    )),
+                    Err(e) => Err(Error::Entry {
+                        name,
+                        source: e.into(),
+                    }),
+                },
+                Err(source) => Err(Error::Parse { name, source }),
             }
+        });
+        self.buffers = buffers;
+        let parsed = parsed.transpose();
+        #[cfg(feature = "tracing")]
+        if let Some(result) = &parsed {
+            trace_parsed(result);
+        }
+        parsed
+    }
 
-            let mut inner_file = self.zip.by_index(self.index)?;
-            match inner_file.name().rsplit_once('.') {
-                Some((_, "zip")) => {
-                    let mut inner = Cursor::new(Vec::with_capacity(inner_file.size() as usize));
-                    std::io::copy(&mut inner_file, &mut inner)?;
-                    inner.rewind()?;
-                    self.inner_zip = Some(zip::ZipArchive::new(inner)?);
+    /// Walk to the next XML file, and read it with `read`.
+    fn next_entry<T>(&mut self, read: &mut ReadFile<T>) -> Result<Option<T>, Error> {
+        loop {
+            let depth = self.stack.len();
+            let (name, data) = if let Some((nested, index)) = self.stack.last_mut() {
+                if *index >= nested.len() {
+                    self.stack.pop();
+                    if self.stack.is_empty() {
+                        self.index += 1;
+                    }
+                    continue;
+                }
+                let idx = *index;
+                *index += 1;
+                let Some(name) = nested.name_for_index(idx).map(str::to_string) else {
+                    continue;
+                };
+                if !self.options.is_nested_selected(&name)
+                    || self.options.is_duplicate(nested, idx, &name)
+                {
+                    continue;
                 }
-                Some((_, "xml")) => {
-                    let mut inner = Cursor::new(Vec::with_capacity(inner_file.size() as usize));
-                    std::io::copy(&mut inner_file, &mut inner)?;
+                if !name.ends_with(".zip") {
+                    // self.index is advanced once the nested archive is exhausted
+                    return read_file(nested, idx, name, read).map(Some);
+                }
+                let data = read_entry(nested, idx);
+                (name, data)
+            } else {
+                if self.index >= self.zip.len() {
+                    return Ok(None);
+                }
+                let idx = self.index;
+                let Some(name) = self.zip.name_for_index(idx).map(str::to_string) else {
                     self.index += 1;
-                    return Ok(Some((inner_file.name().to_string(), inner.into_inner())));
+                    continue;
+                };
+                if !self.options.is_selected(&name)
+                    || self.options.is_duplicate(&mut self.zip, idx, &name)
+                {
+                    self.index += 1;
+                    continue;
                 }
-                _ => {
+                if !name.ends_with(".zip") {
                     self.index += 1;
+                    return read_file(&mut self.zip, idx, name, read).map(Some);
+                }
+                let data = read_entry(&mut self.zip, idx);
+                (name, data)
+            };
+
+            match data.and_then(|data| self.options.open_nested(data, depth + 1)) {
+                // self.index is advanced once the nested archive is exhausted
+                Ok(nested) => self.stack.push((nested, 0)),
+                Err(source) => {
+                    if depth == 0 {
+                        self.index += 1;
+                    }
+                    return Err(Error::Entry { name, source });
                 }
             }
         }
-        Ok(None)
     }
 }
 
 impl<R: Read + Seek> Iterator for ZipPackageIter<R> {
-    type Item = std::io::Result<(String, Vec<u8>)>;
+    type Item = Result<(String, Vec<u8>), Error>;
 
-    fn next(&mut self) -> Option<std::io::Result<(String, Vec<u8>)>> {
-        self.next_inner().transpose()
+    fn next(&mut self) -> Option<Result<(String, Vec<u8>), Error>> {
+        self.next_entry(&mut |name, file, size| {
+            let mut data = Vec::with_capacity(size as usize);
+            match file.read_to_end(&mut data) {
+                Ok(_) => Ok((name, data)),
+                Err(e) => Err(Error::Entry {
+                    name,
+                    source: e.into(),
+                }),
+            }
+        })
+        .transpose()
     }
 }
 
+/// A SHA-256 digest as 64 lowercase hex digits.
+pub(crate) fn to_hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Passes the data of a file through, hashing it if there is a hasher.
+struct HashingReader<'a> {
+    inner: &'a mut dyn Read,
+    hasher: Option<Sha256>,
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Reads an XML file, given its name, its decompressing reader and its size.
+type ReadFile<'r, T> = dyn FnMut(String, &mut dyn Read, u64) -> Result<T, Error> + 'r;
+
+/// Read an XML file of an archive with `read`, as it is decompressed.
+fn read_file<R: Read + Seek, T>(
+    zip: &mut zip::ZipArchive<R>,
+    index: usize,
+    name: String,
+    read: &mut ReadFile<T>,
+) -> Result<T, Error> {
+    match zip.by_index(index) {
+        Ok(mut file) => {
+            let size = file.size();
+            read(name, &mut file, size)
+        }
+        Err(source) => Err(Error::Entry { name, source }),
+    }
+}
+
+/// Decompress an entry into memory, checking its CRC-32.
+fn read_entry<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+    index: usize,
+) -> zip::result::ZipResult<Vec<u8>> {
+    let mut file = zip.by_index(index)?;
+    let mut cursor = Cursor::new(Vec::with_capacity(file.size() as usize));
+    std::io::copy(&mut file, &mut cursor)?;
+    Ok(cursor.into_inner())
+}
+
 #[cfg(feature = "rayon")]
 mod parallel {
     use super::cloneable_seekable_reader::CloneableSeekableReader;
 
+    use super::{DedupSet, EntryInfo, Error, WalkOptions, outer_entries, read_entry, to_hex};
+    use crate::data::ParsedData;
+    use crate::parser::{MojxmlParser, ParseBuffers, ParseOptions};
+
+    use jprect::etmerc::ExtendedTransverseMercatorProjection;
     use rayon::iter::{ParallelBridge, ParallelIterator};
+    use sha2::{Digest, Sha256};
     use std::{
+        cell::RefCell,
+        collections::{BTreeMap, VecDeque},
         io::{Cursor, Read, Seek},
-        sync::mpsc,
+        sync::{
+            Arc, Condvar, Mutex,
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread::JoinHandle,
+        time::Instant,
     };
 
-    pub struct ZipPackageParallelIter {
-        receiver: mpsc::Receiver<zip::result::ZipResult<(String, Vec<u8>)>>,
+    type Entry = Result<(String, Vec<u8>), Error>;
+    type ParsedEntry = Result<(EntryInfo, ParsedData<'static>), Error>;
+    /// An item sent by the workers, with the share of the budget of its file.
+    type Sent<T> = (T, Option<Reservation>);
+    /// A message of the workers about the outer entry at the given position:
+    /// one of its items, or `None` once it's done (with `with_ordered` only).
+    type Message<T> = (usize, Option<Sent<T>>);
+    /// The items of an outer entry received ahead of its turn, and whether it's done.
+    type Ahead<T> = (VecDeque<Sent<T>>, bool);
+
+    /// Reads the entries of the archive on a rayon thread pool.
+    ///
+    /// The workers are started on the first call to `next()`, so the
+    /// `with_*` options must be set before iterating.
+    pub struct ZipPackageParallelIter<R: Read + Seek + Send + 'static> {
+        zip: Option<zip::ZipArchive<CloneableSeekableReader<R>>>,
+        entries: Vec<(String, u64)>,
+        options: WalkOptions,
+        budget: Option<Arc<MemoryBudget>>,
+        threads: Option<usize>,
+        ordered: bool,
+        receiver: Option<Receiver<Entry>>,
+        /// The share of the budget of the last yielded file
+        held: Option<Reservation>,
     }
 
-    impl Iterator for ZipPackageParallelIter {
-        type Item = zip::result::ZipResult<(String, Vec<u8>)>;
+    /// Limit on the total size of the XML files being read, parsed or used.
+    #[derive(Debug)]
+    struct MemoryBudget {
+        limit: u64,
+        used: Mutex<u64>,
+        released: Condvar,
+        /// With `with_ordered`, the position of the outer entry the receiver
+        /// waits for, whose files don't wait for the budget
+        turn: AtomicUsize,
+    }
 
-        fn next(&mut self) -> Option<zip::result::ZipResult<(String, Vec<u8>)>> {
-            self.receiver.recv().ok()
+    impl MemoryBudget {
+        /// Wait until `size` more bytes fit in the budget. A file larger than
+        /// the whole budget waits until it's alone, but for the `held` bytes of
+        /// the archives it's nested in.
+        ///
+        /// The files of the outer entry at the receiver's turn never wait, as
+        /// the budget may be held by the files received ahead of them.
+        fn reserve(self: &Arc<Self>, size: u64, held: u64, position: usize) -> Reservation {
+            let mut used = self.used.lock().unwrap();
+            while *used > held
+                && *used + size > self.limit
+                && self.turn.load(Ordering::Relaxed) != position
+            {
+                used = self.released.wait(used).unwrap();
+            }
+            *used += size;
+            Reservation {
+                budget: self.clone(),
+                size,
+            }
+        }
+
+        /// Let the files of the outer entry at `position` through.
+        fn set_turn(&self, position: usize) {
+            let _used = self.used.lock().unwrap();
+            self.turn.store(position, Ordering::Relaxed);
+            self.released.notify_all();
         }
     }
 
-    impl ZipPackageParallelIter {
-        pub fn new<R: Read + Seek + Send + 'static>(reader: R) -> std::io::Result<Self> {
+    /// Bytes of a [`MemoryBudget`], given back when dropped.
+    #[derive(Debug)]
+    pub(super) struct Reservation {
+        budget: Arc<MemoryBudget>,
+        size: u64,
+    }
+
+    impl Drop for Reservation {
+        fn drop(&mut self) {
+            *self.budget.used.lock().unwrap() -= self.size;
+            self.budget.released.notify_all();
+        }
+    }
+
+    /// Reserve the uncompressed size of an entry before decompressing it: an
+    /// XML file until it's used, a nested archive while its files are read.
+    fn reserve<R: Read + Seek>(
+        budget: Option<&Arc<MemoryBudget>>,
+        zip: &mut zip::ZipArchive<R>,
+        index: usize,
+        held: u64,
+        position: usize,
+    ) -> Option<Reservation> {
+        let budget = budget?;
+        let size = zip.by_index_raw(index).map(|file| file.size()).unwrap_or(0);
+        Some(budget.reserve(size, held, position))
+    }
+
+    /// Receives the items of the workers, in the order of the outer entries
+    /// with `with_ordered`.
+    struct Receiver<T> {
+        receiver: mpsc::Receiver<Message<T>>,
+        budget: Option<Arc<MemoryBudget>>,
+        /// With `with_ordered`, the items received ahead of their turn, by position
+        ahead: Option<BTreeMap<usize, Ahead<T>>>,
+        /// The position of the outer entry whose items are yielded
+        turn: usize,
+        /// The thread of the workers, joined once they are done
+        producer: Option<JoinHandle<()>>,
+    }
+
+    impl<T> Receiver<T> {
+        fn recv(&mut self) -> Option<Sent<T>> {
+            let item = self.recv_item();
+            if item.is_none() {
+                self.join_producer();
+            }
+            item
+        }
+
+        /// Raise the panic of a worker, if any, instead of ending as if all
+        /// the entries had been read.
+        fn join_producer(&mut self) {
+            if let Some(producer) = self.producer.take()
+                && let Err(panic) = producer.join()
+            {
+                std::panic::resume_unwind(panic);
+            }
+        }
+
+        fn recv_item(&mut self) -> Option<Sent<T>> {
+            let Some(ahead) = &mut self.ahead else {
+                // the positions don't matter
+                return self.receiver.iter().find_map(|(_, item)| item);
+            };
+            loop {
+                if let Some((items, done)) = ahead.get_mut(&self.turn) {
+                    if let Some(item) = items.pop_front() {
+                        return Some(item);
+                    }
+                    if *done {
+                        ahead.remove(&self.turn);
+                        self.turn += 1;
+                        continue;
+                    }
+                }
+                if let Some(budget) = &self.budget {
+                    budget.set_turn(self.turn);
+                }
+                match self.receiver.recv() {
+                    Ok((position, item)) => {
+                        let (items, done) = ahead.entry(position).or_default();
+                        match item {
+                            Some(item) => items.push_back(item),
+                            None => *done = true,
+                        }
+                    }
+                    // the workers stopped early (a worker panicked): the
+                    // entries received so far are yielded in order
+                    Err(_) => {
+                        let mut first = ahead.first_entry()?;
+                        match first.get_mut().0.pop_front() {
+                            Some(item) => return Some(item),
+                            None => {
+                                first.remove();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    impl<R: Read + Seek + Send + 'static> Iterator for ZipPackageParallelIter<R> {
+        type Item = Entry;
+
+        fn next(&mut self) -> Option<Entry> {
+            if self.receiver.is_none() {
+                self.receiver = self.start(|name, data| Ok((name, data)));
+            }
+            // the previous file is given back to the budget
+            self.held = None;
+            let (entry, reservation) = self.receiver.as_mut()?.recv()?;
+            self.held = reservation;
+            Some(entry)
+        }
+    }
+
+    /// Iterator returned by [`ZipPackageParallelIter::parsed`].
+    pub struct ZipPackageParsedIter {
+        receiver: Option<Receiver<ParsedEntry>>,
+    }
+
+    impl Iterator for ZipPackageParsedIter {
+        type Item = ParsedEntry;
+
+        fn next(&mut self) -> Option<ParsedEntry> {
+            let (parsed, reservation) = self.receiver.as_mut()?.recv()?;
+            let parsed = Some(parsed.map(|(mut info, data)| {
+                info.reservation = reservation.map(Arc::new);
+                (info, data)
+            }));
+            #[cfg(feature = "tracing")]
+            if let Some(result) = &parsed {
+                super::trace_parsed(result);
+            }
+            parsed
+        }
+    }
+
+    impl<R: Read + Seek + Send + 'static> ZipPackageParallelIter<R> {
+        pub fn new(reader: R) -> std::io::Result<Self> {
             let clonable_reader = CloneableSeekableReader::new(reader);
-            let zip = zip::ZipArchive::new(clonable_reader)?;
+            let mut zip = zip::ZipArchive::new(clonable_reader)?;
+            let entries = outer_entries(&mut zip)?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(entries = entries.len(), "opened package");
+            Ok(Self {
+                zip: Some(zip),
+                entries,
+                options: WalkOptions::default(),
+                budget: None,
+                threads: None,
+                ordered: false,
+                receiver: None,
+                held: None,
+            })
+        }
+
+        /// Read the entries with `threads` workers, instead of the default
+        /// number of threads of a rayon pool.
+        pub fn with_threads(mut self, threads: usize) -> Self {
+            self.threads = Some(threads);
+            self
+        }
+
+        /// Don't hold more than `bytes` of decompressed XML at the same time,
+        /// to bound the memory used with large files. A file counts from its
+        /// decompression until the next item is requested, or with
+        /// [`parsed`](Self::parsed), until its [`EntryInfo`] is dropped, so
+        /// that the files waiting to be written count too. Drop the items
+        /// once done with them: the workers wait for them once the budget is
+        /// used up.
+        pub fn with_memory_budget(mut self, bytes: u64) -> Self {
+            self.budget = Some(Arc::new(MemoryBudget {
+                limit: bytes,
+                used: Mutex::new(0),
+                released: Condvar::new(),
+                turn: AtomicUsize::new(usize::MAX),
+            }));
+            self
+        }
+
+        /// Yield the files in the order of the names of the outer entries
+        /// (and of the nested entries, in the order of their archive), for
+        /// reproducible outputs.
+        ///
+        /// The files read ahead of their turn are kept until it comes.
+        pub fn with_ordered(mut self) -> Self {
+            self.ordered = true;
+            self
+        }
+
+        /// Only read the outer entries whose name satisfies the predicate.
+        ///
+        /// Rejected entries are never decompressed (or downloaded, for remote archives).
+        pub fn with_entry_filter(
+            mut self,
+            filter: impl Fn(&str) -> bool + Send + Sync + 'static,
+        ) -> Self {
+            self.options.filter = Some(Arc::new(filter));
+            self
+        }
+
+        /// Don't yield the entries whose name is in the set.
+        ///
+        /// The names are matched against both the outer entries and the XML files
+        /// inside nested archives, i.e. the names this iterator yields.
+        pub fn with_skip_set(mut self, names: impl IntoIterator<Item = String>) -> Self {
+            self.options.skip_set = Some(Arc::new(names.into_iter().collect()));
+            self
+        }
+
+        /// Set how many levels of archives nested in the outer archive are read
+        /// (default: [`DEFAULT_MAX_DEPTH`](super::DEFAULT_MAX_DEPTH)). Deeper
+        /// archives are reported as errors.
+        pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+            self.options.max_depth = max_depth;
+            self
+        }
+
+        /// Skip the XML files already recorded in the set, and record the others.
+        pub fn with_dedup(mut self, dedup: DedupSet) -> Self {
+            self.options.dedup = Some(dedup);
+            self
+        }
+
+        /// Compute the SHA-256 of the XML files, in [`EntryInfo::sha256`]
+        /// (with [`parsed`](Self::parsed)), e.g. to prove which exact files
+        /// produced an output.
+        pub fn with_checksums(mut self) -> Self {
+            self.options.checksums = true;
+            self
+        }
 
+        /// Number of outer entries which will be read.
+        ///
+        /// A nested archive counts as one entry, unless the skip set holds the XML
+        /// file named after it. MOJ packages have exactly one such XML file in each
+        /// nested archive, so this matches the number of items yielded.
+        pub fn total_entries(&self) -> usize {
+            self.selected_entries().count()
+        }
+
+        /// Sum of the uncompressed sizes of the outer entries counted by `total_entries`.
+        pub fn total_uncompressed_size(&self) -> u64 {
+            self.selected_entries().map(|(_, size)| size).sum()
+        }
+
+        /// Names and uncompressed sizes of the outer entries counted by `total_entries`.
+        pub fn entries(&self) -> impl Iterator<Item = (&str, u64)> {
+            self.selected_entries()
+                .map(|(name, size)| (name.as_str(), *size))
+        }
+
+        fn selected_entries(&self) -> impl Iterator<Item = &(String, u64)> {
+            self.entries
+                .iter()
+                .filter(|(name, _)| self.options.is_counted(name))
+        }
+
+        /// Parse the XML files in the worker threads as well.
+        ///
+        /// Files skipped by the parser are yielded as errors for which
+        /// [`Error::is_skipped`] returns `true`.
+        pub fn parsed(
+            mut self,
+            options: ParseOptions,
+            projections: Arc<[ExtendedTransverseMercatorProjection; 19]>,
+        ) -> ZipPackageParsedIter {
+            // the files are parsed on the workers, under the caller's span
+            #[cfg(feature = "tracing")]
+            let parent = tracing::Span::current();
+            let checksums = self.options.checksums;
+            let receiver = self.start(move |name, data| {
+                thread_local! {
+                    static BUFFERS: RefCell<ParseBuffers> = RefCell::default();
+                }
+                #[cfg(feature = "tracing")]
+                let _span =
+                    tracing::info_span!(parent: &parent, "mojxml_file", name = %name, size = data.len())
+                        .entered();
+                let start = Instant::now();
+                let parsed = BUFFERS.with_borrow_mut(|buffers| {
+                    MojxmlParser::new(Cursor::new(&data), &projections)
+                        .with_options(options.clone())
+                        .with_capacity_for(&data)
+                        .parse_with(buffers)
+                });
+                match parsed {
+                    Ok(parsed) => Ok((
+                        EntryInfo {
+                            size: data.len() as u64,
+                            name,
+                            parse_time: start.elapsed(),
+                            sha256: checksums.then(|| to_hex(Sha256::new_with_prefix(&data))),
+                            // set by the iterator, with the file's share of the budget
+                            reservation: None,
+                        },
+                        parsed,
+                    )),
+                    Err(source) => Err(Error::Parse { name, source }),
+                }
+            });
+            ZipPackageParsedIter { receiver }
+        }
+
+        /// Start the workers, which send `map(name, data)` for each XML file.
+        fn start<T, F>(&mut self, map: F) -> Option<Receiver<Result<T, Error>>>
+        where
+            T: Send + 'static,
+            F: Fn(String, Vec<u8>) -> Result<T, Error> + Send + Sync + 'static,
+        {
+            let zip = self.zip.take()?;
+            let options = self.options.clone();
+            let budget = self.budget.clone();
+            let threads = self.threads.unwrap_or(0);
+            let ordered = self.ordered;
             let (sender, receiver) = mpsc::sync_channel(100);
+            let receiver = Receiver {
+                receiver,
+                budget: budget.clone(),
+                ahead: ordered.then(BTreeMap::new),
+                turn: 0,
+                producer: None,
+            };
 
-            std::thread::spawn(|| {
+            let producer = std::thread::spawn(move || {
                 rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
                     .build()
                     .unwrap()
                     .install(|| {
-                        Self::producer(zip, sender);
+                        let walker = Walker {
+                            options,
+                            budget,
+                            ordered,
+                            map,
+                            sender,
+                        };
+                        Self::producer(zip, walker);
                     });
             });
 
-            Ok(Self { receiver })
+            Some(Receiver {
+                producer: Some(producer),
+                ..receiver
+            })
         }
 
-        fn producer<R: Clone + Read + Seek + Send>(
-            zip: zip::ZipArchive<R>,
-            sender: mpsc::SyncSender<zip::result::ZipResult<(String, Vec<u8>)>>,
+        fn producer<T: Send, F: Fn(String, Vec<u8>) -> Result<T, Error> + Sync>(
+            zip: zip::ZipArchive<CloneableSeekableReader<R>>,
+            walker: Walker<T, F>,
         ) {
-            fn process_inner_zip(
-                name: String,
-                inner_data: Vec<u8>,
-            ) -> zip::result::ZipResult<Option<(String, Vec<u8>)>> {
-                match name.rsplit_once('.') {
-                    Some((_, "zip")) => {
-                        let mut inner_zip = zip::ZipArchive::new(Cursor::new(inner_data))?;
-                        assert_eq!(inner_zip.len(), 1);
-                        let mut xml = inner_zip.by_index(0)?;
-                        let name = xml.name().to_string();
-                        if name.ends_with(".xml") {
-                            let mut cursor = Cursor::new(Vec::with_capacity(xml.size() as usize));
-                            std::io::copy(&mut xml, &mut cursor).unwrap();
-                            Ok(Some((name, cursor.into_inner())))
-                        } else {
-                            Err(std::io::Error::new(
-                                std::io::ErrorKind::InvalidData,
-                                "inner zip does not contain an xml file",
-                            )
-                            .into())
-                        }
-                    }
-                    Some((_, "xml")) => Ok(Some((name, inner_data))),
-                    _ => Ok(None),
+            let mut indices: Vec<usize> = (0..zip.len()).collect();
+            if walker.ordered {
+                indices.sort_by_key(|&idx| zip.name_for_index(idx));
+            }
+            // the bridge hands out the entries in order, so that the one at
+            // the receiver's turn is always being read
+            let _ = indices
+                .into_iter()
+                .enumerate()
+                .par_bridge()
+                .try_for_each_with(zip, |zip, (position, idx)| {
+                    walker.walk(zip, idx, 0, 0, position)?;
+                    walker.done(position)
+                });
+        }
+    }
+
+    /// What the workers share while walking the archive.
+    struct Walker<T, F> {
+        options: WalkOptions,
+        budget: Option<Arc<MemoryBudget>>,
+        ordered: bool,
+        map: F,
+        sender: mpsc::SyncSender<Message<Result<T, Error>>>,
+    }
+
+    impl<T, F: Fn(String, Vec<u8>) -> Result<T, Error>> Walker<T, F> {
+        /// Send an entry of an archive (the outer one at depth 0) if it's an
+        /// XML file, or the XML files found (recursively) in it if it's a
+        /// nested archive.
+        ///
+        /// Returns `Err(())` once the receiver has hung up.
+        /// Read the entry `idx`, inside archives holding `held` bytes of the budget.
+        fn walk<S: Read + Seek>(
+            &self,
+            zip: &mut zip::ZipArchive<S>,
+            idx: usize,
+            depth: usize,
+            held: u64,
+            position: usize,
+        ) -> Result<(), ()> {
+            let Some(name) = zip.name_for_index(idx).map(str::to_string) else {
+                return Ok(());
+            };
+            let selected = if depth == 0 {
+                self.options.is_selected(&name)
+            } else {
+                self.options.is_nested_selected(&name)
+            };
+            if !selected || self.options.is_duplicate(zip, idx, &name) {
+                return Ok(());
+            }
+            let reservation = reserve(self.budget.as_ref(), zip, idx, held, position);
+            let data = match read_entry(zip, idx) {
+                Ok(data) => data,
+                Err(source) => {
+                    return self.send(position, Err(Error::Entry { name, source }), None);
                 }
+            };
+            if !name.ends_with(".zip") {
+                return self.send(position, (self.map)(name, data), reservation);
             }
+            // the reservation of the archive is kept until its files are read
+            let held = held
+                + reservation
+                    .as_ref()
+                    .map_or(0, |reservation| reservation.size);
+            match self.options.open_nested(data, depth + 1) {
+                Ok(mut nested) => (0..nested.len())
+                    .try_for_each(|idx| self.walk(&mut nested, idx, depth + 1, held, position)),
+                Err(source) => self.send(position, Err(Error::Entry { name, source }), None),
+            }
+        }
 
-            let _ = (0..zip.len())
-                .par_bridge()
-                .try_for_each_with(zip, |zip, idx| {
-                    let mut inner_file = match zip.by_index(idx) {
-                        Err(e) => {
-                            if sender.send(Err(e)).is_err() {
-                                return Err(());
-                            }
-                            return Ok(());
-                        }
-                        Ok(inner_file) => inner_file,
-                    };
-                    let filename = inner_file.name().to_string();
-                    let mut cursor = Cursor::new(Vec::with_capacity(inner_file.size() as usize));
-                    if let Err(e) = std::io::copy(&mut inner_file, &mut cursor) {
-                        if sender.send(Err(e.into())).is_err() {
-                            return Err(());
-                        }
-                    };
-                    let inner_data = cursor.into_inner();
+        fn send(
+            &self,
+            position: usize,
+            item: Result<T, Error>,
+            reservation: Option<Reservation>,
+        ) -> Result<(), ()> {
+            let message = (position, Some((item, reservation)));
+            self.sender.send(message).map_err(|_| ())
+        }
 
-                    match process_inner_zip(filename, inner_data) {
-                        Ok(Some((name, data))) => {
-                            if sender.send(Ok((name, data))).is_err() {
-                                return Err(());
-                            }
-                        }
-                        Ok(None) => {}
-                        Err(e) => {
-                            if sender.send(Err(e)).is_err() {
-                                return Err(());
-                            }
-                        }
-                    }
-                    Ok(())
-                });
+        /// Tell the receiver that the outer entry at `position` is done.
+        fn done(&self, position: usize) -> Result<(), ()> {
+            if !self.ordered {
+                return Ok(());
+            }
+            self.sender.send((position, None)).map_err(|_| ())
         }
     }
 }
 
 #[cfg(feature = "rayon")]
 pub use parallel::*;
+
+#[cfg(test)]
+mod test {
+    use super::{DedupSet, Error, ZipPackageIter, to_hex};
+    use sha2::{Digest, Sha256};
+    use std::io::{Cursor, Write};
+    use test_log::test;
+    use zip::write::SimpleFileOptions;
+
+    fn build_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_continue_after_broken_entry() {
+        let data = build_zip(&[("a.zip", b"not a zip"), ("b.xml", b"<xml/>")]);
+        let mut iter = ZipPackageIter::new(Cursor::new(data)).unwrap();
+        assert_eq!(iter.total_entries(), 2);
+        assert_eq!(iter.total_uncompressed_size(), 15);
+        match iter.next() {
+            Some(Err(Error::Entry { name, .. })) => assert_eq!(name, "a.zip"),
+            _ => panic!("expected an entry error"),
+        }
+        let (name, data) = iter.next().unwrap().unwrap();
+        assert_eq!(name, "b.xml");
+        assert_eq!(data, b"<xml/>");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_next_parsed() {
+        let xml = "<地図><地図名>test</地図名><市区町村コード>13101</市区町村コード></地図>";
+        let nested = build_zip(&[("b.xml", xml.as_bytes())]);
+        let data = build_zip(&[
+            ("a.xml", xml.as_bytes()),
+            ("b.zip", &nested),
+            ("c.xml", b"<xml/>"),
+        ]);
+        let mut iter = ZipPackageIter::new(Cursor::new(data)).unwrap();
+        let projections = crate::parser::jpr_projections();
+        let options = crate::parser::ParseOptions::default();
+        for name in ["a.xml", "b.xml"] {
+            let (info, parsed) = iter.next_parsed(&options, &projections).unwrap().unwrap();
+            assert_eq!(info.name, name);
+            assert_eq!(info.size, xml.len() as u64);
+            assert_eq!(parsed.metadata.municipality_code.as_deref(), Some("13101"));
+        }
+        match iter.next_parsed(&options, &projections) {
+            Some(Err(Error::Parse { name, .. })) => assert_eq!(name, "c.xml"),
+            _ => panic!("expected a parse error"),
+        }
+        assert!(iter.next_parsed(&options, &projections).is_none());
+    }
+
+    #[test]
+    fn test_checksums() {
+        let xml = "<地図><地図名>test</地図名><市区町村コード>13101</市区町村コード></地図>";
+        let data = build_zip(&[("a.xml", xml.as_bytes())]);
+        // the same archive, with a byte of the (stored) XML changed
+        let mut corrupted = data.clone();
+        let offset = memchr::memmem::find(&corrupted, b"13101").unwrap();
+        corrupted[offset + 4] = b'2';
+
+        let projections = crate::parser::jpr_projections();
+        let options = crate::parser::ParseOptions::default();
+        let mut iter = ZipPackageIter::new(Cursor::new(data.clone()))
+            .unwrap()
+            .with_checksums();
+        let (info, _) = iter.next_parsed(&options, &projections).unwrap().unwrap();
+        let sha256 = to_hex(Sha256::new_with_prefix(xml));
+        assert_eq!(info.sha256.as_ref(), Some(&sha256));
+
+        let mut iter = ZipPackageIter::new(Cursor::new(corrupted.clone())).unwrap();
+        match iter.next_parsed(&options, &projections) {
+            Some(Err(e)) => assert_eq!(e.entry_name(), "a.xml"),
+            _ => panic!("expected a checksum error"),
+        }
+
+        #[cfg(feature = "rayon")]
+        {
+            let projections = std::sync::Arc::new(projections);
+            let mut iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+                .unwrap()
+                .with_checksums()
+                .parsed(options.clone(), projections.clone());
+            let (info, _) = iter.next().unwrap().unwrap();
+            assert_eq!(info.sha256, Some(sha256));
+
+            let mut iter = super::ZipPackageParallelIter::new(Cursor::new(corrupted))
+                .unwrap()
+                .parsed(options, projections);
+            assert!(matches!(iter.next(), Some(Err(Error::Entry { .. }))));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_continue_after_broken_entry() {
+        let data = build_zip(&[("a.zip", b"not a zip"), ("b.xml", b"<xml/>")]);
+        let iter = super::ZipPackageParallelIter::new(Cursor::new(data)).unwrap();
+        let (ok, err): (Vec<_>, Vec<_>) = iter.partition(|res| res.is_ok());
+        assert_eq!(ok.len(), 1);
+        assert_eq!(err.len(), 1);
+        assert_eq!(err[0].as_ref().unwrap_err().entry_name(), "a.zip");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_memory_budget() {
+        let nested = build_zip(&[("c.xml", b"<xml/>")]);
+        let data = build_zip(&[
+            ("a.xml", b"<xml/>"),
+            // larger than the budget
+            ("b.xml", b"<xml></xml>"),
+            ("c.zip", &nested),
+        ]);
+        let iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+            .unwrap()
+            .with_memory_budget(8);
+        let mut names: Vec<_> = iter.map(|res| res.unwrap().0).collect();
+        names.sort();
+        assert_eq!(names, ["a.xml", "b.xml", "c.xml"]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_memory_budget_nested() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Counts the bytes read from the archive.
+        struct Counting(Cursor<Vec<u8>>, std::sync::Arc<AtomicUsize>);
+        impl std::io::Read for Counting {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = self.0.read(buf)?;
+                self.1.fetch_add(len, Ordering::Relaxed);
+                Ok(len)
+            }
+        }
+        impl std::io::Seek for Counting {
+            fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                self.0.seek(pos)
+            }
+        }
+
+        let size = 200_000;
+        let nested = build_zip(&[("b.xml", &vec![b' '; size])]);
+        let data = build_zip(&[("a.xml", b"<xml/>"), ("b.zip", &nested)]);
+        let read = std::sync::Arc::new(AtomicUsize::new(0));
+        let mut iter =
+            super::ZipPackageParallelIter::new(Counting(Cursor::new(data), read.clone()))
+                .unwrap()
+                .with_threads(1)
+                .with_memory_budget(size as u64);
+        assert_eq!(iter.next().unwrap().unwrap().0, "a.xml");
+        // the nested archive isn't read while a.xml holds the budget
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(read.load(Ordering::Relaxed) < size);
+        assert_eq!(iter.next().unwrap().unwrap().0, "b.xml");
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_memory_budget_held() {
+        let xml = "<地図><地図名>test</地図名><市区町村コード>13101</市区町村コード></地図>";
+        let data = build_zip(&[("a.xml", xml.as_bytes()), ("b.xml", xml.as_bytes())]);
+        let projections = std::sync::Arc::new(crate::parser::jpr_projections());
+        let mut iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+            .unwrap()
+            .with_memory_budget(xml.len() as u64)
+            .parsed(Default::default(), projections);
+        let first = iter.next().unwrap().unwrap();
+        // the other file is only read once the first one is dropped
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(iter.next().map(|res| res.unwrap().0.name));
+        });
+        let wait = std::time::Duration::from_millis(200);
+        assert!(receiver.recv_timeout(wait).is_err());
+        let name = first.0.name.clone();
+        drop(first);
+        let other = receiver.recv_timeout(wait * 50).unwrap().unwrap();
+        assert_ne!(other, name);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_ordered() {
+        let nested = build_zip(&[("b.xml", b"<b/>")]);
+        let mut entries: Vec<(String, Vec<u8>)> = (0..20)
+            .rev()
+            .map(|i| (format!("f{i:02}.xml"), b"<xml/>".to_vec()))
+            .collect();
+        entries.push(("a.zip".to_string(), nested));
+        let entries: Vec<(&str, &[u8])> = entries
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+            .collect();
+        let data = build_zip(&entries);
+
+        let mut expected = vec!["b.xml".to_string()];
+        expected.extend((0..20).map(|i| format!("f{i:02}.xml")));
+        // the budget holds a single file, so the files read ahead of their
+        // turn must not hold back the one at its turn
+        let names = super::ZipPackageParallelIter::new(Cursor::new(data))
+            .unwrap()
+            .with_threads(4)
+            .with_memory_budget(6)
+            .with_ordered()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_worker_panic() {
+        let nested = build_zip(&[("c.xml", b"<c/>"), ("d.xml", b"<d/>"), ("e.xml", b"<e/>")]);
+        let data = build_zip(&[("a.xml", b"<a/>"), ("b.zip", &nested)]);
+        let iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+            .unwrap()
+            .with_threads(2)
+            .with_ordered()
+            // a.xml fails once b.zip is read: its files wait for their turn,
+            // which never comes
+            .with_entry_filter(|name| {
+                if name == "a.xml" {
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                    panic!("broken filter");
+                }
+                true
+            });
+        let mut names = Vec::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            for res in iter {
+                names.push(res.unwrap().0);
+            }
+        }));
+        assert_eq!(
+            *result.unwrap_err().downcast::<&str>().unwrap(),
+            "broken filter"
+        );
+        assert_eq!(names, ["c.xml", "d.xml", "e.xml"]);
+    }
+
+    #[test]
+    fn test_dedup_across_archives() {
+        let nested = build_zip(&[("a.xml", b"<a/>")]);
+        let first = build_zip(&[("a.zip", &nested), ("b.xml", b"<b/>")]);
+        let second = build_zip(&[("a.xml", b"<a/>"), ("b.xml", b"<changed/>")]);
+
+        let dedup = DedupSet::new();
+        let names = [first, second]
+            .into_iter()
+            .flat_map(|data| {
+                ZipPackageIter::new(Cursor::new(data))
+                    .unwrap()
+                    .with_dedup(dedup.clone())
+            })
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.xml", "b.xml", "b.xml"]);
+    }
+
+    #[test]
+    fn test_total_entries_with_skip_set() {
+        let a = build_zip(&[("a.xml", b"<a/>")]);
+        let b = build_zip(&[("b.xml", b"<b/>")]);
+        let data = build_zip(&[("dir/a.zip", &a), ("dir/b.zip", &b), ("c.xml", b"<c/>")]);
+        let skip = ["a.xml".to_string(), "c.xml".to_string()];
+
+        let iter = ZipPackageIter::new(Cursor::new(data.clone()))
+            .unwrap()
+            .with_skip_set(skip.clone());
+        assert_eq!(iter.total_entries(), 1);
+        assert_eq!(iter.total_uncompressed_size(), b.len() as u64);
+        let names = iter.map(|res| res.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(names, ["b.xml"]);
+
+        #[cfg(feature = "rayon")]
+        {
+            let iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+                .unwrap()
+                .with_skip_set(skip);
+            assert_eq!(iter.total_entries(), 1);
+        }
+    }
+
+    #[test]
+    fn test_nested_depth() {
+        let c = build_zip(&[("c.xml", b"<xml/>")]);
+        let b = build_zip(&[("b.zip", &c)]);
+        let data = build_zip(&[("a.zip", &b), ("d.xml", b"<xml/>")]);
+
+        let names = ZipPackageIter::new(Cursor::new(data.clone()))
+            .unwrap()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["c.xml", "d.xml"]);
+
+        let mut iter = ZipPackageIter::new(Cursor::new(data))
+            .unwrap()
+            .with_max_depth(1);
+        assert_eq!(iter.next().unwrap().unwrap_err().entry_name(), "b.zip");
+        assert_eq!(iter.next().unwrap().unwrap().0, "d.xml");
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_nested_depth() {
+        let c = build_zip(&[("c.xml", b"<xml/>")]);
+        let b = build_zip(&[("b.zip", &c)]);
+        let data = build_zip(&[("a.zip", &b)]);
+
+        let mut iter = super::ZipPackageParallelIter::new(Cursor::new(data.clone())).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, "c.xml");
+
+        let mut iter = super::ZipPackageParallelIter::new(Cursor::new(data))
+            .unwrap()
+            .with_max_depth(1);
+        assert_eq!(iter.next().unwrap().unwrap_err().entry_name(), "b.zip");
+    }
+}
@@ -0,0 +1,269 @@
+//! Random access to a remote archive over HTTP range requests.
+
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Default number of bytes fetched per range request.
+const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Default number of blocks kept in memory.
+const DEFAULT_CACHE_BLOCKS: usize = 8;
+
+/// A [`Read`] + [`Seek`] over a file served by an HTTP(S) server which supports
+/// `Range` requests.
+///
+/// The file is fetched in aligned blocks, and reads are served from the most
+/// recently used ones, so neither the small reads that `zip` issues while
+/// walking the central directory nor the parallel readers of the entries,
+/// which seek back and forth between them, turn each into a request. Only the
+/// parts of the archive which are actually read get downloaded.
+pub struct HttpRangeReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    pos: u64,
+    /// The cached blocks with their index, the most recently used first
+    blocks: VecDeque<(u64, Vec<u8>)>,
+    block_size: usize,
+    cache_blocks: usize,
+}
+
+impl HttpRangeReader {
+    pub fn new(url: &str) -> std::io::Result<Self> {
+        let agent = ureq::Agent::new_with_defaults();
+        let response = agent.head(url).call().map_err(std::io::Error::other)?;
+        let len = response
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "server did not report the content length",
+                )
+            })?;
+        let accepts_ranges = response
+            .headers()
+            .get("accept-ranges")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("bytes"));
+        if !accepts_ranges {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "server does not support range requests",
+            ));
+        }
+
+        Ok(Self {
+            agent,
+            url: url.to_string(),
+            len,
+            pos: 0,
+            blocks: VecDeque::new(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            cache_blocks: DEFAULT_CACHE_BLOCKS,
+        })
+    }
+
+    /// Set the number of bytes fetched per request (1 MB by default).
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self.blocks.clear();
+        self
+    }
+
+    /// Set the number of blocks kept in memory (8 by default).
+    pub fn cache_blocks(mut self, cache_blocks: usize) -> Self {
+        self.cache_blocks = cache_blocks.max(1);
+        self.blocks.truncate(self.cache_blocks);
+        self
+    }
+
+    /// Total length of the remote file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The block at `index`, from the cache or else fetched.
+    fn block(&mut self, index: u64) -> std::io::Result<&[u8]> {
+        if let Some(i) = self.blocks.iter().position(|(cached, _)| *cached == index) {
+            let block = self.blocks.remove(i).expect("cached block");
+            self.blocks.push_front(block);
+        } else {
+            let block = self.fetch_block(index * self.block_size as u64)?;
+            self.blocks.truncate(self.cache_blocks - 1);
+            self.blocks.push_front((index, block));
+        }
+        Ok(&self.blocks[0].1)
+    }
+
+    fn fetch_block(&self, start: u64) -> std::io::Result<Vec<u8>> {
+        let end = (start + self.block_size as u64).min(self.len) - 1;
+        let mut response = self
+            .agent
+            .get(&self.url)
+            .header("Range", &format!("bytes={}-{}", start, end))
+            .call()
+            .map_err(std::io::Error::other)?;
+        if response.status() != 206 {
+            return Err(std::io::Error::other(format!(
+                "unexpected status for a range request: {}",
+                response.status()
+            )));
+        }
+        // the limit fails the read that would go past it, so reading the
+        // whole block (and then the end of the body) needs one more byte
+        let block = response
+            .body_mut()
+            .with_config()
+            .limit(end - start + 2)
+            .read_to_vec()
+            .map_err(std::io::Error::other)?;
+        if block.len() as u64 != end - start + 1 {
+            return Err(std::io::Error::other(format!(
+                "expected {} bytes for a range request, got {}",
+                end - start + 1,
+                block.len()
+            )));
+        }
+        Ok(block)
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_size = self.block_size as u64;
+        let offset = (self.pos % block_size) as usize;
+        let block = self.block(self.pos / block_size)?;
+        let n = buf.len().min(block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        let Some(new_pos) = new_pos else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        };
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HttpRangeReader;
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use test_log::test;
+
+    /// The first and last bytes of the range requests.
+    type Requests = Arc<Mutex<Vec<(usize, usize)>>>;
+
+    /// Serve `data` with range requests on a local port, and return its URL
+    /// and the requested ranges.
+    fn serve(data: Vec<u8>) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/package.zip", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requested = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let head = lines.next().unwrap().unwrap().starts_with("HEAD");
+                let mut range = (0, data.len() - 1);
+                for line in lines {
+                    let line = line.unwrap().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((start, end)) = line
+                        .strip_prefix("range: bytes=")
+                        .and_then(|range| range.split_once('-'))
+                    {
+                        range = (start.parse().unwrap(), end.parse().unwrap());
+                        requested.lock().unwrap().push(range);
+                    }
+                }
+                let body = if head {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n",
+                        data.len()
+                    )
+                    .unwrap();
+                    &[][..]
+                } else {
+                    let body = &data[range.0..=range.1];
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n",
+                        body.len(),
+                        range.0,
+                        range.1,
+                        data.len()
+                    )
+                    .unwrap();
+                    body
+                };
+                stream.write_all(b"Connection: close\r\n\r\n").unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let (url, requests) = serve(data.clone());
+        let mut reader = HttpRangeReader::new(&url)
+            .unwrap()
+            .block_size(100)
+            .cache_blocks(2);
+        assert_eq!(reader.len(), 1000);
+
+        let read = |reader: &mut HttpRangeReader, pos: u64, len: usize| {
+            let mut buf = vec![0; len];
+            reader.seek(SeekFrom::Start(pos)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[pos as usize..pos as usize + len]);
+        };
+        // two readers seeking back and forth share the cache
+        for _ in 0..3 {
+            read(&mut reader, 110, 20);
+            read(&mut reader, 950, 50);
+        }
+        assert_eq!(*requests.lock().unwrap(), [(100, 199), (900, 999)]);
+        // across blocks, evicting the least recently used one
+        read(&mut reader, 180, 40);
+        assert_eq!(requests.lock().unwrap()[2..], [(200, 299)]);
+        read(&mut reader, 960, 10);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[995..]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Golden tests: the files of `tests/fixtures/` are parsed and resolved, and
+//! the result is compared with the dumps of `tests/golden/`.
+//!
+//! After a deliberate change of the output, rewrite the dumps with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and review their diff.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use mojxml::data::{Feature, ParsedData};
+use mojxml::parser::{Error, MojxmlParser, ParseOptions, jpr_projections};
+use mojxml::writer::xml::XmlWriter;
+use test_log::test;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// Everything but the geometries (`keep_source_coordinates`, so the
+/// coordinates of the dumps don't depend on the projection).
+fn options() -> ParseOptions {
+    ParseOptions {
+        parse_kijunten: true,
+        parse_hikkaiten: true,
+        parse_zukaku: true,
+        keep_source_coordinates: true,
+        stable_ids: true,
+        ..Default::default()
+    }
+}
+
+fn dump_features(out: &mut String, kind: &str, data: &ParsedData, features: &[Feature]) {
+    for feature in features {
+        let _ = writeln!(out, "{} {}", kind, feature.id);
+        for (key, value) in &feature.attributes {
+            let _ = writeln!(out, "  {}: {}", key, value);
+        }
+        let geometry = match data.resolve_point(feature.geometry_id) {
+            Some([x, y]) => format!("POINT ({} {})", x, y),
+            None => data
+                .resolve_surface_wkt(feature.geometry_id)
+                .unwrap_or_else(|e| format!("error: {}", e)),
+        };
+        let _ = writeln!(out, "  {}", geometry);
+    }
+}
+
+/// A text dump of the parsed file, in a stable order.
+fn dump(data: &ParsedData) -> String {
+    let mut out = String::new();
+    let metadata = &data.metadata;
+    for (key, value) in [
+        ("地図名", &metadata.map_name),
+        ("市区町村コード", &metadata.municipality_code),
+        ("市区町村名", &metadata.municipality_name),
+        ("座標系", &metadata.crs),
+        ("測地系判別", &metadata.datum),
+    ] {
+        let _ = writeln!(out, "{}: {}", key, value.as_deref().unwrap_or("-"));
+    }
+
+    let mut fudes: Vec<_> = data.fudes.values().collect();
+    fudes.sort_by(|a, b| a.attributes.id.cmp(&b.attributes.id));
+    for fude in fudes {
+        let attributes = &fude.attributes;
+        let _ = writeln!(out, "筆 {}", attributes.id);
+        for (key, value) in [
+            ("大字コード", &attributes.oaza_code),
+            ("丁目コード", &attributes.chome_code),
+            ("小字コード", &attributes.koaza_code),
+            ("予備コード", &attributes.yobi_code),
+            ("大字名", &attributes.oaza),
+            ("丁目名", &attributes.chome),
+            ("小字名", &attributes.koaza),
+            ("予備名", &attributes.yobi),
+            ("地番", &attributes.chiban),
+            ("筆界未定構成筆", &attributes.hikkai_mitei),
+            ("精度区分", &attributes.accuracy_class),
+            ("座標値種別", &attributes.coord_class),
+        ] {
+            if let Some(value) = value {
+                let _ = writeln!(out, "  {}: {}", key, value);
+            }
+        }
+        if let Some(stable_id) = &attributes.stable_id {
+            let _ = writeln!(out, "  stable_id: {}", stable_id);
+        }
+        let geometry = data
+            .resolve_surface_wkt(fude.surface_id)
+            .unwrap_or_else(|e| format!("error: {}", e));
+        let _ = writeln!(out, "  {}", geometry);
+    }
+
+    dump_features(&mut out, "基準点", data, &data.kijunten);
+    dump_features(&mut out, "筆界点", data, &data.hikkaiten);
+    dump_features(&mut out, "図郭", data, &data.zukaku);
+    out
+}
+
+fn check_golden(name: &str) {
+    let document = fixture(&format!("{}.xml", name));
+    let projections = jpr_projections();
+    let borrowed = MojxmlParser::from_slice(&document, &projections)
+        .with_options(options())
+        .parse()
+        .unwrap();
+    let buffered = MojxmlParser::new(document.as_slice(), &projections)
+        .with_options(options())
+        .parse()
+        .unwrap();
+    let actual = dump(&borrowed);
+    assert_eq!(actual, dump(&buffered), "{}: reader and slice differ", name);
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1)", path.display(), e));
+    assert_eq!(
+        actual,
+        expected,
+        "{}: differs from {}",
+        name,
+        path.display()
+    );
+}
+
+#[test]
+fn test_basic() {
+    check_golden("basic");
+}
+
+#[test]
+fn test_arbitrary_crs() {
+    check_golden("arbitrary_crs");
+}
+
+#[test]
+fn test_multi_ring() {
+    check_golden("multi_ring");
+}
+
+#[test]
+fn test_hikkai_mitei() {
+    check_golden("hikkai_mitei");
+}
+
+#[test]
+fn test_unresolved() {
+    check_golden("unresolved");
+}
+
+/// The fixtures written back by the XML writer parse into the same data.
+#[test]
+fn test_xml_round_trip() {
+    let projections = jpr_projections();
+    for name in [
+        "basic",
+        "arbitrary_crs",
+        "multi_ring",
+        "hikkai_mitei",
+        "unresolved",
+    ] {
+        let document = fixture(&format!("{}.xml", name));
+        let data = MojxmlParser::from_slice(&document, &projections)
+            .with_options(options())
+            .parse()
+            .unwrap();
+        let written = XmlWriter::new(Vec::new()).write_map(&data).unwrap();
+        let reparsed = MojxmlParser::from_slice(&written, &projections)
+            .with_options(options())
+            .parse()
+            .unwrap();
+        assert_eq!(dump(&reparsed), dump(&data), "{}", name);
+    }
+}
+
+/// The conversion of the plane rectangular coordinates with the zone of the
+/// file, and the untouched coordinates of the 任意座標系.
+#[test]
+fn test_projection() {
+    let projections = jpr_projections();
+    let document = fixture("basic.xml");
+    let data = MojxmlParser::from_slice(&document, &projections)
+        .parse()
+        .unwrap();
+    let rings = data.resolve_surface(data.fudes["H1"].surface_id).unwrap();
+    // 公共座標9系, X (northing) = -35000, Y (easting) = -6000
+    let (lng, lat, _) = projections[8]
+        .project_inverse(-6000.0, -35000.0, 0.0)
+        .unwrap();
+    assert_eq!(rings[0][0], [lng, lat]);
+
+    let document = fixture("arbitrary_crs.xml");
+    let data = MojxmlParser::from_slice(&document, &projections)
+        .parse()
+        .unwrap();
+    let rings = data.resolve_surface(data.fudes["H1"].surface_id).unwrap();
+    assert_eq!(rings[0][0], [100.0, 200.0]);
+
+    let mut parser = MojxmlParser::from_slice(&document, &projections);
+    parser.skip_arbitrary_crs(true);
+    assert!(matches!(parser.parse(), Err(Error::SkipAll)));
+}